@@ -0,0 +1,120 @@
+use std::{fs::File, io::Read, path::Path};
+
+use mqtt_core::topic::{TopicFilter, TopicName};
+use serde::Deserialize;
+
+/// Decides which topics a client may publish and subscribe to.
+///
+/// The username is None for clients that did not authenticate.
+pub trait Authorizer: Send + Sync {
+    fn authorize_publish(&self, username: Option<&str>, topic: &TopicName) -> bool;
+
+    fn authorize_subscribe(&self, username: Option<&str>, filter: &TopicFilter) -> bool;
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Publish,
+    Subscribe,
+    All,
+}
+
+impl Action {
+    fn permits(&self, action: Action) -> bool {
+        return *self == Action::All || *self == action;
+    }
+}
+
+#[derive(Deserialize)]
+struct AclRule {
+    // applies to every client when omitted, including clients that did not authenticate.
+    user: Option<String>,
+    topic: String,
+    action: Action,
+    allow: bool,
+}
+
+#[derive(Deserialize)]
+struct AclFile {
+    #[serde(default)]
+    rules: Vec<AclRule>,
+}
+
+/// An access control list loaded from a TOML file.
+///
+/// Rules are evaluated in order and the first rule matching the user, topic and action applies.
+/// Requests that match no rule are denied.
+pub struct FileAuthorizer {
+    rules: Vec<(Option<String>, TopicFilter, Action, bool)>,
+}
+
+impl FileAuthorizer {
+    fn authorize(
+        &self,
+        username: Option<&str>,
+        action: Action,
+        matches: impl Fn(&TopicFilter) -> bool,
+    ) -> bool {
+        for (user, filter, rule_action, allow) in self.rules.iter() {
+            if let Some(user) = user {
+                if Some(user.as_str()) != username {
+                    continue;
+                }
+            }
+
+            if rule_action.permits(action) && matches(filter) {
+                return *allow;
+            }
+        }
+        return false;
+    }
+}
+
+impl Authorizer for FileAuthorizer {
+    fn authorize_publish(&self, username: Option<&str>, topic: &TopicName) -> bool {
+        return self.authorize(username, Action::Publish, |filter| topic == filter);
+    }
+
+    fn authorize_subscribe(&self, username: Option<&str>, filter: &TopicFilter) -> bool {
+        return self.authorize(username, Action::Subscribe, |rule| rule.covers(filter));
+    }
+}
+
+impl TryFrom<&Path> for FileAuthorizer {
+    type Error = toml::de::Error;
+    fn try_from(value: &Path) -> Result<Self, toml::de::Error> {
+        let mut file = match File::open(value) {
+            Ok(file) => file,
+            Err(err) => {
+                log::warn!(
+                    "Could not load file: {} to initialize the access control list.",
+                    value.to_str().unwrap_or("")
+                );
+                log::error!("{err}");
+                panic!();
+            }
+        };
+
+        let mut buf = String::new();
+        if let Err(err) = file.read_to_string(&mut buf) {
+            log::warn!("Could not read file {}", value.to_str().unwrap_or(""));
+            log::error!("{err}");
+        }
+
+        let acl: AclFile = toml::from_str(&buf)?;
+
+        let mut rules = vec![];
+        for rule in acl.rules {
+            let filter = TopicFilter::from_str(&rule.topic).expect(&format!(
+                "Invalid topic filter in access control list: {}",
+                rule.topic
+            ));
+            rules.push((rule.user, filter, rule.action, rule.allow));
+        }
+
+        log::info!("Loaded {} access control rules", rules.len());
+
+        return Ok(Self { rules });
+    }
+}
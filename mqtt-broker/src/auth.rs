@@ -0,0 +1,467 @@
+use std::{collections::HashMap, fs, path::Path, str::FromStr, time::Duration};
+
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use crate::config::MqttConfig;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use futures::future::{self, BoxFuture, FutureExt};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use mqtt_core::{
+    err::server::{self, ServerError},
+    topic::{TopicFilter, TopicName},
+    ConnectReturnCode,
+};
+use r2d2_sqlite::SqliteConnectionManager;
+use rustls::pki_types::CertificateDer;
+use serde::{Deserialize, Serialize};
+use sheesh::{
+    harness::{
+        sqlite::user::SqliteHarnessUser,
+        stateless::{StatelessSession, StatelessToken},
+        DbHarness,
+    },
+    id::DefaultIdGenerator,
+    session::{SessionManager, SessionManagerConfig},
+    user::{UserManager, UserManagerConfig},
+};
+
+/// Verifies the credentials a client sends in its CONNECT.
+///
+/// Authentication may have to wait on a database or a remote service, so it returns a future.
+pub trait Authenticator: Send + Sync {
+    /// Returns the identity the client is known by to the [crate::acl::Authorizer], usually the username it sent.
+    fn authenticate<'a>(
+        &'a self,
+        client_id: &'a str,
+        username: &'a str,
+        password: &'a [u8],
+    ) -> BoxFuture<'a, Result<Identity, ServerError>>;
+}
+
+/// The client a connection authenticated as.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    username: String,
+    // the topics the client's credentials are limited to, None leaves every topic to the authorizer.
+    topics: Option<TopicClaims>,
+}
+
+#[derive(Debug, Clone)]
+struct TopicClaims {
+    publish: Vec<TopicFilter>,
+    subscribe: Vec<TopicFilter>,
+}
+
+impl Identity {
+    pub fn new(username: String) -> Self {
+        return Self {
+            username,
+            topics: None,
+        };
+    }
+
+    /// An identity whose credentials only permit the given topics, on top of the broker's [crate::acl::Authorizer].
+    pub fn with_topics(
+        username: String,
+        publish: Vec<TopicFilter>,
+        subscribe: Vec<TopicFilter>,
+    ) -> Self {
+        return Self {
+            username,
+            topics: Some(TopicClaims { publish, subscribe }),
+        };
+    }
+
+    pub fn username(&self) -> &str {
+        return &self.username;
+    }
+
+    pub fn permits_publish(&self, topic: &TopicName) -> bool {
+        match &self.topics {
+            Some(topics) => return topics.publish.iter().any(|filter| topic == filter),
+            None => return true,
+        }
+    }
+
+    /// A subscription is only permitted by a filter that covers the whole requested filter.
+    pub fn permits_subscribe(&self, filter: &TopicFilter) -> bool {
+        match &self.topics {
+            Some(topics) => return topics.subscribe.iter().any(|rule| rule.covers(filter)),
+            None => return true,
+        }
+    }
+}
+
+/// The authenticator selected by `authenticator` in the `[users]` section of the config.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthBackend {
+    #[default]
+    Sqlite,
+    PasswordFile,
+    Webhook,
+    Jwt,
+}
+
+/// The name of a client certificate a client is known by, selected by `cert_username` in the `[connection]` section.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CertUsername {
+    // the subject's common name.
+    #[default]
+    Cn,
+    // the first DNS name, email address or URI of the subject alternative name extension.
+    San,
+}
+
+/// Returns the identity of a client that presented the certificate, None if the certificate does not carry the name.
+///
+/// The certificate must already have been verified by the TLS handshake.
+pub fn cert_identity(cert: &CertificateDer, source: CertUsername) -> Option<Identity> {
+    let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+
+    let username = match source {
+        CertUsername::Cn => cert
+            .subject()
+            .iter_common_name()
+            .next()?
+            .as_str()
+            .ok()?
+            .to_string(),
+        CertUsername::San => {
+            let san = cert.subject_alternative_name().ok()??;
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::DNSName(name)
+                | GeneralName::RFC822Name(name)
+                | GeneralName::URI(name) => Some(name.to_string()),
+                _ => None,
+            })?
+        }
+    };
+
+    return Some(Identity::new(username));
+}
+
+fn bad_credentials(msg: String) -> ServerError {
+    return ServerError::new(
+        server::ErrorKind::ConnectError(ConnectReturnCode::BadUsernameOrPassword),
+        msg,
+    );
+}
+
+/// Authenticates users stored in the broker's sqlite database.
+pub struct SqliteAuthenticator {
+    user: UserManager<DefaultIdGenerator, SqliteHarnessUser>,
+    session: SessionManager<DefaultIdGenerator, StatelessSession, StatelessToken>,
+}
+
+impl SqliteAuthenticator {
+    pub fn new(pool: r2d2::Pool<SqliteConnectionManager>) -> Self {
+        let harness = DbHarness::new_stateless_sqlite(pool);
+
+        harness.init_tables().unwrap();
+
+        let user = UserManagerConfig::default().init(harness.user);
+        let session = SessionManagerConfig::default().init(harness.session, harness.token);
+
+        return Self { user, session };
+    }
+}
+
+impl Authenticator for SqliteAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        _client_id: &'a str,
+        username: &'a str,
+        password: &'a [u8],
+    ) -> BoxFuture<'a, Result<Identity, ServerError>> {
+        let res = match std::str::from_utf8(password) {
+            Ok(password) => match self.user.login(&self.session, username, password) {
+                Ok(_) => Ok(Identity::new(username.to_string())),
+                Err(_) => Err(bad_credentials(String::from(
+                    "Client attempted to connect with invalid credentials",
+                ))),
+            },
+            Err(_) => Err(bad_credentials(String::from(
+                "Client attempted to connect with a password that is not valid UTF-8",
+            ))),
+        };
+        return future::ready(res).boxed();
+    }
+}
+
+/// Authenticates users listed in a password file.
+///
+/// Each line holds a username and a bcrypt or argon2 hash separated by a colon, `alice:$2b$12$...`.
+/// Blank lines and lines starting with `#` are ignored.
+pub struct PasswordFileAuthenticator {
+    hashes: HashMap<String, String>,
+}
+
+impl TryFrom<&Path> for PasswordFileAuthenticator {
+    type Error = std::io::Error;
+    fn try_from(value: &Path) -> Result<Self, std::io::Error> {
+        let contents = fs::read_to_string(value)?;
+
+        let mut hashes = HashMap::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once(':') {
+                Some((username, hash)) => {
+                    hashes.insert(username.to_string(), hash.to_string());
+                }
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Password file line is missing a hash: {line}"),
+                    ));
+                }
+            }
+        }
+
+        log::info!("Loaded {} users from the password file", hashes.len());
+
+        return Ok(Self { hashes });
+    }
+}
+
+fn verify_hash(password: &[u8], hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        return match PasswordHash::new(hash) {
+            Ok(hash) => Argon2::default().verify_password(password, &hash).is_ok(),
+            Err(_) => false,
+        };
+    }
+    return bcrypt::verify(password, hash).unwrap_or(false);
+}
+
+impl Authenticator for PasswordFileAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        _client_id: &'a str,
+        username: &'a str,
+        password: &'a [u8],
+    ) -> BoxFuture<'a, Result<Identity, ServerError>> {
+        return async move {
+            let hash = match self.hashes.get(username) {
+                Some(hash) => hash.clone(),
+                None => {
+                    return Err(bad_credentials(format!(
+                        "Client attempted to connect as unknown user: {username}"
+                    )))
+                }
+            };
+
+            // hashes are deliberately slow to verify, keep them off the runtime's worker threads.
+            let password = password.to_vec();
+            let verified = tokio::task::spawn_blocking(move || verify_hash(&password, &hash))
+                .await
+                .unwrap_or(false);
+
+            if !verified {
+                return Err(bad_credentials(format!(
+                    "Client attempted to connect as user: {username} with an invalid password"
+                )));
+            }
+            return Ok(Identity::new(username.to_string()));
+        }
+        .boxed();
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookRequest<'a> {
+    client_id: &'a str,
+    username: &'a str,
+    password: &'a str,
+}
+
+/// Authenticates clients by posting their credentials to an HTTP endpoint.
+///
+/// The credentials are sent as a JSON object with `client_id`, `username` and `password` fields,
+/// and the client is accepted if the endpoint responds with a 2xx status.
+pub struct WebhookAuthenticator {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookAuthenticator {
+    pub fn new(url: String, timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Could not build the authentication webhook's HTTP client");
+        return Self { client, url };
+    }
+}
+
+impl Authenticator for WebhookAuthenticator {
+    fn authenticate<'a>(
+        &'a self,
+        client_id: &'a str,
+        username: &'a str,
+        password: &'a [u8],
+    ) -> BoxFuture<'a, Result<Identity, ServerError>> {
+        return async move {
+            let password = match std::str::from_utf8(password) {
+                Ok(password) => password,
+                Err(_) => {
+                    return Err(bad_credentials(String::from(
+                        "Client attempted to connect with a password that is not valid UTF-8",
+                    )))
+                }
+            };
+
+            let request = WebhookRequest {
+                client_id,
+                username,
+                password,
+            };
+            match self.client.post(&self.url).json(&request).send().await {
+                Ok(res) if res.status().is_success() => return Ok(Identity::new(username.to_string())),
+                Ok(res) if res.status() == reqwest::StatusCode::FORBIDDEN => {
+                    return Err(ServerError::new(
+                        server::ErrorKind::ConnectError(ConnectReturnCode::NotAuthorized),
+                        format!("Authentication webhook did not authorize user: {username}"),
+                    ))
+                }
+                Ok(res) => {
+                    return Err(bad_credentials(format!(
+                        "Authentication webhook refused user: {username} with status {}",
+                        res.status()
+                    )))
+                }
+                Err(err) => {
+                    // a webhook that cannot be reached refuses every client rather than admitting them.
+                    log::error!("Authentication webhook request failed, {err}");
+                    return Err(ServerError::new(
+                        server::ErrorKind::ConnectError(ConnectReturnCode::ServerUnavailable),
+                        format!("Could not authenticate user: {username}, the authentication webhook is unavailable"),
+                    ));
+                }
+            }
+        }
+        .boxed();
+    }
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    sub: String,
+    publish: Option<Vec<String>>,
+    subscribe: Option<Vec<String>>,
+}
+
+/// Authenticates clients that send a JSON Web Token as their password.
+///
+/// The token's signature, `exp` and, when configured, `iss` and `aud` claims are validated, and the client is known by
+/// its `sub` claim. Tokens may carry `publish` and `subscribe` claims listing the topic filters the client may use,
+/// a token with only one of them permits nothing for the other action.
+pub struct JwtAuthenticator {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl JwtAuthenticator {
+    pub fn new(config: &MqttConfig) -> Self {
+        let algorithm = Algorithm::from_str(config.jwt_algorithm()).expect(&format!(
+            "Invalid JWT algorithm: {}",
+            config.jwt_algorithm()
+        ));
+
+        let key = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => DecodingKey::from_secret(
+                config
+                    .jwt_secret()
+                    .expect("HMAC signed tokens require a jwt_secret")
+                    .as_bytes(),
+            ),
+            _ => {
+                let path = config
+                    .jwt_public_key()
+                    .expect("Asymmetrically signed tokens require a jwt_public_key");
+                let pem = fs::read(&path).expect(&format!(
+                    "Could not read JWT public key: {}",
+                    path.to_str().unwrap_or("")
+                ));
+                let key = match algorithm {
+                    Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+                    Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+                    _ => DecodingKey::from_rsa_pem(&pem),
+                };
+                key.expect("Invalid JWT public key")
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = config.jwt_issuer() {
+            validation.set_issuer(&[issuer]);
+        }
+        match config.jwt_audience() {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        return Self { key, validation };
+    }
+
+    fn verify(&self, token: &[u8]) -> Result<Identity, ServerError> {
+        let token = match std::str::from_utf8(token) {
+            Ok(token) => token,
+            Err(_) => {
+                return Err(bad_credentials(String::from(
+                    "Client attempted to connect with a token that is not valid UTF-8",
+                )))
+            }
+        };
+
+        let claims = match jsonwebtoken::decode::<JwtClaims>(token, &self.key, &self.validation) {
+            Ok(data) => data.claims,
+            Err(err) => {
+                return Err(bad_credentials(format!(
+                    "Client attempted to connect with an invalid token, {err}"
+                )))
+            }
+        };
+
+        if claims.publish.is_none() && claims.subscribe.is_none() {
+            return Ok(Identity::new(claims.sub));
+        }
+
+        let publish = topic_claim(claims.publish)?;
+        let subscribe = topic_claim(claims.subscribe)?;
+        return Ok(Identity::with_topics(claims.sub, publish, subscribe));
+    }
+}
+
+fn topic_claim(filters: Option<Vec<String>>) -> Result<Vec<TopicFilter>, ServerError> {
+    let mut topics = vec![];
+    for filter in filters.unwrap_or_default() {
+        match TopicFilter::from_str(&filter) {
+            Ok(filter) => topics.push(filter),
+            Err(_) => {
+                return Err(bad_credentials(format!(
+                    "Client attempted to connect with a token claiming an invalid topic filter: {filter}"
+                )))
+            }
+        }
+    }
+    return Ok(topics);
+}
+
+impl Authenticator for JwtAuthenticator {
+    /// The username is not checked, the client is known by the token's subject.
+    fn authenticate<'a>(
+        &'a self,
+        _client_id: &'a str,
+        _username: &'a str,
+        password: &'a [u8],
+    ) -> BoxFuture<'a, Result<Identity, ServerError>> {
+        return future::ready(self.verify(password)).boxed();
+    }
+}
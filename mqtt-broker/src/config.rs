@@ -1,30 +1,108 @@
-use core::net::Ipv4Addr;
+use core::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use std::{
     fs::File,
     io::Read,
     path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
 use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 
+use crate::auth::{AuthBackend, CertUsername};
+
 #[derive(Deserialize, Serialize, Default)]
 pub struct MqttConfig {
     connection: Connection,
     users: Users,
     logger: Logger,
     broker: Broker,
+    #[serde(default)]
+    quic: Quic,
+    #[serde(default)]
+    websocket: WebSocket,
+    // listeners in addition to the [connection] listener.
+    #[serde(default, rename = "listener")]
+    listeners: Vec<Listener>,
+    // applied to the topic of every published message, in order.
+    #[serde(default, rename = "rewrite")]
+    rewrite_rules: Vec<RewriteRule>,
 }
 
 impl MqttConfig {
-    pub fn addr(&self) -> String {
-        return self.connection.ip.to_string() + ":" + &self.connection.port.to_string();
+    /// Every TCP listener the broker accepts connections on.
+    ///
+    /// The `[connection]` listener comes first, followed by the `[websocket]` listener if it is enabled and each
+    /// `[[listener]]` entry. The `[websocket]` listener uses the certificate and key of the `[connection]` listener.
+    pub fn listeners(&self) -> Vec<Listener> {
+        let mut listeners = vec![Listener {
+            address: self.connection.ip.into(),
+            port: self.connection.port,
+            tls: self.connection.tls,
+            cert_path: self.connection.cert_path.clone(),
+            key_path: self.connection.key_path.clone(),
+            websocket: false,
+        }];
+
+        if self.websocket.enabled {
+            listeners.push(Listener {
+                address: self.connection.ip.into(),
+                port: self.websocket.port,
+                tls: self.websocket.tls,
+                cert_path: self.connection.cert_path.clone(),
+                key_path: self.connection.key_path.clone(),
+                websocket: true,
+            });
+        }
+
+        listeners.extend(self.listeners.iter().cloned());
+        return listeners;
     }
 
-    pub fn is_tls_enabled(&self) -> bool {
-        return self.connection.tls;
+    /// The `[[rewrite]]` rules applied to the topics of published messages.
+    pub fn rewrite_rules(&self) -> &[RewriteRule] {
+        return &self.rewrite_rules;
+    }
+
+    /// The certificate chain of the `[connection]` listener.
+    pub fn cert_path(&self) -> PathBuf {
+        return tls_path(&self.connection.cert_path, "tls/cert.pem");
+    }
+
+    /// The private key of the `[connection]` listener.
+    pub fn key_path(&self) -> PathBuf {
+        return tls_path(&self.connection.key_path, "tls/key.pem");
+    }
+
+    /// The address of the experimental QUIC listener, None if the listener is disabled.
+    ///
+    /// The QUIC listener uses the same certificate and key as the `[connection]` listener.
+    pub fn quic_addr(&self) -> Option<SocketAddr> {
+        if !self.quic.enabled {
+            return None;
+        }
+        return Some(SocketAddr::new(self.connection.ip.into(), self.quic.port));
+    }
+
+    /// Whether TLS clients must present a certificate signed by the CA bundle at [Self::client_ca_path].
+    pub fn require_client_cert(&self) -> bool {
+        return self.connection.require_client_cert;
+    }
+
+    pub fn client_ca_path(&self) -> PathBuf {
+        match &self.connection.client_ca_path {
+            Some(path) => {
+                PathBuf::from_str(&path).expect(&format!("Invalid client CA bundle path: {path}"))
+            }
+            None => PathBuf::from_str("tls/ca.pem").unwrap(),
+        }
+    }
+
+    /// Which name of a client certificate the client is known by to the access control list.
+    pub fn cert_username(&self) -> CertUsername {
+        return self.connection.cert_username;
     }
 
     pub fn should_log_file(&self) -> bool {
@@ -48,10 +126,245 @@ impl MqttConfig {
         return self.users.authenticate;
     }
 
+    /// Which authenticator verifies the credentials of connecting clients.
+    pub fn auth_backend(&self) -> AuthBackend {
+        return self.users.authenticator;
+    }
+
+    /// The password file read by the `password_file` authenticator.
+    pub fn password_file(&self) -> PathBuf {
+        match &self.users.password_file {
+            Some(path) => {
+                PathBuf::from_str(&path).expect(&format!("Invalid password file path: {path}"))
+            }
+            None => PathBuf::from_str("passwords").unwrap(),
+        }
+    }
+
+    /// The endpoint the `webhook` authenticator posts credentials to.
+    pub fn auth_webhook_url(&self) -> Option<&str> {
+        return self.users.webhook_url.as_deref();
+    }
+
+    /// The signing algorithm of the tokens accepted by the `jwt` authenticator, e.g. HS256 or RS256.
+    pub fn jwt_algorithm(&self) -> &str {
+        return &self.users.jwt_algorithm;
+    }
+
+    /// The shared secret of HMAC signed tokens.
+    pub fn jwt_secret(&self) -> Option<&str> {
+        return self.users.jwt_secret.as_deref();
+    }
+
+    /// The PEM encoded public key of RSA, ECDSA or EdDSA signed tokens.
+    pub fn jwt_public_key(&self) -> Option<PathBuf> {
+        return self.users.jwt_public_key.as_ref().map(|path| {
+            PathBuf::from_str(&path).expect(&format!("Invalid JWT public key path: {path}"))
+        });
+    }
+
+    /// The `iss` claim tokens must carry, None accepts any issuer.
+    pub fn jwt_issuer(&self) -> Option<&str> {
+        return self.users.jwt_issuer.as_deref();
+    }
+
+    /// The `aud` claim tokens must carry, None accepts any audience.
+    pub fn jwt_audience(&self) -> Option<&str> {
+        return self.users.jwt_audience.as_deref();
+    }
+
+    /// How long the `webhook` authenticator waits for a response before refusing the client.
+    pub fn auth_webhook_timeout(&self) -> Duration {
+        return Duration::from_secs(self.users.webhook_timeout);
+    }
+
+    /// The access control list file, None if every client may use every topic.
+    pub fn acl_path(&self) -> Option<PathBuf> {
+        return self.users.acl_path.as_ref().map(|path| {
+            PathBuf::from_str(&path).expect(&format!("Invalid access control list path: {path}"))
+        });
+    }
+
     pub fn max_queued_messages(&self) -> usize {
         return self.broker.max_queued_messages;
     }
 
+    /// How long a new connection has to send its CONNECT packet before it is closed.
+    pub fn connect_timeout(&self) -> Duration {
+        return Duration::from_secs(self.broker.connect_timeout);
+    }
+
+    /// How long, in seconds, a persistent session is retained after its client disconnects, 0 retains sessions indefinitely.
+    pub fn session_expiry_interval(&self) -> u64 {
+        return self.broker.session_expiry_interval;
+    }
+
+    /// How long, in seconds, the will of a lost v3.1.1 client is held back, 0 publishes wills immediately.
+    ///
+    /// MQTT 5.0 clients send their own Will Delay Interval.
+    pub fn will_delay_interval(&self) -> u64 {
+        return self.broker.will_delay_interval;
+    }
+
+    /// Whether disconnected sessions are written to the broker's database and restored on startup.
+    pub fn persist_sessions(&self) -> bool {
+        return self.broker.persist_sessions;
+    }
+
+    /// Whether legacy clients may connect with MQTT v3.1, they are refused with an Invalid protocol CONNACK otherwise.
+    pub fn allow_mqtt_v3_1(&self) -> bool {
+        return self.broker.allow_mqtt_v3_1;
+    }
+
+    /// The highest Topic Alias an MQTT 5.0 client may send, 0 disables topic aliases.
+    pub fn topic_alias_maximum(&self) -> u16 {
+        return self.broker.topic_alias_maximum;
+    }
+
+    /// How long a write to a client may stall before the connection is considered failed.
+    ///
+    /// Returns None if writes never time out.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        if self.broker.write_timeout == 0 {
+            return None;
+        }
+        return Some(Duration::from_secs(self.broker.write_timeout));
+    }
+
+    /// The number of bytes a session may hold in unacknowledged packets before the broker stops forwarding messages to it.
+    ///
+    /// Returns None if the limit is disabled.
+    pub fn max_session_memory(&self) -> Option<usize> {
+        if self.broker.max_session_memory == 0 {
+            return None;
+        }
+        return Some(self.broker.max_session_memory);
+    }
+
+    /// How often session statistics are published to the $SYS topics.
+    ///
+    /// Returns None if $SYS reports are disabled.
+    pub fn sys_interval(&self) -> Option<Duration> {
+        if self.broker.sys_interval == 0 {
+            return None;
+        }
+        return Some(Duration::from_secs(self.broker.sys_interval));
+    }
+
+    /// Returns None if the number of retained messages is unlimited.
+    pub fn max_retained_messages(&self) -> Option<usize> {
+        if self.broker.max_retained_messages == 0 {
+            return None;
+        }
+        return Some(self.broker.max_retained_messages);
+    }
+
+    /// Returns None if the payload size of retained messages is unlimited.
+    pub fn max_retained_payload(&self) -> Option<usize> {
+        if self.broker.max_retained_payload == 0 {
+            return None;
+        }
+        return Some(self.broker.max_retained_payload);
+    }
+
+    /// How often topics with no subscribers and no retained message are removed.
+    ///
+    /// Returns None if compaction is disabled.
+    pub fn compaction_interval(&self) -> Option<Duration> {
+        if self.broker.compaction_interval == 0 {
+            return None;
+        }
+        return Some(Duration::from_secs(self.broker.compaction_interval));
+    }
+
+    /// How long the broker waits for connected clients to be disconnected when it shuts down.
+    pub fn shutdown_timeout(&self) -> Duration {
+        return Duration::from_secs(self.broker.shutdown_timeout);
+    }
+
+    /// The largest packet a client may send, including its fixed header.
+    ///
+    /// Returns None if packets up to the protocol maximum of 256MB are accepted.
+    pub fn max_packet_size(&self) -> Option<usize> {
+        if self.broker.max_packet_size == 0 {
+            return None;
+        }
+        return Some(self.broker.max_packet_size);
+    }
+
+    /// The number of QoS 1 messages sent to a client that may await acknowledgement at once.
+    ///
+    /// Returns None if the number is unlimited.
+    pub fn max_inflight_qos1(&self) -> Option<usize> {
+        if self.broker.max_inflight_qos1 == 0 {
+            return None;
+        }
+        return Some(self.broker.max_inflight_qos1);
+    }
+
+    /// The number of QoS 2 messages sent to a client that may await the completion of their handshake at once.
+    ///
+    /// Returns None if the number is unlimited.
+    pub fn max_inflight_qos2(&self) -> Option<usize> {
+        if self.broker.max_inflight_qos2 == 0 {
+            return None;
+        }
+        return Some(self.broker.max_inflight_qos2);
+    }
+
+    /// The number of QoS 2 messages received from a client that may await release at once, MQTT 5.0 clients are
+    /// told it as the Receive Maximum.
+    ///
+    /// Returns None if the number is unlimited.
+    pub fn receive_maximum(&self) -> Option<usize> {
+        if self.broker.receive_maximum == 0 {
+            return None;
+        }
+        return Some(self.broker.receive_maximum);
+    }
+
+    /// Returns None if the number of connections is unlimited.
+    pub fn max_connections(&self) -> Option<usize> {
+        if self.broker.max_connections == 0 {
+            return None;
+        }
+        return Some(self.broker.max_connections);
+    }
+
+    /// Returns None if the number of connections from one address is unlimited.
+    pub fn max_connections_per_ip(&self) -> Option<usize> {
+        if self.broker.max_connections_per_ip == 0 {
+            return None;
+        }
+        return Some(self.broker.max_connections_per_ip);
+    }
+
+    /// The number of connects accepted from one address per window, and the window length.
+    ///
+    /// Returns None if connect rate limiting is disabled.
+    pub fn connect_rate_limit(&self) -> Option<(u32, Duration)> {
+        if self.broker.connect_rate_burst == 0 || self.broker.connect_rate_window == 0 {
+            return None;
+        }
+        return Some((
+            self.broker.connect_rate_burst,
+            Duration::from_secs(self.broker.connect_rate_window),
+        ));
+    }
+
+    /// The number of identical messages logged per window, and the window length.
+    ///
+    /// Returns None if rate limiting is disabled.
+    pub fn log_rate_limit(&self) -> Option<(u32, Duration)> {
+        if self.logger.rate_limit_burst == 0 || self.logger.rate_limit_window == 0 {
+            return None;
+        }
+        return Some((
+            self.logger.rate_limit_burst,
+            Duration::from_secs(self.logger.rate_limit_window),
+        ));
+    }
+
     pub fn log_level(&self) -> LevelFilter {
         return LevelFilter::from_str(&self.logger.level).expect(&format!(
             "Invalid log level provided: {}. Accepted levels are: Off, Error, Warn, Info, Debug",
@@ -106,6 +419,16 @@ struct Connection {
     tls: bool,
     ip: Ipv4Addr,
     port: u16,
+    #[serde(default)]
+    cert_path: Option<String>,
+    #[serde(default)]
+    key_path: Option<String>,
+    #[serde(default)]
+    require_client_cert: bool,
+    #[serde(default)]
+    client_ca_path: Option<String>,
+    #[serde(default)]
+    cert_username: CertUsername,
 }
 
 impl Default for Connection {
@@ -114,30 +437,62 @@ impl Default for Connection {
             tls: false,
             ip: Ipv4Addr::new(127, 0, 0, 1),
             port: 1883,
+            cert_path: None,
+            key_path: None,
+            require_client_cert: false,
+            client_ca_path: None,
+            cert_username: CertUsername::Cn,
         };
     }
 }
 
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Users {
     authenticate: bool,
+    authenticator: AuthBackend,
     user_db_path: Option<String>,
+    password_file: Option<String>,
+    webhook_url: Option<String>,
+    // seconds
+    webhook_timeout: u64,
+    jwt_algorithm: String,
+    jwt_secret: Option<String>,
+    jwt_public_key: Option<String>,
+    jwt_issuer: Option<String>,
+    jwt_audience: Option<String>,
+    acl_path: Option<String>,
 }
 
 impl Default for Users {
     fn default() -> Self {
         return Self {
             authenticate: false,
+            authenticator: AuthBackend::Sqlite,
             user_db_path: None,
+            password_file: None,
+            webhook_url: None,
+            webhook_timeout: 5,
+            jwt_algorithm: String::from("HS256"),
+            jwt_secret: None,
+            jwt_public_key: None,
+            jwt_issuer: None,
+            jwt_audience: None,
+            acl_path: None,
         };
     }
 }
 
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Logger {
     console: bool,
     file: bool,
     level: String,
+    // number of identical messages logged per window before they are suppressed, 0 disables rate limiting.
+    rate_limit_burst: u32,
+    // seconds
+    rate_limit_window: u64,
 }
 
 impl Default for Logger {
@@ -146,19 +501,193 @@ impl Default for Logger {
             console: true,
             file: true,
             level: String::from("trace"),
+            rate_limit_burst: 5,
+            rate_limit_window: 60,
         };
     }
 }
 
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Broker {
     max_queued_messages: usize,
+    // seconds
+    connect_timeout: u64,
+    // seconds, 0 disables the timeout.
+    write_timeout: u64,
+    // seconds, 0 retains disconnected sessions indefinitely.
+    session_expiry_interval: u64,
+    // seconds, 0 publishes the will of a lost client immediately.
+    will_delay_interval: u64,
+    // keep disconnected sessions in the user database so they survive a restart.
+    persist_sessions: bool,
+    // accept CONNECT packets with the v3.1 Protocol Name "MQIsdp".
+    allow_mqtt_v3_1: bool,
+    // the number of topic aliases an MQTT 5.0 client may establish, 0 disables topic aliases.
+    topic_alias_maximum: u16,
+    // bytes, 0 disables the limit.
+    max_session_memory: usize,
+    // seconds, 0 disables $SYS reports.
+    sys_interval: u64,
+    // 0 disables the limit.
+    max_retained_messages: usize,
+    // bytes, 0 disables the limit.
+    max_retained_payload: usize,
+    // seconds, 0 disables topic compaction.
+    compaction_interval: u64,
+    // seconds
+    shutdown_timeout: u64,
+    // 0 disables the limit.
+    max_connections: usize,
+    // 0 disables the limit.
+    max_connections_per_ip: usize,
+    // number of connects accepted from one address per window, 0 disables rate limiting.
+    connect_rate_burst: u32,
+    // seconds
+    connect_rate_window: u64,
+    // bytes, 0 accepts packets up to the protocol maximum.
+    max_packet_size: usize,
+    // 0 disables the limit.
+    max_inflight_qos1: usize,
+    // 0 disables the limit.
+    max_inflight_qos2: usize,
+    // QoS 2 messages a client may have awaiting release, 0 disables the limit.
+    receive_maximum: usize,
 }
 
 impl Default for Broker {
     fn default() -> Self {
         return Self {
             max_queued_messages: 128,
+            connect_timeout: 10,
+            write_timeout: 30,
+            session_expiry_interval: 2 * 60 * 60,
+            will_delay_interval: 0,
+            persist_sessions: false,
+            allow_mqtt_v3_1: false,
+            topic_alias_maximum: 16,
+            max_session_memory: 64 * 1024 * 1024,
+            sys_interval: 10,
+            max_retained_messages: 10_000,
+            max_retained_payload: 1024 * 1024,
+            compaction_interval: 60,
+            shutdown_timeout: 10,
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            connect_rate_burst: 0,
+            connect_rate_window: 60,
+            max_packet_size: 1024 * 1024,
+            max_inflight_qos1: 0,
+            max_inflight_qos2: 0,
+            receive_maximum: 0,
+        };
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Quic {
+    enabled: bool,
+    port: u16,
+}
+
+impl Default for Quic {
+    fn default() -> Self {
+        return Self {
+            enabled: false,
+            port: 14567,
+        };
+    }
+}
+
+fn tls_path(path: &Option<String>, default: &str) -> PathBuf {
+    match path {
+        Some(path) => {
+            PathBuf::from_str(&path).expect(&format!("Invalid certificate or key path: {path}"))
+        }
+        None => PathBuf::from_str(default).unwrap(),
+    }
+}
+
+/// A TCP listener serving MQTT, or MQTT over WebSockets, optionally over TLS.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Listener {
+    address: IpAddr,
+    port: u16,
+    #[serde(default)]
+    tls: bool,
+    // defaults to tls/cert.pem
+    #[serde(default)]
+    cert_path: Option<String>,
+    // defaults to tls/key.pem
+    #[serde(default)]
+    key_path: Option<String>,
+    #[serde(default)]
+    websocket: bool,
+}
+
+impl Listener {
+    pub fn addr(&self) -> SocketAddr {
+        return SocketAddr::new(self.address, self.port);
+    }
+
+    pub fn is_tls_enabled(&self) -> bool {
+        return self.tls;
+    }
+
+    /// Whether clients connect over WebSockets, upgraded with the `mqtt` subprotocol.
+    pub fn is_websocket(&self) -> bool {
+        return self.websocket;
+    }
+
+    pub fn cert_path(&self) -> PathBuf {
+        return tls_path(&self.cert_path, "tls/cert.pem");
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        return tls_path(&self.key_path, "tls/key.pem");
+    }
+}
+
+/// Rewrites topics starting with `prefix`, or matching the regular expression `pattern`, using `replacement`.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RewriteRule {
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    pattern: Option<String>,
+    replacement: String,
+}
+
+impl RewriteRule {
+    pub fn prefix(&self) -> Option<&str> {
+        return self.prefix.as_deref();
+    }
+
+    pub fn pattern(&self) -> Option<&str> {
+        return self.pattern.as_deref();
+    }
+
+    pub fn replacement(&self) -> &str {
+        return &self.replacement;
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebSocket {
+    enabled: bool,
+    port: u16,
+    // serve wss instead of ws.
+    tls: bool,
+}
+
+impl Default for WebSocket {
+    fn default() -> Self {
+        return Self {
+            enabled: false,
+            port: 8080,
+            tls: false,
         };
     }
 }
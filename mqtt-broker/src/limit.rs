@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::config::MqttConfig;
+
+/// The limit a refused connection would have exceeded, see [ConnectionLimiter::try_acquire].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitExceeded {
+    Connections,
+    ConnectionsPerIp,
+    ConnectRate,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connections => return write!(f, "Broker reached its connection limit"),
+            Self::ConnectionsPerIp => return write!(f, "Address reached its connection limit"),
+            Self::ConnectRate => return write!(f, "Address exceeded its connect rate"),
+        }
+    }
+}
+
+/// Caps the number of connections the broker holds, in total and per address, and how often one address may connect.
+pub struct ConnectionLimiter {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    // connects allowed from one address per window.
+    connect_rate: Option<(u32, Duration)>,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    connections: usize,
+    connections_per_ip: HashMap<IpAddr, usize>,
+    // the start of each address's rate window, and the connects made within it.
+    connects: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: &MqttConfig) -> Self {
+        return Self {
+            max_connections: config.max_connections(),
+            max_connections_per_ip: config.max_connections_per_ip(),
+            connect_rate: config.connect_rate_limit(),
+            state: Mutex::new(LimiterState {
+                connections: 0,
+                connections_per_ip: HashMap::new(),
+                connects: HashMap::new(),
+            }),
+        };
+    }
+
+    /// Counts a new connection from the address towards the limits.
+    ///
+    /// The connection is counted until the returned permit is dropped. Refused connects still count towards the connect rate,
+    /// so a client retrying in a loop stays refused until it backs off.
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, LimitExceeded> {
+        return self.try_acquire_at(ip, Instant::now());
+    }
+
+    fn try_acquire_at(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        now: Instant,
+    ) -> Result<ConnectionPermit, LimitExceeded> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some((burst, window)) = self.connect_rate {
+            // expire old windows so the map does not grow with every address that ever connected.
+            state
+                .connects
+                .retain(|_, (started, _)| now.duration_since(*started) < window);
+
+            let (_, count) = state.connects.entry(ip).or_insert((now, 0));
+            *count = count.saturating_add(1);
+            if *count > burst {
+                return Err(LimitExceeded::ConnectRate);
+            }
+        }
+
+        if let Some(max) = self.max_connections_per_ip {
+            if state.connections_per_ip.get(&ip).copied().unwrap_or(0) >= max {
+                return Err(LimitExceeded::ConnectionsPerIp);
+            }
+        }
+
+        if let Some(max) = self.max_connections {
+            if state.connections >= max {
+                return Err(LimitExceeded::Connections);
+            }
+        }
+
+        state.connections += 1;
+        *state.connections_per_ip.entry(ip).or_insert(0) += 1;
+
+        return Ok(ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip,
+        });
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut state = self.state.lock().unwrap();
+        state.connections -= 1;
+
+        if let Some(count) = state.connections_per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                state.connections_per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Holds a connection's place within the [ConnectionLimiter] limits until it is dropped.
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod limits {
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use super::{ConnectionLimiter, LimitExceeded, LimiterState};
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn limiter(
+        max_connections: Option<usize>,
+        max_connections_per_ip: Option<usize>,
+        connect_rate: Option<(u32, Duration)>,
+    ) -> Arc<ConnectionLimiter> {
+        return Arc::new(ConnectionLimiter {
+            max_connections,
+            max_connections_per_ip,
+            connect_rate,
+            state: Mutex::new(LimiterState {
+                connections: 0,
+                connections_per_ip: HashMap::new(),
+                connects: HashMap::new(),
+            }),
+        });
+    }
+
+    #[test]
+    fn connections_per_ip() {
+        let limiter = limiter(None, Some(2), None);
+
+        let _first = limiter.try_acquire(CLIENT).unwrap();
+        let _second = limiter.try_acquire(CLIENT).unwrap();
+        assert_eq!(
+            limiter.try_acquire(CLIENT).err(),
+            Some(LimitExceeded::ConnectionsPerIp)
+        );
+        // other addresses have their own limit.
+        assert!(limiter.try_acquire(OTHER).is_ok());
+    }
+
+    #[test]
+    fn connections() {
+        let limiter = limiter(Some(1), None, None);
+
+        let _permit = limiter.try_acquire(CLIENT).unwrap();
+        assert_eq!(
+            limiter.try_acquire(OTHER).err(),
+            Some(LimitExceeded::Connections)
+        );
+    }
+
+    #[test]
+    fn permit_released_on_drop() {
+        let limiter = limiter(Some(1), Some(1), None);
+
+        let permit = limiter.try_acquire(CLIENT).unwrap();
+        assert!(limiter.try_acquire(CLIENT).is_err());
+
+        drop(permit);
+        let state = limiter.state.lock().unwrap();
+        assert_eq!(state.connections, 0);
+        assert!(state.connections_per_ip.is_empty());
+        drop(state);
+
+        assert!(limiter.try_acquire(CLIENT).is_ok());
+    }
+
+    #[test]
+    fn connect_rate_window() {
+        let limiter = limiter(None, None, Some((2, WINDOW)));
+        let now = Instant::now();
+
+        // permits are dropped straight away, so only the connect rate can refuse the connection.
+        assert!(limiter.try_acquire_at(CLIENT, now).is_ok());
+        assert!(limiter
+            .try_acquire_at(CLIENT, now + Duration::from_secs(1))
+            .is_ok());
+        assert_eq!(
+            limiter
+                .try_acquire_at(CLIENT, now + Duration::from_secs(2))
+                .err(),
+            Some(LimitExceeded::ConnectRate)
+        );
+        assert!(limiter
+            .try_acquire_at(OTHER, now + Duration::from_secs(2))
+            .is_ok());
+
+        // refused connects count too, so retrying within the window stays refused.
+        assert!(limiter
+            .try_acquire_at(CLIENT, now + WINDOW - Duration::from_secs(1))
+            .is_err());
+
+        // the window is counted from the first connect, not the last.
+        assert!(limiter.try_acquire_at(CLIENT, now + WINDOW).is_ok());
+    }
+}
@@ -1,6 +1,10 @@
 use std::{
+    collections::{HashMap, VecDeque},
     fs::{self},
     io::Write,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
 };
 
 use colored::*;
@@ -12,6 +16,7 @@ use crate::config::MqttConfig;
 pub struct BrokerLogger {
     write_file: bool,
     write_console: bool,
+    rate_limiter: Option<Mutex<RateLimiter>>,
 }
 
 const TIMESTAMP_FORMAT_UTC: &[FormatItem] = time::macros::format_description!(
@@ -24,44 +29,90 @@ impl log::Log for BrokerLogger {
     }
 
     fn log(&self, record: &Record) {
-        // TODO: figure out how to get the color strings to be held in a variable evaluated at compile time.
-        if self.enabled(record.metadata()) {
-            let timestamp = OffsetDateTime::now_utc()
-                .format(TIMESTAMP_FORMAT_UTC)
-                .expect("Logger could not format the UTC time. It is likely that your system does not support UTC.");
-
-            let colorized_level_string = match record.level() {
-                Level::Error => format!("{:<5}", record.level().to_string())
-                    .red()
-                    .to_string(),
-                Level::Warn => format!("{:<5}", record.level().to_string())
-                    .yellow()
-                    .to_string(),
-                Level::Info => format!("{:<5}", record.level().to_string())
-                    .cyan()
-                    .to_string(),
-                Level::Debug => format!("{:<5}", record.level().to_string())
-                    .purple()
-                    .to_string(),
-                Level::Trace => format!("{:<5}", record.level().to_string())
-                    .normal()
-                    .to_string(),
-            };
-
-            if self.write_console {
-                self.log_console(record, &colorized_level_string, &timestamp);
-            }
+        if !self.enabled(record.metadata()) {
+            return;
+        }
 
-            if self.write_file {
-                self.log_file(record, &colorized_level_string, &timestamp);
-            }
+        let rate_limiter = match &self.rate_limiter {
+            Some(rate_limiter) => rate_limiter,
+            None => return self.write(record),
+        };
+
+        let message = record.args().to_string();
+        let (should_log, summaries) = match rate_limiter.lock() {
+            Ok(mut rate_limiter) => rate_limiter.check(record.level(), &message, Instant::now()),
+            // a panic while holding the lock should not silence the logger.
+            Err(_) => (true, vec![]),
+        };
+
+        self.write_summaries(summaries);
+
+        if should_log {
+            self.write(record);
         }
     }
 
-    fn flush(&self) {}
+    /// Writes the summaries of the rate limited messages whose window has elapsed.
+    fn flush(&self) {
+        let summaries = match &self.rate_limiter {
+            Some(rate_limiter) => match rate_limiter.lock() {
+                Ok(mut rate_limiter) => rate_limiter.expire(Instant::now()),
+                Err(_) => return,
+            },
+            None => return,
+        };
+
+        self.write_summaries(summaries);
+    }
 }
 
 impl BrokerLogger {
+    fn write_summaries(&self, summaries: Vec<(Level, u32, String)>) {
+        for (level, count, message) in summaries {
+            self.write(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!(
+                        "Previous message repeated {count} times: {message}"
+                    ))
+                    .build(),
+            );
+        }
+    }
+
+    fn write(&self, record: &Record) {
+        // TODO: figure out how to get the color strings to be held in a variable evaluated at compile time.
+        let timestamp = OffsetDateTime::now_utc()
+            .format(TIMESTAMP_FORMAT_UTC)
+            .expect("Logger could not format the UTC time. It is likely that your system does not support UTC.");
+
+        let colorized_level_string = match record.level() {
+            Level::Error => format!("{:<5}", record.level().to_string())
+                .red()
+                .to_string(),
+            Level::Warn => format!("{:<5}", record.level().to_string())
+                .yellow()
+                .to_string(),
+            Level::Info => format!("{:<5}", record.level().to_string())
+                .cyan()
+                .to_string(),
+            Level::Debug => format!("{:<5}", record.level().to_string())
+                .purple()
+                .to_string(),
+            Level::Trace => format!("{:<5}", record.level().to_string())
+                .normal()
+                .to_string(),
+        };
+
+        if self.write_console {
+            self.log_console(record, &colorized_level_string, &timestamp);
+        }
+
+        if self.write_file {
+            self.log_file(record, &colorized_level_string, &timestamp);
+        }
+    }
+
     fn log_file(&self, record: &Record, colorized_level_string: &str, timestamp: &str) {
         // col delim is ';' row delim is ';\n'
         let log_string = format!("{};{};{};\n", record.level(), record.args(), timestamp);
@@ -123,11 +174,178 @@ impl BrokerLogger {
         return Self {
             write_file: config.should_log_file(),
             write_console: config.should_log_console(),
+            rate_limiter: config
+                .log_rate_limit()
+                .map(|(burst, window)| Mutex::new(RateLimiter::new(burst, window))),
         };
     }
 
     pub fn init(self, level: LevelFilter) -> Result<(), SetLoggerError> {
+        let window = self
+            .rate_limiter
+            .as_ref()
+            .and_then(|rate_limiter| rate_limiter.lock().ok())
+            .map(|rate_limiter| rate_limiter.window);
+
         log::set_max_level(level);
-        log::set_boxed_logger(Box::new(self))
+        log::set_boxed_logger(Box::new(self))?;
+
+        // the summary of a burst would otherwise wait for the next message to be logged, which may never come.
+        if let Some(window) = window {
+            thread::spawn(move || loop {
+                thread::sleep(window);
+                log::logger().flush();
+            });
+        }
+        return Ok(());
+    }
+}
+
+/// Suppresses identical log messages once they have been logged `burst` times within a window.
+///
+/// Messages are keyed by their text, which includes the error and the address of the client that caused it,
+/// so one misbehaving client cannot flood the logs while other clients' errors are still logged.
+struct RateLimiter {
+    burst: u32,
+    window: Duration,
+    entries: HashMap<String, RateLimitEntry>,
+    // every window has the same length, so the order windows started in is the order they expire in.
+    expiries: VecDeque<(Instant, String)>,
+}
+
+struct RateLimitEntry {
+    level: Level,
+    count: u32,
+}
+
+impl RateLimiter {
+    fn new(burst: u32, window: Duration) -> Self {
+        return Self {
+            burst,
+            window,
+            entries: HashMap::new(),
+            expiries: VecDeque::new(),
+        };
+    }
+
+    /// Records an occurrence of the message.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the message should be logged, and the summaries returned by [RateLimiter::expire].
+    fn check(
+        &mut self,
+        level: Level,
+        message: &str,
+        now: Instant,
+    ) -> (bool, Vec<(Level, u32, String)>) {
+        let summaries = self.expire(now);
+
+        if !self.entries.contains_key(message) {
+            self.expiries.push_back((now, message.to_string()));
+        }
+        let entry = self
+            .entries
+            .entry(message.to_string())
+            .or_insert(RateLimitEntry { level, count: 0 });
+        entry.count = entry.count.saturating_add(1);
+
+        return (entry.count <= self.burst, summaries);
+    }
+
+    /// Forgets the messages whose window has elapsed, so the map does not grow with every message ever logged.
+    ///
+    /// ## Returns
+    ///
+    /// A summary of every forgotten message with suppressed occurrences.
+    fn expire(&mut self, now: Instant) -> Vec<(Level, u32, String)> {
+        let mut summaries = vec![];
+
+        while let Some((started, _)) = self.expiries.front() {
+            if now.duration_since(*started) < self.window {
+                break;
+            }
+
+            let (_, message) = self.expiries.pop_front().unwrap();
+            if let Some(entry) = self.entries.remove(&message) {
+                if entry.count > self.burst {
+                    summaries.push((entry.level, entry.count - self.burst, message));
+                }
+            }
+        }
+        return summaries;
+    }
+}
+
+#[cfg(test)]
+mod rate_limiter {
+    use std::time::{Duration, Instant};
+
+    use log::Level;
+
+    use super::RateLimiter;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn logs_burst() {
+        let mut limiter = RateLimiter::new(3, WINDOW);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(Level::Error, "error", now), (true, vec![]));
+        }
+        assert_eq!(limiter.check(Level::Error, "error", now), (false, vec![]));
+    }
+
+    #[test]
+    fn suppresses_per_message() {
+        let mut limiter = RateLimiter::new(1, WINDOW);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(Level::Error, "client a", now), (true, vec![]));
+        assert_eq!(
+            limiter.check(Level::Error, "client a", now),
+            (false, vec![])
+        );
+        // a different message is counted on its own.
+        assert_eq!(limiter.check(Level::Error, "client b", now), (true, vec![]));
+    }
+
+    #[test]
+    fn summarises_on_next_message() {
+        let mut limiter = RateLimiter::new(1, WINDOW);
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            limiter.check(Level::Warn, "warning", now);
+        }
+
+        let later = now + WINDOW;
+        assert_eq!(
+            limiter.check(Level::Warn, "warning", later),
+            (true, vec![(Level::Warn, 3, String::from("warning"))])
+        );
+    }
+
+    #[test]
+    fn summarises_on_expire() {
+        let mut limiter = RateLimiter::new(2, WINDOW);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            limiter.check(Level::Error, "burst", now);
+        }
+        limiter.check(Level::Error, "quiet", now + Duration::from_secs(5));
+
+        assert_eq!(limiter.expire(now + Duration::from_secs(9)), vec![]);
+        assert_eq!(
+            limiter.expire(now + WINDOW),
+            vec![(Level::Error, 1, String::from("burst"))]
+        );
+        // a window without suppressed messages expires without a summary.
+        assert_eq!(limiter.expire(now + WINDOW * 2), vec![]);
+        assert!(limiter.entries.is_empty());
+        assert!(limiter.expiries.is_empty());
     }
 }
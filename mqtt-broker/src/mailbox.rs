@@ -1,11 +1,12 @@
-use std::sync::Arc;
+use std::{future, sync::Arc};
 
+use futures::{future::select_all, FutureExt};
 use mqtt_core::{
     qos::QosLevel,
     topic::{TopicFilter, TopicName},
     v3::PublishPacket,
 };
-use tokio::sync::broadcast::{error::TryRecvError, Receiver};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
 
 use mqtt_core::err::server::{self, ServerError};
 
@@ -37,6 +38,38 @@ impl Mailbox {
             self.mail_mut().remove(idx);
         }
     }
+
+    /// Waits for the next message on any of the mailbox's subscriptions, and the QoS the subscription was granted.
+    ///
+    /// Cancel safe, no message is lost if the future is dropped before it completes. Never completes while the
+    /// mailbox is empty. A subscription whose broadcast has closed is removed from the mailbox.
+    pub async fn recv(&mut self) -> Result<(QosLevel, Arc<PublishPacket>), ServerError> {
+        if self.0.is_empty() {
+            return future::pending().await;
+        }
+
+        let (out, idx, _) = select_all(
+            self.0
+                .iter_mut()
+                .map(|mail| Box::pin(async move { (mail.qos(), mail.recv().await) })),
+        )
+        .await;
+
+        match out {
+            (qos, Ok(packet)) => return Ok((qos, packet)),
+            (_, Err(err)) => {
+                if matches!(err.kind(), server::ErrorKind::BroadcastError) {
+                    self.0.remove(idx);
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    /// Returns a message that has already arrived, or None if no message is waiting.
+    pub fn try_recv(&mut self) -> Option<Result<(QosLevel, Arc<PublishPacket>), ServerError>> {
+        return self.recv().now_or_never();
+    }
 }
 
 #[derive(Debug)]
@@ -60,18 +93,16 @@ impl Mail {
         };
     }
 
-    pub fn recv(&mut self) -> Result<Option<Arc<PublishPacket>>, ServerError> {
-        match self.receiver.try_recv() {
-            Ok(packet) => return Ok(Some(packet)),
+    /// Waits for the next message published to the topic.
+    pub async fn recv(&mut self) -> Result<Arc<PublishPacket>, ServerError> {
+        match self.receiver.recv().await {
+            Ok(packet) => return Ok(packet),
             Err(err) => match err {
-                TryRecvError::Closed => Err(ServerError::new(
+                RecvError::Closed => Err(ServerError::new(
                     server::ErrorKind::BroadcastError,
                     String::from("Server Broadcast closed. You've encountered a bug. Please report this issue on GitHub."),
                 )),
-                TryRecvError::Empty => {
-                    return Ok(None);
-                }
-                TryRecvError::Lagged(val) => {
+                RecvError::Lagged(val) => {
                     return Err(ServerError::new(server::ErrorKind::FullMailbox(val),
                     format!("Server could not forward broadcast messages in time, dropping {val} messages.")))
                 }
@@ -1,42 +1,80 @@
+mod acl;
+mod auth;
 mod config;
 mod init;
+mod limit;
 mod logger;
 mod mailbox;
+mod protocol;
+mod rewrite;
 mod session;
+mod store;
+mod stream;
+mod tls;
 mod topic;
+mod websocket;
 
 use core::str;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+use acl::FileAuthorizer;
+use auth::{
+    cert_identity, AuthBackend, Authenticator, Identity, JwtAuthenticator,
+    PasswordFileAuthenticator, SqliteAuthenticator, WebhookAuthenticator,
+};
 use bytes::Bytes;
 use config::MqttConfig;
+use futures::future::join_all;
 use init::MqttEnv;
+use limit::{ConnectionLimiter, ConnectionPermit, LimitExceeded};
+use tls::TlsReloader;
 
 use mqtt_core::{
     err::server::{self, ServerError},
-    io::read_packet,
+    io::FrameReader,
     qos::{QosLevel, SubAckQoS},
     topic::{TopicFilter, TopicName},
     v3::{
-        ConnAckPacket, FilterResult, MqttPacket, PingRespPacket, PubAckPacket, PubCompPacket,
-        PublishPacket, SubAckPacket, UnsubAckPacket,
+        ConnectPacket, FilterResult, MqttPacket, PingRespPacket, PublishPacket, SubAckPacket,
+        UnsubAckPacket, Will,
     },
-    ConnectReturnCode,
+    v5::ReasonCode,
+    ConnectReturnCode, MqttVersion,
 };
 
-use sheesh::user::UserMeta;
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
     join,
     net::TcpListener,
-    sync::{Mutex, RwLock},
+    sync::{oneshot, watch, Mutex, RwLock},
+    task::JoinHandle,
+    time::{self, timeout},
 };
 
-use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+use rustls::pki_types::CertificateDer;
 use tokio_rustls::TlsAcceptor;
 
 use mailbox::{Mail, Mailbox};
+use protocol::Protocol;
+use r2d2_sqlite::SqliteConnectionManager;
+use rewrite::TopicRewriter;
 use session::{ActiveSession, AuthManager, DisconnectedSessions};
+use store::SqliteSessionStore;
+#[cfg(feature = "quic")]
+use stream::QuicStream;
+use stream::WriteTimeout;
 use topic::ServerTopics;
 
 struct MqttServer {
@@ -47,48 +85,286 @@ struct MqttServer {
     // Mutex is only locked when a client disconnects or connects.
     // Should this be a tokio mutex or a std mutex? Contention will probably be higher than topics though...
     dc_sessions: Arc<Mutex<DisconnectedSessions>>,
+    // wills held back by a will delay, keyed by client id. Removed when the client reconnects.
+    pending_wills: Mutex<HashMap<String, PendingWill>>,
+    // the connection holding each connected client id, a std mutex so a [Registration] can release its id when dropped.
+    connections: Arc<std::sync::Mutex<HashMap<String, ConnectionHandle>>>,
+    next_connection: AtomicU64,
+    limiter: Arc<ConnectionLimiter>,
     auth_manager: AuthManager,
+    rewriter: TopicRewriter,
+    // set once the broker starts shutting down, ends every session's event loop.
+    shutdown: watch::Sender<bool>,
+}
+
+/// The connection currently holding a client id, see [MqttServer::register_connection].
+struct ConnectionHandle {
+    id: u64,
+    // closes the connection's event loop.
+    takeover: oneshot::Sender<()>,
+    // resolves once the connection has stored its session.
+    closed: oneshot::Receiver<()>,
+}
+
+/// Held by a connection for as long as it holds its client id.
+///
+/// The client id is released when the registration is dropped, unless a newer connection has taken it over. This
+/// covers every way a connection ends, including a failed CONNACK, a connect timeout or a panic.
+struct Registration {
+    id: u64,
+    client_id: String,
+    takeover: oneshot::Receiver<()>,
+    connections: Arc<std::sync::Mutex<HashMap<String, ConnectionHandle>>>,
+    // dropped once the session is stored, which lets a connection taking the session over continue.
+    _closed: oneshot::Sender<()>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // a poisoned map only means another connection panicked while holding it.
+        let mut connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(handle) = connections.get(&self.client_id) {
+            if handle.id == self.id {
+                connections.remove(&self.client_id);
+            }
+        }
+    }
+}
+
+/// A will waiting out its will delay before it is published.
+struct PendingWill {
+    task: JoinHandle<()>,
+    will: Will,
+    identity: Option<Identity>,
 }
 
 impl MqttServer {
     /// Creates a new MqttServer instance holding a mutex to topics and a mutex to disconnected sessions.
     pub fn new(config: MqttConfig) -> Self {
+        let mut topics = ServerTopics::new(config.max_queued_messages());
+        topics.set_max_retained_messages(config.max_retained_messages());
+        topics.set_max_retained_payload(config.max_retained_payload());
+
+        // users and persisted sessions share one database.
+        let pool = r2d2::Pool::new(SqliteConnectionManager::file(config.user_db())).unwrap();
+
+        let dc_sessions = if config.persist_sessions() {
+            let store = SqliteSessionStore::new(pool.clone()).unwrap();
+
+            let retained = store.load_retained().unwrap();
+            log::info!("Restored {} retained messages", retained.len());
+            for packet in retained {
+                topics.retain_message(packet);
+            }
+
+            DisconnectedSessions::with_store(Box::new(store)).unwrap()
+        } else {
+            DisconnectedSessions::new()
+        };
+
+        let authenticator: Box<dyn Authenticator> = match config.auth_backend() {
+            AuthBackend::Sqlite => Box::new(SqliteAuthenticator::new(pool)),
+            AuthBackend::PasswordFile => {
+                let path = config.password_file();
+                Box::new(
+                    PasswordFileAuthenticator::try_from(path.as_path()).expect(&format!(
+                        "Could not load password file: {}",
+                        path.to_str().unwrap_or("")
+                    )),
+                )
+            }
+            AuthBackend::Webhook => Box::new(WebhookAuthenticator::new(
+                config
+                    .auth_webhook_url()
+                    .expect("The webhook authenticator requires a webhook_url")
+                    .to_string(),
+                config.auth_webhook_timeout(),
+            )),
+            AuthBackend::Jwt => Box::new(JwtAuthenticator::new(&config)),
+        };
+
+        let mut auth_manager = AuthManager::new(authenticator);
+        if let Some(path) = config.acl_path() {
+            auth_manager
+                .set_authorizer(Box::new(FileAuthorizer::try_from(path.as_path()).unwrap()));
+        }
+
         MqttServer {
-            auth_manager: AuthManager::new(config.user_db()),
-            topics: Arc::new(RwLock::new(ServerTopics::new(config.max_queued_messages()))),
+            limiter: Arc::new(ConnectionLimiter::new(&config)),
+            auth_manager,
+            rewriter: TopicRewriter::new(config.rewrite_rules()),
+            topics: Arc::new(RwLock::new(topics)),
             config: config,
-            dc_sessions: Arc::new(Mutex::new(DisconnectedSessions::new())),
+            dc_sessions: Arc::new(Mutex::new(dc_sessions)),
+            pending_wills: Mutex::new(HashMap::new()),
+            connections: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            next_connection: AtomicU64::new(0),
+            shutdown: watch::channel(false).0,
         }
     }
 
+    /// Binds every configured listener, then accepts connections on all of them until the broker receives SIGINT or
+    /// SIGTERM, see [MqttServer::shutdown].
+    ///
+    /// Panics if a listener cannot be bound or its certificates cannot be loaded.
     pub async fn start(self) {
-        let addr = self.config.addr();
+        if let Some(interval) = self.config.compaction_interval() {
+            let topics = Arc::clone(&self.topics);
+            tokio::spawn(compact_topics(topics, interval));
+        }
+
+        let server = Arc::new(self);
+
+        // each TLS listener holds its own certificates, reloaded together on SIGHUP.
+        let mut reloaders = vec![];
+        let mut listeners = vec![];
+        for config in server.config.listeners() {
+            let addr = config.addr();
+            let listener = TcpListener::bind(addr)
+                .await
+                .expect(&format!("Could not bind listener at addr: {addr}"));
+            log::info!("Server listening at: {addr}");
+
+            let tls = match config.is_tls_enabled() {
+                true => {
+                    let reloader =
+                        TlsReloader::new(&server.config, config.cert_path(), config.key_path())
+                            .expect(&format!(
+                                "Could not load the TLS configuration of listener at addr: {addr}"
+                            ));
+                    let reloader = Arc::new(reloader);
+                    reloaders.push(Arc::clone(&reloader));
+                    Some(reloader)
+                }
+                false => None,
+            };
 
-        let listener = TcpListener::bind(&addr).await.unwrap();
+            listeners.push(tokio::spawn(Arc::clone(&server).start_listener(
+                listener,
+                config.is_websocket(),
+                tls,
+            )));
+        }
 
-        log::info!("Server listening at: {}", addr);
+        #[cfg(feature = "quic")]
+        if let Some(addr) = server.config.quic_addr() {
+            let reloader = TlsReloader::new(
+                &server.config,
+                server.config.cert_path(),
+                server.config.key_path(),
+            )
+            .expect("Could not load the TLS configuration of the QUIC listener");
+            let reloader = Arc::new(reloader);
+            reloaders.push(Arc::clone(&reloader));
+            listeners.push(tokio::spawn(Arc::clone(&server).start_quic(addr, reloader)));
+        }
 
-        if self.config.is_tls_enabled() {
-            self.start_tls(listener).await;
-        } else {
-            self.start_plaintext(listener).await;
+        #[cfg(unix)]
+        if reloaders.len() > 0 {
+            tokio::spawn(reload_tls_on_sighup(Arc::clone(&server), reloaders));
+        }
+
+        let accepting = listeners
+            .iter()
+            .map(JoinHandle::abort_handle)
+            .collect::<Vec<_>>();
+
+        tokio::select! {
+            _ = join_all(listeners) => {}
+            _ = shutdown_signal() => {
+                // stop accepting new connections before the connected clients are disconnected.
+                for listener in accepting {
+                    listener.abort();
+                }
+                server.shutdown().await;
+            }
         }
     }
 
-    async fn start_plaintext(self, listener: TcpListener) {
-        let server = Arc::new(self);
+    /// Disconnects every connected client, storing their sessions, then persists the retained messages.
+    ///
+    /// Each session forwards the messages already in its mailbox before it is closed. Clients are disconnected
+    /// gracefully, so their wills are not published.
+    async fn shutdown(&self) {
+        log::info!("Shutting down, disconnecting {} clients", self.connected());
+        self.shutdown.send_replace(true);
+
+        // sessions are stored before their connection is unregistered.
+        let drained = timeout(self.config.shutdown_timeout(), async {
+            while self.connected() > 0 {
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+
+        if drained.is_err() {
+            log::warn!(
+                "{} clients were not disconnected within {} seconds",
+                self.connected(),
+                self.config.shutdown_timeout().as_secs()
+            );
+        }
+
+        let store = self.dc_sessions.lock().await.store().cloned();
+        if let Some(store) = store {
+            let retained = self.topics.read().await.retained_messages();
+            let count = retained.len();
+            // also waits for the sessions queued before it to be persisted.
+            match store
+                .call(move |store| store.save_retained(&retained))
+                .await
+            {
+                Some(Ok(())) => log::info!("Persisted {count} retained messages"),
+                Some(Err(err)) => log::error!("Failed to persist retained messages, {err}"),
+                None => {
+                    log::error!("Failed to persist retained messages, the session store stopped")
+                }
+            }
+        }
+
+        log::info!("Broker stopped");
+    }
+
+    async fn start_listener(
+        self: Arc<Self>,
+        listener: TcpListener,
+        websocket: bool,
+        tls: Option<Arc<TlsReloader>>,
+    ) {
+        match (websocket, tls) {
+            (true, tls) => self.start_websocket(listener, tls).await,
+            (false, Some(tls)) => self.start_tls(listener, tls).await,
+            (false, None) => self.start_plaintext(listener).await,
+        }
+    }
+
+    async fn start_plaintext(self: Arc<Self>, listener: TcpListener) {
+        let server = self;
         loop {
             server.clean_expired_sessions().await;
             match listener.accept().await {
                 Ok((mut stream, addr)) => {
                     log::info!("New connection attempt: {addr}");
 
+                    let permit = match server.admit(addr) {
+                        Some(permit) => permit,
+                        None => continue,
+                    };
+
                     let server_clone = Arc::clone(&server);
 
                     // let mut stream = BufReader::new(stream);
 
                     tokio::spawn(async move {
-                        if let Err(err) = handle_client(server_clone, &mut stream).await {
+                        let _permit = match permit {
+                            Ok(permit) => permit,
+                            Err(_) => return refuse_client(server_clone, &mut stream, addr).await,
+                        };
+
+                        if let Err(err) = handle_client(server_clone, &mut stream, None).await {
                             log::warn!("Error handling client: {err}, Closing connection: {addr}")
                         } else {
                             log::info!("Gracefully closing connection: {addr}")
@@ -102,76 +378,237 @@ impl MqttServer {
         }
     }
 
-    async fn start_tls(self, listener: TcpListener) {
-        let server = Arc::new(self);
+    async fn start_tls(self: Arc<Self>, listener: TcpListener, tls: Arc<TlsReloader>) {
+        let server = self;
 
-        let certs = CertificateDer::pem_file_iter("tls/cert.pem")
-            .unwrap()
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
+        if let Ok(addr) = listener.local_addr() {
+            log::info!("Initialized TLS on TCP listener at addr: {addr}");
+        }
 
-        if certs.len() == 0 {
-            log::warn!("No certificates were provided. Check the ./tls/cert.pem file")
+        loop {
+            let (stream, addr) = listener.accept().await.unwrap();
+            // picks up certificates reloaded since the last connection.
+            let acceptor = TlsAcceptor::from(tls.current());
+
+            let permit = match server.admit(addr) {
+                Some(permit) => permit,
+                None => continue,
+            };
+
+            server.clean_expired_sessions().await;
+
+            let server_clone = Arc::clone(&server);
+            // The handshake is performed in the connection's task, so a stalled handshake does not block new connections.
+            tokio::spawn(async move {
+                let handshake = match timeout(
+                    server_clone.config.connect_timeout(),
+                    acceptor.accept(stream),
+                )
+                .await
+                {
+                    Ok(handshake) => handshake,
+                    Err(_) => {
+                        log::warn!("TLS handshake timed out, closing connection: {addr}");
+                        return;
+                    }
+                };
+
+                let tls_stream = match handshake {
+                    Ok(tls_stream) => tls_stream,
+                    Err(err) => {
+                        log::error!("{}", err);
+                        log::warn!("Rejected TCP connection");
+                        return;
+                    }
+                };
+
+                log::info!("New connection attempt from: {addr}");
+                let peer = server_clone.peer_identity(tls_stream.get_ref().1.peer_certificates());
+                let mut tls_stream = BufReader::new(tls_stream);
+
+                let _permit = match permit {
+                    Ok(permit) => permit,
+                    Err(_) => return refuse_client(server_clone, &mut tls_stream, addr).await,
+                };
+
+                if let Err(err) = handle_client(server_clone, &mut tls_stream, peer).await {
+                    log::error!("Error handling client: {err}");
+                    log::warn!("Closing connection: {addr}")
+                } else {
+                    if let Err(_) = tls_stream.shutdown().await {
+                        log::error!("Did not gracefully close connection: {addr}")
+                    } else {
+                        log::info!("Gracefully closing connection: {addr}")
+                    }
+                }
+            });
         }
+    }
 
-        let key = PrivateKeyDer::from_pem_file("tls/key.pem").unwrap();
+    /// Accepts MQTT connections over QUIC, each connection carries MQTT on its first bidirectional stream.
+    #[cfg(feature = "quic")]
+    async fn start_quic(self: Arc<Self>, addr: SocketAddr, tls: Arc<TlsReloader>) {
+        let server = self;
 
-        let config = rustls::ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)
-            .unwrap();
+        let mut reloads = tls.subscribe();
+        let quic_config = match quic_server_config(&reloads.borrow_and_update()) {
+            Ok(quic_config) => quic_config,
+            Err(err) => {
+                log::error!("Could not initialize the QUIC listener: {err}");
+                return;
+            }
+        };
 
-        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let endpoint = match quinn::Endpoint::server(quic_config, addr) {
+            Ok(endpoint) => endpoint,
+            Err(err) => {
+                log::error!("Could not bind the QUIC listener at addr: {addr}, {err}");
+                return;
+            }
+        };
 
-        log::info!(
-            "Initialized TLS on TCP listener at addr: {}",
-            server.config.addr()
-        );
+        log::info!("QUIC listening at: {addr}");
 
         loop {
-            let (stream, addr) = listener.accept().await.unwrap();
-            let acceptor = acceptor.clone();
+            let incoming = tokio::select! {
+                incoming = endpoint.accept() => match incoming {
+                    Some(incoming) => incoming,
+                    None => break,
+                },
+                // established connections keep the configuration they were accepted with.
+                Ok(()) = reloads.changed() => {
+                    match quic_server_config(&reloads.borrow_and_update()) {
+                        Ok(quic_config) => endpoint.set_server_config(Some(quic_config)),
+                        Err(err) => log::error!("Could not reload the QUIC listener's TLS configuration: {err}"),
+                    }
+                    continue;
+                }
+            };
 
-            server.clean_expired_sessions().await;
-            match acceptor.accept(stream).await {
-                Ok(tls_stream) => {
-                    log::info!("New connection attempt from: {addr}");
+            // QUIC connections over the limits are refused before the handshake.
+            let permit = match server.admit(incoming.remote_address()) {
+                Some(Ok(permit)) => permit,
+                _ => {
+                    incoming.refuse();
+                    continue;
+                }
+            };
 
-                    let server_clone = Arc::clone(&server);
+            let server_clone = Arc::clone(&server);
 
-                    let mut tls_stream = BufReader::new(tls_stream);
+            // The handshake is performed on the connection's task, so a stalled handshake does not block new connections.
+            tokio::spawn(async move {
+                let addr = incoming.remote_address();
+                let connect_timeout = server_clone.config.connect_timeout();
 
-                    tokio::spawn(async move {
-                        if let Err(err) = handle_client(server_clone, &mut tls_stream).await {
-                            log::error!("Error handling client: {err}");
-                            log::warn!("Closing connection: {addr}")
-                        } else {
-                            if let Err(_) = tls_stream.shutdown().await {
-                                log::error!("Did not gracefully close connection: {addr}")
-                            } else {
-                                log::info!("Gracefully closing connection: {addr}")
-                            }
-                        }
-                    });
+                let connection = match timeout(connect_timeout, incoming).await {
+                    Ok(Ok(connection)) => connection,
+                    Ok(Err(err)) => {
+                        log::warn!("Rejected QUIC connection: {addr}, {err}");
+                        return;
+                    }
+                    Err(_) => {
+                        log::warn!("QUIC handshake timed out, closing connection: {addr}");
+                        return;
+                    }
+                };
+
+                log::info!("New QUIC connection attempt from: {addr}");
+
+                let (send, recv) = match timeout(connect_timeout, connection.accept_bi()).await {
+                    Ok(Ok(streams)) => streams,
+                    _ => {
+                        log::warn!("QUIC client did not open a stream, closing connection: {addr}");
+                        return;
+                    }
+                };
+
+                let certs = connection
+                    .peer_identity()
+                    .and_then(|certs| certs.downcast::<Vec<CertificateDer<'static>>>().ok());
+                let peer = server_clone.peer_identity(certs.as_deref().map(Vec::as_slice));
+
+                let _permit = permit;
+                let mut stream = QuicStream::new(send, recv);
+                if let Err(err) = handle_client(server_clone, &mut stream, peer).await {
+                    log::warn!("Error handling client: {err}, Closing connection: {addr}")
+                } else {
+                    log::info!("Gracefully closing connection: {addr}")
                 }
+                connection.close(0u32.into(), b"");
+            });
+        }
+    }
+
+    /// Accepts MQTT connections over WebSockets, upgrading each connection with the `mqtt` subprotocol.
+    async fn start_websocket(
+        self: Arc<Self>,
+        listener: TcpListener,
+        tls: Option<Arc<TlsReloader>>,
+    ) {
+        let server = self;
+
+        if let Ok(addr) = listener.local_addr() {
+            log::info!("WebSocket listening at: {addr}");
+        }
+
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(conn) => conn,
                 Err(err) => {
-                    log::error!("{}", err);
-                    log::warn!("Rejected TCP connection")
+                    log::error!("Rejected TCP connection: {}", err);
+                    continue;
                 }
-            }
+            };
+
+            // WebSocket connections over the limits are dropped before the handshake.
+            let permit = match server.admit(addr) {
+                Some(Ok(permit)) => permit,
+                _ => continue,
+            };
+
+            server.clean_expired_sessions().await;
+            let server_clone = Arc::clone(&server);
+            // picks up certificates reloaded since the last connection.
+            let acceptor = tls.as_ref().map(|tls| TlsAcceptor::from(tls.current()));
+
+            // The handshakes are performed on the connection's task, so a stalled handshake does not block new connections.
+            tokio::spawn(async move {
+                let _permit = permit;
+                match acceptor {
+                    Some(acceptor) => {
+                        let connect_timeout = server_clone.config.connect_timeout();
+                        match timeout(connect_timeout, acceptor.accept(stream)).await {
+                            Ok(Ok(tls_stream)) => {
+                                let peer = server_clone
+                                    .peer_identity(tls_stream.get_ref().1.peer_certificates());
+                                handle_websocket(server_clone, tls_stream, addr, peer).await
+                            }
+                            Ok(Err(err)) => log::warn!("Rejected TLS connection: {addr}, {err}"),
+                            Err(_) => {
+                                log::warn!("TLS handshake timed out, closing connection: {addr}")
+                            }
+                        }
+                    }
+                    None => handle_websocket(server_clone, stream, addr, None).await,
+                }
+            });
         }
     }
 
-    /// Sends  to the broadcast channel for the given TopicName.
+    /// Dispatches the message to the subscribers of the topic, after the topic rewrite rules are applied.
     ///
-    /// ## Error result
-    ///
-    /// Attempts to send a value to all active [Receiver] handles, returning it back if it could not be sent.
-    ///
-    /// A successful send occurs when there is at least one active [Receiver] handle.
-    /// An unsuccessful send would be one where all associated [Receiver] handles have already been dropped.
-    /// If we encounter an error, it is intended that we ignore the error.
-    async fn publish_to_topic(&self, topic: &TopicName, packet: Arc<PublishPacket>) {
+    /// A topic without subscribers drops the message, a topic that does not exist yet is created.
+    async fn publish_to_topic(&self, topic: &TopicName, mut packet: Arc<PublishPacket>) {
+        let rewritten = self.rewriter.rewrite(topic);
+        let topic = match &rewritten {
+            Some(rewritten) => {
+                Arc::make_mut(&mut packet).set_topic(rewritten);
+                rewritten
+            }
+            None => topic,
+        };
+
         let mut topics = self.topics.write().await;
 
         match topics.topic_mut(topic) {
@@ -214,7 +651,9 @@ impl MqttServer {
                 if let Some(retained_message) = topic.get_retained_message() {
                     // Performance overhead (clone into an Arc). The message assurance might need some refractoring...
                     let packet = session.origin(&Arc::new(retained_message.clone()));
-                    stream.write_all(&packet.encode()?).await?;
+                    stream
+                        .write_all(&session.encode(MqttPacket::Publish(packet))?)
+                        .await?;
                 }
 
                 let receiver = topic.subscribe();
@@ -225,56 +664,223 @@ impl MqttServer {
         return Ok(());
     }
 
-    async fn publish_will(&self, session: &mut ActiveSession) -> Result<(), ServerError> {
-        if let Some(will) = session.will().clone() {
-            let mut packet = PublishPacket::new(
-                will.will_topic(),
-                Bytes::copy_from_slice(will.will_message().as_bytes()),
-            );
+    /// Applies the connection limits to a new connection.
+    ///
+    /// Returns None if the connection should be dropped. A connection refused because the broker is full is
+    /// returned as an error, so the client can be told the broker is unavailable.
+    fn admit(&self, addr: SocketAddr) -> Option<Result<ConnectionPermit, LimitExceeded>> {
+        match self.limiter.try_acquire(addr.ip()) {
+            Ok(permit) => return Some(Ok(permit)),
+            Err(LimitExceeded::Connections) => {
+                log::warn!(
+                    "{}, refusing connection: {addr}",
+                    LimitExceeded::Connections
+                );
+                return Some(Err(LimitExceeded::Connections));
+            }
+            Err(limit) => {
+                log::warn!("{limit}, dropping connection: {addr}");
+                return None;
+            }
+        }
+    }
 
-            match will.will_qos() {
-                QosLevel::AtMostOnce => {}
-                QosLevel::AtLeastOnce => {
-                    let id = session.next_id();
-                    if let Some(id) = id {
-                        packet.set_qos_atleastonce(id);
-                    } else {
-                        session.clean_session();
-                        packet.set_qos_atleastonce(
-                            session.next_id().expect("No valid Id's available"),
-                        );
-                    }
-                }
-                QosLevel::ExactlyOnce => {
-                    let id = session.next_id();
-                    if let Some(id) = id {
-                        packet.set_qos_exactlyonce(id);
-                    } else {
-                        session.clean_session();
-                        packet.set_qos_exactlyonce(
-                            session.next_id().expect("No valid Id's available"),
-                        );
+    /// Generates a client id for a client that connected without one.
+    ///
+    /// The start time keeps ids unique across restarts, for brokers that persist sessions.
+    fn generate_client_id(&self) -> String {
+        let count = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        return format!(
+            "auto-{:x}-{:x}",
+            chrono::Utc::now().timestamp_micros(),
+            count
+        );
+    }
+
+    /// Records the connection as the holder of the client id.
+    ///
+    /// A connection already holding the client id is closed [MQTT-3.1.4-2]. Returns once the old connection
+    /// has stored its session, so the new connection can resume it.
+    async fn register_connection(&self, client_id: &str) -> Registration {
+        let id = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        let (takeover_tx, takeover_rx) = oneshot::channel();
+        let (closed_tx, closed_rx) = oneshot::channel();
+
+        let handle = ConnectionHandle {
+            id,
+            takeover: takeover_tx,
+            closed: closed_rx,
+        };
+        let old = self
+            .connections
+            .lock()
+            .unwrap()
+            .insert(client_id.to_string(), handle);
+
+        if let Some(old) = old {
+            log::info!("Client: {client_id} connected again, taking over the existing connection.");
+            // the old connection may have already closed on its own.
+            let _ = old.takeover.send(());
+            let _ = old.closed.await;
+        }
+
+        return Registration {
+            id,
+            client_id: client_id.to_string(),
+            takeover: takeover_rx,
+            connections: self.connections.clone(),
+            _closed: closed_tx,
+        };
+    }
+
+    /// The number of connections holding a client id.
+    fn connected(&self) -> usize {
+        return self.connections.lock().unwrap().len();
+    }
+
+    /// Retains the session of a closed connection, and publishes its will unless the connection closed gracefully.
+    ///
+    /// A connection closes gracefully when the client sends a DISCONNECT that does not ask for the will.
+    ///
+    /// A will with a will delay is published once the delay has passed, unless the client reconnects first.
+    async fn close_session(self: &Arc<Self>, session: ActiveSession, graceful: bool) {
+        if let (false, Some(will)) = (graceful, session.will().clone()) {
+            let delay = session.will_delay();
+            if delay.is_zero() {
+                self.publish_will(session.client_id(), session.identity(), &will)
+                    .await;
+            } else {
+                log::info!(
+                    "Delaying the will of client: {} for {} seconds.",
+                    session.client_id(),
+                    delay.as_secs()
+                );
+
+                // hold the lock until the task is recorded, so the task cannot finish before it is inserted.
+                let mut pending_wills = self.pending_wills.lock().await;
+
+                let server = self.clone();
+                let client_id = session.client_id().to_string();
+                let task = tokio::spawn(async move {
+                    time::sleep(delay).await;
+                    if let Some(pending) = server.pending_wills.lock().await.remove(&client_id) {
+                        server
+                            .publish_will(&client_id, pending.identity.as_ref(), &pending.will)
+                            .await;
                     }
-                }
+                });
+
+                pending_wills.insert(
+                    session.client_id().to_string(),
+                    PendingWill {
+                        task,
+                        will,
+                        identity: session.identity().cloned(),
+                    },
+                );
             }
+        }
 
-            if will.will_retain() {
-                let retain_fut = self.retain_message(packet.clone());
-                let pub_fut = self.publish_to_topic(packet.topic(), Arc::new(packet.clone()));
+        if !session.expires_on_disconnect() {
+            self.dc_sessions.lock().await.add_session(session.into());
+        }
+    }
 
-                join!(retain_fut, pub_fut);
-            } else {
-                self.publish_to_topic(packet.topic(), Arc::new(packet.clone()))
+    /// Cancels the delayed will of a reconnecting client.
+    ///
+    /// A client starting a clean session ends its previous session, which publishes the will immediately.
+    async fn cancel_will(&self, client_id: &str, clean_session: bool) {
+        let pending = self.pending_wills.lock().await.remove(client_id);
+        if let Some(pending) = pending {
+            pending.task.abort();
+            if clean_session {
+                self.publish_will(client_id, pending.identity.as_ref(), &pending.will)
                     .await;
             }
         }
+    }
 
-        return Ok(());
+    /// Returns true if the client may publish to the topic, and to the topic the rewrite rules dispatch it to.
+    fn authorize_publish(&self, identity: Option<&Identity>, topic: &TopicName) -> bool {
+        if !self.auth_manager.authorize_publish(identity, topic) {
+            return false;
+        }
+
+        match self.rewriter.rewrite(topic) {
+            Some(rewritten) => return self.auth_manager.authorize_publish(identity, &rewritten),
+            None => return true,
+        }
     }
 
-    async fn retain_message(&self, packet: PublishPacket) {
+    async fn publish_will(&self, client_id: &str, identity: Option<&Identity>, will: &Will) {
+        if !self.authorize_publish(identity, will.will_topic()) {
+            log::warn!(
+                "Client: {} is not authorized to publish its will to: {}",
+                client_id,
+                will.will_topic().clone().to_string()
+            );
+            return;
+        }
+
+        let mut packet = PublishPacket::new(will.will_topic(), will.will_message().clone());
+
+        // subscribers assign their own packet ids when the will is forwarded to them.
+        match will.will_qos() {
+            QosLevel::AtMostOnce => {}
+            QosLevel::AtLeastOnce => packet.set_qos_atleastonce(0),
+            QosLevel::ExactlyOnce => packet.set_qos_exactlyonce(0),
+        }
+
+        if will.will_retain() {
+            let retain_fut = self.retain_message(packet.clone());
+            let pub_fut = self.publish_to_topic(packet.topic(), Arc::new(packet.clone()));
+
+            join!(retain_fut, pub_fut);
+        } else {
+            self.publish_to_topic(packet.topic(), Arc::new(packet.clone()))
+                .await;
+        }
+    }
+
+    /// Publishes the bytes held by the session to `$SYS/sessions/<client_id>/memory`.
+    async fn publish_session_memory(&self, session: &ActiveSession) {
+        let topic_name =
+            match TopicName::from_str(&format!("$SYS/sessions/{}/memory", session.client_id())) {
+                Ok(topic_name) => topic_name,
+                // client ids containing '/' or wildcards cannot be used in a topic name.
+                Err(_) => return,
+            };
+
+        let payload = Bytes::from(session.memory_usage().to_string());
+        let packet = PublishPacket::new(&topic_name, payload);
+        self.publish_to_topic(&topic_name, Arc::new(packet)).await;
+    }
+
+    /// Retains the message under its topic, after the topic rewrite rules are applied.
+    async fn retain_message(&self, mut packet: PublishPacket) {
+        if let Some(rewritten) = self.rewriter.rewrite(packet.topic()) {
+            packet.set_topic(&rewritten);
+        }
+
         let mut topics = self.topics.write().await;
-        topics.retain_message(packet);
+        let len = packet.payload().len();
+        if !topics.retain_message(packet) {
+            log::warn!("Retained message payload of {len} bytes exceeded the maximum retained payload size, message was not retained and the topic's retained message was cleared.");
+        }
+    }
+
+    /// Maps the certificate a TLS client presented to the identity it is known by, None if client certificates are not required.
+    fn peer_identity(&self, certs: Option<&[CertificateDer]>) -> Option<Identity> {
+        if !self.config.require_client_cert() {
+            return None;
+        }
+
+        let cert = certs?.first()?;
+        let identity = cert_identity(cert, self.config.cert_username());
+        if identity.is_none() {
+            log::warn!("Client certificate does not carry the name configured by cert_username");
+        }
+        return identity;
     }
 
     async fn clean_expired_sessions(&self) {
@@ -282,38 +888,194 @@ impl MqttServer {
     }
 }
 
+/// Resolves once the broker is asked to stop by SIGINT (Ctrl+C) or, on unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(err) => {
+                log::error!("Could not listen for SIGTERM: {err}");
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Reloads the TLS certificates whenever the broker receives SIGHUP, so renewed certificates are used without a restart.
+#[cfg(unix)]
+async fn reload_tls_on_sighup(server: Arc<MqttServer>, reloaders: Vec<Arc<TlsReloader>>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            log::error!(
+                "Could not listen for SIGHUP, TLS certificates will not be reloaded: {err}"
+            );
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        for tls in reloaders.iter() {
+            match tls.reload(&server.config) {
+                Ok(()) => log::info!("Reloaded TLS certificates"),
+                Err(err) => {
+                    log::error!("Could not reload TLS certificates, keeping the current certificates: {err}")
+                }
+            }
+        }
+    }
+}
+
+/// Builds the QUIC listener's configuration from the broker's TLS configuration, offering the `mqtt` ALPN protocol.
+#[cfg(feature = "quic")]
+fn quic_server_config(tls_config: &rustls::ServerConfig) -> Result<quinn::ServerConfig, String> {
+    let mut tls_config = tls_config.clone();
+    tls_config.alpn_protocols = vec![b"mqtt".to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)
+        .map_err(|err| err.to_string())?;
+    return Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)));
+}
+
+/// Periodically removes topics that have no subscribers and no retained message.
+async fn compact_topics(topics: Arc<RwLock<ServerTopics>>, period: Duration) {
+    let mut interval = time::interval(period);
+    // the first tick completes immediately.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        let removed = topics.write().await.compact();
+        if removed > 0 {
+            log::info!("Compacted {removed} unused topics.");
+        }
+    }
+}
+
+/// Upgrades the connection to a WebSocket and handles the client over it.
+async fn handle_websocket<S: AsyncRead + AsyncWrite + Unpin>(
+    server: Arc<MqttServer>,
+    stream: S,
+    addr: SocketAddr,
+    peer: Option<Identity>,
+) {
+    let connect_timeout = server.config.connect_timeout();
+    let mut stream = match timeout(connect_timeout, websocket::accept(stream)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            log::warn!("Rejected WebSocket connection: {addr}, {err}");
+            return;
+        }
+        Err(_) => {
+            log::warn!("WebSocket handshake timed out, closing connection: {addr}");
+            return;
+        }
+    };
+
+    log::info!("New WebSocket connection attempt from: {addr}");
+
+    if let Err(err) = handle_client(server, &mut stream, peer).await {
+        log::warn!("Error handling client: {err}, Closing connection: {addr}")
+    } else {
+        log::info!("Gracefully closing connection: {addr}")
+    }
+}
+
 /// Handle a single TCP client connection event loop.
+///
+/// `peer` is the identity of a client that authenticated with a certificate during the TLS handshake.
 async fn handle_client<S: AsyncReadExt + AsyncWrite + Unpin>(
     server: Arc<MqttServer>,
     stream: &mut S,
+    peer: Option<Identity>,
 ) -> Result<(), ServerError> {
-    let active_session = establish_session(&server, stream).await?;
+    let stream = &mut WriteTimeout::new(stream, server.config.write_timeout());
+
+    // Refuses oversized packets before they are buffered, and keeps packets sent right after the CONNECT
+    // buffered for the session's event loop.
+    let mut reader = FrameReader::new();
+    reader.set_max_packet_size(server.config.max_packet_size());
+
+    // Don't allow a client to hold a socket open indefinitely without sending a CONNECT packet.
+    let connect_timeout = server.config.connect_timeout();
+    let active_session = match timeout(
+        connect_timeout,
+        establish_session(&server, &mut reader, stream, peer),
+    )
+    .await
+    {
+        Ok(session) => session?,
+        Err(_) => {
+            return Err(ServerError::new(
+                server::ErrorKind::ConnectTimeout,
+                format!(
+                    "Client did not send a CONNECT packet within {} seconds.",
+                    connect_timeout.as_secs()
+                ),
+            ))
+        }
+    };
 
     log::info!("connected");
 
-    if let Some(mut session) = active_session {
-        match handle_session(&server, stream, &mut session).await {
-            Ok(()) => {
-                return Ok(());
-            }
-            // If the session encounters an error, the error should close the connection.
-            Err(err) => {
-                let pub_to_server_fut = server.publish_will(&mut session);
-                let dc_sessions_fut = server.dc_sessions.lock();
-
-                let (_, mut dc_sessions) = join!(pub_to_server_fut, dc_sessions_fut);
-
-                dc_sessions.add_session(session.into());
-
-                return Err(err);
-            }
-        }
+    if let Some((mut session, mut registration)) = active_session {
+        // The session only ends without an error when the client sends a DISCONNECT, any other way
+        // the connection closes, including a keep alive timeout, a takeover or a reset, publishes the will.
+        // An MQTT 5.0 client may also ask for its will to be published with its DISCONNECT.
+        let res = handle_session(
+            &server,
+            &mut reader,
+            stream,
+            &mut session,
+            &mut registration.takeover,
+        )
+        .await;
+        let graceful = res.is_ok() && !session.disconnect_with_will();
+        server.close_session(session, graceful).await;
+        // the client id is released once the session is stored.
+        drop(registration);
+        return res;
     } else {
         // we received a PINGREQ so close the connection.
         return Ok(());
     }
 }
 
+/// Answers the CONNECT of a client the broker has no room for with a Server unavailable CONNACK, then closes the connection.
+async fn refuse_client<S: AsyncReadExt + AsyncWriteExt + Unpin>(
+    server: Arc<MqttServer>,
+    stream: &mut S,
+    addr: SocketAddr,
+) {
+    let connect_timeout = server.config.connect_timeout();
+    let mut reader = FrameReader::new();
+    reader.set_max_packet_size(server.config.max_packet_size());
+
+    let (header, body) =
+        match timeout(connect_timeout, reader.read_frame::<_, ServerError>(stream)).await {
+            Ok(Ok(frame)) => frame,
+            _ => return,
+        };
+
+    if let Ok((MqttPacket::Connect(_), protocol)) = Protocol::decode_first_packet(header, body) {
+        if let Ok(buf) = protocol.encode_connack_refusal(ConnectReturnCode::ServerUnavailable) {
+            let _ = stream.write_all(&buf).await;
+        }
+    }
+
+    log::info!("Refused connection: {addr}");
+}
+
 /// Reads the initial packet sent from the client
 ///
 /// The first packet from the client should be a CONNECT packet or a PINGREQ packet,
@@ -326,174 +1088,380 @@ async fn handle_client<S: AsyncReadExt + AsyncWrite + Unpin>(
 ///
 /// If a CONNECT packet is the first packet sent, the function will return an Ok(Session) value.
 ///
+/// A client whose CONNECT is refused is sent a CONNACK with the return code of the
+/// [server::ErrorKind::ConnectError] that refused it before the error is returned.
+///
 /// This function is NOT part of the main event loop of the client's connection instance.
 ///
 
 async fn establish_session<S: AsyncWriteExt + AsyncReadExt + Unpin>(
     server: &Arc<MqttServer>,
+    reader: &mut FrameReader,
     stream: &mut S,
-) -> Result<Option<ActiveSession>, ServerError> {
-    loop {
-        match read_packet::<_, ServerError>(stream).await {
-            Ok(packet_opt) => {
-                match packet_opt {
-                    Some(packet) => {
-                        let session: ActiveSession;
-                        match packet {
-                            MqttPacket::PingReq(_) => {
-                                stream
-                                    .write_all(&mut PingRespPacket::new().encode())
-                                    .await?;
-
-                                return Ok(None);
-                            }
+    peer: Option<Identity>,
+) -> Result<Option<(ActiveSession, Registration)>, ServerError> {
+    let (header, body) = reader.read_frame::<_, ServerError>(stream).await?;
+    let (packet, mut protocol) = match Protocol::decode_first_packet(header, body) {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            // The Server MUST respond to the CONNECT Packet with a CONNACK return code 0x01 (unacceptable protocol
+            // level) and then disconnect the Client if the Protocol Level is not supported by the Server [MQTT-3.1.2-2].
+            if let server::ErrorKind::ConnectError(code) = err.kind() {
+                stream
+                    .write_all(&Protocol::V3.encode_connack_refusal(*code)?)
+                    .await?;
+            }
+            return Err(err);
+        }
+    };
 
-                            MqttPacket::Connect(packet) => {
-                                let mut connack = ConnAckPacket::new(false, ConnectReturnCode::Accept);
-
-                                // authenticate the request
-                                /*
-                                 * TODO: the user is mut here becuase clippy isn't able to tell that the user can only be assigned once.
-                                 * Maybe change the control flow for the function to safegaurd against inadvertant assignments to the user variable?
-                                 */
-                                let mut user: Option<UserMeta> = None;
-
-                                if server.config.require_auth() {
-                                    match (packet.username(), packet.password()) {
-                                        (Some(username), Some(password)) => {
-                                            let password = str::from_utf8(&password).unwrap();
-                                            user = Some(server.auth_manager.verify_credentials(username, password)?);
-                                        }
-                                        _ => {
-                                            return Err(ServerError::new(server::ErrorKind::ConnectError(ConnectReturnCode::BadUsernameOrPassword), String::from("Client attempted to connect without provided a username or password")))
-                                        }
-                                    }
-                                }
-
-                                let mut sessions = server.dc_sessions.lock().await;
-                                // Check if the server has a session history.
-                                if let Some(dc_session) = sessions.remove_session(packet.client_id()) {
-                                    if packet.clean_session() {
-                                        // The client requested a new session, drop the old session history and continue.
-                                        session = ActiveSession::new(packet, user);
-                                    } else {
-                                        // The client requested to resume from a client's prior history.
-                                        connack.set_session_present(true);
-                                        session = dc_session.into_active(packet)?;
-                                    }
-                                } else {
-                                    // The server does not have any session history.
-                                    session = ActiveSession::new(packet, user);
-                                }
-
-                                stream.write_all(&connack.encode()).await?;
-                            }
-                            _ => {
-                                return Err(ServerError::new(
-                                    server::ErrorKind::ProtocolError,
-                                    String::from(
-                                        "Cannot initialize connection without first receiving a CONNECT packet",
-                                    ),
-                                ))
-                            }
-                        };
+    match packet {
+        MqttPacket::PingReq(_) => {
+            stream
+                .write_all(&mut PingRespPacket::new().encode())
+                .await?;
 
-                        return Ok(Some(session));
-                    }
+            return Ok(None);
+        }
 
-                    // requeue the task.
-                    None => continue,
+        MqttPacket::Connect(packet) => {
+            match handle_connect_packet(server, &mut protocol, packet, peer).await {
+                Ok((mut session, registration, session_present)) => {
+                    let res = match protocol.encode_connack(session_present) {
+                        Ok(connack) => stream.write_all(&connack).await.map_err(ServerError::from),
+                        Err(err) => Err(err),
+                    };
+                    session.set_protocol(protocol);
+
+                    if let Err(err) = res {
+                        // the client never learned the session was resumed, keep it for the client's next attempt.
+                        if session_present {
+                            server.dc_sessions.lock().await.add_session(session.into());
+                        }
+                        return Err(err);
+                    }
+                    return Ok(Some((session, registration)));
+                }
+                Err(err) => {
+                    if let server::ErrorKind::ConnectError(code) = err.kind() {
+                        stream
+                            .write_all(&protocol.encode_connack_refusal(*code)?)
+                            .await?;
+                    }
+                    return Err(err);
                 }
             }
-            Err(err) => {
-                return Err(err.into());
+        }
+        _ => {
+            return Err(ServerError::new(
+                server::ErrorKind::ProtocolError,
+                String::from(
+                    "Cannot initialize connection without first receiving a CONNECT packet",
+                ),
+            ))
+        }
+    };
+}
+
+/// Authenticates the client and creates or resumes its session.
+///
+/// Returns the session, its connection's registration and whether a prior session was resumed. A client that
+/// may not connect is refused with a [server::ErrorKind::ConnectError] holding the CONNACK return code it is sent.
+async fn handle_connect_packet(
+    server: &Arc<MqttServer>,
+    protocol: &mut Protocol,
+    mut packet: ConnectPacket,
+    peer: Option<Identity>,
+) -> Result<(ActiveSession, Registration, bool), ServerError> {
+    let mut session_present = false;
+    let mut session: ActiveSession;
+
+    if protocol.version() == MqttVersion::V3_1 && !server.config.allow_mqtt_v3_1() {
+        return Err(ServerError::new(
+            server::ErrorKind::ConnectError(ConnectReturnCode::InvalidProtocol),
+            format!(
+                "Client: {} attempted to connect with MQTT v3.1.",
+                packet.client_id()
+            ),
+        ));
+    }
+
+    // A Server MAY allow a Client to supply a ClientId that has a length of zero bytes, however if it does so
+    // the Server MUST treat this as a special case and assign a unique ClientId to that Client [MQTT-3.1.3-6].
+    if packet.client_id().is_empty() {
+        if !protocol.accepts_empty_client_id(packet.clean_session()) {
+            // If the Client supplies a zero-byte ClientId with CleanSession set to 0, the Server MUST respond to
+            // the CONNECT Packet with a CONNACK return code 0x02 (Identifier rejected) [MQTT-3.1.3-8].
+            return Err(ServerError::new(
+                server::ErrorKind::ConnectError(ConnectReturnCode::IdentifierRejected),
+                String::from("Client attempted to resume a session without a client id."),
+            ));
+        }
+
+        packet.client_id = server.generate_client_id();
+        protocol.set_assigned_client_id(packet.client_id().to_string());
+        log::info!("Assigned client id: {}", packet.client_id());
+    }
+
+    // authenticate the request
+    /*
+     * TODO: the user is mut here becuase clippy isn't able to tell that the user can only be assigned once.
+     * Maybe change the control flow for the function to safegaurd against inadvertant assignments to the user variable?
+     */
+    let mut user: Option<Identity> = peer;
+
+    // A client that presented a certificate is known by it and does not need a password.
+    if server.config.require_auth() && user.is_none() {
+        match (packet.username(), packet.password()) {
+            (Some(username), Some(password)) => {
+                user = Some(
+                    server
+                        .auth_manager
+                        .verify_credentials(packet.client_id(), username, password)
+                        .await?,
+                );
+            }
+            _ => {
+                return Err(ServerError::new(
+                    server::ErrorKind::ConnectError(ConnectReturnCode::BadUsernameOrPassword),
+                    String::from(
+                        "Client attempted to connect without provided a username or password",
+                    ),
+                ))
             }
         }
     }
+
+    let registration = server.register_connection(packet.client_id()).await;
+    server
+        .cancel_will(packet.client_id(), packet.clean_session())
+        .await;
+
+    let mut sessions = server.dc_sessions.lock().await;
+    // Check if the server has a session history.
+    if let Some(dc_session) = sessions.remove_session(packet.client_id()) {
+        if packet.clean_session() {
+            // The client requested a new session, drop the old session history and continue.
+            session = ActiveSession::new(packet, user);
+        } else {
+            // The client requested to resume from a client's prior history.
+            session_present = true;
+            session = match dc_session.clone().into_active(packet, user) {
+                Ok(session) => session,
+                Err(err) => {
+                    // keep the session history for the client's next attempt.
+                    sessions.add_session(dc_session);
+                    return Err(err);
+                }
+            };
+        }
+    } else {
+        // The server does not have any session history.
+        session = ActiveSession::new(packet, user);
+    }
+
+    protocol.set_topic_alias_maximum(server.config.topic_alias_maximum());
+    protocol.set_receive_limits(
+        server.config.max_packet_size(),
+        server.config.receive_maximum(),
+    );
+    session.set_inflight_limits(
+        server.config.max_inflight_qos1(),
+        server.config.max_inflight_qos2(),
+    );
+    session.set_session_expiry(protocol.session_expiry(server.config.session_expiry_interval()));
+    session.set_will_delay(protocol.will_delay(server.config.will_delay_interval()));
+
+    return Ok((session, registration, session_present));
 }
 
 /// Handle a MQTT client connection event loop after CONNECT packet receipt.
 async fn handle_session<S: AsyncRead + AsyncWrite + Unpin>(
     server: &Arc<MqttServer>,
+    reader: &mut FrameReader,
     mut stream: &mut S,
     session: &mut ActiveSession,
+    takeover: &mut oneshot::Receiver<()>,
 ) -> Result<(), ServerError> {
     let mut mailbox = Mailbox::new();
 
-    loop {
-        // read in all packets.
-        while let Some(packet) = read_packet::<_, ServerError>(stream).await? {
-            if session.timed_out() {
-                // if session has timed out, exit the main event loop
-                return Ok(());
-            } else {
-                // if session has NOT timed out, update the last_read value of the session and continue the main event loop.
-                session.update_last_read();
-            }
+    // A resumed session keeps its subscriptions, rebuild the mailbox from the stored topic filters.
+    // This also forwards any retained messages for the restored subscriptions.
+    let topic_filters = session.topic_filters().clone();
+    for (filter, qos) in topic_filters.iter() {
+        // sessions are resumed by client id, so the client resuming one may not be the client that subscribed.
+        if !server
+            .auth_manager
+            .authorize_subscribe(session.identity(), filter)
+        {
+            log::warn!(
+                "Client: {} is not authorized to resume the subscription to: {}",
+                session.client_id(),
+                filter.clone().to_string()
+            );
+            session.remove_topic_filter(filter);
+            continue;
+        }
 
-            let should_shutdown =
-                handle_packet(server, &mut stream, session, &mut mailbox, packet).await?;
+        server
+            .subscribe_to_filter(stream, session, &mut mailbox, filter, *qos)
+            .await?;
+    }
 
-            if should_shutdown {
-                return Ok(());
-            };
+    if topic_filters.len() > 0 {
+        log::info!(
+            "Restored {} subscriptions for client: {}",
+            topic_filters.len(),
+            session.client_id()
+        );
+    }
+
+    let mut shutdown = server.shutdown.subscribe();
+    let mut throttled = false;
+    let mut last_report = Instant::now();
+    // drives retries of unacknowledged packets and the keep alive check, the shortest retry duration is 200ms.
+    let mut retry_interval = time::interval(Duration::from_millis(100));
+
+    loop {
+        // Stop forwarding messages to a session holding too many unacknowledged packets. The topic's broadcast
+        // channel drops the oldest messages instead, so one slow client cannot exhaust the broker's memory.
+        let over_budget = match server.config.max_session_memory() {
+            Some(max) => session.exceeds_memory(max),
+            None => false,
+        };
+
+        if over_budget && !throttled {
+            log::warn!(
+                "Session for client: {} exceeded the memory limit, holding {} bytes. Pausing message delivery.",
+                session.client_id(),
+                session.memory_usage()
+            );
         }
+        throttled = over_budget;
 
-        // WRITE all newly received packets
-        for mail in mailbox.mail_mut() {
-            // read all mail in this receiver.
-            loop {
-                match mail.recv() {
-                    Ok(message) => match message {
-                        Some(packet) => {
-                            if mail.qos() == packet.qos() {
-                                let buf = session.origin(&packet).encode()?;
-                                // forward received mail to the client.
-                                stream.write_all(&buf).await?;
-                            } else {
-                                match mail.qos().min(packet.qos()) {
-                                    QosLevel::AtMostOnce => {
-                                        let mut packet = (*packet).clone();
-                                        packet.set_qos_atmostonce();
-                                        stream.write_all(&packet.encode()?).await?;
-                                    }
-                                    QosLevel::AtLeastOnce => {
-                                        // this has extra memory overhead. The message assurance might need some refractoring...
-                                        let mut packet = (*packet).clone();
-                                        packet.set_qos_atleastonce(0);
-                                        session.origin(&Arc::new(packet.clone())).encode()?;
-                                        stream.write_all(&packet.encode()?).await?;
-                                    }
-                                    QosLevel::ExactlyOnce => {
-                                        unreachable!();
-                                    }
-                                }
-                            }
+        // leave the remaining mail queued until the client acknowledges an in-flight message.
+        let accepting_mail = !over_budget && !session.inflight_window_full();
+
+        // Each branch is cancel safe, a packet or message that was partially received when another branch
+        // completes is picked up on the next iteration.
+        tokio::select! {
+            packet = session.read_packet(reader, stream) => {
+                let packet = packet?;
+                session.update_last_read();
+
+                let should_shutdown =
+                    handle_packet(server, &mut stream, session, &mut mailbox, packet).await?;
+
+                if should_shutdown {
+                    return Ok(());
+                };
+            }
+            mail = mailbox.recv(), if accepting_mail => {
+                match mail {
+                    // forward received mail to the client.
+                    Ok((qos, packet)) => forward_mail(stream, session, qos, packet).await?,
+                    Err(err) => match err.kind() {
+                        server::ErrorKind::FullMailbox(count) => {
+                            log::warn!("Mailbox was filled, lost {count} messages. Continuing to read from oldest available message.");
+                        }
+                        _ => {
+                            log::error!("{}", err);
                         }
-                        // no more mail in the receiver, move on to the next receiver.
-                        None => break,
                     },
-                    Err(err) => {
-                        match err.kind() {
-                            server::ErrorKind::FullMailbox(count) => {
-                                log::warn!("Mailbox was filled, lost {count} messages. Continuing to read from oldest available message.");
-                                continue;
-                            }
-                            _ => {
-                                log::error!("{}", err);
-                            }
-                        };
+                }
+            }
+            _ = shutdown.wait_for(|stopping| *stopping) => {
+                // forward the messages that already arrived before the client is disconnected.
+                while !session.inflight_window_full() {
+                    match mailbox.try_recv() {
+                        Some(Ok((qos, packet))) => forward_mail(stream, session, qos, packet).await?,
+                        Some(Err(_)) => continue,
+                        None => break,
+                    }
+                }
+
+                if let Some(buf) = session.encode_disconnect(ReasonCode::ServerShuttingDown)? {
+                    stream.write_all(&buf).await?;
+                }
+                stream.flush().await?;
+                return Ok(());
+            }
+            _ = &mut *takeover => {
+                // another connection with the same client id is taking the session over.
+                if let Some(buf) = session.encode_disconnect(ReasonCode::SessionTakenOver)? {
+                    stream.write_all(&buf).await?;
+                }
+                return Err(ServerError::new(
+                    server::ErrorKind::SessionTakenOver,
+                    format!(
+                        "Client: {} connected on another connection.",
+                        session.client_id()
+                    ),
+                ));
+            }
+            _ = retry_interval.tick() => {
+                // The connection is closed as if it was lost, so the will is published.
+                if session.timed_out() {
+                    return Err(ServerError::new(
+                        server::ErrorKind::KeepAliveTimeout,
+                        format!(
+                            "Client: {} did not send a packet within one and a half times its keep alive.",
+                            session.client_id()
+                        ),
+                    ));
+                }
+
+                // RETRY all already sent packets
+                session.retry_packets(stream).await?;
+
+                if let Some(interval) = server.config.sys_interval() {
+                    if last_report.elapsed() > interval {
+                        last_report = Instant::now();
+                        server.publish_session_memory(session).await;
                     }
                 }
             }
         }
 
         session.clean_session();
-        // RETRY all already sent packets
-        session.retry_packets(stream).await?;
     }
 }
 
+/// Forwards a message published to one of the session's subscriptions, downgraded to the QoS granted for the subscription.
+async fn forward_mail<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    session: &mut ActiveSession,
+    qos: QosLevel,
+    packet: Arc<PublishPacket>,
+) -> Result<(), ServerError> {
+    if qos == packet.qos() {
+        let packet = session.origin(&packet);
+        let buf = session.encode(MqttPacket::Publish(packet))?;
+        stream.write_all(&buf).await?;
+    } else {
+        match qos.min(packet.qos()) {
+            QosLevel::AtMostOnce => {
+                let mut packet = (*packet).clone();
+                packet.set_qos_atmostonce();
+                let buf = session.encode(MqttPacket::Publish(packet))?;
+                stream.write_all(&buf).await?;
+            }
+            QosLevel::AtLeastOnce => {
+                // this has extra memory overhead. The message assurance might need some refractoring...
+                let mut packet = (*packet).clone();
+                packet.set_qos_atleastonce(0);
+                let packet = session.origin(&Arc::new(packet));
+                let buf = session.encode(MqttPacket::Publish(packet))?;
+                stream.write_all(&buf).await?;
+            }
+            QosLevel::ExactlyOnce => {
+                unreachable!();
+            }
+        }
+    }
+    return Ok(());
+}
+
 /// If the client disconnects gracefully return Ok(true), else returns Ok(false).
 async fn handle_packet<S: AsyncRead + AsyncWrite + Unpin>(
     server: &Arc<MqttServer>,
@@ -502,6 +1470,8 @@ async fn handle_packet<S: AsyncRead + AsyncWrite + Unpin>(
     mailbox: &mut Mailbox,
     packet: MqttPacket,
 ) -> Result<bool, ServerError> {
+    log::trace!("Received {packet} from client: {}", session.client_id());
+
     match packet {
         MqttPacket::Subscribe(packet) => {
             let mut resp: Vec<SubAckQoS> = vec![];
@@ -516,9 +1486,24 @@ async fn handle_packet<S: AsyncRead + AsyncWrite + Unpin>(
             for topic in packet.topic_filters() {
                 match topic {
                     FilterResult::Ok { filter, qos } => {
+                        if !server
+                            .auth_manager
+                            .authorize_subscribe(session.identity(), &filter)
+                        {
+                            log::warn!(
+                                "Client: {} is not authorized to subscribe to: {}",
+                                session.client_id(),
+                                filter.clone().to_string()
+                            );
+                            session.deny_subscription(packet.id(), resp.len());
+                            resp.push(SubAckQoS::Err);
+                            continue;
+                        }
+
                         server
                             .subscribe_to_filter(stream, session, mailbox, &filter, qos)
                             .await?;
+                        session.add_topic_filter(filter, qos);
                         resp.push(qos.into());
                     }
                     FilterResult::Err => {
@@ -527,15 +1512,46 @@ async fn handle_packet<S: AsyncRead + AsyncWrite + Unpin>(
                 }
             }
 
-            let mut buf = SubAckPacket::new(packet.id(), resp).encode()?;
-            stream.write_all(&mut buf).await?;
+            let buf = session.encode(MqttPacket::SubAck(SubAckPacket::new(packet.id(), resp)))?;
+            stream.write_all(&buf).await?;
         }
         MqttPacket::Publish(mut packet) => {
             packet.set_dup(false);
 
+            // Each QoS 2 message is held until the client releases it, a client that never does would otherwise
+            // grow the session without bound. The limit is checked before the message is stored or retained.
+            if let (QosLevel::ExactlyOnce, Some(id), Some(max)) =
+                (packet.qos(), packet.id(), server.config.receive_maximum())
+            {
+                if !session.awaiting_release(id) && session.incoming_qos2() >= max {
+                    if let Some(buf) =
+                        session.encode_disconnect(ReasonCode::ReceiveMaximumExceeded)?
+                    {
+                        stream.write_all(&buf).await?;
+                    }
+                    return Err(ServerError::new(
+                        server::ErrorKind::InflightLimitExceeded,
+                        format!(
+                            "Client: {} exceeded the limit of {max} QoS 2 messages awaiting release.",
+                            session.client_id()
+                        ),
+                    ));
+                }
+            }
+
+            // MQTT v3.1.1 cannot refuse a PUBLISH, an unauthorized message is acknowledged as usual and then discarded.
+            let authorized = server.authorize_publish(session.identity(), packet.topic());
+            if !authorized {
+                log::warn!(
+                    "Client: {} is not authorized to publish to: {}",
+                    session.client_id(),
+                    packet.topic().clone().to_string()
+                );
+            }
+
             // The retain flag has different meanings in the context which side is receiving the packet.
             // Therefore, the retain flag should be reset after the effects are handled by the broker.
-            if packet.retain() {
+            if packet.retain() && authorized {
                 server.retain_message(packet.clone()).await;
             }
 
@@ -543,11 +1559,11 @@ async fn handle_packet<S: AsyncRead + AsyncWrite + Unpin>(
 
             match packet.qos() {
                 QosLevel::ExactlyOnce => {
-                    let res_packet = session.publish(packet);
-                    match res_packet {
+                    match session.publish(packet) {
                         Some(packet) => {
                             // We received a new packet, send the appropriate response.
-                            stream.write_all(&packet.encode()).await?;
+                            let buf = session.encode(MqttPacket::PubRec(packet))?;
+                            stream.write_all(&buf).await?;
                         }
                         None => {
                             // Do nothing, we already received the packet earlier. Wait for the timeout to hit.
@@ -555,46 +1571,59 @@ async fn handle_packet<S: AsyncRead + AsyncWrite + Unpin>(
                     }
                 }
                 QosLevel::AtLeastOnce => {
-                    let id = packet.id().expect("At Least Once packet had no packet id");
+                    let buf = session.encode(
+                        packet
+                            .ack_response()
+                            .expect("At Least Once packet had no packet id"),
+                    )?;
                     let topic = packet.topic().clone();
                     let arc_packet = Arc::new(packet);
-                    let buf = PubAckPacket::new(id).encode();
 
                     // if the server fails to respond, we want to fail the rest of the function, this
                     // allows the client to force a retry attempt on successive PUBLISH packets.
                     stream.write_all(&buf).await?;
-                    server.publish_to_topic(&topic, arc_packet).await;
+                    if authorized {
+                        server.publish_to_topic(&topic, arc_packet).await;
+                    }
                 }
                 _ => {
-                    let arc_packet = Arc::new(packet);
-                    let _ = server
-                        .publish_to_topic(&arc_packet.topic().clone(), arc_packet)
-                        .await;
+                    if authorized {
+                        let arc_packet = Arc::new(packet);
+                        let _ = server
+                            .publish_to_topic(&arc_packet.topic().clone(), arc_packet)
+                            .await;
+                    }
                 }
             }
         }
         MqttPacket::PingReq(_packet) => {
-            stream.write_all(&PingRespPacket::new().encode()).await?;
+            let buf = session.encode(MqttPacket::PingResp(PingRespPacket::new()))?;
+            stream.write_all(&buf).await?;
         }
         MqttPacket::PubAck(in_packet) => {
-            let buf = session.ack(in_packet.id()).encode();
+            let packet = session.ack(in_packet.id());
+            let buf = session.encode(MqttPacket::PubAck(packet))?;
             stream.write_all(&buf).await?
         }
         MqttPacket::PubRec(in_packet) => {
             if let Some(packet) = session.rec(in_packet.id()) {
-                stream.write_all(&packet.encode()).await?;
+                let buf = session.encode(MqttPacket::PubRel(packet))?;
+                stream.write_all(&buf).await?;
             }
         }
         MqttPacket::PubRel(in_packet) => {
             if let Some(forw_packet) = session.rel(in_packet.id()) {
                 // if the server fails to respond, we want to fail the rest of the function, this
                 // allows the client to force a retry attempt on successive PUBREL packets.
-                stream
-                    .write_all(&PubCompPacket::new(in_packet.id()).encode())
-                    .await?;
-                server
-                    .publish_to_topic(&forw_packet.topic().clone(), forw_packet)
-                    .await;
+                let buf = session.encode(MqttPacket::PubComp(in_packet.next()))?;
+                stream.write_all(&buf).await?;
+
+                // the refusal was logged when the PUBLISH was received.
+                if server.authorize_publish(session.identity(), forw_packet.topic()) {
+                    server
+                        .publish_to_topic(&forw_packet.topic().clone(), forw_packet)
+                        .await;
+                }
             } else {
                 // if there is no current history of that packet, do nothing.
             }
@@ -605,15 +1634,26 @@ async fn handle_packet<S: AsyncRead + AsyncWrite + Unpin>(
         MqttPacket::Unsubscribe(in_packet) => {
             for filter in in_packet.filters() {
                 mailbox.remove(filter);
+                session.remove_topic_filter(filter);
             }
-            stream
-                .write_all(&UnsubAckPacket::new(in_packet.id()).encode())
-                .await?;
+            let buf = session.encode(MqttPacket::UnsubAck(UnsubAckPacket::new(in_packet.id())))?;
+            stream.write_all(&buf).await?;
         }
         MqttPacket::Disconnect(_packet) => {
+            session.renegotiate_session_expiry(server.config.session_expiry_interval());
             stream.shutdown().await?;
             return Ok(true);
         }
+        MqttPacket::Connect(_packet) => {
+            // The Server MUST process a second CONNECT Packet sent from a Client as a protocol violation and disconnect the Client [MQTT-3.1.0-2].
+            if let Some(buf) = session.encode_disconnect(ReasonCode::ProtocolError)? {
+                stream.write_all(&buf).await?;
+            }
+            return Err(ServerError::new(
+                server::ErrorKind::DuplicateConnect,
+                String::from("Received a second CONNECT packet on an established connection."),
+            ));
+        }
         _ => {
             // Received invalid packet, drop the
             return Err(ServerError::new(
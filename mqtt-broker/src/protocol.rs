@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use mqtt_core::{
+    err::{
+        server::{self, ServerError},
+        DecodeError, DecodeErrorKind,
+    },
+    qos::{QosLevel, SubAckQoS},
+    topic::TopicName,
+    v3::{self, MqttPacket},
+    v5::{self, Property, ReasonCode},
+    ConnectReturnCode, MqttVersion,
+};
+
+/// The version of MQTT spoken on a connection, negotiated from the Protocol Level of the CONNECT packet.
+///
+/// The broker is written against v3.1.1 packets, MQTT 5.0 packets are translated to and from their v3.1.1
+/// equivalents as they are read from and written to the connection.
+#[derive(Debug, Clone)]
+pub enum Protocol {
+    V3,
+    // v3.1 shares the v3.1.1 packet formats, but the CONNACK has no Session Present flag.
+    V3_1,
+    V5(V5State),
+}
+
+impl Protocol {
+    /// Decodes the first packet sent on a connection.
+    ///
+    /// A CONNECT packet with a Protocol Level of 5 negotiates MQTT 5.0 for the rest of the connection and a
+    /// CONNECT packet with the v3.1 Protocol Name "MQIsdp" negotiates v3.1, every other packet is decoded as v3.1.1.
+    ///
+    /// A CONNECT packet for an unsupported version of MQTT is refused with an Invalid protocol
+    /// [server::ErrorKind::ConnectError].
+    pub fn decode_first_packet(
+        mut header: Bytes,
+        mut body: Bytes,
+    ) -> Result<(MqttPacket, Self), ServerError> {
+        if header[0] & 0xF0 == v3::PacketType::CONNECT as u8
+            && protocol_level(&body) == Some(v5::PROTOCOL_LEVEL)
+        {
+            let f_header = v5::FixedHeader::decode(&mut header)?;
+            if let v5::MqttPacket::Connect(packet) =
+                v5::decode_packet(f_header, &mut body).map_err(unsupported_protocol)?
+            {
+                let state = V5State::new(&packet)?;
+                return Ok((
+                    MqttPacket::Connect(into_v3_connect(packet)),
+                    Self::V5(state),
+                ));
+            }
+        }
+
+        let f_header = v3::FixedHeader::decode(&mut header)?;
+        let packet = v3::decode_packet(f_header, &mut body).map_err(unsupported_protocol)?;
+        if let MqttPacket::Connect(connect) = &packet {
+            if connect.version() == MqttVersion::V3_1 {
+                return Ok((packet, Self::V3_1));
+            }
+        }
+        return Ok((packet, Self::V3));
+    }
+
+    /// Decodes a packet read from the connection into its v3.1.1 equivalent.
+    pub fn decode(
+        &mut self,
+        mut header: Bytes,
+        mut body: Bytes,
+    ) -> Result<MqttPacket, ServerError> {
+        match self {
+            Self::V3 | Self::V3_1 => {
+                let f_header = v3::FixedHeader::decode(&mut header)?;
+                return Ok(v3::decode_packet(f_header, &mut body)?);
+            }
+            Self::V5(state) => {
+                let f_header = v5::FixedHeader::decode(&mut header)?;
+                return state.decode(v5::decode_packet(f_header, &mut body)?);
+            }
+        }
+    }
+
+    /// Encodes a v3.1.1 packet for the connection, translating it to MQTT 5.0 if it was negotiated.
+    pub fn encode(&mut self, packet: MqttPacket) -> Result<Bytes, ServerError> {
+        match self {
+            Self::V3 | Self::V3_1 => return Ok(packet.encode()?),
+            Self::V5(state) => return state.encode(packet),
+        }
+    }
+
+    /// Encodes the CONNACK accepting the connection.
+    pub fn encode_connack(&self, session_present: bool) -> Result<Bytes, ServerError> {
+        match self {
+            Self::V3 => {
+                return Ok(
+                    v3::ConnAckPacket::new(session_present, ConnectReturnCode::Accept).encode(),
+                );
+            }
+            Self::V3_1 => {
+                return Ok(v3::ConnAckPacket::new(false, ConnectReturnCode::Accept).encode());
+            }
+            Self::V5(state) => return state.encode_connack(session_present),
+        }
+    }
+
+    /// Encodes a CONNACK refusing the connection.
+    pub fn encode_connack_refusal(&self, code: ConnectReturnCode) -> Result<Bytes, ServerError> {
+        match self {
+            Self::V3 | Self::V3_1 => return Ok(v3::ConnAckPacket::new(false, code).encode()),
+            Self::V5(_) => {
+                let connack = v5::ConnAckPacket::new(false, reason_code(code));
+                return Ok(connack.encode()?);
+            }
+        }
+    }
+
+    pub fn version(&self) -> MqttVersion {
+        match self {
+            Self::V3 => return MqttVersion::V3_1_1,
+            Self::V3_1 => return MqttVersion::V3_1,
+            Self::V5(_) => return MqttVersion::V5,
+        }
+    }
+
+    /// Returns true if a client may connect with a zero length client id, and have the broker assign one.
+    ///
+    /// A v3.1.1 client must also start a clean session [MQTT-3.1.3-7], MQTT 5.0 has no such restriction.
+    /// v3.1 requires every client to send a client id.
+    pub fn accepts_empty_client_id(&self, clean_session: bool) -> bool {
+        match self {
+            Self::V3 => return clean_session,
+            Self::V3_1 => return false,
+            Self::V5(_) => return true,
+        }
+    }
+
+    /// Records the client id assigned by the broker, MQTT 5.0 clients are sent it in the CONNACK.
+    pub fn set_assigned_client_id(&mut self, client_id: String) {
+        if let Self::V5(state) = self {
+            state.assigned_client_id = Some(client_id);
+        }
+    }
+
+    /// Encodes a DISCONNECT sent by the broker, returns None for v3.1.1 where only the client sends a DISCONNECT.
+    pub fn encode_disconnect(&self, reason: ReasonCode) -> Result<Option<Bytes>, ServerError> {
+        match self {
+            Self::V3 | Self::V3_1 => return Ok(None),
+            Self::V5(_) => return Ok(Some(v5::DisconnectPacket::new(reason).encode()?)),
+        }
+    }
+
+    /// Sets the highest Topic Alias the client may send, 0 disables topic aliases.
+    pub fn set_topic_alias_maximum(&mut self, max: u16) {
+        if let Self::V5(state) = self {
+            state.topic_alias_maximum = max;
+        }
+    }
+
+    /// Sets the limits an MQTT 5.0 client is told about in the CONNACK, None leaves a limit at the protocol maximum.
+    ///
+    /// `max_packet_size` is the largest packet the client may send, `receive_maximum` the number of QoS 2 messages it may
+    /// have awaiting release. QoS 1 messages are acknowledged as soon as they are received.
+    pub fn set_receive_limits(
+        &mut self,
+        max_packet_size: Option<usize>,
+        receive_maximum: Option<usize>,
+    ) {
+        if let Self::V5(state) = self {
+            state.maximum_packet_size =
+                max_packet_size.map_or(0, |max| max.min(u32::MAX as usize) as u32);
+            state.server_receive_maximum =
+                receive_maximum.map_or(0, |max| max.min(u16::MAX as usize) as u16);
+        }
+    }
+
+    /// Negotiates how long, in seconds, the session is retained after the client disconnects.
+    ///
+    /// `max` is the broker's session expiry interval, where 0 retains sessions indefinitely.
+    /// v3.1.1 clients are always granted the broker's interval.
+    pub fn session_expiry(&mut self, max: u64) -> u64 {
+        match self {
+            Self::V3 | Self::V3_1 => return max,
+            Self::V5(state) => return state.negotiate_session_expiry(max),
+        }
+    }
+
+    /// How long, in seconds, the client's will is held back after the connection is lost.
+    ///
+    /// `default` is the broker's will delay, which is used for v3.1.1 clients.
+    pub fn will_delay(&self, default: u64) -> u64 {
+        match self {
+            Self::V3 | Self::V3_1 => return default,
+            Self::V5(state) => return state.will_delay as u64,
+        }
+    }
+
+    /// Returns true if the session should be discarded as soon as the client disconnects.
+    ///
+    /// An MQTT 5.0 client requests this with a Session Expiry Interval of 0, which is the default.
+    pub fn expires_on_disconnect(&self) -> bool {
+        match self {
+            Self::V3 | Self::V3_1 => return false,
+            Self::V5(state) => return state.requested_expiry == 0,
+        }
+    }
+
+    /// Records that the topic filter at `index` of the SUBSCRIBE with packet id `id` was refused by the ACL.
+    ///
+    /// MQTT 5.0 clients are sent Not authorized for the filter rather than Unspecified error, v3.1.1 has no such code.
+    pub fn deny_subscription(&mut self, id: u16, index: usize) {
+        if let Self::V5(state) = self {
+            state
+                .denied_subscriptions
+                .entry(id)
+                .or_default()
+                .push(index);
+        }
+    }
+
+    /// Returns true if the client's DISCONNECT asked for its will to be published, which only MQTT 5.0 clients can.
+    pub fn disconnect_with_will(&self) -> bool {
+        match self {
+            Self::V3 | Self::V3_1 => return false,
+            Self::V5(state) => {
+                return state.disconnect_reason == Some(ReasonCode::DisconnectWithWillMessage)
+            }
+        }
+    }
+
+    /// The number of QoS 1 and QoS 2 publications the client is willing to process concurrently.
+    ///
+    /// Returns None for v3.1.1 clients, which do not limit in-flight publications.
+    pub fn receive_maximum(&self) -> Option<u16> {
+        match self {
+            Self::V3 | Self::V3_1 => return None,
+            Self::V5(state) => return Some(state.receive_maximum),
+        }
+    }
+}
+
+/// Connection state that only exists for MQTT 5.0 clients.
+#[derive(Debug, Clone)]
+pub struct V5State {
+    receive_maximum: u16,
+    // the highest Topic Alias the client may send, 0 disables topic aliases.
+    topic_alias_maximum: u16,
+    topic_aliases: HashMap<u16, TopicName>,
+    // seconds, the Session Expiry Interval sent in the CONNECT packet.
+    requested_expiry: u32,
+    // seconds, the Session Expiry Interval the broker granted.
+    granted_expiry: u32,
+    // seconds, the Will Delay Interval sent with the will.
+    will_delay: u32,
+    // set when the client connected without a client id.
+    assigned_client_id: Option<String>,
+    // bytes, the largest packet the client may send, 0 is the protocol maximum.
+    maximum_packet_size: u32,
+    // the number of QoS 2 messages the client may have awaiting release, 0 is the protocol maximum.
+    server_receive_maximum: u16,
+    // the number of topic filters in each UNSUBSCRIBE awaiting an UNSUBACK, keyed by packet id.
+    unsubscribes: HashMap<u16, usize>,
+    // the positions of the topic filters refused by the ACL in each SUBSCRIBE awaiting a SUBACK, keyed by packet id.
+    denied_subscriptions: HashMap<u16, Vec<usize>>,
+    // the Reason Code of the client's DISCONNECT.
+    disconnect_reason: Option<ReasonCode>,
+}
+
+impl V5State {
+    fn new(packet: &v5::ConnectPacket) -> Result<Self, ServerError> {
+        let properties = packet.properties();
+
+        // It is a Protocol Error to include the Receive Maximum value of 0.
+        let receive_maximum = properties.receive_maximum().unwrap_or(u16::MAX);
+        if receive_maximum == 0 {
+            return Err(ServerError::new(
+                server::ErrorKind::ProtocolError,
+                String::from("Client sent a Receive Maximum of 0."),
+            ));
+        }
+
+        let requested_expiry = properties.session_expiry_interval().unwrap_or(0);
+        let will_delay = packet
+            .will()
+            .as_ref()
+            .and_then(|will| will.properties().will_delay_interval())
+            .unwrap_or(0);
+
+        return Ok(Self {
+            receive_maximum,
+            topic_alias_maximum: 0,
+            topic_aliases: HashMap::new(),
+            requested_expiry,
+            granted_expiry: requested_expiry,
+            will_delay,
+            assigned_client_id: None,
+            maximum_packet_size: 0,
+            server_receive_maximum: 0,
+            unsubscribes: HashMap::new(),
+            denied_subscriptions: HashMap::new(),
+            disconnect_reason: None,
+        });
+    }
+
+    fn negotiate_session_expiry(&mut self, max: u64) -> u64 {
+        // a broker maximum of 0 retains sessions indefinitely, so any request is accepted.
+        if max != 0 && self.requested_expiry as u64 > max {
+            self.granted_expiry = max.min(u32::MAX as u64) as u32;
+        }
+
+        // 0xFFFFFFFF means the session does not expire.
+        if self.granted_expiry == u32::MAX {
+            return 0;
+        }
+        return self.granted_expiry as u64;
+    }
+
+    fn encode_connack(&self, session_present: bool) -> Result<Bytes, ServerError> {
+        let mut connack = v5::ConnAckPacket::new(session_present, ReasonCode::Success);
+        let properties = connack.properties_mut();
+
+        // The Server uses this property to inform the Client that it is using a value other than that sent by the Client.
+        if self.granted_expiry != self.requested_expiry {
+            properties.push(Property::SessionExpiryInterval(self.granted_expiry));
+        }
+
+        if self.topic_alias_maximum > 0 {
+            properties.push(Property::TopicAliasMaximum(self.topic_alias_maximum));
+        }
+
+        if self.maximum_packet_size > 0 {
+            properties.push(Property::MaximumPacketSize(self.maximum_packet_size));
+        }
+
+        if self.server_receive_maximum > 0 {
+            properties.push(Property::ReceiveMaximum(self.server_receive_maximum));
+        }
+
+        if let Some(client_id) = &self.assigned_client_id {
+            properties.push(Property::AssignedClientIdentifier(client_id.clone()));
+        }
+
+        properties.push(Property::SubscriptionIdentifierAvailable(0));
+        properties.push(Property::SharedSubscriptionAvailable(0));
+
+        return Ok(connack.encode()?);
+    }
+
+    fn decode(&mut self, packet: v5::MqttPacket) -> Result<MqttPacket, ServerError> {
+        let out = match packet {
+            v5::MqttPacket::Publish(packet) => MqttPacket::Publish(self.resolve_publish(packet)?),
+            v5::MqttPacket::PubAck(packet) => {
+                MqttPacket::PubAck(v3::PubAckPacket::new(packet.id()))
+            }
+            v5::MqttPacket::PubRec(packet) => {
+                MqttPacket::PubRec(v3::PubRecPacket::new(packet.id()))
+            }
+            v5::MqttPacket::PubRel(packet) => {
+                MqttPacket::PubRel(v3::PubRelPacket::new(packet.id()))
+            }
+            v5::MqttPacket::PubComp(packet) => {
+                MqttPacket::PubComp(v3::PubCompPacket::new(packet.id()))
+            }
+            v5::MqttPacket::Subscribe(packet) => {
+                let filters = packet
+                    .filters()
+                    .iter()
+                    .map(|(filter, options)| (filter.clone(), options.qos()))
+                    .collect();
+                MqttPacket::Subscribe(v3::SubscribePacket::new(packet.id(), filters))
+            }
+            v5::MqttPacket::Unsubscribe(packet) => {
+                self.unsubscribes
+                    .insert(packet.id(), packet.filters().len());
+                MqttPacket::Unsubscribe(v3::UnsubscribePacket::new(
+                    packet.id(),
+                    packet.filters().clone(),
+                ))
+            }
+            v5::MqttPacket::PingReq(_) => MqttPacket::PingReq(v3::PingReqPacket::new()),
+            v5::MqttPacket::Disconnect(packet) => {
+                // The Client may change the Session Expiry Interval when it disconnects.
+                if let Some(expiry) = packet.properties().session_expiry_interval() {
+                    // If the Session Expiry Interval in the CONNECT packet was zero, then it is a Protocol Error to
+                    // set a non-zero Session Expiry Interval in the DISCONNECT packet sent by the Client.
+                    if self.requested_expiry == 0 && expiry != 0 {
+                        return Err(ServerError::new(
+                            server::ErrorKind::ProtocolError,
+                            String::from("Client set a Session Expiry Interval on DISCONNECT after connecting with 0."),
+                        ));
+                    }
+                    self.requested_expiry = expiry;
+                    self.granted_expiry = expiry;
+                }
+
+                self.disconnect_reason = Some(packet.reason_code());
+                MqttPacket::Disconnect(v3::DisconnectPacket::new())
+            }
+            v5::MqttPacket::Connect(packet) => MqttPacket::Connect(into_v3_connect(packet)),
+            packet => {
+                return Err(ServerError::new(
+                    server::ErrorKind::ProtocolError,
+                    format!("MQTT Broker does not support packet type: {packet}"),
+                ));
+            }
+        };
+
+        return Ok(out);
+    }
+
+    /// Resolves the topic of a PUBLISH packet, recording any Topic Alias the client established.
+    fn resolve_publish(
+        &mut self,
+        packet: v5::PublishPacket,
+    ) -> Result<v3::PublishPacket, ServerError> {
+        let topic = match packet.properties().topic_alias() {
+            Some(alias) => {
+                // A Topic Alias of 0 or greater than the Topic Alias Maximum is a Protocol Error.
+                if alias == 0 || alias > self.topic_alias_maximum {
+                    return Err(ServerError::new(
+                        server::ErrorKind::ProtocolError,
+                        format!(
+                            "Client sent topic alias: {alias}, the topic alias maximum is {}.",
+                            self.topic_alias_maximum
+                        ),
+                    ));
+                }
+
+                match packet.topic() {
+                    Some(topic) => {
+                        self.topic_aliases.insert(alias, topic.clone());
+                        topic.clone()
+                    }
+                    None => match self.topic_aliases.get(&alias) {
+                        Some(topic) => topic.clone(),
+                        None => {
+                            return Err(ServerError::new(
+                                server::ErrorKind::ProtocolError,
+                                format!("Client sent unknown topic alias: {alias}."),
+                            ));
+                        }
+                    },
+                }
+            }
+            None => match packet.topic() {
+                Some(topic) => topic.clone(),
+                None => {
+                    return Err(ServerError::new(
+                        server::ErrorKind::ProtocolError,
+                        String::from("Client sent a PUBLISH packet without a topic."),
+                    ));
+                }
+            },
+        };
+
+        let mut out = v3::PublishPacket::new(&topic, packet.payload().clone());
+        match (packet.qos(), packet.id()) {
+            (QosLevel::AtLeastOnce, Some(id)) => out.set_qos_atleastonce(id),
+            (QosLevel::ExactlyOnce, Some(id)) => out.set_qos_exactlyonce(id),
+            _ => {}
+        }
+        out.set_retain(packet.retain());
+        out.set_dup(packet.dup());
+
+        return Ok(out);
+    }
+
+    fn encode(&mut self, packet: MqttPacket) -> Result<Bytes, ServerError> {
+        let out = match packet {
+            MqttPacket::Publish(packet) => {
+                let mut out = v5::PublishPacket::new(packet.topic(), packet.payload().clone());
+                match (packet.qos(), packet.id()) {
+                    (QosLevel::AtLeastOnce, Some(id)) => out.set_qos_atleastonce(id),
+                    (QosLevel::ExactlyOnce, Some(id)) => out.set_qos_exactlyonce(id),
+                    _ => {}
+                }
+                out.set_retain(packet.retain());
+                out.set_dup(packet.dup());
+                v5::MqttPacket::Publish(out)
+            }
+            MqttPacket::PubAck(packet) => {
+                v5::MqttPacket::PubAck(v5::PubAckPacket::new(packet.id(), ReasonCode::Success))
+            }
+            MqttPacket::PubRec(packet) => {
+                v5::MqttPacket::PubRec(v5::PubRecPacket::new(packet.id(), ReasonCode::Success))
+            }
+            MqttPacket::PubRel(packet) => {
+                v5::MqttPacket::PubRel(v5::PubRelPacket::new(packet.id(), ReasonCode::Success))
+            }
+            MqttPacket::PubComp(packet) => {
+                v5::MqttPacket::PubComp(v5::PubCompPacket::new(packet.id(), ReasonCode::Success))
+            }
+            MqttPacket::SubAck(packet) => {
+                let denied = self
+                    .denied_subscriptions
+                    .remove(&packet.id())
+                    .unwrap_or_default();
+                let reason_codes = packet
+                    .filters()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, qos)| match qos {
+                        SubAckQoS::QOS(QosLevel::AtMostOnce) => ReasonCode::Success,
+                        SubAckQoS::QOS(QosLevel::AtLeastOnce) => ReasonCode::GrantedQoS1,
+                        SubAckQoS::QOS(QosLevel::ExactlyOnce) => ReasonCode::GrantedQoS2,
+                        SubAckQoS::Err if denied.contains(&index) => ReasonCode::NotAuthorized,
+                        SubAckQoS::Err => ReasonCode::UnspecifiedError,
+                    })
+                    .collect();
+                v5::MqttPacket::SubAck(v5::SubAckPacket::new(packet.id(), reason_codes))
+            }
+            MqttPacket::UnsubAck(packet) => {
+                // The UNSUBACK MUST contain a Reason Code for each Topic Filter in the UNSUBSCRIBE packet.
+                let count = self.unsubscribes.remove(&packet.id()).unwrap_or(1);
+                v5::MqttPacket::UnsubAck(v5::UnsubAckPacket::new(
+                    packet.id(),
+                    vec![ReasonCode::Success; count],
+                ))
+            }
+            MqttPacket::PingResp(_) => v5::MqttPacket::PingResp(v5::PingRespPacket::new()),
+            packet => {
+                return Err(ServerError::new(
+                    server::ErrorKind::ProtocolError,
+                    format!("Cannot send {packet} to an MQTT 5.0 client."),
+                ));
+            }
+        };
+
+        return Ok(out.encode()?);
+    }
+}
+
+/// The MQTT 5.0 Reason Code refusing a connection for the same reason as a v3.1.1 return code.
+fn reason_code(code: ConnectReturnCode) -> ReasonCode {
+    match code {
+        ConnectReturnCode::Accept => return ReasonCode::Success,
+        ConnectReturnCode::InvalidProtocol => return ReasonCode::UnsupportedProtocolVersion,
+        ConnectReturnCode::IdentifierRejected => return ReasonCode::ClientIdentifierNotValid,
+        ConnectReturnCode::ServerUnavailable => return ReasonCode::ServerUnavailable,
+        ConnectReturnCode::BadUsernameOrPassword => return ReasonCode::BadUserNameOrPassword,
+        ConnectReturnCode::NotAuthorized => return ReasonCode::NotAuthorized,
+    }
+}
+
+/// The CONNECT packets of unsupported protocol versions are refused rather than treated as malformed.
+fn unsupported_protocol(err: DecodeError) -> ServerError {
+    if err.kind() == DecodeErrorKind::InvalidProtocol {
+        return ServerError::new(
+            server::ErrorKind::ConnectError(ConnectReturnCode::InvalidProtocol),
+            err.message().to_string(),
+        );
+    }
+    return err.into();
+}
+
+/// Reads the Protocol Level, which follows the length prefixed Protocol Name in the CONNECT variable header.
+fn protocol_level(body: &Bytes) -> Option<u8> {
+    let name_len = u16::from_be_bytes([*body.get(0)?, *body.get(1)?]) as usize;
+    return body.get(2 + name_len).copied();
+}
+
+/// The Will Properties and the MQTT 5.0 only CONNECT properties are dropped.
+fn into_v3_connect(packet: v5::ConnectPacket) -> v3::ConnectPacket {
+    let will = packet.will().as_ref().map(|will| {
+        v3::Will::new(
+            will.topic().clone(),
+            will.payload().clone(),
+            will.qos(),
+            will.retain(),
+        )
+    });
+
+    return v3::ConnectPacket::new(
+        packet.clean_start(),
+        packet.keep_alive(),
+        packet.client_id().to_string(),
+        will,
+        packet.username().clone(),
+        packet.password().clone(),
+    );
+}
@@ -0,0 +1,174 @@
+use mqtt_core::topic::TopicName;
+use regex::Regex;
+
+use crate::config::RewriteRule;
+
+enum Matcher {
+    Prefix(String),
+    Pattern(Regex),
+}
+
+struct Rule {
+    matcher: Matcher,
+    // may reference the pattern's capture groups as $1, $2, ... or ${name}.
+    replacement: String,
+}
+
+/// Rewrites the topic of published messages before they are dispatched to subscribers and retained.
+///
+/// Rules are tried in the order they are configured and only the first matching rule is applied.
+pub struct TopicRewriter {
+    rules: Vec<Rule>,
+}
+
+impl TopicRewriter {
+    /// Panics if a rule sets both or neither of `prefix` and `pattern`, or its pattern is not a valid regular expression.
+    pub fn new(rules: &[RewriteRule]) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let matcher = match (rule.prefix(), rule.pattern()) {
+                    (Some(prefix), None) => Matcher::Prefix(prefix.to_string()),
+                    (None, Some(pattern)) => Matcher::Pattern(
+                        Regex::new(pattern)
+                            .expect(&format!("Invalid topic rewrite pattern: {pattern}")),
+                    ),
+                    _ => panic!("A topic rewrite rule requires exactly one of prefix or pattern"),
+                };
+                return Rule {
+                    matcher,
+                    replacement: rule.replacement().to_string(),
+                };
+            })
+            .collect();
+
+        return Self { rules };
+    }
+
+    /// Returns the topic a message published to `topic` is dispatched to, None if no rule rewrites it.
+    ///
+    /// A rule that would produce an invalid topic name leaves the topic as it is.
+    pub fn rewrite(&self, topic: &TopicName) -> Option<TopicName> {
+        if self.rules.is_empty() {
+            return None;
+        }
+
+        let name = topic.clone().to_string();
+        for rule in self.rules.iter() {
+            let rewritten = match &rule.matcher {
+                Matcher::Prefix(prefix) => match name.strip_prefix(prefix.as_str()) {
+                    Some(rest) => format!("{}{rest}", rule.replacement),
+                    None => continue,
+                },
+                Matcher::Pattern(regex) => {
+                    if !regex.is_match(&name) {
+                        continue;
+                    }
+                    regex.replace(&name, rule.replacement.as_str()).into_owned()
+                }
+            };
+
+            match TopicName::from_str(&rewritten) {
+                Ok(topic) => return Some(topic),
+                Err(_) => {
+                    log::warn!("Topic: {name} was rewritten to an invalid topic name: {rewritten}, the topic was not rewritten");
+                    return None;
+                }
+            }
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod rules {
+    use mqtt_core::topic::TopicName;
+    use serde::Deserialize;
+
+    use super::TopicRewriter;
+    use crate::config::RewriteRule;
+
+    #[derive(Deserialize)]
+    struct Rules {
+        rewrite: Vec<RewriteRule>,
+    }
+
+    /// Builds the rewriter from `[[rewrite]]` tables, as they are written in the broker's config.
+    fn rewriter(rules: &str) -> TopicRewriter {
+        let rules: Rules = toml::from_str(rules).unwrap();
+        return TopicRewriter::new(&rules.rewrite);
+    }
+
+    fn rewrite(rewriter: &TopicRewriter, topic: &str) -> Option<String> {
+        return rewriter
+            .rewrite(&TopicName::from_str(topic).unwrap())
+            .map(|topic| topic.to_string());
+    }
+
+    #[test]
+    fn prefix() {
+        let rewriter = rewriter("[[rewrite]]\nprefix = 'legacy/'\nreplacement = 'v2/'");
+
+        assert_eq!(
+            rewrite(&rewriter, "legacy/status"),
+            Some(String::from("v2/status"))
+        );
+        // the prefix only matches the start of the topic.
+        assert_eq!(rewrite(&rewriter, "devices/legacy/status"), None);
+    }
+
+    #[test]
+    fn pattern() {
+        let rewriter = rewriter(
+            "[[rewrite]]\npattern = '^devices/([^/]+)/telemetry$'\nreplacement = 'tenant/$1/telemetry'",
+        );
+
+        assert_eq!(
+            rewrite(&rewriter, "devices/acme/telemetry"),
+            Some(String::from("tenant/acme/telemetry"))
+        );
+        assert_eq!(rewrite(&rewriter, "devices/acme/telemetry/raw"), None);
+    }
+
+    #[test]
+    fn named_capture_groups() {
+        let rewriter = rewriter(
+            "[[rewrite]]\npattern = '^(?P<site>[^/]+)/(?P<device>[^/]+)$'\nreplacement = '${device}/${site}'",
+        );
+
+        assert_eq!(
+            rewrite(&rewriter, "factory/press"),
+            Some(String::from("press/factory"))
+        );
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let rewriter = rewriter(
+            "[[rewrite]]\nprefix = 'devices/'\nreplacement = 'first/'\n\n\
+             [[rewrite]]\npattern = '^devices/(.+)$'\nreplacement = 'second/$1'\n\n\
+             [[rewrite]]\nprefix = 'sensors/'\nreplacement = 'third/'",
+        );
+
+        assert_eq!(
+            rewrite(&rewriter, "devices/acme"),
+            Some(String::from("first/acme"))
+        );
+        assert_eq!(
+            rewrite(&rewriter, "sensors/temp"),
+            Some(String::from("third/temp"))
+        );
+    }
+
+    #[test]
+    fn invalid_rewritten_topic() {
+        let rewriter = rewriter(
+            "[[rewrite]]\nprefix = 'alerts/'\nreplacement = 'alerts/+/'\n\n\
+             [[rewrite]]\nprefix = 'alerts/'\nreplacement = 'v2/'",
+        );
+
+        // the topic is left as it is, later rules are not tried.
+        assert_eq!(rewrite(&rewriter, "alerts/fire"), None);
+    }
+}
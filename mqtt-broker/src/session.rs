@@ -1,52 +1,72 @@
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use mqtt_core::err::server::{self, ServerError};
 use mqtt_core::id::{IdGenType, IdGenerator};
 use mqtt_core::qos::QosLevel;
-use mqtt_core::topic::TopicFilter;
-use mqtt_core::ConnectReturnCode;
-use r2d2_sqlite::SqliteConnectionManager;
-use sheesh::harness::sqlite::user::SqliteHarnessUser;
-use sheesh::harness::stateless::{StatelessSession, StatelessToken};
-use sheesh::harness::DbHarness;
-use sheesh::id::DefaultIdGenerator;
-use sheesh::session::{SessionManager, SessionManagerConfig};
-use std::path::PathBuf;
-use std::{collections::HashMap, sync::Arc, time::Instant};
-use tokio::io::{AsyncWrite, AsyncWriteExt};
-
-use mqtt_core::msg_assurance::{AtLeastOnceList, ExactlyOnceList, RetryDuration};
+use mqtt_core::topic::{TopicFilter, TopicName};
+use std::time::Duration;
+use std::{collections::HashMap, sync::Arc};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::acl::Authorizer;
+use crate::auth::{Authenticator, Identity};
+use crate::protocol::Protocol;
+use crate::store::{SessionStore, StoreWorker};
+
+use mqtt_core::io::FrameReader;
+use mqtt_core::msg_assurance::{
+    AtLeastOnceList, ExactlyOnceList, Instant, PubPack, QoS1Stage, QoS2Stage, RetryDuration,
+};
 use mqtt_core::v3::{
     ConnectPacket, MqttPacket, PubAckPacket, PubRecPacket, PubRelPacket, PublishPacket, Will,
 };
+use mqtt_core::v5::ReasonCode;
 
-pub type AtLeastOnceListType = AtLeastOnceList<Arc<PublishPacket>, Instant, RetryDuration>;
-pub type ExactlyOnceListType = ExactlyOnceList<Arc<PublishPacket>, Instant, RetryDuration>;
+pub type AtLeastOnceListType<I> = AtLeastOnceList<Arc<PublishPacket>, I, RetryDuration>;
+pub type ExactlyOnceListType<I> = ExactlyOnceList<Arc<PublishPacket>, I, RetryDuration>;
 
+/// The clock is generic so keep alive and retry behavior can be driven by a manual clock in tests.
 #[derive(Debug, Clone)]
-pub struct ActiveSession {
+pub struct ActiveSession<I: Instant = std::time::Instant> {
     client_id: String,
-    user: Option<UserMeta>,
+    // None if the client did not authenticate.
+    identity: Option<Identity>,
     will: Option<Will>,
     keep_alive: u64,
-    last_read: Instant,
-    topic_filters: Vec<TopicFilter>,
-    qos1_packets: AtLeastOnceListType,
-    qos2_packets: ExactlyOnceListType,
+    // seconds a disconnected session is retained for, 0 retains the session indefinitely.
+    session_expiry: u64,
+    // seconds the will is held back after the connection is lost.
+    will_delay: u64,
+    last_read: I,
+    topic_filters: Vec<(TopicFilter, QosLevel)>,
+    qos1_packets: AtLeastOnceListType<I>,
+    qos2_packets: ExactlyOnceListType<I>,
     id_gen: IdGenerator,
+    // bytes held by qos1_packets and qos2_packets.
+    memory_usage: usize,
+    // the broker's limits on unacknowledged messages sent to the client, None is unlimited.
+    max_inflight_qos1: Option<usize>,
+    max_inflight_qos2: Option<usize>,
+    protocol: Protocol,
 }
 
-impl ActiveSession {
-    pub fn new(packet: ConnectPacket, user: Option<UserMeta>) -> Self {
+impl<I: Instant> ActiveSession<I> {
+    pub fn new(packet: ConnectPacket, identity: Option<Identity>) -> Self {
         return Self {
             client_id: packet.client_id().to_string(),
-            user,
+            identity,
             will: packet.will,
             keep_alive: packet.keep_alive.into(),
-            last_read: Instant::now(),
+            session_expiry: 0,
+            will_delay: 0,
+            last_read: I::now(),
             topic_filters: vec![],
             qos1_packets: AtLeastOnceList::new(),
             qos2_packets: ExactlyOnceList::new(),
             id_gen: IdGenerator::new(IdGenType::Broker),
+            memory_usage: 0,
+            max_inflight_qos1: None,
+            max_inflight_qos2: None,
+            protocol: Protocol::V3,
         };
     }
 
@@ -54,12 +74,198 @@ impl ActiveSession {
         return &self.will;
     }
 
+    pub fn client_id(&self) -> &str {
+        return &self.client_id;
+    }
+
+    /// The name the client authenticated with, None if the client did not authenticate.
+    pub fn username(&self) -> Option<&str> {
+        return self.identity.as_ref().map(Identity::username);
+    }
+
+    pub fn identity(&self) -> Option<&Identity> {
+        return self.identity.as_ref();
+    }
+
+    /// The topic filters the client is subscribed to, and the QoS granted for each filter.
+    pub fn topic_filters(&self) -> &Vec<(TopicFilter, QosLevel)> {
+        return &self.topic_filters;
+    }
+
+    /// Records a subscription so that it can be restored when a persistent session is resumed.
+    ///
+    /// Subscribing to a filter the session is already subscribed to replaces the granted QoS.
+    pub fn add_topic_filter(&mut self, filter: TopicFilter, qos: QosLevel) {
+        match self.topic_filters.iter_mut().find(|(f, _)| *f == filter) {
+            Some(entry) => entry.1 = qos,
+            None => self.topic_filters.push((filter, qos)),
+        }
+    }
+
+    pub fn remove_topic_filter(&mut self, filter: &TopicFilter) {
+        self.topic_filters.retain(|(f, _)| f != filter);
+    }
+
+    /// Sets how long the session is retained after the client disconnects, in seconds.
+    ///
+    /// Zero retains the session until the client reconnects with the clean session flag set.
+    pub fn set_session_expiry(&mut self, secs: u64) {
+        self.session_expiry = secs;
+    }
+
+    /// Negotiates the session expiry again after an MQTT 5.0 client changed it in its DISCONNECT.
+    ///
+    /// `max` is the broker's session expiry interval, see [Protocol::session_expiry].
+    pub fn renegotiate_session_expiry(&mut self, max: u64) {
+        self.session_expiry = self.protocol.session_expiry(max);
+    }
+
+    /// Returns true if the client disconnected asking for its will to be published, see [Protocol::disconnect_with_will].
+    pub fn disconnect_with_will(&self) -> bool {
+        return self.protocol.disconnect_with_will();
+    }
+
+    /// Sets how long the will is held back after the connection is lost, in seconds.
+    pub fn set_will_delay(&mut self, secs: u64) {
+        self.will_delay = secs;
+    }
+
+    /// How long the will is held back after the connection is lost.
+    ///
+    /// The will is published when the session ends if that happens first.
+    pub fn will_delay(&self) -> Duration {
+        let mut secs = self.will_delay;
+        if self.expires_on_disconnect() {
+            secs = 0;
+        } else if self.session_expiry > 0 {
+            secs = secs.min(self.session_expiry);
+        }
+        return Duration::from_secs(secs);
+    }
+
+    /// Sets the protocol negotiated for the connection, sessions are created speaking v3.1.1.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Returns true if the session should not be retained once the client disconnects.
+    pub fn expires_on_disconnect(&self) -> bool {
+        return self.protocol.expires_on_disconnect();
+    }
+
+    /// Waits for the next packet from the client, see [FrameReader::read_frame].
+    ///
+    /// MQTT 5.0 packets are translated to their v3.1.1 equivalent.
+    pub async fn read_packet<S: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut FrameReader,
+        stream: &mut S,
+    ) -> Result<MqttPacket, ServerError> {
+        let (header, body) = reader.read_frame::<_, ServerError>(stream).await?;
+        return self.protocol.decode(header, body);
+    }
+
+    /// Encodes a packet in the protocol negotiated with the client.
+    pub fn encode(&mut self, packet: MqttPacket) -> Result<Bytes, ServerError> {
+        return self.protocol.encode(packet);
+    }
+
+    /// Records a topic filter refused by the ACL, see [Protocol::deny_subscription].
+    pub fn deny_subscription(&mut self, id: u16, index: usize) {
+        self.protocol.deny_subscription(id, index);
+    }
+
+    /// Encodes a DISCONNECT sent by the broker, see [Protocol::encode_disconnect].
+    pub fn encode_disconnect(&self, reason: ReasonCode) -> Result<Option<Bytes>, ServerError> {
+        return self.protocol.encode_disconnect(reason);
+    }
+
+    /// The number of QoS 1 and QoS 2 messages sent to the client that have not yet been acknowledged.
+    pub fn inflight(&self) -> usize {
+        return self.inflight_qos1() + self.inflight_qos2();
+    }
+
+    /// The number of QoS 1 messages sent to the client that have not yet been acknowledged.
+    pub fn inflight_qos1(&self) -> usize {
+        return self
+            .qos1_packets
+            .iter()
+            .filter(|packet| packet.stage() == QoS1Stage::Origin)
+            .count();
+    }
+
+    /// The number of QoS 2 messages sent to the client that have not yet been completed.
+    pub fn inflight_qos2(&self) -> usize {
+        return self
+            .qos2_packets
+            .iter()
+            .filter(|packet| matches!(packet.stage(), QoS2Stage::Origin | QoS2Stage::Rec))
+            .count();
+    }
+
+    /// The number of QoS 2 messages received from the client that have not yet been released.
+    pub fn incoming_qos2(&self) -> usize {
+        return self
+            .qos2_packets
+            .iter()
+            .filter(|packet| packet.stage() == QoS2Stage::Publish)
+            .count();
+    }
+
+    /// Returns true if a QoS 2 message received from the client with the packet id has not yet been released.
+    pub fn awaiting_release(&self, packet_id: u16) -> bool {
+        return self
+            .qos2_packets
+            .iter()
+            .any(|packet| packet.stage() == QoS2Stage::Publish && packet.id() == packet_id);
+    }
+
+    /// Sets the number of unacknowledged QoS 1 and QoS 2 messages that may be sent to the client, None is unlimited.
+    pub fn set_inflight_limits(&mut self, qos1: Option<usize>, qos2: Option<usize>) {
+        self.max_inflight_qos1 = qos1;
+        self.max_inflight_qos2 = qos2;
+    }
+
+    /// Returns true if no more messages may be sent until an in-flight message is acknowledged, because the
+    /// client's Receive Maximum or one of the broker's in-flight limits is reached.
+    pub fn inflight_window_full(&self) -> bool {
+        if self.receive_maximum_reached() {
+            return true;
+        }
+        if let Some(max) = self.max_inflight_qos1 {
+            if self.inflight_qos1() >= max {
+                return true;
+            }
+        }
+        if let Some(max) = self.max_inflight_qos2 {
+            if self.inflight_qos2() >= max {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    /// Returns true if the client's Receive Maximum is reached, no more QoS 1 or QoS 2 messages may be sent
+    /// until an in-flight message is acknowledged.
+    pub fn receive_maximum_reached(&self) -> bool {
+        match self.protocol.receive_maximum() {
+            Some(max) => return self.inflight() >= max as usize,
+            None => return false,
+        }
+    }
+
     pub fn update_last_read(&mut self) {
-        self.last_read = Instant::now();
+        self.last_read = I::now();
     }
 
+    /// Returns true if the client has not sent a packet within one and a half times its keep alive [MQTT-3.1.2-24].
+    ///
+    /// A keep alive of zero turns the mechanism off.
     pub fn timed_out(&self) -> bool {
-        return self.last_read.elapsed().as_secs() > self.keep_alive;
+        if self.keep_alive == 0 {
+            return false;
+        }
+        return self.last_read.elapsed() > Duration::from_millis(self.keep_alive * 1500);
     }
 
     pub async fn retry_packets<S: AsyncWrite + Unpin>(
@@ -75,10 +281,10 @@ impl ActiveSession {
                         MqttPacket::Publish(mut packet) => {
                             // indicate to the client that this is a re-transmission.
                             packet.set_dup(true);
-                            buf.put_slice(&packet.encode()?);
+                            buf.put_slice(&self.protocol.encode(MqttPacket::Publish(packet))?);
                         }
                         _ => {
-                            buf.put_slice(&retry_packet.encode()?);
+                            buf.put_slice(&self.protocol.encode(retry_packet)?);
                         }
                     }
                     packet.update_retry_duration();
@@ -89,7 +295,7 @@ impl ActiveSession {
         for packet in self.qos2_packets.iter_mut() {
             if packet.should_retry() {
                 if let Some(retry_packet) = packet.get_retry_packet() {
-                    buf.put_slice(&retry_packet.encode()?);
+                    buf.put_slice(&self.protocol.encode(retry_packet)?);
                     packet.update_retry_duration();
                 }
             }
@@ -117,6 +323,32 @@ impl ActiveSession {
                 self.id_gen.free_id(id);
             }
         }
+        self.memory_usage = self.qos1_packets.byte_len() + self.qos2_packets.byte_len();
+    }
+
+    /// The number of bytes held by the session's in-flight QoS 1 and QoS 2 packets.
+    ///
+    /// Messages waiting in the session's mailbox are not counted, they are shared between every subscriber
+    /// to the topic and bounded by the broker's max_queued_messages.
+    pub fn memory_usage(&self) -> usize {
+        return self.memory_usage;
+    }
+
+    /// Returns true if the session holds more than `max` bytes once acknowledged packets are released.
+    pub fn exceeds_memory(&mut self, max: usize) -> bool {
+        if self.memory_usage <= max {
+            return false;
+        }
+
+        for id in self.qos1_packets.clean() {
+            self.id_gen.free_id(id);
+        }
+        for id in self.qos2_packets.clean() {
+            self.id_gen.free_id(id);
+        }
+        self.memory_usage = self.qos1_packets.byte_len() + self.qos2_packets.byte_len();
+
+        return self.memory_usage > max;
     }
 
     pub fn ack(&mut self, packet_id: u16) -> PubAckPacket {
@@ -136,9 +368,11 @@ impl ActiveSession {
                 return packet;
             }
             QosLevel::AtLeastOnce => {
+                self.memory_usage += packet.byte_len();
                 return self.qos1_packets.origin(packet.clone(), new_id.unwrap());
             }
             QosLevel::ExactlyOnce => {
+                self.memory_usage += packet.byte_len();
                 return self.qos2_packets.origin(packet.clone(), new_id.unwrap());
             }
         }
@@ -146,7 +380,14 @@ impl ActiveSession {
 
     pub fn publish(&mut self, packet: PublishPacket) -> Option<PubRecPacket> {
         let id = packet.id().unwrap();
-        return self.qos2_packets.publish(packet, id);
+        let len = packet.topic().len() + packet.payload().len();
+
+        let res = self.qos2_packets.publish(packet, id);
+        // None is returned for duplicates, which are not stored.
+        if res.is_some() {
+            self.memory_usage += len;
+        }
+        return res;
     }
 
     pub fn rec(&mut self, packet_id: u16) -> Option<PubRelPacket> {
@@ -164,10 +405,10 @@ impl ActiveSession {
 
 // TODO:
 // Okay... this type signature is disgusting...
-impl TryFrom<(DisconnectedSession, ConnectPacket)> for ActiveSession {
+impl<I: Instant> TryFrom<(DisconnectedSession<I>, ConnectPacket)> for ActiveSession<I> {
     type Error = ServerError;
     fn try_from(
-        (dc_session, packet): (DisconnectedSession, ConnectPacket),
+        (dc_session, packet): (DisconnectedSession<I>, ConnectPacket),
     ) -> Result<Self, Self::Error> {
         let mut id_gen = IdGenerator::new(IdGenType::Broker);
 
@@ -179,27 +420,34 @@ impl TryFrom<(DisconnectedSession, ConnectPacket)> for ActiveSession {
             id_gen.set_id(packet.id());
         }
 
+        let memory_usage = dc_session.qos1_packets.byte_len() + dc_session.qos2_packets.byte_len();
+
         return Ok(Self {
             client_id: packet.client_id.to_owned(),
-            user: dc_session.user,
+            identity: None,
             will: packet.will.to_owned(),
             keep_alive: packet.keep_alive.into(),
-            last_read: Instant::now(),
+            session_expiry: dc_session.session_expiry,
+            will_delay: 0,
+            last_read: I::now(),
             topic_filters: dc_session.topic_filters.clone(),
             id_gen,
             qos1_packets: dc_session.qos1_packets,
             qos2_packets: dc_session.qos2_packets,
+            memory_usage,
+            max_inflight_qos1: None,
+            max_inflight_qos2: None,
+            protocol: Protocol::V3,
         });
     }
 }
 
-impl From<ActiveSession> for DisconnectedSession {
-    fn from(value: ActiveSession) -> Self {
+impl<I: Instant> From<ActiveSession<I>> for DisconnectedSession<I> {
+    fn from(value: ActiveSession<I>) -> Self {
         Self {
             client_id: value.client_id,
-            user: value.user,
-            keep_alive: value.keep_alive,
-            last_read: value.last_read,
+            session_expiry: value.session_expiry,
+            disconnected_at: I::now(),
             qos1_packets: value.qos1_packets,
             qos2_packets: value.qos2_packets,
             topic_filters: value.topic_filters,
@@ -210,17 +458,16 @@ impl From<ActiveSession> for DisconnectedSession {
 /// This structure is used to save connection state after a disconnection where the connection is established
 /// with the clean_session bitflag set to false.
 #[derive(Clone)]
-pub struct DisconnectedSession {
+pub struct DisconnectedSession<I: Instant = std::time::Instant> {
     client_id: String,
-    user: Option<UserMeta>,
-    keep_alive: u64,
-    last_read: Instant,
-    qos1_packets: AtLeastOnceListType,
-    qos2_packets: ExactlyOnceListType,
-    topic_filters: Vec<TopicFilter>,
+    session_expiry: u64,
+    disconnected_at: I,
+    qos1_packets: AtLeastOnceListType<I>,
+    qos2_packets: ExactlyOnceListType<I>,
+    topic_filters: Vec<(TopicFilter, QosLevel)>,
 }
 
-impl PartialEq for DisconnectedSession {
+impl<I: Instant> PartialEq for DisconnectedSession<I> {
     fn eq(&self, other: &Self) -> bool {
         self.client_id == other.client_id
     }
@@ -230,36 +477,105 @@ impl PartialEq for DisconnectedSession {
     }
 }
 
-impl DisconnectedSession {
+impl<I: Instant> DisconnectedSession<I> {
     pub fn expired(&self) -> bool {
-        // setting the keep_alive value to zero has the effect of disabling session expiry.
-        if self.keep_alive == 0 {
+        // setting the session expiry to zero has the effect of disabling session expiry.
+        if self.session_expiry == 0 {
             return false;
         }
 
-        return self.last_read.elapsed().as_secs() > self.keep_alive;
+        return self.disconnected_at.elapsed().as_secs() > self.session_expiry;
     }
 
     pub fn client_id<'a>(&'a self) -> &'a str {
         return self.client_id.as_str();
     }
 
-    pub fn into_active(self, packet: ConnectPacket) -> Result<ActiveSession, ServerError> {
-        return ActiveSession::try_from((self, packet));
+    pub fn session_expiry(&self) -> u64 {
+        return self.session_expiry;
+    }
+
+    pub fn disconnected_for(&self) -> Duration {
+        return self.disconnected_at.elapsed();
+    }
+
+    pub fn topic_filters(&self) -> &Vec<(TopicFilter, QosLevel)> {
+        return &self.topic_filters;
+    }
+
+    pub fn qos1_packets(&self) -> &AtLeastOnceListType<I> {
+        return &self.qos1_packets;
+    }
+
+    pub fn qos2_packets(&self) -> &ExactlyOnceListType<I> {
+        return &self.qos2_packets;
+    }
+
+    /// The identity is taken from the resuming connection, users are re-authenticated on every CONNECT.
+    pub fn into_active(
+        self,
+        packet: ConnectPacket,
+        identity: Option<Identity>,
+    ) -> Result<ActiveSession<I>, ServerError> {
+        let mut session = ActiveSession::try_from((self, packet))?;
+        session.identity = identity;
+        return Ok(session);
+    }
+}
+
+impl DisconnectedSession {
+    /// Rebuilds a session loaded from a [SessionStore].
+    pub fn restore(
+        client_id: String,
+        session_expiry: u64,
+        disconnected_for: Duration,
+        topic_filters: Vec<(TopicFilter, QosLevel)>,
+        qos1_packets: AtLeastOnceListType<std::time::Instant>,
+        qos2_packets: ExactlyOnceListType<std::time::Instant>,
+    ) -> Self {
+        let now = std::time::Instant::now();
+        return Self {
+            client_id,
+            session_expiry,
+            disconnected_at: now.checked_sub(disconnected_for).unwrap_or(now),
+            qos1_packets,
+            qos2_packets,
+            topic_filters,
+        };
     }
 }
 
 pub struct DisconnectedSessions {
     dc_sessions: HashMap<String, DisconnectedSession>,
+    // when set, sessions are mirrored to the store so they survive a restart.
+    store: Option<StoreWorker>,
 }
 
 impl DisconnectedSessions {
     pub fn new() -> Self {
         return Self {
             dc_sessions: HashMap::new(),
+            store: None,
         };
     }
 
+    /// Loads the sessions held by the store, the store is then kept up to date as sessions are added and removed.
+    ///
+    /// Must be called from within the tokio runtime, see [StoreWorker::spawn].
+    pub fn with_store(store: Box<dyn SessionStore>) -> Result<Self, ServerError> {
+        let mut dc_sessions = HashMap::new();
+        for session in store.load()? {
+            dc_sessions.insert(session.client_id().to_string(), session);
+        }
+
+        log::info!("Restored {} disconnected sessions", dc_sessions.len());
+
+        return Ok(Self {
+            dc_sessions,
+            store: Some(StoreWorker::spawn(store)),
+        });
+    }
+
     // pub fn len(&self) -> usize {
     //     return self.dc_sessions.len();
     // }
@@ -268,7 +584,7 @@ impl DisconnectedSessions {
         let expired = self.find_sessions(|x| x.expired());
 
         for client_id in expired {
-            self.dc_sessions.remove(&client_id);
+            self.remove_session(&client_id);
         }
     }
 
@@ -284,12 +600,37 @@ impl DisconnectedSessions {
         return out;
     }
 
+    pub fn store(&self) -> Option<&StoreWorker> {
+        return self.store.as_ref();
+    }
+
     pub fn add_session(&mut self, session: DisconnectedSession) {
+        if let Some(store) = &self.store {
+            let session = session.clone();
+            store.run(move |store| {
+                if let Err(err) = store.save(&session) {
+                    log::error!(
+                        "Failed to persist session for client: {}, {err}",
+                        session.client_id()
+                    );
+                }
+            });
+        }
+
         let client_id = session.client_id().to_string();
         self.dc_sessions.insert(client_id, session);
     }
 
     pub fn remove_session(&mut self, id: &str) -> Option<DisconnectedSession> {
+        if let Some(store) = &self.store {
+            let id = id.to_string();
+            store.run(move |store| {
+                if let Err(err) = store.remove(&id) {
+                    log::error!("Failed to remove persisted session for client: {id}, {err}");
+                }
+            });
+        }
+
         return self.dc_sessions.remove(id);
     }
 }
@@ -300,36 +641,64 @@ impl DisconnectedSessions {
  *
  */
 
-use sheesh::user::{UserManager, UserManagerConfig, UserMeta};
-
 pub struct AuthManager {
-    user: UserManager<DefaultIdGenerator, SqliteHarnessUser>,
-    session: SessionManager<DefaultIdGenerator, StatelessSession, StatelessToken>,
+    authenticator: Box<dyn Authenticator>,
+    // every topic is permitted when no authorizer is set.
+    authorizer: Option<Box<dyn Authorizer>>,
 }
 
 impl AuthManager {
-    pub fn new(path: PathBuf) -> Self {
-        let conn_manager = SqliteConnectionManager::file(path);
-        let pool = r2d2::Pool::new(conn_manager).unwrap();
-        let harness = DbHarness::new_stateless_sqlite(pool);
+    pub fn new(authenticator: Box<dyn Authenticator>) -> Self {
+        return Self {
+            authenticator,
+            authorizer: None,
+        };
+    }
 
-        harness.init_tables().unwrap();
+    pub fn set_authorizer(&mut self, authorizer: Box<dyn Authorizer>) {
+        self.authorizer = Some(authorizer);
+    }
 
-        let user = UserManagerConfig::default().init(harness.user);
-        let session = SessionManagerConfig::default().init(harness.session, harness.token);
+    /// A topic must be permitted by both the client's credentials and the authorizer.
+    pub fn authorize_publish(&self, identity: Option<&Identity>, topic: &TopicName) -> bool {
+        if let Some(identity) = identity {
+            if !identity.permits_publish(topic) {
+                return false;
+            }
+        }
 
-        return Self { user, session };
+        let username = identity.map(Identity::username);
+        match &self.authorizer {
+            Some(authorizer) => return authorizer.authorize_publish(username, topic),
+            None => return true,
+        }
     }
 
-    pub fn verify_credentials(&self, username: &str, pwd: &str) -> Result<UserMeta, ServerError> {
-        match self.user.login(&self.session, username, pwd) {
-            Ok((user, _, _)) => return Ok(user),
-            Err(_) => {
-                return Err(ServerError::new(
-                    server::ErrorKind::ConnectError(ConnectReturnCode::BadUsernameOrPassword),
-                    String::from("Client attempted to connect with invalid credentials"),
-                ));
+    /// A filter must be permitted by both the client's credentials and the authorizer.
+    pub fn authorize_subscribe(&self, identity: Option<&Identity>, filter: &TopicFilter) -> bool {
+        if let Some(identity) = identity {
+            if !identity.permits_subscribe(filter) {
+                return false;
             }
         }
+
+        let username = identity.map(Identity::username);
+        match &self.authorizer {
+            Some(authorizer) => return authorizer.authorize_subscribe(username, filter),
+            None => return true,
+        }
+    }
+
+    /// Returns the identity the client is authorized as, see [Authenticator::authenticate].
+    pub async fn verify_credentials(
+        &self,
+        client_id: &str,
+        username: &str,
+        pwd: &[u8],
+    ) -> Result<Identity, ServerError> {
+        return self
+            .authenticator
+            .authenticate(client_id, username, pwd)
+            .await;
     }
 }
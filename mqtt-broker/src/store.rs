@@ -0,0 +1,560 @@
+use std::{
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mqtt_core::{
+    err::server::{self, ServerError},
+    msg_assurance::{AtLeastOnceList, ExactlyOnceList, QoS1Stage, QoS2Stage},
+    qos::QosLevel,
+    topic::TopicFilter,
+    v3::{decode_from, MqttPacket, PublishPacket},
+};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::params;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+};
+
+use crate::session::DisconnectedSession;
+
+/// Persists disconnected sessions so subscriptions and in-flight QoS 1 and QoS 2 messages survive a broker restart.
+pub trait SessionStore: Send {
+    /// Inserts the session, replacing any session stored for the same client id.
+    fn save(&self, session: &DisconnectedSession) -> Result<(), ServerError>;
+
+    fn remove(&self, client_id: &str) -> Result<(), ServerError>;
+
+    /// Loads every stored session, called once when the broker starts.
+    fn load(&self) -> Result<Vec<DisconnectedSession>, ServerError>;
+
+    /// Replaces the stored retained messages, called when the broker shuts down.
+    fn save_retained(&self, messages: &[PublishPacket]) -> Result<(), ServerError>;
+
+    /// Loads the retained messages stored by the last shutdown, called once when the broker starts.
+    fn load_retained(&self) -> Result<Vec<PublishPacket>, ServerError>;
+}
+
+type StoreJob = Box<dyn FnOnce(&dyn SessionStore) + Send>;
+
+/// Runs the calls to a [SessionStore] on a blocking thread, one at a time and in the order they are queued.
+///
+/// Calls are queued while the disconnected sessions are locked, so the store sees the sessions change in the same
+/// order they do, and a slow disk delays the store rather than every CONNECT.
+#[derive(Clone)]
+pub struct StoreWorker {
+    jobs: mpsc::UnboundedSender<StoreJob>,
+}
+
+impl StoreWorker {
+    /// Must be called from within the tokio runtime, the worker stops once every handle to it is dropped.
+    pub fn spawn(store: Box<dyn SessionStore>) -> Self {
+        let (jobs, mut receiver) = mpsc::unbounded_channel::<StoreJob>();
+        task::spawn_blocking(move || {
+            while let Some(job) = receiver.blocking_recv() {
+                job(store.as_ref());
+            }
+        });
+        return Self { jobs };
+    }
+
+    /// Queues the call without waiting for it to complete.
+    pub fn run(&self, job: impl FnOnce(&dyn SessionStore) + Send + 'static) {
+        // the worker outlives every handle, so the send only fails if the worker panicked.
+        let _ = self.jobs.send(Box::new(job));
+    }
+
+    /// Queues the call and waits for it, and so every call queued before it, to complete.
+    ///
+    /// Returns None if the worker panicked.
+    pub async fn call<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&dyn SessionStore) -> T + Send + 'static,
+    ) -> Option<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.run(move |store| {
+            let _ = sender.send(job(store));
+        });
+        return receiver.await.ok();
+    }
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS mqtt_sessions (
+    client_id TEXT PRIMARY KEY,
+    session_expiry INTEGER NOT NULL,
+    disconnected_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS mqtt_session_filters (
+    client_id TEXT NOT NULL,
+    filter TEXT NOT NULL,
+    qos INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS mqtt_session_packets (
+    client_id TEXT NOT NULL,
+    packet_id INTEGER NOT NULL,
+    stage TEXT NOT NULL,
+    packet BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS mqtt_retained (
+    topic TEXT PRIMARY KEY,
+    packet BLOB NOT NULL
+);
+";
+
+// the last known state of a stored packet, see mqtt_core::msg_assurance.
+const QOS1_ORIGIN: &str = "qos1_origin";
+const QOS2_ORIGIN: &str = "qos2_origin";
+const QOS2_PUBLISH: &str = "qos2_publish";
+const QOS2_REC: &str = "qos2_rec";
+
+/// Stores sessions in the broker's sqlite database, sharing the connection pool with the user database.
+pub struct SqliteSessionStore {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl SqliteSessionStore {
+    pub fn new(pool: Pool<SqliteConnectionManager>) -> Result<Self, ServerError> {
+        let conn = pool.get().map_err(store_error)?;
+        conn.execute_batch(SCHEMA).map_err(store_error)?;
+        return Ok(Self { pool });
+    }
+}
+
+impl SessionStore for SqliteSessionStore {
+    fn save(&self, session: &DisconnectedSession) -> Result<(), ServerError> {
+        let mut conn = self.pool.get().map_err(store_error)?;
+        let tx = conn.transaction().map_err(store_error)?;
+        let client_id = session.client_id();
+
+        delete_session(&tx, client_id)?;
+
+        let disconnected_at = unix_time().saturating_sub(session.disconnected_for().as_secs());
+        tx.execute(
+            "INSERT INTO mqtt_sessions (client_id, session_expiry, disconnected_at) VALUES (?1, ?2, ?3)",
+            params![client_id, session.session_expiry() as i64, disconnected_at as i64],
+        )
+        .map_err(store_error)?;
+
+        for (filter, qos) in session.topic_filters() {
+            tx.execute(
+                "INSERT INTO mqtt_session_filters (client_id, filter, qos) VALUES (?1, ?2, ?3)",
+                params![client_id, filter.clone().to_string(), *qos as u8],
+            )
+            .map_err(store_error)?;
+        }
+
+        let mut packets = vec![];
+        for packet in session.qos1_packets().iter() {
+            // acknowledged packets are waiting to be cleaned, they do not need to be restored.
+            if packet.stage() == QoS1Stage::Origin {
+                packets.push((packet.id(), QOS1_ORIGIN, packet.inner()));
+            }
+        }
+        for packet in session.qos2_packets().iter() {
+            let stage = match packet.stage() {
+                QoS2Stage::Origin => QOS2_ORIGIN,
+                QoS2Stage::Publish => QOS2_PUBLISH,
+                QoS2Stage::Rec => QOS2_REC,
+                _ => continue,
+            };
+            packets.push((packet.id(), stage, packet.inner()));
+        }
+
+        for (packet_id, stage, packet) in packets {
+            tx.execute(
+                "INSERT INTO mqtt_session_packets (client_id, packet_id, stage, packet) VALUES (?1, ?2, ?3, ?4)",
+                params![client_id, packet_id, stage, packet.encode()?.to_vec()],
+            )
+            .map_err(store_error)?;
+        }
+
+        tx.commit().map_err(store_error)?;
+        return Ok(());
+    }
+
+    fn remove(&self, client_id: &str) -> Result<(), ServerError> {
+        let conn = self.pool.get().map_err(store_error)?;
+        return delete_session(&conn, client_id);
+    }
+
+    fn load(&self) -> Result<Vec<DisconnectedSession>, ServerError> {
+        let conn = self.pool.get().map_err(store_error)?;
+
+        let mut stmt = conn
+            .prepare("SELECT client_id, session_expiry, disconnected_at FROM mqtt_sessions")
+            .map_err(store_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })
+            .map_err(store_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(store_error)?;
+
+        let mut filter_stmt = conn
+            .prepare("SELECT filter, qos FROM mqtt_session_filters WHERE client_id = ?1")
+            .map_err(store_error)?;
+        let mut packet_stmt = conn
+            .prepare(
+                "SELECT packet_id, stage, packet FROM mqtt_session_packets WHERE client_id = ?1",
+            )
+            .map_err(store_error)?;
+
+        let mut sessions = vec![];
+        for (client_id, session_expiry, disconnected_at) in rows {
+            let mut topic_filters = vec![];
+            let filters = filter_stmt
+                .query_map(params![client_id], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, u8>(1)?))
+                })
+                .map_err(store_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(store_error)?;
+            for (filter, qos) in filters {
+                topic_filters.push((TopicFilter::from_str(&filter)?, QosLevel::try_from(qos)?));
+            }
+
+            let mut qos1_packets = AtLeastOnceList::new();
+            let mut qos2_packets = ExactlyOnceList::new();
+            let packets = packet_stmt
+                .query_map(params![client_id], |row| {
+                    Ok((
+                        row.get::<_, u16>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                })
+                .map_err(store_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(store_error)?;
+            for (packet_id, stage, packet) in packets {
+                let packet = decode_publish(&packet)?;
+                match stage.as_str() {
+                    QOS1_ORIGIN => {
+                        qos1_packets.origin(Arc::new(packet), packet_id);
+                    }
+                    QOS2_ORIGIN => {
+                        qos2_packets.origin(Arc::new(packet), packet_id);
+                    }
+                    QOS2_PUBLISH => {
+                        qos2_packets.publish(packet, packet_id);
+                    }
+                    QOS2_REC => {
+                        qos2_packets.origin(Arc::new(packet), packet_id);
+                        qos2_packets.receive(packet_id);
+                    }
+                    _ => {
+                        return Err(ServerError::new(
+                            server::ErrorKind::SessionStoreError,
+                            format!("Stored packet for client: {client_id} has an unknown stage: {stage}"),
+                        ));
+                    }
+                }
+            }
+
+            let disconnected_for = unix_time().saturating_sub(disconnected_at as u64);
+            sessions.push(DisconnectedSession::restore(
+                client_id,
+                session_expiry as u64,
+                Duration::from_secs(disconnected_for),
+                topic_filters,
+                qos1_packets,
+                qos2_packets,
+            ));
+        }
+
+        return Ok(sessions);
+    }
+
+    fn save_retained(&self, messages: &[PublishPacket]) -> Result<(), ServerError> {
+        let mut conn = self.pool.get().map_err(store_error)?;
+        let tx = conn.transaction().map_err(store_error)?;
+
+        tx.execute("DELETE FROM mqtt_retained", [])
+            .map_err(store_error)?;
+
+        for packet in messages {
+            tx.execute(
+                "INSERT INTO mqtt_retained (topic, packet) VALUES (?1, ?2)",
+                params![
+                    packet.topic().clone().to_string(),
+                    packet.encode()?.to_vec()
+                ],
+            )
+            .map_err(store_error)?;
+        }
+
+        tx.commit().map_err(store_error)?;
+        return Ok(());
+    }
+
+    fn load_retained(&self) -> Result<Vec<PublishPacket>, ServerError> {
+        let conn = self.pool.get().map_err(store_error)?;
+
+        let mut stmt = conn
+            .prepare("SELECT packet FROM mqtt_retained")
+            .map_err(store_error)?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(store_error)?;
+
+        let mut messages = vec![];
+        for row in rows {
+            messages.push(decode_publish(&row.map_err(store_error)?)?);
+        }
+        return Ok(messages);
+    }
+}
+
+fn delete_session(conn: &rusqlite::Connection, client_id: &str) -> Result<(), ServerError> {
+    for table in [
+        "mqtt_sessions",
+        "mqtt_session_filters",
+        "mqtt_session_packets",
+    ] {
+        conn.execute(
+            &format!("DELETE FROM {table} WHERE client_id = ?1"),
+            params![client_id],
+        )
+        .map_err(store_error)?;
+    }
+    return Ok(());
+}
+
+fn decode_publish(bytes: &[u8]) -> Result<PublishPacket, ServerError> {
+    match decode_from(bytes)? {
+        Some((MqttPacket::Publish(packet), _)) => return Ok(packet),
+        _ => {
+            return Err(ServerError::new(
+                server::ErrorKind::SessionStoreError,
+                String::from("Stored packet is not a PUBLISH packet."),
+            ));
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+}
+
+fn store_error(err: impl Display) -> ServerError {
+    return ServerError::new(server::ErrorKind::SessionStoreError, err.to_string());
+}
+
+#[cfg(test)]
+mod sqlite {
+    use std::{sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+    use mqtt_core::{
+        msg_assurance::{AtLeastOnceList, ExactlyOnceList, QoS1Stage, QoS2Stage},
+        qos::QosLevel,
+        topic::{TopicFilter, TopicName},
+        v3::PublishPacket,
+    };
+    use r2d2::Pool;
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    use super::{SessionStore, SqliteSessionStore, StoreWorker};
+    use crate::session::DisconnectedSession;
+
+    fn store() -> SqliteSessionStore {
+        // every in-memory connection opens its own database, so the pool must only hold one.
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        return SqliteSessionStore::new(pool).unwrap();
+    }
+
+    fn message(topic: &str, payload: &'static [u8]) -> PublishPacket {
+        return PublishPacket::new(
+            &TopicName::from_str(topic).unwrap(),
+            Bytes::from_static(payload),
+        );
+    }
+
+    fn session(client_id: &str) -> DisconnectedSession {
+        let mut qos1_packets = AtLeastOnceList::new();
+        let mut packet = message("sensors/temp", b"qos1");
+        packet.set_qos_atleastonce(1);
+        qos1_packets.origin(Arc::new(packet), 1);
+
+        let mut qos2_packets = ExactlyOnceList::new();
+        let mut packet = message("sensors/temp", b"qos2 origin");
+        packet.set_qos_exactlyonce(2);
+        qos2_packets.origin(Arc::new(packet), 2);
+        let mut packet = message("sensors/temp", b"qos2 rec");
+        packet.set_qos_exactlyonce(3);
+        qos2_packets.origin(Arc::new(packet), 3);
+        qos2_packets.receive(3);
+        let mut packet = message("sensors/humidity", b"qos2 publish");
+        packet.set_qos_exactlyonce(4);
+        qos2_packets.publish(packet, 4);
+
+        return DisconnectedSession::restore(
+            client_id.to_string(),
+            3600,
+            Duration::from_secs(60),
+            vec![
+                (
+                    TopicFilter::from_str("sensors/#").unwrap(),
+                    QosLevel::ExactlyOnce,
+                ),
+                (
+                    TopicFilter::from_str("alerts/+").unwrap(),
+                    QosLevel::AtMostOnce,
+                ),
+            ],
+            qos1_packets,
+            qos2_packets,
+        );
+    }
+
+    #[test]
+    fn save_and_load_session() {
+        let store = store();
+        store.save(&session("client")).unwrap();
+
+        let sessions = store.load().unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.client_id(), "client");
+        assert_eq!(session.session_expiry(), 3600);
+        assert!(session.disconnected_for() >= Duration::from_secs(60));
+
+        let mut filters = session.topic_filters().clone();
+        filters.sort();
+        assert_eq!(
+            filters,
+            vec![
+                (
+                    TopicFilter::from_str("alerts/+").unwrap(),
+                    QosLevel::AtMostOnce
+                ),
+                (
+                    TopicFilter::from_str("sensors/#").unwrap(),
+                    QosLevel::ExactlyOnce
+                ),
+            ]
+        );
+
+        let qos1 = session
+            .qos1_packets()
+            .iter()
+            .map(|packet| {
+                (
+                    packet.id(),
+                    packet.stage(),
+                    packet.inner().payload().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            qos1,
+            vec![(1, QoS1Stage::Origin, Bytes::from_static(b"qos1"))]
+        );
+
+        let mut qos2 = session
+            .qos2_packets()
+            .iter()
+            .map(|packet| {
+                (
+                    packet.id(),
+                    packet.stage(),
+                    packet.inner().payload().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        qos2.sort_by_key(|(id, _, _)| *id);
+        assert_eq!(
+            qos2,
+            vec![
+                (2, QoS2Stage::Origin, Bytes::from_static(b"qos2 origin")),
+                (3, QoS2Stage::Rec, Bytes::from_static(b"qos2 rec")),
+                (4, QoS2Stage::Publish, Bytes::from_static(b"qos2 publish")),
+            ]
+        );
+    }
+
+    #[test]
+    fn save_replaces_session() {
+        let store = store();
+        store.save(&session("client")).unwrap();
+        store
+            .save(&DisconnectedSession::restore(
+                String::from("client"),
+                10,
+                Duration::ZERO,
+                vec![],
+                AtLeastOnceList::new(),
+                ExactlyOnceList::new(),
+            ))
+            .unwrap();
+
+        let sessions = store.load().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_expiry(), 10);
+        assert!(sessions[0].topic_filters().is_empty());
+        assert_eq!(sessions[0].qos1_packets().len(), 0);
+        assert_eq!(sessions[0].qos2_packets().len(), 0);
+    }
+
+    #[test]
+    fn remove_session() {
+        let store = store();
+        store.save(&session("client")).unwrap();
+        store.save(&session("other")).unwrap();
+        store.remove("client").unwrap();
+
+        let sessions = store.load().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].client_id(), "other");
+    }
+
+    #[test]
+    fn save_and_load_retained() {
+        let store = store();
+        store
+            .save_retained(&[
+                message("sensors/temp", b"20"),
+                message("sensors/humidity", b"40"),
+            ])
+            .unwrap();
+        // every save replaces the previously stored messages.
+        store
+            .save_retained(&[message("sensors/temp", b"21")])
+            .unwrap();
+
+        let retained = store.load_retained().unwrap();
+        assert_eq!(retained.len(), 1);
+        assert_eq!(
+            retained[0].topic(),
+            &TopicName::from_str("sensors/temp").unwrap()
+        );
+        assert_eq!(retained[0].payload(), &Bytes::from_static(b"21"));
+    }
+
+    #[tokio::test]
+    async fn worker_runs_in_order() {
+        let worker = StoreWorker::spawn(Box::new(store()));
+        worker.run(|store| store.save(&session("client")).unwrap());
+        worker.run(|store| store.remove("client").unwrap());
+        worker.run(|store| store.save(&session("other")).unwrap());
+
+        let sessions = worker.call(|store| store.load().unwrap()).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].client_id(), "other");
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, Sleep},
+};
+
+/// Fails writes to the inner stream that cannot make progress within the timeout.
+///
+/// A client that stops reading its socket fills the TCP send buffer, after which every write would block forever.
+/// The timer starts when a write or flush first returns Pending, and is reset once the write completes.
+pub struct WriteTimeout<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> WriteTimeout<S> {
+    /// A None timeout disables the timeout.
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        return Self {
+            inner,
+            timeout,
+            deadline: None,
+        };
+    }
+
+    fn poll_deadline<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        if poll.is_ready() {
+            self.deadline = None;
+            return poll;
+        }
+
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return poll,
+        };
+
+        let deadline = self
+            .deadline
+            .get_or_insert_with(|| Box::pin(sleep(timeout)));
+        if deadline.as_mut().poll(cx).is_ready() {
+            self.deadline = None;
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "Write did not complete within {} ms, the client stopped reading from the connection.",
+                    timeout.as_millis()
+                ),
+            )));
+        }
+        return Poll::Pending;
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WriteTimeout<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        return Pin::new(&mut self.inner).poll_read(cx, buf);
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WriteTimeout<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        return self.poll_deadline(cx, poll);
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_flush(cx);
+        return self.poll_deadline(cx, poll);
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return Pin::new(&mut self.inner).poll_shutdown(cx);
+    }
+}
+
+/// Joins the halves of a QUIC bidirectional stream into a single stream.
+#[cfg(feature = "quic")]
+pub struct QuicStream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
+
+#[cfg(feature = "quic")]
+impl QuicStream {
+    pub fn new(send: quinn::SendStream, recv: quinn::RecvStream) -> Self {
+        return Self { send, recv };
+    }
+}
+
+#[cfg(feature = "quic")]
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        return Pin::new(&mut self.recv).poll_read(cx, buf);
+    }
+}
+
+#[cfg(feature = "quic")]
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        return AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf);
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return AsyncWrite::poll_flush(Pin::new(&mut self.send), cx);
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use mqtt_core::err::server::{self, ServerError};
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use tokio::sync::watch;
+
+use crate::config::MqttConfig;
+
+fn tls_error(msg: String) -> ServerError {
+    return ServerError::new(server::ErrorKind::TlsError, msg);
+}
+
+/// Loads a listener's certificate chain and private key.
+///
+/// If client certificates are required they are verified against the configured CA bundle.
+pub fn load_tls_config(
+    config: &MqttConfig,
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<ServerConfig, ServerError> {
+    let cert_str = cert_path.to_str().unwrap_or("");
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| tls_error(format!("Could not read {cert_str}, {err}")))?;
+
+    if certs.len() == 0 {
+        log::warn!("No certificates were provided. Check the {cert_str} file")
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|err| {
+        tls_error(format!(
+            "Could not read {}, {err}",
+            key_path.to_str().unwrap_or("")
+        ))
+    })?;
+
+    let builder = ServerConfig::builder();
+    let builder = if config.require_client_cert() {
+        let path = config.client_ca_path();
+        let path_str = path.to_str().unwrap_or("");
+        let mut roots = RootCertStore::empty();
+        let ca_certs = CertificateDer::pem_file_iter(&path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| {
+                tls_error(format!(
+                    "Could not read client CA bundle: {path_str}, {err}"
+                ))
+            })?;
+        for cert in ca_certs {
+            roots
+                .add(cert)
+                .map_err(|err| tls_error(format!("Invalid client CA certificate, {err}")))?;
+        }
+
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(|err| {
+                tls_error(format!(
+                    "Could not build the client certificate verifier, {err}"
+                ))
+            })?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+
+    return builder
+        .with_single_cert(certs, key)
+        .map_err(|err| tls_error(format!("Invalid certificate or private key, {err}")));
+}
+
+/// Holds the TLS configuration shared by the broker's listeners, so certificates can be replaced while the broker runs.
+///
+/// A reload only applies to new connections, established connections keep the configuration they were accepted with.
+pub struct TlsReloader {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: watch::Sender<Arc<ServerConfig>>,
+}
+
+impl TlsReloader {
+    pub fn new(
+        config: &MqttConfig,
+        cert_path: PathBuf,
+        key_path: PathBuf,
+    ) -> Result<Self, ServerError> {
+        let tls_config = load_tls_config(config, &cert_path, &key_path)?;
+        let (current, _) = watch::channel(Arc::new(tls_config));
+        return Ok(Self {
+            cert_path,
+            key_path,
+            current,
+        });
+    }
+
+    /// The configuration new connections should be accepted with.
+    pub fn current(&self) -> Arc<ServerConfig> {
+        return self.current.borrow().clone();
+    }
+
+    /// Notifies listeners that build their own configuration, such as the QUIC listener, of each reload.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<ServerConfig>> {
+        return self.current.subscribe();
+    }
+
+    /// Loads the certificates again, keeping the previous configuration if they cannot be loaded.
+    pub fn reload(&self, config: &MqttConfig) -> Result<(), ServerError> {
+        let tls_config = load_tls_config(config, &self.cert_path, &self.key_path)?;
+        self.current.send_replace(Arc::new(tls_config));
+        return Ok(());
+    }
+}
@@ -1,7 +1,11 @@
 use mqtt_core::{topic::TopicName, v3::PublishPacket};
 use std::{
     collections::{hash_map, HashMap},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Instant,
 };
 use tokio::sync::broadcast;
 
@@ -9,6 +13,9 @@ use tokio::sync::broadcast;
 pub struct ServerTopics {
     topics: HashMap<TopicName, ServerTopic>,
     max_queued_messages: usize,
+    max_retained_messages: Option<usize>,
+    max_retained_payload: Option<usize>,
+    retained_count: usize,
 }
 
 impl ServerTopics {
@@ -16,26 +23,60 @@ impl ServerTopics {
         return Self {
             topics: HashMap::new(),
             max_queued_messages,
+            max_retained_messages: None,
+            max_retained_payload: None,
+            retained_count: 0,
         };
     }
 
+    /// Limits the number of retained messages held by the broker, once exceeded the least recently used message is evicted.
+    pub fn set_max_retained_messages(&mut self, max: Option<usize>) {
+        self.max_retained_messages = max;
+    }
+
+    /// Limits the payload size of a retained message, larger messages are still published but are not retained.
+    pub fn set_max_retained_payload(&mut self, max: Option<usize>) {
+        self.max_retained_payload = max;
+    }
+
     pub fn create_topic(&mut self, topic_name: TopicName) {
         self.topics
             .insert(topic_name, ServerTopic::new(self.max_queued_messages));
     }
 
-    pub fn retain_message(&mut self, packet: PublishPacket) {
-        let topic_name = packet.topic().clone();
-        match self.topic_mut(&topic_name) {
-            Some(channel) => {
-                channel.retain_message(packet);
-            }
-            None => {
-                let mut topic = ServerTopic::new(self.max_queued_messages);
-                topic.retain_message(packet);
-                self.topics.insert(topic_name, topic);
+    /// Retains the message on its topic, an empty payload clears the topic's retained message.
+    ///
+    /// Returns false if the payload exceeded the maximum retained payload size and the message was not retained. The
+    /// topic's previous retained message is cleared regardless, as the publisher has replaced it.
+    pub fn retain_message(&mut self, packet: PublishPacket) -> bool {
+        if let Some(max) = self.max_retained_payload {
+            if packet.payload().len() > max {
+                if let Some(topic) = self.topics.get_mut(packet.topic()) {
+                    if topic.retained_message.take().is_some() {
+                        self.retained_count -= 1;
+                    }
+                }
+                return false;
             }
         }
+
+        let max_queued_messages = self.max_queued_messages;
+        let topic = self
+            .topics
+            .entry(packet.topic().clone())
+            .or_insert_with(|| ServerTopic::new(max_queued_messages));
+
+        let had_retained = topic.retained_message.is_some();
+        topic.retain_message(packet);
+
+        match (had_retained, topic.retained_message.is_some()) {
+            (false, true) => self.retained_count += 1,
+            (true, false) => self.retained_count -= 1,
+            _ => {}
+        }
+
+        self.evict_retained();
+        return true;
     }
 
     pub fn topic_mut(&mut self, topic_name: &TopicName) -> Option<&mut ServerTopic> {
@@ -45,12 +86,61 @@ impl ServerTopics {
     pub fn iter(&self) -> hash_map::Iter<'_, TopicName, ServerTopic> {
         return self.topics.iter();
     }
+
+    pub fn retained_count(&self) -> usize {
+        return self.retained_count;
+    }
+
+    /// A copy of every retained message, used to persist them when the broker shuts down.
+    pub fn retained_messages(&self) -> Vec<PublishPacket> {
+        return self
+            .topics
+            .values()
+            .filter_map(|topic| topic.retained_message.clone())
+            .collect();
+    }
+
+    /// Removes topics that have no subscribers and no retained message.
+    ///
+    /// Returns the number of topics removed.
+    pub fn compact(&mut self) -> usize {
+        let len = self.topics.len();
+        self.topics.retain(|_, topic| {
+            topic.channel.receiver_count() > 0 || topic.retained_message.is_some()
+        });
+        return len - self.topics.len();
+    }
+
+    fn evict_retained(&mut self) {
+        let max = match self.max_retained_messages {
+            Some(max) => max,
+            None => return,
+        };
+
+        while self.retained_count > max {
+            let lru = self
+                .topics
+                .values_mut()
+                .filter(|topic| topic.retained_message.is_some())
+                .min_by_key(|topic| topic.last_used.load(Ordering::Relaxed));
+
+            match lru {
+                Some(topic) => {
+                    topic.retained_message = None;
+                    self.retained_count -= 1;
+                }
+                None => break,
+            }
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct ServerTopic {
     channel: broadcast::Sender<Arc<PublishPacket>>,
     retained_message: Option<PublishPacket>,
+    // milliseconds since the broker started, updated when the retained message is set or delivered.
+    last_used: AtomicU64,
 }
 
 impl ServerTopic {
@@ -58,10 +148,14 @@ impl ServerTopic {
         return Self {
             channel: broadcast::Sender::new(size),
             retained_message: None,
+            last_used: AtomicU64::new(now_millis()),
         };
     }
 
     pub fn get_retained_message(&self) -> Option<&PublishPacket> {
+        if self.retained_message.is_some() {
+            self.last_used.store(now_millis(), Ordering::Relaxed);
+        }
         return self.retained_message.as_ref();
     }
 
@@ -70,6 +164,7 @@ impl ServerTopic {
             self.retained_message = None;
         } else {
             self.retained_message = Some(message);
+            self.last_used.store(now_millis(), Ordering::Relaxed);
         }
     }
 
@@ -81,3 +176,88 @@ impl ServerTopic {
         return self.channel.subscribe();
     }
 }
+
+// Milliseconds since the first call, used to order retained messages by their last use.
+fn now_millis() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    return EPOCH.get_or_init(Instant::now).elapsed().as_millis() as u64;
+}
+
+#[cfg(test)]
+mod retained {
+    use bytes::Bytes;
+    use mqtt_core::{topic::TopicName, v3::PublishPacket};
+    use std::sync::atomic::Ordering;
+
+    use super::ServerTopics;
+
+    fn message(topic: &str, payload: &'static [u8]) -> PublishPacket {
+        return PublishPacket::new(
+            &TopicName::from_str(topic).unwrap(),
+            Bytes::from_static(payload),
+        );
+    }
+
+    fn retained(topics: &mut ServerTopics, topic: &str) -> Option<Bytes> {
+        return topics
+            .topic_mut(&TopicName::from_str(topic).unwrap())?
+            .get_retained_message()
+            .map(|packet| packet.payload().clone());
+    }
+
+    #[test]
+    fn oversized_payload_clears_retained() {
+        let mut topics = ServerTopics::new(8);
+        topics.set_max_retained_payload(Some(4));
+
+        assert!(topics.retain_message(message("sensors/temp", b"20")));
+        assert_eq!(topics.retained_count(), 1);
+
+        // the publisher replaced the retained message, so the old one must not be delivered either.
+        assert!(!topics.retain_message(message("sensors/temp", b"too large")));
+        assert_eq!(retained(&mut topics, "sensors/temp"), None);
+        assert_eq!(topics.retained_count(), 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut topics = ServerTopics::new(8);
+        for (topic, last_used) in [("a", 30), ("b", 10), ("c", 20)] {
+            topics.retain_message(message(topic, b"retained"));
+            topics
+                .topic_mut(&TopicName::from_str(topic).unwrap())
+                .unwrap()
+                .last_used
+                .store(last_used, Ordering::Relaxed);
+        }
+
+        topics.set_max_retained_messages(Some(2));
+        topics.evict_retained();
+
+        assert_eq!(topics.retained_count(), 2);
+        assert!(retained(&mut topics, "b").is_none());
+        assert!(retained(&mut topics, "a").is_some());
+        assert!(retained(&mut topics, "c").is_some());
+    }
+
+    #[test]
+    fn compact() {
+        let mut topics = ServerTopics::new(8);
+        topics.create_topic(TopicName::from_str("empty").unwrap());
+        topics.create_topic(TopicName::from_str("subscribed").unwrap());
+        topics.retain_message(message("retained", b"retained"));
+
+        let receiver = topics
+            .topic_mut(&TopicName::from_str("subscribed").unwrap())
+            .unwrap()
+            .subscribe();
+
+        // only the topic without subscribers or a retained message is removed.
+        assert_eq!(topics.compact(), 1);
+        assert_eq!(topics.iter().count(), 2);
+
+        drop(receiver);
+        assert_eq!(topics.compact(), 1);
+        assert!(retained(&mut topics, "retained").is_some());
+    }
+}
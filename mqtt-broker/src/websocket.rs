@@ -0,0 +1,45 @@
+use mqtt_core::websocket::WsStream;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        self,
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue, StatusCode},
+    },
+};
+
+/// Performs the WebSocket handshake, selecting the `mqtt` subprotocol.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+) -> Result<WsStream<S>, tungstenite::Error> {
+    let inner = accept_hdr_async(stream, select_subprotocol).await?;
+    return Ok(WsStream::new(inner));
+}
+
+/// The Client MUST include "mqtt" in the list of WebSocket Sub Protocols it offers [MQTT-6.0.0-3].
+fn select_subprotocol(
+    request: &Request,
+    mut response: Response,
+) -> Result<Response, ErrorResponse> {
+    let offered = request
+        .headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .any(|protocol| protocol.trim() == "mqtt");
+
+    if !offered {
+        let mut err = ErrorResponse::new(Some(String::from(
+            "The mqtt WebSocket subprotocol is required.",
+        )));
+        *err.status_mut() = StatusCode::BAD_REQUEST;
+        return Err(err);
+    }
+
+    response
+        .headers_mut()
+        .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("mqtt"));
+    return Ok(response);
+}
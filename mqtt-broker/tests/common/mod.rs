@@ -0,0 +1,363 @@
+// Each integration test binary only uses part of the harness.
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    net::TcpListener,
+    path::PathBuf,
+    process::{self, Child, Command, Stdio},
+    time::Duration,
+};
+
+use mqtt_client::{r#async::AsyncClient, transport::Transport};
+use mqtt_core::{
+    err::client::ClientError,
+    io::{unfused_read_frame, unfused_read_packet},
+    qos::QosLevel,
+    topic::{TopicFilter, TopicName},
+    v3::{ConnectPacket, MqttPacket, PublishPacket, SubscribePacket},
+    v5,
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream, time::timeout};
+
+/// How long a test will wait on the broker before failing.
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `[users]` config authenticating clients against a `passwords` file, see [TestBrokerBuilder::file].
+pub const PASSWORD_FILE: &str =
+    "authenticate = true\nauthenticator = \"password_file\"\npassword_file = \"passwords\"";
+
+/// A broker process running on an ephemeral port out of its own temporary directory.
+///
+/// The process is killed and the directory is removed when the broker is dropped.
+pub struct TestBroker {
+    child: Child,
+    port: u16,
+    dir: PathBuf,
+    websocket_port: Option<u16>,
+    listener_port: Option<u16>,
+}
+
+/// Configures a [TestBroker] before it is started.
+pub struct TestBrokerBuilder {
+    name: String,
+    users_config: String,
+    broker_config: String,
+    tables: String,
+    files: Vec<(String, String)>,
+    websocket: bool,
+    listener: bool,
+}
+
+impl TestBrokerBuilder {
+    /// Appends TOML to the `[users]` section of the config.
+    pub fn users(mut self, config: &str) -> Self {
+        self.users_config.push_str(config);
+        self.users_config.push('\n');
+        return self;
+    }
+
+    /// Appends TOML to the `[broker]` section of the config.
+    pub fn broker(mut self, config: &str) -> Self {
+        self.broker_config.push_str(config);
+        self.broker_config.push('\n');
+        return self;
+    }
+
+    /// Appends tables, such as `[[rewrite]]`, to the end of the config.
+    pub fn tables(mut self, tables: &str) -> Self {
+        self.tables.push_str(tables);
+        self.tables.push('\n');
+        return self;
+    }
+
+    /// Writes the file into the broker's directory before it is started.
+    pub fn file(mut self, name: &str, contents: &str) -> Self {
+        self.files.push((name.to_string(), contents.to_string()));
+        return self;
+    }
+
+    /// Enables the WebSocket listener, see [TestBroker::websocket_url].
+    pub fn websocket(mut self) -> Self {
+        self.websocket = true;
+        return self;
+    }
+
+    /// Adds a second plaintext listener, see [TestBroker::listener_addr].
+    pub fn listener(mut self) -> Self {
+        self.listener = true;
+        return self;
+    }
+
+    /// Starts the broker and waits for every listener to accept connections.
+    pub async fn start(self) -> TestBroker {
+        let dir = env::temp_dir().join(format!("mqtt-e2e-{}-{}", self.name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        // the broker shells out to openssl when the tls directory is missing, even for plaintext listeners.
+        fs::create_dir_all(dir.join("tls")).expect("Could not create test directory");
+
+        let port = free_port();
+        let mut tables = self.tables;
+        let websocket_port = self.websocket.then(free_port);
+        if let Some(ws_port) = websocket_port {
+            tables.push_str(&format!("[websocket]\nenabled = true\nport = {ws_port}\n"));
+        }
+        let listener_port = self.listener.then(free_port);
+        if let Some(listener_port) = listener_port {
+            tables.push_str(&format!(
+                "[[listener]]\naddress = \"127.0.0.1\"\nport = {listener_port}\n"
+            ));
+        }
+
+        let config = format!(
+            "[connection]\ntls = false\nip = \"127.0.0.1\"\nport = {port}\n\n\
+             [users]\n{}\n\
+             [logger]\nconsole = false\nfile = false\nlevel = \"off\"\n\n\
+             [broker]\n{}\n\
+             {tables}",
+            self.users_config, self.broker_config,
+        );
+        fs::write(dir.join("config.toml"), config).expect("Could not write test config");
+
+        for (file, contents) in &self.files {
+            fs::write(dir.join(file), contents).expect("Could not write test file");
+        }
+
+        let broker = TestBroker {
+            child: spawn(&dir),
+            port,
+            dir,
+            websocket_port,
+            listener_port,
+        };
+        wait_for_listener(&broker.addr()).await;
+        for port in [websocket_port, listener_port].into_iter().flatten() {
+            wait_for_listener(&format!("127.0.0.1:{port}")).await;
+        }
+        return broker;
+    }
+}
+
+impl TestBroker {
+    pub fn builder(name: &str) -> TestBrokerBuilder {
+        return TestBrokerBuilder {
+            name: name.to_string(),
+            users_config: String::new(),
+            broker_config: String::new(),
+            tables: String::new(),
+            files: vec![],
+            websocket: false,
+            listener: false,
+        };
+    }
+
+    /// Kills the broker process and starts a new one on the same port, out of the same directory.
+    pub async fn restart(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+
+        self.child = spawn(&self.dir);
+        wait_for_listener(&self.addr()).await;
+    }
+
+    /// Sends the broker SIGTERM and waits for it to shut down.
+    pub fn terminate(&mut self) {
+        Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .expect("Could not signal broker");
+        let _ = self.child.wait();
+    }
+
+    pub fn addr(&self) -> String {
+        return format!("127.0.0.1:{}", self.port);
+    }
+
+    /// The url of the WebSocket listener enabled by [TestBrokerBuilder::websocket].
+    pub fn websocket_url(&self) -> String {
+        let port = self.websocket_port.expect("WebSocket listener not enabled");
+        return format!("ws://127.0.0.1:{port}/mqtt");
+    }
+
+    /// The address of the listener added by [TestBrokerBuilder::listener].
+    pub fn listener_addr(&self) -> String {
+        let port = self.listener_port.expect("Listener not added");
+        return format!("127.0.0.1:{port}");
+    }
+
+    pub async fn stream(&self) -> TcpStream {
+        return TcpStream::connect(self.addr())
+            .await
+            .expect("Could not connect to broker");
+    }
+
+    /// Connects a new client with a keep alive of 60 seconds.
+    pub async fn client(&self, client_id: &str, clean_session: bool) -> AsyncClient<TcpStream> {
+        let mut client = AsyncClient::new(self.stream().await);
+        let packet =
+            ConnectPacket::new(clean_session, 60, String::from(client_id), None, None, None);
+
+        timeout(RECV_TIMEOUT, client.connect(packet))
+            .await
+            .expect("Timed out waiting for CONNACK")
+            .expect("Could not connect client");
+        return client;
+    }
+}
+
+impl Drop for TestBroker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn wait_for_listener(addr: &str) {
+    let connect = async {
+        loop {
+            if TcpStream::connect(addr).await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    timeout(RECV_TIMEOUT, connect)
+        .await
+        .expect("Broker did not start listening");
+}
+
+fn spawn(dir: &PathBuf) -> Child {
+    return Command::new(env!("CARGO_BIN_EXE_mqtt-server"))
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Could not start broker");
+}
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Could not find a free port");
+    return listener.local_addr().unwrap().port();
+}
+
+/// A client without the graceful DISCONNECT on drop of [AsyncClient], used to simulate a lost connection.
+pub struct RawClient {
+    stream: TcpStream,
+}
+
+impl RawClient {
+    pub async fn new(broker: &TestBroker) -> Self {
+        return Self {
+            stream: broker.stream().await,
+        };
+    }
+
+    pub async fn send(&mut self, packet: MqttPacket) {
+        self.stream
+            .write_all(&packet.encode().unwrap())
+            .await
+            .expect("Could not write to broker");
+    }
+
+    /// Writes bytes the client's packet types cannot encode, such as malformed packets.
+    pub async fn send_bytes(&mut self, bytes: &[u8]) {
+        self.stream
+            .write_all(bytes)
+            .await
+            .expect("Could not write to broker");
+    }
+
+    pub async fn recv(&mut self) -> MqttPacket {
+        return timeout(
+            RECV_TIMEOUT,
+            unfused_read_packet::<_, ClientError>(&mut self.stream),
+        )
+        .await
+        .expect("Timed out waiting for packet")
+        .expect("Could not read packet")
+        .expect("Broker closed the connection");
+    }
+
+    pub async fn send_v5(&mut self, packet: v5::MqttPacket) {
+        self.stream
+            .write_all(&packet.encode().unwrap())
+            .await
+            .expect("Could not write to broker");
+    }
+
+    pub async fn recv_v5(&mut self) -> v5::MqttPacket {
+        let (mut header, mut body) = timeout(
+            RECV_TIMEOUT,
+            unfused_read_frame::<_, ClientError>(&mut self.stream),
+        )
+        .await
+        .expect("Timed out waiting for packet")
+        .expect("Could not read packet");
+
+        let f_header = v5::FixedHeader::decode(&mut header).unwrap();
+        return v5::decode_packet(f_header, &mut body).expect("Could not decode packet");
+    }
+}
+
+/// Subscribes to a single topic and waits for the SUBACK.
+///
+/// The broker only routes messages to topics that exist when the subscription is made,
+/// so an empty retained message is published first to create the topic.
+pub async fn subscribe(client: &mut AsyncClient<TcpStream>, topic: &str, qos: QosLevel) {
+    let mut packet = PublishPacket::new(&TopicName::from_str(topic).unwrap(), Default::default());
+    packet.set_retain(true);
+    client.publish(packet).await.unwrap();
+
+    let packet = SubscribePacket::new(
+        client.next_packet_id().unwrap(),
+        vec![(TopicFilter::from_str(topic).unwrap(), qos)],
+    );
+    client.sub(packet).await.unwrap();
+
+    let suback = async {
+        loop {
+            if let Some(MqttPacket::SubAck(_)) = client.recv_packet().await.unwrap() {
+                return;
+            }
+        }
+    };
+    timeout(RECV_TIMEOUT, suback)
+        .await
+        .expect("Timed out waiting for SUBACK");
+}
+
+/// Waits for the next PUBLISH packet, discarding any other packets.
+pub async fn recv_publish(client: &mut AsyncClient<TcpStream>) -> PublishPacket {
+    let publish = async {
+        loop {
+            if let Some(MqttPacket::Publish(packet)) = client.recv_packet().await.unwrap() {
+                return packet;
+            }
+        }
+    };
+    return timeout(RECV_TIMEOUT, publish)
+        .await
+        .expect("Timed out waiting for PUBLISH");
+}
+
+/// Waits for the first packet that matches the predicate, discarding any other packets.
+pub async fn recv_until<T: Transport>(
+    client: &mut AsyncClient<T>,
+    cb: impl Fn(&MqttPacket) -> bool,
+) -> MqttPacket {
+    let packet = async {
+        loop {
+            if let Some(packet) = client.recv_packet().await.unwrap() {
+                if cb(&packet) {
+                    return packet;
+                }
+            }
+        }
+    };
+    return timeout(RECV_TIMEOUT, packet)
+        .await
+        .expect("Timed out waiting for packet");
+}
@@ -0,0 +1,1509 @@
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use common::{
+    recv_publish, recv_until, subscribe, RawClient, TestBroker, PASSWORD_FILE, RECV_TIMEOUT,
+};
+use futures::StreamExt;
+use mqtt_client::{
+    interceptor::Interceptor,
+    managed::ManagedClient,
+    r#async::AsyncClient,
+    reconnect::{ConnectionEvent, ReconnectPolicy},
+    websocket,
+};
+use mqtt_core::{
+    err::client,
+    qos::{QosLevel, SubAckQoS},
+    topic::{TopicFilter, TopicName},
+    v3::{
+        ConnectPacket, DisconnectPacket, MqttPacket, PingReqPacket, PublishPacket, SubscribePacket,
+        Will,
+    },
+    v5::{self, Property, ReasonCode, SubscriptionOptions},
+    ConnectReturnCode, MqttVersion,
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream, time::timeout};
+
+#[tokio::test]
+async fn qos0_delivery() {
+    let broker = TestBroker::builder("qos0").start().await;
+    let mut sub = broker.client("qos0_sub", true).await;
+    let mut publisher = broker.client("qos0_pub", true).await;
+    subscribe(&mut sub, "qos0", QosLevel::AtMostOnce).await;
+
+    let topic_name = TopicName::from_str("qos0").unwrap();
+    publisher
+        .publish_qos0(&topic_name, b"hello", false)
+        .await
+        .unwrap();
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.qos(), QosLevel::AtMostOnce);
+    assert_eq!(packet.payload(), &Bytes::from_static(b"hello"));
+}
+
+#[tokio::test]
+async fn qos1_delivery() {
+    let broker = TestBroker::builder("qos1").start().await;
+    let mut sub = broker.client("qos1_sub", true).await;
+    let mut publisher = broker.client("qos1_pub", true).await;
+    subscribe(&mut sub, "qos1", QosLevel::AtLeastOnce).await;
+
+    let id = publisher.next_packet_id().unwrap();
+    let mut packet = PublishPacket::new(
+        &TopicName::from_str("qos1").unwrap(),
+        Bytes::from_static(b"at least once"),
+    );
+    packet.set_qos_atleastonce(id);
+    publisher.publish(packet).await.unwrap();
+
+    let puback = recv_until(&mut publisher, |packet| {
+        matches!(packet, MqttPacket::PubAck(_))
+    })
+    .await;
+    assert_eq!(puback.packet_id(), Some(id));
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.qos(), QosLevel::AtLeastOnce);
+    assert_eq!(packet.payload(), &Bytes::from_static(b"at least once"));
+    sub.ack(packet.id().unwrap()).await.unwrap();
+}
+
+#[tokio::test]
+async fn qos2_delivery() {
+    let broker = TestBroker::builder("qos2").start().await;
+    let mut sub = broker.client("qos2_sub", true).await;
+    let mut publisher = broker.client("qos2_pub", true).await;
+    subscribe(&mut sub, "qos2", QosLevel::ExactlyOnce).await;
+
+    let id = publisher.next_packet_id().unwrap();
+    let mut packet = PublishPacket::new(
+        &TopicName::from_str("qos2").unwrap(),
+        Bytes::from_static(b"exactly once"),
+    );
+    packet.set_qos_exactlyonce(id);
+    publisher.publish(packet).await.unwrap();
+
+    recv_until(&mut publisher, |packet| {
+        matches!(packet, MqttPacket::PubRec(_))
+    })
+    .await;
+    publisher.rel(id).await.unwrap();
+    recv_until(&mut publisher, |packet| {
+        matches!(packet, MqttPacket::PubComp(_))
+    })
+    .await;
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.qos(), QosLevel::ExactlyOnce);
+    assert_eq!(packet.payload(), &Bytes::from_static(b"exactly once"));
+
+    let id = packet.id().unwrap();
+    sub.rec(id).await.unwrap();
+    recv_until(&mut sub, |packet| matches!(packet, MqttPacket::PubRel(_))).await;
+    sub.comp(id).await.unwrap();
+}
+
+#[tokio::test]
+async fn retained_message_on_subscribe() {
+    let broker = TestBroker::builder("retained").start().await;
+    let mut publisher = broker.client("retained_pub", true).await;
+
+    // publish at QoS 1 so the PUBACK confirms the message was retained before subscribing.
+    let id = publisher.next_packet_id().unwrap();
+    let mut packet = PublishPacket::new(
+        &TopicName::from_str("retained").unwrap(),
+        Bytes::from_static(b"retained"),
+    );
+    packet.set_qos_atleastonce(id);
+    packet.set_retain(true);
+    publisher.publish(packet).await.unwrap();
+    recv_until(&mut publisher, |packet| {
+        matches!(packet, MqttPacket::PubAck(_))
+    })
+    .await;
+
+    let mut sub = broker.client("retained_sub", true).await;
+    let packet = SubscribePacket::new(
+        sub.next_packet_id().unwrap(),
+        vec![(
+            TopicFilter::from_str("retained").unwrap(),
+            QosLevel::AtLeastOnce,
+        )],
+    );
+    sub.sub(packet).await.unwrap();
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"retained"));
+}
+
+#[tokio::test]
+async fn will_published_on_lost_connection() {
+    let broker = TestBroker::builder("will").start().await;
+    let mut sub = broker.client("will_sub", true).await;
+    subscribe(&mut sub, "will", QosLevel::AtMostOnce).await;
+
+    let will = Will::new(
+        TopicName::from_str("will").unwrap(),
+        Bytes::from_static(b"gone"),
+        QosLevel::AtMostOnce,
+        false,
+    );
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(ConnectPacket::new(
+            true,
+            60,
+            String::from("will_client"),
+            Some(will),
+            None,
+            None,
+        )))
+        .await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+
+    // close the socket without sending a DISCONNECT packet.
+    drop(client);
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"gone"));
+}
+
+#[tokio::test]
+async fn v5_binary_will() {
+    let broker = TestBroker::builder("v5_will").start().await;
+    let mut sub = broker.client("v5_will_sub", true).await;
+    subscribe(&mut sub, "v5_will", QosLevel::AtMostOnce).await;
+
+    let payload = Bytes::from_static(&[0x00, 0xff, 0xfe]);
+    let will = v5::Will::new(
+        TopicName::from_str("v5_will").unwrap(),
+        payload.clone(),
+        QosLevel::AtMostOnce,
+        false,
+    );
+    let connect = v5::ConnectPacket::new(
+        true,
+        60,
+        String::from("v5_will_client"),
+        Some(will),
+        None,
+        None,
+    );
+    let mut client = RawClient::new(&broker).await;
+    client.send_v5(v5::MqttPacket::Connect(connect)).await;
+    assert!(matches!(client.recv_v5().await, v5::MqttPacket::ConnAck(_)));
+
+    drop(client);
+
+    // the will is forwarded to the v3.1.1 subscriber byte for byte.
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &payload);
+}
+
+#[tokio::test]
+async fn v5_disconnect_with_will() {
+    let broker = TestBroker::builder("v5_dc_will").start().await;
+    let mut sub = broker.client("v5_dc_will_sub", true).await;
+    subscribe(&mut sub, "v5_dc_will", QosLevel::AtMostOnce).await;
+
+    let will = v5::Will::new(
+        TopicName::from_str("v5_dc_will").unwrap(),
+        Bytes::from_static(b"gone"),
+        QosLevel::AtMostOnce,
+        false,
+    );
+    let connect = v5::ConnectPacket::new(
+        true,
+        60,
+        String::from("v5_dc_will_client"),
+        Some(will),
+        None,
+        None,
+    );
+    let mut client = RawClient::new(&broker).await;
+    client.send_v5(v5::MqttPacket::Connect(connect)).await;
+    assert!(matches!(client.recv_v5().await, v5::MqttPacket::ConnAck(_)));
+
+    // the client disconnects, but asks for its will to be published anyway.
+    client
+        .send_v5(v5::MqttPacket::Disconnect(v5::DisconnectPacket::new(
+            ReasonCode::DisconnectWithWillMessage,
+        )))
+        .await;
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"gone"));
+}
+
+#[tokio::test]
+async fn v5_disconnect_session_expiry() {
+    let broker = TestBroker::builder("v5_dc_expiry").start().await;
+    let connect = || {
+        let mut connect =
+            v5::ConnectPacket::new(false, 60, String::from("v5_dc_expiry"), None, None, None);
+        connect
+            .properties_mut()
+            .push(Property::SessionExpiryInterval(60));
+        return v5::MqttPacket::Connect(connect);
+    };
+
+    let mut client = RawClient::new(&broker).await;
+    client.send_v5(connect()).await;
+    assert!(matches!(client.recv_v5().await, v5::MqttPacket::ConnAck(_)));
+
+    // a Session Expiry Interval of 0 on DISCONNECT ends the session with the connection.
+    let mut disconnect = v5::DisconnectPacket::new(ReasonCode::Success);
+    disconnect
+        .properties_mut()
+        .push(Property::SessionExpiryInterval(0));
+    client.send_v5(v5::MqttPacket::Disconnect(disconnect)).await;
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let mut client = RawClient::new(&broker).await;
+    client.send_v5(connect()).await;
+    match client.recv_v5().await {
+        v5::MqttPacket::ConnAck(connack) => assert!(!connack.session_present()),
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+}
+
+/// Connects a client with a QoS 0 will of "gone" on the topic.
+async fn connect_with_will(broker: &TestBroker, client_id: &str, topic: &str) -> RawClient {
+    let will = Will::new(
+        TopicName::from_str(topic).unwrap(),
+        Bytes::from_static(b"gone"),
+        QosLevel::AtMostOnce,
+        false,
+    );
+    let mut client = RawClient::new(broker).await;
+    client
+        .send(MqttPacket::Connect(ConnectPacket::new(
+            false,
+            60,
+            String::from(client_id),
+            Some(will),
+            None,
+            None,
+        )))
+        .await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+    return client;
+}
+
+#[tokio::test]
+async fn will_suppressed_on_disconnect() {
+    let broker = TestBroker::builder("graceful").start().await;
+    let mut sub = broker.client("graceful_sub", true).await;
+    subscribe(&mut sub, "graceful", QosLevel::AtMostOnce).await;
+
+    let mut client = connect_with_will(&broker, "graceful_client", "graceful").await;
+    client
+        .send(MqttPacket::Disconnect(DisconnectPacket::new()))
+        .await;
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    // the first message the subscriber receives is the marker, not the will.
+    let mut publisher = broker.client("graceful_pub", true).await;
+    let topic_name = TopicName::from_str("graceful").unwrap();
+    publisher
+        .publish(PublishPacket::new(
+            &topic_name,
+            Bytes::from_static(b"marker"),
+        ))
+        .await
+        .unwrap();
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"marker"));
+}
+
+#[tokio::test]
+async fn will_delay_cancelled_by_reconnect() {
+    let broker = TestBroker::builder("will_delay")
+        .broker("will_delay_interval = 1")
+        .start()
+        .await;
+    let mut sub = broker.client("will_delay_sub", true).await;
+    subscribe(&mut sub, "will_delay", QosLevel::AtMostOnce).await;
+
+    // lose the connection, then reconnect before the will delay has passed.
+    drop(connect_with_will(&broker, "will_delay_client", "will_delay").await);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _client = connect_with_will(&broker, "will_delay_client", "will_delay").await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let mut publisher = broker.client("will_delay_pub", true).await;
+    let topic_name = TopicName::from_str("will_delay").unwrap();
+    publisher
+        .publish(PublishPacket::new(
+            &topic_name,
+            Bytes::from_static(b"marker"),
+        ))
+        .await
+        .unwrap();
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"marker"));
+}
+
+#[tokio::test]
+async fn session_kept_when_connack_fails() {
+    let broker = TestBroker::builder("connack_fails").start().await;
+    let connect = || {
+        MqttPacket::Connect(ConnectPacket::new(
+            false,
+            60,
+            String::from("connack_fails"),
+            None,
+            None,
+            None,
+        ))
+    };
+
+    // store a session for the client.
+    let mut client = RawClient::new(&broker).await;
+    client.send(connect()).await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+    client
+        .send(MqttPacket::Disconnect(DisconnectPacket::new()))
+        .await;
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    // resume it, then reset the connection so the broker fails to write the CONNACK.
+    let mut stream = broker.stream().await;
+    stream.set_linger(Some(Duration::ZERO)).unwrap();
+    stream
+        .write_all(&connect().encode().unwrap())
+        .await
+        .unwrap();
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let mut client = RawClient::new(&broker).await;
+    client.send(connect()).await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => assert!(connack.session_present()),
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn duplicate_client_id_takes_over_session() {
+    let broker = TestBroker::builder("takeover").start().await;
+    let mut sub = broker.client("takeover_sub", true).await;
+    subscribe(&mut sub, "takeover", QosLevel::AtMostOnce).await;
+
+    let _old = connect_with_will(&broker, "takeover_client", "takeover").await;
+
+    // the old connection is closed as if it was lost, and its session is handed to the new connection.
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(ConnectPacket::new(
+            false,
+            60,
+            String::from("takeover_client"),
+            None,
+            None,
+            None,
+        )))
+        .await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => assert!(connack.session_present()),
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"gone"));
+}
+
+#[tokio::test]
+async fn empty_client_id() {
+    let broker = TestBroker::builder("empty_client_id").start().await;
+
+    // a client without a client id cannot resume a session.
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(ConnectPacket::new(
+            false,
+            60,
+            String::new(),
+            None,
+            None,
+            None,
+        )))
+        .await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.return_code(), ConnectReturnCode::IdentifierRejected)
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+
+    // the broker assigns an id to a client starting a clean session.
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(ConnectPacket::new(
+            true,
+            60,
+            String::new(),
+            None,
+            None,
+            None,
+        )))
+        .await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.return_code(), ConnectReturnCode::Accept)
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+    client.send(MqttPacket::PingReq(PingReqPacket::new())).await;
+    assert!(matches!(client.recv().await, MqttPacket::PingResp(_)));
+}
+
+#[tokio::test]
+async fn connection_limit_refuses_clients() {
+    let broker = TestBroker::builder("max_connections")
+        .broker("max_connections = 1")
+        .start()
+        .await;
+    // let the connections made while waiting for the broker to start close.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let _client = broker.client("max_connections_1", true).await;
+
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(ConnectPacket::new(
+            true,
+            60,
+            String::from("max_connections_2"),
+            None,
+            None,
+            None,
+        )))
+        .await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.return_code(), ConnectReturnCode::ServerUnavailable)
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn oversized_packet_disconnects_client() {
+    let broker = TestBroker::builder("max_packet_size")
+        .broker("max_packet_size = 64")
+        .start()
+        .await;
+    let mut sub = broker.client("max_packet_size_sub", true).await;
+    subscribe(&mut sub, "max_packet_size", QosLevel::AtMostOnce).await;
+
+    let mut client = connect_with_will(&broker, "max_packet_size_client", "max_packet_size").await;
+    client
+        .send(MqttPacket::Publish(PublishPacket::new(
+            &TopicName::from_str("max_packet_size").unwrap(),
+            Bytes::from(vec![0; 128]),
+        )))
+        .await;
+
+    // the broker closes the connection without reading the packet, which publishes the will.
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"gone"));
+}
+
+#[tokio::test]
+async fn receive_maximum_exceeded() {
+    let broker = TestBroker::builder("receive_max")
+        .broker("receive_maximum = 1")
+        .start()
+        .await;
+    let topic_name = TopicName::from_str("receive_max").unwrap();
+    let publish = |id: u16, payload: &'static [u8]| {
+        let mut packet = v5::PublishPacket::new(&topic_name, Bytes::from_static(payload));
+        packet.set_qos_exactlyonce(id);
+        packet.set_retain(true);
+        return v5::MqttPacket::Publish(packet);
+    };
+
+    let connect = v5::ConnectPacket::new(true, 60, String::from("receive_max"), None, None, None);
+    let mut client = RawClient::new(&broker).await;
+    client.send_v5(v5::MqttPacket::Connect(connect)).await;
+    match client.recv_v5().await {
+        v5::MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.properties().receive_maximum(), Some(1))
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+
+    // the first message is held until it is released, which the client never does.
+    client.send_v5(publish(1, b"first")).await;
+    assert!(matches!(client.recv_v5().await, v5::MqttPacket::PubRec(_)));
+
+    client.send_v5(publish(2, b"second")).await;
+    match client.recv_v5().await {
+        v5::MqttPacket::Disconnect(disconnect) => {
+            assert_eq!(disconnect.reason_code(), ReasonCode::ReceiveMaximumExceeded)
+        }
+        packet => panic!("Expected DISCONNECT, received {packet}"),
+    }
+
+    // the refused message is not retained.
+    let mut sub = broker.client("receive_max_sub", true).await;
+    let id = sub.next_packet_id().unwrap();
+    sub.sub(SubscribePacket::new(
+        id,
+        vec![(
+            TopicFilter::from_str("receive_max").unwrap(),
+            QosLevel::AtMostOnce,
+        )],
+    ))
+    .await
+    .unwrap();
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"first"));
+}
+
+#[tokio::test]
+async fn keep_alive_expiry_publishes_will() {
+    let broker = TestBroker::builder("keep_alive").start().await;
+    let mut sub = broker.client("keep_alive_sub", true).await;
+    subscribe(&mut sub, "keep_alive", QosLevel::AtMostOnce).await;
+
+    let will = Will::new(
+        TopicName::from_str("keep_alive").unwrap(),
+        Bytes::from_static(b"expired"),
+        QosLevel::AtMostOnce,
+        false,
+    );
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(ConnectPacket::new(
+            true,
+            1,
+            String::from("keep_alive_client"),
+            Some(will),
+            None,
+            None,
+        )))
+        .await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+
+    // keep the socket open without sending any packets, the broker closes it after 1.5 seconds.
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"expired"));
+    drop(client);
+}
+
+#[tokio::test]
+async fn client_pings_within_keep_alive() {
+    let broker = TestBroker::builder("auto_ping").start().await;
+    let mut client = AsyncClient::new(broker.stream().await);
+    let packet = ConnectPacket::new(true, 1, String::from("auto_ping"), None, None, None);
+    timeout(RECV_TIMEOUT, client.connect(packet))
+        .await
+        .unwrap()
+        .unwrap();
+
+    // an idle client sends a PINGREQ after a second, so the broker holds the connection open past its 1.5 second deadline.
+    let mut pings = 0;
+    let idle = async {
+        loop {
+            if let Some(MqttPacket::PingResp(_)) = client.recv_packet().await.unwrap() {
+                pings += 1;
+                if pings == 2 {
+                    return;
+                }
+            }
+        }
+    };
+    timeout(RECV_TIMEOUT, idle)
+        .await
+        .expect("Timed out waiting for PINGRESP");
+}
+
+#[tokio::test]
+async fn resume_persistent_session() {
+    let broker = TestBroker::builder("resume").start().await;
+    let topic_name = TopicName::from_str("resume").unwrap();
+
+    // a graceful disconnect discards the session, so the persistent session is created by a lost connection.
+    let connect = ConnectPacket::new(false, 60, String::from("resume"), None, None, None);
+    let mut client = RawClient::new(&broker).await;
+    client.send(MqttPacket::Connect(connect.clone())).await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+
+    // create the topic before subscribing to it, see common::subscribe.
+    let mut packet = PublishPacket::new(&topic_name, Bytes::new());
+    packet.set_retain(true);
+    client.send(MqttPacket::Publish(packet)).await;
+    client
+        .send(MqttPacket::Subscribe(SubscribePacket::new(
+            1,
+            vec![(
+                TopicFilter::from_str("resume").unwrap(),
+                QosLevel::AtMostOnce,
+            )],
+        )))
+        .await;
+    assert!(matches!(client.recv().await, MqttPacket::SubAck(_)));
+    drop(client);
+
+    // give the broker a moment to store the disconnected session.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let mut client = RawClient::new(&broker).await;
+    client.send(MqttPacket::Connect(connect)).await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => assert!(connack.session_present()),
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+
+    // subscriptions are restored before the first packet is handled, so the PINGRESP orders the publish after them.
+    client.send(MqttPacket::PingReq(PingReqPacket::new())).await;
+    assert!(matches!(client.recv().await, MqttPacket::PingResp(_)));
+
+    let mut publisher = broker.client("resume_pub", true).await;
+    publisher
+        .publish_qos0(&topic_name, b"resumed", false)
+        .await
+        .unwrap();
+
+    match client.recv().await {
+        MqttPacket::Publish(packet) => {
+            assert_eq!(packet.payload(), &Bytes::from_static(b"resumed"))
+        }
+        packet => panic!("Expected PUBLISH, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn session_survives_restart() {
+    let mut broker = TestBroker::builder("restart")
+        .broker("persist_sessions = true")
+        .start()
+        .await;
+
+    let connect = ConnectPacket::new(false, 60, String::from("restart"), None, None, None);
+    let mut client = RawClient::new(&broker).await;
+    client.send(MqttPacket::Connect(connect.clone())).await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => assert!(!connack.session_present()),
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+    drop(client);
+
+    // give the broker a moment to store the disconnected session.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    broker.restart().await;
+
+    let mut client = RawClient::new(&broker).await;
+    client.send(MqttPacket::Connect(connect)).await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => assert!(connack.session_present()),
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn shutdown_disconnects_clients() {
+    let mut broker = TestBroker::builder("shutdown").start().await;
+
+    let connect = v5::ConnectPacket::new(true, 60, String::from("shutdown"), None, None, None);
+    let mut client = RawClient::new(&broker).await;
+    client.send_v5(v5::MqttPacket::Connect(connect)).await;
+    match client.recv_v5().await {
+        v5::MqttPacket::ConnAck(connack) => assert_eq!(connack.reason_code(), ReasonCode::Success),
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+
+    broker.terminate();
+    match client.recv_v5().await {
+        v5::MqttPacket::Disconnect(disconnect) => {
+            assert_eq!(disconnect.reason_code(), ReasonCode::ServerShuttingDown)
+        }
+        packet => panic!("Expected DISCONNECT, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn retained_message_survives_shutdown() {
+    let mut broker = TestBroker::builder("shutdown_retained")
+        .broker("persist_sessions = true")
+        .start()
+        .await;
+    let mut publisher = broker.client("shutdown_pub", true).await;
+
+    let id = publisher.next_packet_id().unwrap();
+    let mut packet = PublishPacket::new(
+        &TopicName::from_str("shutdown").unwrap(),
+        Bytes::from_static(b"retained"),
+    );
+    packet.set_qos_atleastonce(id);
+    packet.set_retain(true);
+    publisher.publish(packet).await.unwrap();
+    recv_until(&mut publisher, |packet| {
+        matches!(packet, MqttPacket::PubAck(_))
+    })
+    .await;
+    drop(publisher);
+
+    broker.terminate();
+    broker.restart().await;
+
+    let mut sub = broker.client("shutdown_sub", true).await;
+    let packet = SubscribePacket::new(
+        sub.next_packet_id().unwrap(),
+        vec![(
+            TopicFilter::from_str("shutdown").unwrap(),
+            QosLevel::AtLeastOnce,
+        )],
+    );
+    sub.sub(packet).await.unwrap();
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"retained"));
+}
+
+#[tokio::test]
+async fn v3_1_client() {
+    let mut connect = ConnectPacket::new(true, 60, String::from("legacy"), None, None, None);
+    connect.set_version(MqttVersion::V3_1);
+
+    let broker = TestBroker::builder("v3_1_refused").start().await;
+    let mut client = RawClient::new(&broker).await;
+    client.send(MqttPacket::Connect(connect.clone())).await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.return_code(), ConnectReturnCode::InvalidProtocol)
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+
+    let broker = TestBroker::builder("v3_1")
+        .broker("allow_mqtt_v3_1 = true")
+        .start()
+        .await;
+    let mut client = RawClient::new(&broker).await;
+    client.send(MqttPacket::Connect(connect)).await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.return_code(), ConnectReturnCode::Accept)
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn unsupported_protocol_level_refused() {
+    let broker = TestBroker::builder("protocol_level").start().await;
+
+    let connect = ConnectPacket::new(true, 60, String::from("level"), None, None, None);
+    let mut buf = connect.encode().unwrap().to_vec();
+    // the Protocol Level follows the fixed header and the length prefixed Protocol Name "MQTT".
+    buf[8] = 6;
+
+    let mut client = RawClient::new(&broker).await;
+    client.send_bytes(&buf).await;
+    match client.recv().await {
+        MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.return_code(), ConnectReturnCode::InvalidProtocol)
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn topic_rewrite() {
+    let broker = TestBroker::builder("rewrite")
+        .tables(
+            "[[rewrite]]\npattern = '^devices/([^/]+)/telemetry$'\nreplacement = 'tenant/$1/telemetry'\n\n\
+         [[rewrite]]\nprefix = 'legacy/'\nreplacement = 'v2/'",
+        )
+        .start()
+        .await;
+    let mut sub = broker.client("rewrite_sub", true).await;
+    let mut publisher = broker.client("rewrite_pub", true).await;
+    subscribe(&mut sub, "tenant/acme/telemetry", QosLevel::AtMostOnce).await;
+    subscribe(&mut sub, "v2/status", QosLevel::AtMostOnce).await;
+
+    let packet = PublishPacket::new(
+        &TopicName::from_str("devices/acme/telemetry").unwrap(),
+        Bytes::from_static(b"21"),
+    );
+    publisher.publish(packet).await.unwrap();
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(
+        packet.topic(),
+        &TopicName::from_str("tenant/acme/telemetry").unwrap()
+    );
+    assert_eq!(packet.payload(), &Bytes::from_static(b"21"));
+
+    let packet = PublishPacket::new(
+        &TopicName::from_str("legacy/status").unwrap(),
+        Bytes::from_static(b"up"),
+    );
+    publisher.publish(packet).await.unwrap();
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.topic(), &TopicName::from_str("v2/status").unwrap());
+}
+
+/// Drops outgoing messages with the payload "drop".
+struct DropPublish;
+
+impl Interceptor for DropPublish {
+    fn outgoing(&mut self, packet: MqttPacket) -> Option<MqttPacket> {
+        match &packet {
+            MqttPacket::Publish(publish) if publish.payload() == &Bytes::from_static(b"drop") => {
+                return None
+            }
+            _ => return Some(packet),
+        }
+    }
+}
+
+#[tokio::test]
+async fn managed_client_publish_batch() {
+    let broker = TestBroker::builder("managed_batch").start().await;
+    let mut sub = broker.client("managed_batch_sub", true).await;
+    let mut publisher = ManagedClient::from(broker.client("managed_batch_pub", true).await);
+    publisher.client_mut().add_interceptor(DropPublish);
+    subscribe(&mut sub, "batch", QosLevel::AtMostOnce).await;
+
+    let topic_name = TopicName::from_str("batch").unwrap();
+    let mut packets = vec![];
+    for (payload, qos) in [
+        (&b"zero"[..], QosLevel::AtMostOnce),
+        (&b"one"[..], QosLevel::AtLeastOnce),
+        (&b"drop"[..], QosLevel::AtLeastOnce),
+        (&b"two"[..], QosLevel::ExactlyOnce),
+    ] {
+        let mut packet = PublishPacket::new(&topic_name, Bytes::copy_from_slice(payload));
+        match qos {
+            QosLevel::AtMostOnce => {}
+            // the managed client assigns the packet ids.
+            QosLevel::AtLeastOnce => packet.set_qos_atleastonce(0),
+            QosLevel::ExactlyOnce => packet.set_qos_exactlyonce(0),
+        }
+        packets.push(packet);
+    }
+
+    // the dropped message is never acknowledged, so awaiting it would time out.
+    timeout(RECV_TIMEOUT, publisher.publish_batch(packets))
+        .await
+        .expect("Timed out waiting for the batch to be acknowledged")
+        .unwrap();
+    assert_eq!(publisher.inflight(), 0);
+
+    for payload in [&b"zero"[..], b"one", b"two"] {
+        let packet = recv_publish(&mut sub).await;
+        assert_eq!(packet.payload(), &Bytes::copy_from_slice(payload));
+    }
+}
+
+#[tokio::test]
+async fn topic_rewrite_authorized() {
+    let hash = bcrypt::hash("secret", 4).unwrap();
+    let acl = "[[rules]]\nuser = \"alice\"\ntopic = \"#\"\naction = \"all\"\nallow = true\n\n\
+               [[rules]]\nuser = \"bob\"\ntopic = \"devices/#\"\naction = \"all\"\nallow = true\n\n\
+               [[rules]]\nuser = \"bob\"\ntopic = \"public/#\"\naction = \"all\"\nallow = true\n";
+    let broker = TestBroker::builder("rewrite_acl")
+        .users(PASSWORD_FILE)
+        .users("acl_path = \"acl.toml\"")
+        .file("passwords", &format!("alice:{hash}\nbob:{hash}\n"))
+        .file("acl.toml", acl)
+        .tables("[[rewrite]]\nprefix = 'devices/'\nreplacement = 'private/'")
+        .start()
+        .await;
+
+    let connect = |client_id: &str, username: &str| {
+        ConnectPacket::new(
+            true,
+            60,
+            String::from(client_id),
+            None,
+            Some(String::from(username)),
+            Some(Bytes::from_static(b"secret")),
+        )
+    };
+
+    let mut sub = AsyncClient::new(broker.stream().await);
+    timeout(
+        RECV_TIMEOUT,
+        sub.connect(connect("rewrite_acl_sub", "alice")),
+    )
+    .await
+    .expect("Timed out waiting for CONNACK")
+    .unwrap();
+    subscribe(&mut sub, "private/data", QosLevel::AtMostOnce).await;
+    subscribe(&mut sub, "public/data", QosLevel::AtMostOnce).await;
+
+    let mut publisher = AsyncClient::new(broker.stream().await);
+    timeout(
+        RECV_TIMEOUT,
+        publisher.connect(connect("rewrite_acl_pub", "bob")),
+    )
+    .await
+    .expect("Timed out waiting for CONNACK")
+    .unwrap();
+
+    // bob may publish to devices/data, but not to private/data which the rule rewrites it to.
+    for topic in ["devices/data", "public/data"] {
+        publisher
+            .publish(PublishPacket::new(
+                &TopicName::from_str(topic).unwrap(),
+                Bytes::copy_from_slice(topic.as_bytes()),
+            ))
+            .await
+            .unwrap();
+    }
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"public/data"));
+}
+
+#[tokio::test]
+async fn managed_client_handshakes() {
+    let broker = TestBroker::builder("managed").start().await;
+    let mut sub = ManagedClient::from(broker.client("managed_sub", true).await);
+    let mut publisher = ManagedClient::from(broker.client("managed_pub", true).await);
+    subscribe(sub.client_mut(), "managed", QosLevel::ExactlyOnce).await;
+
+    let topic_name = TopicName::from_str("managed").unwrap();
+    timeout(
+        RECV_TIMEOUT,
+        publisher.publish_qos1(&topic_name, Bytes::from_static(b"one"), false),
+    )
+    .await
+    .expect("Timed out waiting for PUBACK")
+    .unwrap();
+    timeout(
+        RECV_TIMEOUT,
+        publisher.publish_qos2(&topic_name, Bytes::from_static(b"two"), false),
+    )
+    .await
+    .expect("Timed out waiting for PUBCOMP")
+    .unwrap();
+    assert_eq!(publisher.inflight(), 0);
+
+    for payload in [&b"one"[..], &b"two"[..]] {
+        let packet = timeout(RECV_TIMEOUT, sub.recv())
+            .await
+            .expect("Timed out waiting for PUBLISH")
+            .unwrap();
+        assert_eq!(packet.payload(), &Bytes::from_static(payload));
+    }
+}
+
+#[tokio::test]
+async fn subscription_stream() {
+    let broker = TestBroker::builder("stream").start().await;
+    let mut sub = ManagedClient::from(broker.client("stream_sub", true).await);
+    let mut publisher = ManagedClient::from(broker.client("stream_pub", true).await);
+
+    // create the topic before subscribing to it, see common::subscribe.
+    let topic_name = TopicName::from_str("sensors/temp").unwrap();
+    timeout(
+        RECV_TIMEOUT,
+        publisher.publish_qos1(&topic_name, Bytes::from_static(b"20"), true),
+    )
+    .await
+    .expect("Timed out waiting for PUBACK")
+    .unwrap();
+
+    let mut temps = sub
+        .subscribe(
+            TopicFilter::from_str("sensors/+").unwrap(),
+            QosLevel::AtLeastOnce,
+        )
+        .await
+        .unwrap();
+
+    // the retained message is routed to the stream while the client is driven.
+    let packet = tokio::select! {
+        res = sub.run() => panic!("Client stopped: {res:?}"),
+        packet = timeout(RECV_TIMEOUT, temps.next()) => packet.expect("Timed out waiting for PUBLISH"),
+    };
+    assert_eq!(packet.unwrap().payload(), &Bytes::from_static(b"20"));
+}
+
+#[tokio::test]
+async fn managed_client_reconnects() {
+    let mut broker = TestBroker::builder("reconnect").start().await;
+    let topic_name = TopicName::from_str("reconnect").unwrap();
+
+    let mut sub = ManagedClient::new(broker.stream().await);
+    let addr = broker.addr();
+    let mut policy = ReconnectPolicy::new();
+    policy.set_initial_delay(Duration::from_millis(10));
+    policy.set_jitter(0.0);
+    sub.set_reconnect(move || TcpStream::connect(addr.clone()), policy);
+    let mut events = sub.connection_events();
+
+    let connect = ConnectPacket::new(true, 60, String::from("reconnect"), None, None, None);
+    sub.connect(connect).await.unwrap();
+
+    // create the topic before subscribing to it, see common::subscribe.
+    sub.publish_qos0(&topic_name, b"", true).await.unwrap();
+    sub.sub(SubscribePacket::new(
+        1,
+        vec![(
+            TopicFilter::from_str("reconnect").unwrap(),
+            QosLevel::AtMostOnce,
+        )],
+    ))
+    .await
+    .unwrap();
+
+    broker.restart().await;
+
+    // the restarted broker has no topics, so the retained message recreates the topic before the client resubscribes.
+    let mut publisher = ManagedClient::from(broker.client("reconnect_pub", true).await);
+    timeout(
+        RECV_TIMEOUT,
+        publisher.publish_qos1(&topic_name, Bytes::from_static(b"after"), true),
+    )
+    .await
+    .expect("Timed out waiting for PUBACK")
+    .unwrap();
+
+    let packet = timeout(RECV_TIMEOUT, sub.recv())
+        .await
+        .expect("Timed out waiting for PUBLISH")
+        .unwrap();
+    assert_eq!(packet.payload(), &Bytes::from_static(b"after"));
+
+    assert!(matches!(
+        events.try_recv(),
+        Ok(ConnectionEvent::Disconnected(_))
+    ));
+    assert_eq!(events.try_recv(), Ok(ConnectionEvent::Reconnecting(1)));
+    assert_eq!(events.try_recv(), Ok(ConnectionEvent::Reconnected));
+}
+
+#[tokio::test]
+async fn additional_listener() {
+    let broker = TestBroker::builder("listener").listener().start().await;
+    let addr = broker.listener_addr();
+    let mut publisher = broker.client("listener_pub", true).await;
+
+    let mut sub = AsyncClient::new(TcpStream::connect(&addr).await.unwrap());
+    let connect = ConnectPacket::new(true, 60, String::from("listener_sub"), None, None, None);
+    timeout(RECV_TIMEOUT, sub.connect(connect))
+        .await
+        .expect("Timed out waiting for CONNACK")
+        .unwrap();
+    subscribe(&mut sub, "listener", QosLevel::AtMostOnce).await;
+
+    publisher
+        .publish_qos0(
+            &TopicName::from_str("listener").unwrap(),
+            b"across listeners",
+            false,
+        )
+        .await
+        .unwrap();
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"across listeners"));
+}
+
+#[tokio::test]
+async fn websocket_client() {
+    let broker = TestBroker::builder("ws_client").websocket().start().await;
+    let url = broker.websocket_url();
+    let mut publisher = broker.client("ws_pub", true).await;
+
+    let addr = url.trim_start_matches("ws://").trim_end_matches("/mqtt");
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let transport = websocket::connect(&url, stream).await.unwrap();
+    let mut sub = AsyncClient::new(transport);
+    let connect = ConnectPacket::new(true, 60, String::from("ws_sub"), None, None, None);
+    timeout(RECV_TIMEOUT, sub.connect(connect))
+        .await
+        .expect("Timed out waiting for CONNACK")
+        .unwrap();
+
+    // create the topic before subscribing to it, see common::subscribe.
+    let topic_name = TopicName::from_str("ws").unwrap();
+    let mut packet = PublishPacket::new(&topic_name, Bytes::new());
+    packet.set_retain(true);
+    sub.publish(packet).await.unwrap();
+    sub.sub(SubscribePacket::new(
+        1,
+        vec![(TopicFilter::from_str("ws").unwrap(), QosLevel::AtMostOnce)],
+    ))
+    .await
+    .unwrap();
+    recv_until(&mut sub, |packet| matches!(packet, MqttPacket::SubAck(_))).await;
+
+    publisher
+        .publish_qos0(&topic_name, b"over websocket", false)
+        .await
+        .unwrap();
+
+    let packet = recv_until(&mut sub, |packet| matches!(packet, MqttPacket::Publish(_))).await;
+    match packet {
+        MqttPacket::Publish(packet) => {
+            assert_eq!(packet.payload(), &Bytes::from_static(b"over websocket"))
+        }
+        packet => panic!("Expected PUBLISH, received {packet}"),
+    }
+}
+
+#[tokio::test]
+async fn password_file_authentication() {
+    let hash = bcrypt::hash("secret", 4).unwrap();
+    let broker = TestBroker::builder("password_file")
+        .users(PASSWORD_FILE)
+        .file("passwords", &format!("alice:{hash}\n"))
+        .start()
+        .await;
+
+    let connect = |client_id: &str, password: &'static [u8]| {
+        ConnectPacket::new(
+            true,
+            60,
+            String::from(client_id),
+            None,
+            Some(String::from("alice")),
+            Some(Bytes::from_static(password)),
+        )
+    };
+
+    let mut client = AsyncClient::new(broker.stream().await);
+    timeout(
+        RECV_TIMEOUT,
+        client.connect(connect("password_ok", b"secret")),
+    )
+    .await
+    .expect("Timed out waiting for CONNACK")
+    .expect("Client with valid credentials was refused");
+
+    let mut client = AsyncClient::new(broker.stream().await);
+    let res = timeout(
+        RECV_TIMEOUT,
+        client.connect(connect("password_bad", b"wrong")),
+    )
+    .await
+    .expect("Timed out waiting for the broker to refuse the client");
+    match res {
+        Err(err) => assert!(matches!(
+            err.kind(),
+            client::ErrorKind::ConnectError(ConnectReturnCode::BadUsernameOrPassword)
+        )),
+        Ok(()) => panic!("Client with invalid credentials was accepted"),
+    }
+}
+
+#[tokio::test]
+async fn jwt_topic_claims() {
+    let broker = TestBroker::builder("jwt")
+        .users("authenticate = true\nauthenticator = \"jwt\"\njwt_secret = \"jwt-secret\"")
+        .start()
+        .await;
+
+    let exp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 60;
+    let claims = serde_json::json!({ "sub": "sensor-1", "exp": exp, "subscribe": ["sensors/#"] });
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(b"jwt-secret"),
+    )
+    .unwrap();
+
+    let connect = |client_id: &str, token: &str| {
+        ConnectPacket::new(
+            true,
+            60,
+            String::from(client_id),
+            None,
+            Some(String::from("sensor-1")),
+            Some(Bytes::copy_from_slice(token.as_bytes())),
+        )
+    };
+
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(connect("jwt_ok", &token)))
+        .await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+    client
+        .send(MqttPacket::Subscribe(SubscribePacket::new(
+            1,
+            vec![
+                (
+                    TopicFilter::from_str("sensors/temp").unwrap(),
+                    QosLevel::AtMostOnce,
+                ),
+                (
+                    TopicFilter::from_str("private/+").unwrap(),
+                    QosLevel::AtMostOnce,
+                ),
+            ],
+        )))
+        .await;
+    match client.recv().await {
+        MqttPacket::SubAck(suback) => assert_eq!(
+            suback.filters(),
+            &vec![SubAckQoS::QOS(QosLevel::AtMostOnce), SubAckQoS::Err]
+        ),
+        packet => panic!("Expected SUBACK, received {packet}"),
+    }
+
+    let mut client = AsyncClient::new(broker.stream().await);
+    let res = timeout(
+        RECV_TIMEOUT,
+        client.connect(connect("jwt_bad", "not.a.token")),
+    )
+    .await
+    .expect("Timed out waiting for the broker to refuse the client");
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn acl_refuses_subscription() {
+    let acl = "[[rules]]\ntopic = \"sensors/#\"\naction = \"all\"\nallow = true\n";
+    let broker = TestBroker::builder("acl")
+        .users("acl_path = \"acl.toml\"")
+        .file("acl.toml", acl)
+        .start()
+        .await;
+    let mut sub = broker.client("acl_sub", true).await;
+    let mut publisher = broker.client("acl_pub", true).await;
+    subscribe(&mut sub, "sensors/temp", QosLevel::AtMostOnce).await;
+
+    let connect = ConnectPacket::new(true, 60, String::from("acl_raw"), None, None, None);
+    let mut client = RawClient::new(&broker).await;
+    client.send(MqttPacket::Connect(connect)).await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+    client
+        .send(MqttPacket::Subscribe(SubscribePacket::new(
+            1,
+            vec![(
+                TopicFilter::from_str("private/+").unwrap(),
+                QosLevel::AtMostOnce,
+            )],
+        )))
+        .await;
+    match client.recv().await {
+        MqttPacket::SubAck(suback) => assert_eq!(suback.filters(), &vec![SubAckQoS::Err]),
+        packet => panic!("Expected SUBACK, received {packet}"),
+    }
+
+    // MQTT 5.0 clients are told the refused filter is not authorized.
+    let connect = v5::ConnectPacket::new(true, 60, String::from("acl_v5"), None, None, None);
+    let mut v5_client = RawClient::new(&broker).await;
+    v5_client.send_v5(v5::MqttPacket::Connect(connect)).await;
+    assert!(matches!(
+        v5_client.recv_v5().await,
+        v5::MqttPacket::ConnAck(_)
+    ));
+    v5_client
+        .send_v5(v5::MqttPacket::Subscribe(v5::SubscribePacket::new(
+            1,
+            vec![
+                (
+                    TopicFilter::from_str("sensors/humidity").unwrap(),
+                    SubscriptionOptions::new(QosLevel::AtMostOnce),
+                ),
+                (
+                    TopicFilter::from_str("private/+").unwrap(),
+                    SubscriptionOptions::new(QosLevel::AtMostOnce),
+                ),
+            ],
+        )))
+        .await;
+    match v5_client.recv_v5().await {
+        v5::MqttPacket::SubAck(suback) => assert_eq!(
+            suback.reason_codes(),
+            &vec![ReasonCode::Success, ReasonCode::NotAuthorized]
+        ),
+        packet => panic!("Expected SUBACK, received {packet}"),
+    }
+
+    publisher
+        .publish_qos0(&TopicName::from_str("sensors/temp").unwrap(), b"21", false)
+        .await
+        .unwrap();
+
+    let packet = recv_publish(&mut sub).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"21"));
+}
+
+#[tokio::test]
+async fn acl_filters_resumed_subscriptions() {
+    let hash = bcrypt::hash("secret", 4).unwrap();
+    let acl = "[[rules]]\nuser = \"alice\"\ntopic = \"#\"\naction = \"all\"\nallow = true\n\n\
+               [[rules]]\nuser = \"bob\"\ntopic = \"public/#\"\naction = \"all\"\nallow = true\n";
+    let broker = TestBroker::builder("acl_resume")
+        .users(PASSWORD_FILE)
+        .users("acl_path = \"acl.toml\"")
+        .file("passwords", &format!("alice:{hash}\nbob:{hash}\n"))
+        .file("acl.toml", acl)
+        .start()
+        .await;
+
+    let connect = |client_id: &str, username: &str, clean_session: bool| {
+        ConnectPacket::new(
+            clean_session,
+            60,
+            String::from(client_id),
+            None,
+            Some(String::from(username)),
+            Some(Bytes::from_static(b"secret")),
+        )
+    };
+
+    // alice subscribes to a private topic on a persistent session, then loses the connection.
+    let mut client = RawClient::new(&broker).await;
+    client
+        .send(MqttPacket::Connect(connect("acl_shared", "alice", false)))
+        .await;
+    assert!(matches!(client.recv().await, MqttPacket::ConnAck(_)));
+    client
+        .send(MqttPacket::Subscribe(SubscribePacket::new(
+            1,
+            vec![(
+                TopicFilter::from_str("private/data").unwrap(),
+                QosLevel::AtMostOnce,
+            )],
+        )))
+        .await;
+    assert!(matches!(client.recv().await, MqttPacket::SubAck(_)));
+    drop(client);
+
+    let mut publisher = AsyncClient::new(broker.stream().await);
+    timeout(
+        RECV_TIMEOUT,
+        publisher.connect(connect("acl_resume_pub", "alice", true)),
+    )
+    .await
+    .expect("Timed out waiting for CONNACK")
+    .unwrap();
+
+    let id = publisher.next_packet_id().unwrap();
+    let mut packet = PublishPacket::new(
+        &TopicName::from_str("private/data").unwrap(),
+        Bytes::from_static(b"private"),
+    );
+    packet.set_qos_atleastonce(id);
+    packet.set_retain(true);
+    publisher.publish(packet).await.unwrap();
+    recv_until(&mut publisher, |packet| {
+        matches!(packet, MqttPacket::PubAck(_))
+    })
+    .await;
+
+    // give the broker a moment to store the disconnected session.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    // bob resumes alice's session by its client id, but may not keep the private subscription.
+    let mut bob = AsyncClient::new(broker.stream().await);
+    timeout(
+        RECV_TIMEOUT,
+        bob.connect(connect("acl_shared", "bob", false)),
+    )
+    .await
+    .expect("Timed out waiting for CONNACK")
+    .unwrap();
+    subscribe(&mut bob, "public/data", QosLevel::AtMostOnce).await;
+
+    publisher
+        .publish(PublishPacket::new(
+            &TopicName::from_str("private/data").unwrap(),
+            Bytes::from_static(b"private"),
+        ))
+        .await
+        .unwrap();
+    publisher
+        .publish(PublishPacket::new(
+            &TopicName::from_str("public/data").unwrap(),
+            Bytes::from_static(b"public"),
+        ))
+        .await
+        .unwrap();
+
+    let packet = recv_publish(&mut bob).await;
+    assert_eq!(packet.payload(), &Bytes::from_static(b"public"));
+}
+
+#[tokio::test]
+async fn v5_session() {
+    let broker = TestBroker::builder("v5")
+        .broker("session_expiry_interval = 60\ntopic_alias_maximum = 4")
+        .start()
+        .await;
+    let topic_name = TopicName::from_str("v5").unwrap();
+
+    let mut connect = v5::ConnectPacket::new(true, 60, String::from("v5"), None, None, None);
+    connect
+        .properties_mut()
+        .push(Property::SessionExpiryInterval(60 * 60));
+
+    let mut client = RawClient::new(&broker).await;
+    client.send_v5(v5::MqttPacket::Connect(connect)).await;
+    match client.recv_v5().await {
+        v5::MqttPacket::ConnAck(connack) => {
+            assert_eq!(connack.reason_code(), ReasonCode::Success);
+            // the requested interval is capped at the broker's session expiry interval.
+            assert_eq!(connack.properties().session_expiry_interval(), Some(60));
+            assert_eq!(connack.properties().topic_alias_maximum(), Some(4));
+        }
+        packet => panic!("Expected CONNACK, received {packet}"),
+    }
+
+    // create the topic before subscribing to it, establishing topic alias 1 along the way.
+    let mut packet = v5::PublishPacket::new(&topic_name, Bytes::new());
+    packet.properties_mut().push(Property::TopicAlias(1));
+    client.send_v5(v5::MqttPacket::Publish(packet)).await;
+
+    client
+        .send_v5(v5::MqttPacket::Subscribe(v5::SubscribePacket::new(
+            1,
+            vec![(
+                TopicFilter::from_str("v5").unwrap(),
+                SubscriptionOptions::new(QosLevel::AtMostOnce),
+            )],
+        )))
+        .await;
+    match client.recv_v5().await {
+        v5::MqttPacket::SubAck(suback) => {
+            assert_eq!(suback.reason_codes(), &vec![ReasonCode::Success])
+        }
+        packet => panic!("Expected SUBACK, received {packet}"),
+    }
+
+    let mut packet = v5::PublishPacket::new(&topic_name, Bytes::from_static(b"aliased"));
+    packet.set_topic(None);
+    packet.properties_mut().push(Property::TopicAlias(1));
+    client.send_v5(v5::MqttPacket::Publish(packet)).await;
+
+    match client.recv_v5().await {
+        v5::MqttPacket::Publish(packet) => {
+            assert_eq!(packet.topic(), Some(&topic_name));
+            assert_eq!(packet.payload(), &Bytes::from_static(b"aliased"));
+        }
+        packet => panic!("Expected PUBLISH, received {packet}"),
+    }
+    // a second CONNECT is a protocol error.
+    let connect = v5::ConnectPacket::new(true, 60, String::from("v5"), None, None, None);
+    client.send_v5(v5::MqttPacket::Connect(connect)).await;
+    match client.recv_v5().await {
+        v5::MqttPacket::Disconnect(disconnect) => {
+            assert_eq!(disconnect.reason_code(), ReasonCode::ProtocolError)
+        }
+        packet => panic!("Expected DISCONNECT, received {packet}"),
+    }
+}
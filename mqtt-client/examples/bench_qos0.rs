@@ -0,0 +1,47 @@
+use bytes::Bytes;
+use mqtt_client::r#async::AsyncClient;
+use mqtt_core::{
+    topic::TopicName,
+    v3::{ConnectPacket, PublishPacket},
+};
+use tokio::{net::TcpStream, time::Instant};
+
+const MAXPUB: u32 = 1000000;
+
+#[tokio::main]
+async fn main() {
+    let stream = TcpStream::connect("127.0.0.1:1883").await.unwrap();
+    let mut client = AsyncClient::new(stream);
+    let topic_name = TopicName::from_str("bench/qos0").unwrap();
+    let payload = b"TEST QOS 0";
+
+    let packet = ConnectPacket::new(true, 10, String::from("bench_qos0"), None, None, None);
+    client.connect(packet).await.unwrap();
+
+    let start = Instant::now();
+    for _ in 0..MAXPUB {
+        let packet = PublishPacket::new(&topic_name, Bytes::copy_from_slice(payload));
+        client.publish(packet).await.unwrap();
+    }
+    let owned = Instant::now().duration_since(start);
+
+    let start = Instant::now();
+    for _ in 0..MAXPUB {
+        client
+            .publish_qos0(&topic_name, payload, false)
+            .await
+            .unwrap();
+    }
+    let borrowed = Instant::now().duration_since(start);
+
+    println!(
+        "PublishPacket: {} ms, average {} ns",
+        owned.as_millis(),
+        owned.as_nanos() / MAXPUB as u128
+    );
+    println!(
+        "publish_qos0: {} ms, average {} ns",
+        borrowed.as_millis(),
+        borrowed.as_nanos() / MAXPUB as u128
+    );
+}
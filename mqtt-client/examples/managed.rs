@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use mqtt_client::managed::ManagedClient;
+use mqtt_core::{topic::TopicName, v3::ConnectPacket};
+use tokio::{net::TcpStream, time::sleep};
+
+#[tokio::main]
+async fn main() {
+    let stream = TcpStream::connect("127.0.0.1:1883").await.unwrap();
+    let mut client = ManagedClient::new(stream);
+    let topic_name = TopicName::from_str("managed").unwrap();
+
+    let packet = ConnectPacket::new(false, 10, String::from("managed_id"), None, None, None);
+    client.connect(packet).await.unwrap();
+
+    let mut idx = 0;
+    loop {
+        sleep(Duration::from_millis(10)).await;
+
+        // each publish resolves once the broker has completed the handshake.
+        let payload = Bytes::from(format!("TEST QOS 1, idx: {idx}"));
+        client
+            .publish_qos1(&topic_name, payload, false)
+            .await
+            .unwrap();
+
+        let payload = Bytes::from(format!("TEST QOS 2, idx: {idx}"));
+        client
+            .publish_qos2(&topic_name, payload, false)
+            .await
+            .unwrap();
+
+        println!("idx: {idx}");
+        idx += 1;
+    }
+}
@@ -0,0 +1,56 @@
+use bytes::Bytes;
+use mqtt_client::r#async::AsyncClient;
+use mqtt_core::{
+    topic::TopicName,
+    v3::{ConnectPacket, MqttPacket, PublishPacket},
+};
+use tokio::{net::TcpStream, time::Instant};
+
+const BATCH_SIZE: usize = 1000;
+const MAXBATCH: u32 = 100;
+
+#[tokio::main]
+async fn main() {
+    let stream = TcpStream::connect("127.0.0.1:1883").await.unwrap();
+    let mut client = AsyncClient::new(stream);
+    let topic_name = TopicName::from_str("batch").unwrap();
+
+    let packet = ConnectPacket::new(true, 10, String::from("pub_batch"), None, None, None);
+    client.connect(packet).await.unwrap();
+
+    let start = Instant::now();
+
+    for batch in 0..MAXBATCH {
+        let mut packets = Vec::with_capacity(BATCH_SIZE);
+        for idx in 0..BATCH_SIZE {
+            let mut packet = PublishPacket::new(
+                &topic_name,
+                Bytes::copy_from_slice(&format!("batch: {batch}, idx: {idx}").as_bytes()),
+            );
+            packet.set_qos_atleastonce(client.next_packet_id().unwrap());
+            packets.push(packet);
+        }
+
+        let mut ids = client.publish_batch(packets).await.unwrap();
+
+        // wait for every packet in the batch to be acknowledged.
+        while ids.len() > 0 {
+            if let Some(packet) = client.recv_packet().await.unwrap() {
+                match packet {
+                    MqttPacket::PubAck(packet) => {
+                        ids.retain(|id| *id != packet.id());
+                    }
+                    _ => {
+                        panic!("ERR: recieved packet other than PUBACK")
+                    }
+                }
+            }
+        }
+    }
+
+    println!(
+        "Total sent: {}, Total Time: {} ms",
+        BATCH_SIZE as u32 * MAXBATCH,
+        Instant::now().duration_since(start).as_millis()
+    );
+}
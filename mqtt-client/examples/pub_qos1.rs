@@ -14,7 +14,7 @@ async fn main() {
 
     let will = Will::new(
         topic_name.clone(),
-        "RETAIN".to_string(),
+        Bytes::from_static(b"RETAIN"),
         mqtt_core::qos::QosLevel::AtLeastOnce,
         true,
     );
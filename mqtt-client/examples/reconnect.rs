@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use mqtt_client::{managed::ManagedClient, reconnect::ReconnectPolicy};
+use mqtt_core::{
+    qos::QosLevel,
+    topic::TopicFilter,
+    v3::{ConnectPacket, SubscribePacket},
+};
+use tokio::net::TcpStream;
+
+#[tokio::main]
+async fn main() {
+    let stream = TcpStream::connect("127.0.0.1:1883").await.unwrap();
+    let mut client = ManagedClient::new(stream);
+
+    let mut policy = ReconnectPolicy::new();
+    policy.set_max_delay(Duration::from_secs(10));
+    client.set_reconnect(|| TcpStream::connect("127.0.0.1:1883"), policy);
+
+    let mut events = client.connection_events();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            println!("connection: {event:?}");
+        }
+    });
+
+    let packet = ConnectPacket::new(false, 10, String::from("reconnect_id"), None, None, None);
+    client.connect(packet).await.unwrap();
+
+    let packet = SubscribePacket::new(
+        client.client_mut().next_packet_id().unwrap(),
+        vec![(
+            TopicFilter::from_str("qos1").unwrap(),
+            QosLevel::AtLeastOnce,
+        )],
+    );
+    client.sub(packet).await.unwrap();
+
+    // restart the broker while the client is running, the subscription is restored once it reconnects.
+    loop {
+        let packet = client.recv().await.unwrap();
+        println!(
+            "{}: {:?}",
+            packet.topic().clone().to_string(),
+            packet.payload()
+        );
+    }
+}
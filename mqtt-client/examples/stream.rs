@@ -0,0 +1,36 @@
+use futures::StreamExt;
+use mqtt_client::managed::ManagedClient;
+use mqtt_core::{qos::QosLevel, topic::TopicFilter, v3::ConnectPacket};
+use tokio::net::TcpStream;
+
+#[tokio::main]
+async fn main() {
+    let stream = TcpStream::connect("127.0.0.1:1883").await.unwrap();
+    let mut client = ManagedClient::new(stream);
+
+    let packet = ConnectPacket::new(true, 10, String::from("stream_id"), None, None, None);
+    client.connect(packet).await.unwrap();
+
+    let mut qos1 = client
+        .subscribe(
+            TopicFilter::from_str("qos1").unwrap(),
+            QosLevel::AtLeastOnce,
+        )
+        .await
+        .unwrap();
+
+    // the client owns the connection, so it is driven from its own task while the stream is consumed here.
+    tokio::spawn(async move {
+        if let Err(err) = client.run().await {
+            println!("connection closed: {err}");
+        }
+    });
+
+    while let Some(packet) = qos1.next().await {
+        println!(
+            "{}: {:?}",
+            packet.topic().clone().to_string(),
+            packet.payload()
+        );
+    }
+}
@@ -1,14 +1,8 @@
-use std::{sync::Arc, time::Duration};
+use std::time::Duration;
 
-use tokio_rustls::{
-    rustls::pki_types::{pem::PemObject, ServerName},
-    TlsConnector,
-};
-
-use mqtt_client::r#async::AsyncClient;
+use mqtt_client::{r#async::AsyncClient, tls::TlsOptions};
 use mqtt_core::v3::{ConnectPacket, MqttPacket};
 use tokio::{net::TcpStream, time::Instant};
-use tokio_rustls::rustls::{self, pki_types::CertificateDer};
 
 const MAXPING: u32 = 10000;
 #[tokio::main]
@@ -16,20 +10,12 @@ async fn main() {
     let stream = TcpStream::connect("127.0.0.1:8883").await.unwrap();
     // let stream = BufReader::new(stream);
 
-    let domain = "test.mqtt.com";
-
-    let mut root_cert_store = rustls::RootCertStore::empty();
-    for cert in CertificateDer::pem_file_iter("../mqtt-broker/tls/cert.pem").unwrap() {
-        root_cert_store.add(cert.unwrap()).unwrap();
-    }
-
-    let config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_cert_store)
-        .with_no_client_auth(); // i guess this was previously the default?
-    let connector = TlsConnector::from(Arc::new(config));
-    let domain = ServerName::try_from(domain).unwrap().to_owned();
+    let mut tls = TlsOptions::new();
+    tls.add_ca_file("../mqtt-broker/tls/cert.pem").unwrap();
+    // the broker's certificate is issued to this domain, not the address we connected to.
+    tls.set_server_name("test.mqtt.com");
 
-    let stream = connector.connect(domain, stream).await.unwrap();
+    let stream = tls.connect("127.0.0.1", stream).await.unwrap();
 
     let mut client = AsyncClient::new(stream);
 
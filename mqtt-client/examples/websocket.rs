@@ -0,0 +1,33 @@
+use mqtt_client::{r#async::AsyncClient, websocket::WsTransport};
+use mqtt_core::{
+    topic::TopicName,
+    v3::{ConnectPacket, MqttPacket},
+};
+use tokio::net::TcpStream;
+
+#[tokio::main]
+async fn main() {
+    // see the broker's [websocket] config section.
+    let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
+    let transport = WsTransport::connect("ws://127.0.0.1:8080/mqtt", stream)
+        .await
+        .unwrap();
+    let mut client = AsyncClient::new(transport);
+
+    let packet = ConnectPacket::new(true, 10, String::from("ws_id"), None, None, None);
+    client.connect(packet).await.unwrap();
+
+    let topic_name = TopicName::from_str("websocket").unwrap();
+    client
+        .publish_qos0(&topic_name, b"hello over websocket", false)
+        .await
+        .unwrap();
+
+    client.ping().await.unwrap();
+    loop {
+        if let Some(MqttPacket::PingResp(_)) = client.recv_packet().await.unwrap() {
+            println!("received PINGRESP");
+            break;
+        }
+    }
+}
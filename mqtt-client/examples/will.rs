@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use mqtt_client::r#async::AsyncClient;
 use mqtt_core::{
     topic::TopicName,
@@ -12,7 +13,7 @@ async fn main() {
 
     let will = Will::new(
         TopicName::from_str("test").unwrap(),
-        "RETAIN".to_string(),
+        Bytes::from_static(b"RETAIN"),
         mqtt_core::qos::QosLevel::AtMostOnce,
         true,
     );
@@ -1,56 +1,157 @@
+use std::time::{Duration, Instant};
+
+use bytes::{BufMut, Bytes, BytesMut};
 use futures::executor::block_on;
 use mqtt_core::{
     err::client::{self, ClientError},
     id::{IdGenType, IdGenerator},
     io::read_packet,
+    topic::TopicName,
     v3::{
         ConnectPacket, DisconnectPacket, MqttPacket, PingReqPacket, PubAckPacket, PubCompPacket,
         PubRecPacket, PubRelPacket, PublishPacket, SubscribePacket, UnsubscribePacket,
     },
+    ConnectReturnCode,
 };
 
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncWriteExt, BufReader};
+
+use crate::{
+    cache::RetainedCache,
+    interceptor::Interceptor,
+    observer::{Direction, PacketObserver, PacketRecord},
+    transport::Transport,
+};
+
+/// Default window the client will wait for a PINGRESP after sending a PINGREQ.
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct AsyncClient<T>
 where
-    T: AsyncRead + AsyncWriteExt + Unpin,
+    T: Transport,
 {
     stream: BufReader<T>,
     id_gen: IdGenerator,
+    // reusable buffer for the QoS 0 fast path.
+    write_buf: BytesMut,
+    ping_timeout: Duration,
+    // Set when a PINGREQ is sent, cleared once the matching PINGRESP is read.
+    pending_ping: Option<Instant>,
+    // the keep alive sent in the CONNECT packet, None if the client does not send PINGREQs on its own.
+    keep_alive: Option<Duration>,
+    // when the last packet was written, the broker only counts packets sent by the client towards the keep alive.
+    last_write: Instant,
+    interceptors: Vec<Box<dyn Interceptor>>,
+    cache: Option<RetainedCache>,
+    observer: Option<Box<dyn PacketObserver>>,
 }
 
 impl<T> AsyncClient<T>
 where
-    T: AsyncRead + AsyncWrite + Unpin,
+    T: Transport,
 {
     pub fn new(stream: T) -> Self {
         return Self {
             stream: BufReader::new(stream),
             id_gen: IdGenerator::new(IdGenType::Client),
+            write_buf: BytesMut::new(),
+            ping_timeout: DEFAULT_PING_TIMEOUT,
+            pending_ping: None,
+            keep_alive: None,
+            last_write: Instant::now(),
+            interceptors: vec![],
+            cache: None,
+            observer: None,
         };
     }
 
+    /// Registers a hook that is run on every packet sent and received by the client.
+    ///
+    /// Interceptors are run in the order they were added.
+    pub fn add_interceptor(&mut self, interceptor: impl Interceptor + 'static) {
+        self.interceptors.push(Box::new(interceptor));
+    }
+
+    /// Registers a hook that receives a record of every packet read from and written to the stream.
+    ///
+    /// Intended for debugging protocol issues, inbound packets are re-encoded to measure their size.
+    pub fn set_packet_observer(&mut self, observer: impl PacketObserver + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Caches the latest message received on each topic, see [AsyncClient::latest].
+    pub fn enable_retained_cache(&mut self) {
+        if self.cache.is_none() {
+            self.cache = Some(RetainedCache::new());
+        }
+    }
+
+    /// Returns the latest message received on the topic, if the retained cache is enabled.
+    pub fn latest(&self, topic: &TopicName) -> Option<&PublishPacket> {
+        return self.cache.as_ref().and_then(|cache| cache.latest(topic));
+    }
+
+    /// Replaces the stream the client reads from and writes to, dropping the old stream.
+    ///
+    /// Used to resume a client on a new connection, the caller must send a CONNECT before any other packet.
+    pub fn set_stream(&mut self, stream: T) {
+        self.stream = BufReader::new(stream);
+        self.pending_ping = None;
+        self.last_write = Instant::now();
+    }
+
+    /// Sets the window in which a PINGRESP must be received after a PINGREQ is sent.
+    ///
+    /// If the window elapses, the connection is considered dead and the stream is closed.
+    pub fn set_ping_timeout(&mut self, timeout: Duration) {
+        self.ping_timeout = timeout;
+    }
+
     pub fn next_packet_id(&mut self) -> Option<u16> {
         return self.id_gen.next_id();
     }
 
+    /// Returns the next available packet id and marks it as in use until it is released with
+    /// [AsyncClient::free_packet_id], unlike [AsyncClient::next_packet_id].
+    pub fn reserve_packet_id(&mut self) -> Option<u16> {
+        return self.id_gen.next_persistant_id();
+    }
+
+    /// Releases a packet id once the handshake it was used for has completed.
+    pub fn free_packet_id(&mut self, id: u16) {
+        self.id_gen.free_id(id);
+    }
+
+    /// Sends the CONNECT packet and waits for the CONNACK.
+    ///
+    /// A non zero keep alive in the packet turns on automatic pings, see [AsyncClient::recv_packet].
+    ///
+    /// ## Error
+    ///
+    /// Returns a ConnectError holding the CONNACK's return code if the broker refused the connection.
     pub async fn connect(&mut self, packet: ConnectPacket) -> Result<(), ClientError> {
-        self.stream.write_all(&mut packet.encode().unwrap()).await?;
+        self.keep_alive = match packet.keep_alive {
+            0 => None,
+            secs => Some(Duration::from_secs(secs.into())),
+        };
+        self.send_packet(MqttPacket::Connect(packet)).await?;
         self.stream.flush().await?;
         loop {
-            if let Some(packet) = read_packet::<_, ClientError>(&mut self.stream)
-                .await
-                .unwrap()
-            {
+            if let Some(packet) = self.recv_packet().await? {
                 match packet {
-                    MqttPacket::ConnAck(..) => return Ok(()),
+                    MqttPacket::ConnAck(connack) => match connack.return_code() {
+                        ConnectReturnCode::Accept => return Ok(()),
+                        code => {
+                            return Err(ClientError::new(
+                                client::ErrorKind::ConnectError(code),
+                                format!("Broker refused the connection: {code}."),
+                            ));
+                        }
+                    },
                     _ => {
-                        println!("packet: {:?}", packet);
                         return Err(ClientError::new(
                             client::ErrorKind::ProtocolError,
-                            String::from(
-                                "First packet received from broker was not a CONNACK packet.",
-                            ),
+                            format!("First packet received from broker was not a CONNACK packet, received {packet}."),
                         ));
                     }
                 }
@@ -58,69 +159,211 @@ where
         }
     }
 
+    /// Reads the next packet from the stream.
+    ///
+    /// Sends a PINGREQ if no packet has been written for the keep alive, so the client must be polled at least
+    /// that often to hold the connection open.
+    ///
+    /// ## Error
+    ///
+    /// Returns a Timeout error and closes the stream if a PINGRESP was not received within the ping timeout.
     pub async fn recv_packet(&mut self) -> Result<Option<MqttPacket>, ClientError> {
-        return read_packet(&mut self.stream).await;
+        if let Some(keep_alive) = self.keep_alive {
+            if self.pending_ping.is_none() && self.last_write.elapsed() >= keep_alive {
+                self.ping().await?;
+            }
+        }
+
+        let packet = read_packet::<_, ClientError>(&mut self.stream).await?;
+
+        if let (Some(observer), Some(packet)) = (self.observer.as_mut(), &packet) {
+            let size = packet.encode().map(|bytes| bytes.len()).unwrap_or(0);
+            observer.observe(PacketRecord::new(Direction::Inbound, packet, size));
+        }
+
+        let packet = packet.and_then(|packet| self.intercept_incoming(packet));
+
+        match &packet {
+            Some(MqttPacket::PingResp(_)) => {
+                self.pending_ping = None;
+            }
+            Some(MqttPacket::Publish(publish)) => {
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.insert(publish);
+                }
+                self.check_ping_timeout().await?;
+            }
+            _ => {
+                self.check_ping_timeout().await?;
+            }
+        }
+
+        return Ok(packet);
     }
 
     pub async fn ping(&mut self) -> Result<(), ClientError> {
-        self.stream
-            .write_all(&PingReqPacket::new().encode())
+        self.send_packet(MqttPacket::PingReq(PingReqPacket::new()))
             .await?;
+
+        // only track the oldest outstanding PINGREQ.
+        if self.pending_ping.is_none() {
+            self.pending_ping = Some(Instant::now());
+        }
         return Ok(());
     }
 
+    async fn check_ping_timeout(&mut self) -> Result<(), ClientError> {
+        if let Some(sent) = self.pending_ping {
+            if sent.elapsed() > self.ping_timeout {
+                self.pending_ping = None;
+                // the connection is dead, don't wait on the TCP timeout to close the socket.
+                let _ = self.stream.shutdown().await;
+                return Err(ClientError::new(
+                    client::ErrorKind::Timeout,
+                    format!(
+                        "Did not receive a PINGRESP within {} ms of sending PINGREQ.",
+                        self.ping_timeout.as_millis()
+                    ),
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Writes a packet to the stream after it has been passed through the interceptors.
+    pub async fn send_packet(&mut self, packet: MqttPacket) -> Result<(), ClientError> {
+        if let Some(packet) = self.intercept_outgoing(packet) {
+            let bytes = packet.encode()?;
+            self.observe_outgoing(&packet, bytes.len());
+            self.stream.write_all(&bytes).await?;
+            self.last_write = Instant::now();
+        }
+        return Ok(());
+    }
+
+    fn observe_outgoing(&mut self, packet: &MqttPacket, size: usize) {
+        if let Some(observer) = self.observer.as_mut() {
+            observer.observe(PacketRecord::new(Direction::Outbound, packet, size));
+        }
+    }
+
+    fn intercept_outgoing(&mut self, mut packet: MqttPacket) -> Option<MqttPacket> {
+        for interceptor in self.interceptors.iter_mut() {
+            packet = interceptor.outgoing(packet)?;
+        }
+        return Some(packet);
+    }
+
+    fn intercept_incoming(&mut self, mut packet: MqttPacket) -> Option<MqttPacket> {
+        for interceptor in self.interceptors.iter_mut() {
+            packet = interceptor.incoming(packet)?;
+        }
+        return Some(packet);
+    }
+
     pub async fn publish(&mut self, packet: PublishPacket) -> Result<(), ClientError> {
-        self.stream.write_all(&packet.encode()?).await?;
+        return self.send_packet(MqttPacket::Publish(packet)).await;
+    }
+
+    /// Publishes a QoS 0 message from a borrowed topic and payload.
+    ///
+    /// The packet is encoded directly into a buffer owned by the client, so no per packet allocations are made once the buffer has grown.
+    pub async fn publish_qos0(
+        &mut self,
+        topic: &TopicName,
+        payload: &[u8],
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        if self.interceptors.len() > 0 || self.observer.is_some() {
+            // interceptors and observers operate on packets, so the fast path cannot be used.
+            let mut packet = PublishPacket::new(topic, Bytes::copy_from_slice(payload));
+            packet.set_retain(retain);
+            return self.publish(packet).await;
+        }
+
+        self.write_buf.clear();
+        PublishPacket::encode_qos0_into(&mut self.write_buf, topic, payload, retain)?;
+        self.stream.write_all(&self.write_buf).await?;
+        self.last_write = Instant::now();
         return Ok(());
     }
 
+    /// Encodes every packet into a single buffer and writes it to the stream with one write and flush.
+    ///
+    /// ## Returns
+    ///
+    /// The packet Ids of the QoS 1 and QoS 2 packets in the batch, in the order they were written,
+    /// so the caller can track their acknowledgements. Packets dropped by an interceptor were not written, so their
+    /// Ids are not returned. See [crate::managed::ManagedClient::publish_batch] to have the acknowledgements awaited.
+    pub async fn publish_batch(
+        &mut self,
+        packets: Vec<PublishPacket>,
+    ) -> Result<Vec<u16>, ClientError> {
+        let mut buf = BytesMut::new();
+        let mut ids = vec![];
+
+        for packet in packets.into_iter() {
+            if let Some(packet) = self.intercept_outgoing(MqttPacket::Publish(packet)) {
+                if let MqttPacket::Publish(publish) = &packet {
+                    if let Some(id) = publish.id() {
+                        ids.push(id);
+                    }
+                }
+                let bytes = packet.encode()?;
+                self.observe_outgoing(&packet, bytes.len());
+                buf.put_slice(&bytes);
+            }
+        }
+
+        self.stream.write_all(&buf).await?;
+        self.stream.flush().await?;
+        self.last_write = Instant::now();
+        return Ok(ids);
+    }
+
     pub async fn ack(&mut self, packet_id: u16) -> Result<(), ClientError> {
-        self.stream
-            .write_all(&PubAckPacket::new(packet_id).encode())
-            .await?;
-        return Ok(());
+        return self
+            .send_packet(MqttPacket::PubAck(PubAckPacket::new(packet_id)))
+            .await;
     }
 
     pub async fn rec(&mut self, packet_id: u16) -> Result<(), ClientError> {
-        self.stream
-            .write_all(&PubRecPacket::new(packet_id).encode())
-            .await?;
-        return Ok(());
+        return self
+            .send_packet(MqttPacket::PubRec(PubRecPacket::new(packet_id)))
+            .await;
     }
 
     pub async fn rel(&mut self, packet_id: u16) -> Result<(), ClientError> {
-        self.stream
-            .write_all(&PubRelPacket::new(packet_id).encode())
-            .await?;
-        return Ok(());
+        return self
+            .send_packet(MqttPacket::PubRel(PubRelPacket::new(packet_id)))
+            .await;
     }
 
     pub async fn comp(&mut self, packet_id: u16) -> Result<(), ClientError> {
-        self.stream
-            .write_all(&PubCompPacket::new(packet_id).encode())
-            .await?;
-        return Ok(());
+        return self
+            .send_packet(MqttPacket::PubComp(PubCompPacket::new(packet_id)))
+            .await;
     }
 
     pub async fn sub(&mut self, packet: SubscribePacket) -> Result<(), ClientError> {
-        self.stream.write_all(&packet.encode()?).await?;
-        return Ok(());
+        return self.send_packet(MqttPacket::Subscribe(packet)).await;
     }
 
     pub async fn unsub(&mut self, packet: UnsubscribePacket) -> Result<(), ClientError> {
-        self.stream.write_all(&packet.encode()?).await?;
-        return Ok(());
+        return self.send_packet(MqttPacket::Unsubscribe(packet)).await;
     }
 
     pub async fn disconnect(&mut self) -> Result<(), ClientError> {
         let disconnect_packet = DisconnectPacket::new();
-        self.stream.write_all(&disconnect_packet.encode()).await?;
-        return Ok(());
+        return self
+            .send_packet(MqttPacket::Disconnect(disconnect_packet))
+            .await;
     }
 }
 
-impl<T: AsyncRead + AsyncWrite + Unpin> Drop for AsyncClient<T> {
+impl<T: Transport> Drop for AsyncClient<T> {
     fn drop(&mut self) {
-        block_on(self.disconnect()).unwrap();
+        // the connection may already be gone, there is nothing left to do if the DISCONNECT cannot be sent.
+        let _ = block_on(self.disconnect());
     }
 }
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use mqtt_core::{topic::TopicName, v3::PublishPacket};
+
+/// Stores the latest message received on each topic, mirroring the broker's retained message semantics.
+///
+/// A message with an empty payload clears the cached message for its topic.
+#[derive(Debug, Clone)]
+pub struct RetainedCache {
+    messages: HashMap<TopicName, PublishPacket>,
+}
+
+impl RetainedCache {
+    pub fn new() -> Self {
+        return Self {
+            messages: HashMap::new(),
+        };
+    }
+
+    pub fn insert(&mut self, packet: &PublishPacket) {
+        if packet.payload().len() == 0 {
+            self.messages.remove(packet.topic());
+        } else {
+            self.messages.insert(packet.topic().clone(), packet.clone());
+        }
+    }
+
+    /// Returns the latest message received on the topic.
+    pub fn latest(&self, topic: &TopicName) -> Option<&PublishPacket> {
+        return self.messages.get(topic);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.messages.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod cache {
+    use bytes::Bytes;
+    use mqtt_core::{topic::TopicName, v3::PublishPacket};
+
+    use super::RetainedCache;
+
+    #[test]
+    fn latest_and_clear() {
+        let mut cache = RetainedCache::new();
+        let topic = TopicName::from_str("sensors/temp").unwrap();
+
+        cache.insert(&PublishPacket::new(&topic, Bytes::from_static(b"20")));
+        cache.insert(&PublishPacket::new(&topic, Bytes::from_static(b"21")));
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.latest(&topic).unwrap().payload(), "21");
+
+        // zero length payloads clear the cached message.
+        cache.insert(&PublishPacket::new(&topic, Bytes::new()));
+        assert!(cache.latest(&topic).is_none());
+    }
+}
@@ -0,0 +1,35 @@
+use mqtt_core::v3::MqttPacket;
+
+/// Hooks that observe or mutate the packets flowing through a client.
+///
+/// Useful for custom logging, injecting data into outgoing packets, or injecting faults in tests.
+/// Returning None from a hook drops the packet, and it is not passed to any interceptors registered after this one.
+///
+/// ## Examples
+///
+/// ```
+/// use mqtt_client::interceptor::Interceptor;
+/// use mqtt_core::v3::MqttPacket;
+///
+/// struct DropPingResp;
+///
+/// impl Interceptor for DropPingResp {
+///     fn incoming(&mut self, packet: MqttPacket) -> Option<MqttPacket> {
+///         match packet {
+///             MqttPacket::PingResp(_) => None,
+///             _ => Some(packet),
+///         }
+///     }
+/// }
+/// ```
+pub trait Interceptor: Send {
+    /// Called before a packet is written to the stream.
+    fn outgoing(&mut self, packet: MqttPacket) -> Option<MqttPacket> {
+        return Some(packet);
+    }
+
+    /// Called after a packet is read from the stream, before it is handled by the client.
+    fn incoming(&mut self, packet: MqttPacket) -> Option<MqttPacket> {
+        return Some(packet);
+    }
+}
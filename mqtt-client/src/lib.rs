@@ -3,3 +3,11 @@ pub mod err {
 }
 
 pub mod r#async;
+pub mod cache;
+pub mod interceptor;
+pub mod managed;
+pub mod observer;
+pub mod reconnect;
+pub mod tls;
+pub mod transport;
+pub mod websocket;
@@ -0,0 +1,620 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use mqtt_core::{
+    err::client::{self, ClientError},
+    msg_assurance::{AtLeastOnceList, ExactlyOnceList, QoS1Stage, QoS2Stage, RetryDuration},
+    qos::QosLevel,
+    topic::{TopicFilter, TopicName},
+    v3::{
+        ConnectPacket, FilterResult, MqttPacket, PublishPacket, SubscribePacket, UnsubscribePacket,
+    },
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    r#async::AsyncClient,
+    reconnect::{ConnectionEvent, Connector, ReconnectPolicy},
+    transport::Transport,
+};
+
+type AtLeastOnce = AtLeastOnceList<Arc<PublishPacket>, Instant, RetryDuration>;
+type ExactlyOnce = ExactlyOnceList<Arc<PublishPacket>, Instant, RetryDuration>;
+
+/// Wraps an [AsyncClient] and drives the QoS 1 and QoS 2 handshakes on behalf of the caller.
+///
+/// Acknowledgements are sent as packets are read, and unacknowledged messages are re-sent with an exponential backoff.
+/// The connection is only read while one of the client's futures is awaited, messages received while waiting on a
+/// handshake are routed to the matching [Subscription] streams, or queued and returned by [ManagedClient::recv].
+pub struct ManagedClient<T>
+where
+    T: Transport,
+{
+    client: AsyncClient<T>,
+    // QoS 1 messages published by this client, waiting on a PUBACK.
+    outgoing_qos1: AtLeastOnce,
+    // QoS 2 messages published by this client, waiting on a PUBREC or PUBCOMP.
+    outgoing_qos2: ExactlyOnce,
+    // QoS 2 messages received from the broker, waiting on a PUBREL. Kept apart from outgoing_qos2 as the broker
+    // assigns these packet ids.
+    incoming_qos2: ExactlyOnce,
+    // streams returned by ManagedClient::subscribe, closed streams are removed as messages are routed.
+    routes: Vec<(TopicFilter, mpsc::UnboundedSender<PublishPacket>)>,
+    // messages that did not match a route.
+    received: VecDeque<PublishPacket>,
+    // replayed with clean_session unset when the client reconnects.
+    connect_packet: Option<ConnectPacket>,
+    // filters subscribed through the managed client, restored when the client reconnects.
+    subscriptions: Vec<(TopicFilter, QosLevel)>,
+    reconnect: Option<(Box<dyn Connector<T>>, ReconnectPolicy)>,
+    events: Option<mpsc::UnboundedSender<ConnectionEvent>>,
+}
+
+impl<T> ManagedClient<T>
+where
+    T: Transport,
+{
+    pub fn new(stream: T) -> Self {
+        return Self::from(AsyncClient::new(stream));
+    }
+
+    /// The wrapped client, for configuring the client and sending packets the managed client does not cover.
+    ///
+    /// Packets read directly from the wrapped client are not handled by the managed client.
+    pub fn client_mut(&mut self) -> &mut AsyncClient<T> {
+        return &mut self.client;
+    }
+
+    /// Re-dials the broker when the connection is lost, waiting between attempts as set by the policy.
+    ///
+    /// Once reconnected the CONNECT packet is replayed with clean_session unset, subscriptions made with
+    /// [ManagedClient::sub] are restored and inflight messages are re-sent. The future that was awaited when the
+    /// connection was lost continues on the new connection, and only fails once the policy's max attempts are used.
+    pub fn set_reconnect(
+        &mut self,
+        connector: impl Connector<T> + 'static,
+        policy: ReconnectPolicy,
+    ) {
+        self.reconnect = Some((Box::new(connector), policy));
+    }
+
+    /// Returns a channel that receives a [ConnectionEvent] whenever the client loses or restores its connection.
+    ///
+    /// Only the most recently returned receiver is sent events.
+    pub fn connection_events(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.events = Some(sender);
+        return receiver;
+    }
+
+    pub async fn connect(&mut self, packet: ConnectPacket) -> Result<(), ClientError> {
+        self.connect_packet = Some(packet.clone());
+        return self.client.connect(packet).await;
+    }
+
+    /// Subscribes to the packet's topic filters, the filters are restored if the client reconnects.
+    pub async fn sub(&mut self, packet: SubscribePacket) -> Result<(), ClientError> {
+        for filter in packet.topic_filters() {
+            if let FilterResult::Ok { filter, qos } = filter {
+                match self.subscriptions.iter_mut().find(|(f, _)| *f == filter) {
+                    Some((_, prev_qos)) => *prev_qos = qos,
+                    None => self.subscriptions.push((filter, qos)),
+                }
+            }
+        }
+        return self.client.sub(packet).await;
+    }
+
+    /// Subscribes to the filter and returns a stream of the messages received on topics matching it.
+    ///
+    /// The stream is fed while the client is driven, i.e. by [ManagedClient::run] in the task that owns the client.
+    /// Messages matched by a stream are not returned by [ManagedClient::recv].
+    pub async fn subscribe(
+        &mut self,
+        filter: TopicFilter,
+        qos: QosLevel,
+    ) -> Result<Subscription, ClientError> {
+        let id = self.next_packet_id()?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.routes.push((filter.clone(), sender));
+
+        self.sub(SubscribePacket::new(id, vec![(filter.clone(), qos)]))
+            .await?;
+        return Ok(Subscription { filter, receiver });
+    }
+
+    /// Unsubscribes from the filter and ends the streams returned for it by [ManagedClient::subscribe].
+    pub async fn unsubscribe(&mut self, filter: TopicFilter) -> Result<(), ClientError> {
+        let id = self.next_packet_id()?;
+        self.routes.retain(|(f, _)| *f != filter);
+        return self.unsub(UnsubscribePacket::new(id, vec![filter])).await;
+    }
+
+    pub async fn unsub(&mut self, packet: UnsubscribePacket) -> Result<(), ClientError> {
+        self.subscriptions
+            .retain(|(filter, _)| !packet.filters().contains(filter));
+        return self.client.unsub(packet).await;
+    }
+
+    pub async fn publish_qos0(
+        &mut self,
+        topic: &TopicName,
+        payload: &[u8],
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        return self.client.publish_qos0(topic, payload, retain).await;
+    }
+
+    /// Publishes a QoS 1 message, resolving once the broker has acknowledged it with a PUBACK.
+    pub async fn publish_qos1(
+        &mut self,
+        topic: &TopicName,
+        payload: Bytes,
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        let id = self.next_packet_id()?;
+        let mut packet = PublishPacket::new(topic, payload);
+        packet.set_retain(retain);
+        packet.set_qos_atleastonce(id);
+
+        let packet = self.outgoing_qos1.origin(Arc::new(packet), id);
+        self.client.publish(packet).await?;
+
+        while self
+            .outgoing_qos1
+            .iter()
+            .any(|packet| packet.id() == id && packet.stage() == QoS1Stage::Origin)
+        {
+            self.poll().await?;
+        }
+
+        for id in self.outgoing_qos1.clean() {
+            self.client.free_packet_id(id);
+        }
+        return Ok(());
+    }
+
+    /// Publishes a QoS 2 message, resolving once the broker has completed the handshake with a PUBCOMP.
+    pub async fn publish_qos2(
+        &mut self,
+        topic: &TopicName,
+        payload: Bytes,
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        let id = self.next_packet_id()?;
+        let mut packet = PublishPacket::new(topic, payload);
+        packet.set_retain(retain);
+        packet.set_qos_exactlyonce(id);
+
+        let packet = self.outgoing_qos2.origin(Arc::new(packet), id);
+        self.client.publish(packet).await?;
+
+        while self.outgoing_qos2.iter().any(|packet| {
+            packet.id() == id && matches!(packet.stage(), QoS2Stage::Origin | QoS2Stage::Rec)
+        }) {
+            self.poll().await?;
+        }
+
+        for id in self.outgoing_qos2.clean() {
+            self.client.free_packet_id(id);
+        }
+        return Ok(());
+    }
+
+    /// Publishes the messages with a single write, resolving once every QoS 1 and QoS 2 message in the batch has
+    /// completed its handshake.
+    ///
+    /// Each message is sent at its packet's QoS, packet ids are assigned by the client. Messages dropped by an
+    /// interceptor are not awaited.
+    pub async fn publish_batch(&mut self, packets: Vec<PublishPacket>) -> Result<(), ClientError> {
+        let mut batch = Vec::with_capacity(packets.len());
+        let mut pending = vec![];
+        for mut packet in packets.into_iter() {
+            let qos = packet.qos();
+            if qos != QosLevel::AtMostOnce {
+                let id = match self.next_packet_id() {
+                    Ok(id) => id,
+                    Err(err) => {
+                        for (id, _, _) in pending {
+                            self.client.free_packet_id(id);
+                        }
+                        return Err(err);
+                    }
+                };
+                match qos {
+                    QosLevel::ExactlyOnce => packet.set_qos_exactlyonce(id),
+                    _ => packet.set_qos_atleastonce(id),
+                }
+                pending.push((id, qos, Arc::new(packet.clone())));
+            }
+            batch.push(packet);
+        }
+
+        let written = match self.client.publish_batch(batch).await {
+            Ok(written) => written,
+            Err(err) => {
+                for (id, _, _) in pending {
+                    self.client.free_packet_id(id);
+                }
+                return Err(err);
+            }
+        };
+
+        // only the written messages are tracked, so dropped messages are never re-sent.
+        let mut awaited = vec![];
+        for (id, qos, packet) in pending {
+            if !written.contains(&id) {
+                self.client.free_packet_id(id);
+                continue;
+            }
+            match qos {
+                QosLevel::ExactlyOnce => {
+                    self.outgoing_qos2.origin(packet, id);
+                }
+                _ => {
+                    self.outgoing_qos1.origin(packet, id);
+                }
+            }
+            awaited.push(id);
+        }
+
+        while self
+            .outgoing_qos1
+            .iter()
+            .any(|packet| awaited.contains(&packet.id()) && packet.stage() == QoS1Stage::Origin)
+            || self.outgoing_qos2.iter().any(|packet| {
+                awaited.contains(&packet.id())
+                    && matches!(packet.stage(), QoS2Stage::Origin | QoS2Stage::Rec)
+            })
+        {
+            self.poll().await?;
+        }
+
+        for id in self.outgoing_qos1.clean() {
+            self.client.free_packet_id(id);
+        }
+        for id in self.outgoing_qos2.clean() {
+            self.client.free_packet_id(id);
+        }
+        return Ok(());
+    }
+
+    /// Waits for the next message from the broker.
+    ///
+    /// QoS 2 messages are returned once the broker has released them with a PUBREL, so each message is returned once.
+    pub async fn recv(&mut self) -> Result<PublishPacket, ClientError> {
+        loop {
+            if let Some(packet) = self.received.pop_front() {
+                return Ok(packet);
+            }
+            self.poll().await?;
+        }
+    }
+
+    /// Drives the connection until it fails, feeding the [Subscription] streams.
+    ///
+    /// Messages that do not match a subscription are queued for [ManagedClient::recv].
+    pub async fn run(&mut self) -> Result<(), ClientError> {
+        loop {
+            self.poll().await?;
+        }
+    }
+
+    /// The number of QoS 1 and QoS 2 messages published by this client that have not completed their handshake.
+    pub fn inflight(&self) -> usize {
+        return self.outgoing_qos1.len() + self.outgoing_qos2.len();
+    }
+
+    /// Reserves a packet id, every handshake started by the managed client frees its id once it completes.
+    fn next_packet_id(&mut self) -> Result<u16, ClientError> {
+        match self.client.reserve_packet_id() {
+            Some(id) => return Ok(id),
+            None => {
+                return Err(ClientError::new(
+                    client::ErrorKind::ProtocolError,
+                    String::from("Every packet id is in use by an inflight message."),
+                ));
+            }
+        }
+    }
+
+    /// Re-sends any messages that are due, then reads and handles at most one packet.
+    ///
+    /// Reconnects if the connection is lost and a reconnect policy is set.
+    async fn poll(&mut self) -> Result<(), ClientError> {
+        match self.poll_stream().await {
+            Ok(()) => return Ok(()),
+            Err(err) => match err.kind() {
+                client::ErrorKind::IoError(_) | client::ErrorKind::Timeout
+                    if self.reconnect.is_some() =>
+                {
+                    return self.reconnect(err).await;
+                }
+                _ => return Err(err),
+            },
+        }
+    }
+
+    async fn poll_stream(&mut self) -> Result<(), ClientError> {
+        self.retry_packets().await?;
+
+        let packet = match self.client.recv_packet().await? {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+
+        match packet {
+            MqttPacket::Publish(packet) => match (packet.qos(), packet.id()) {
+                (QosLevel::AtLeastOnce, Some(id)) => {
+                    self.client.ack(id).await?;
+                    self.route(packet);
+                }
+                (QosLevel::ExactlyOnce, Some(id)) => {
+                    if !self.incoming_qos2.iter().any(|packet| packet.id() == id) {
+                        self.incoming_qos2.publish(packet, id);
+                    }
+                    // a duplicate PUBLISH means the PUBREC was lost, so the PUBREC is sent either way.
+                    self.client.rec(id).await?;
+                }
+                _ => self.route(packet),
+            },
+            MqttPacket::PubRel(packet) => {
+                if let Some(publish) = self.incoming_qos2.relay(packet.id()) {
+                    self.route((*publish).clone());
+                }
+                self.incoming_qos2.clean();
+                self.client.comp(packet.id()).await?;
+            }
+            MqttPacket::PubAck(packet) => {
+                self.outgoing_qos1.acknowledge(packet.id());
+            }
+            MqttPacket::PubRec(packet) => {
+                if let Some(pubrel) = self.outgoing_qos2.receive(packet.id()) {
+                    self.client.send_packet(MqttPacket::PubRel(pubrel)).await?;
+                }
+            }
+            MqttPacket::PubComp(packet) => {
+                self.outgoing_qos2.complete(packet.id());
+            }
+            MqttPacket::SubAck(packet) => {
+                self.client.free_packet_id(packet.id());
+            }
+            MqttPacket::UnsubAck(packet) => {
+                self.client.free_packet_id(packet.id());
+            }
+            _ => {}
+        }
+
+        return Ok(());
+    }
+
+    /// Sends the message to every stream subscribed to a matching filter, or queues it for [ManagedClient::recv].
+    fn route(&mut self, packet: PublishPacket) {
+        self.routes.retain(|(_, sender)| !sender.is_closed());
+
+        let mut routed = false;
+        for (filter, sender) in self.routes.iter() {
+            if packet.topic() == filter {
+                let _ = sender.send(packet.clone());
+                routed = true;
+            }
+        }
+
+        if !routed {
+            self.received.push_back(packet);
+        }
+    }
+
+    async fn reconnect(&mut self, err: ClientError) -> Result<(), ClientError> {
+        self.send_event(ConnectionEvent::Disconnected(err.to_string()));
+
+        let mut packet = match self.connect_packet.clone() {
+            Some(packet) => packet,
+            // the client was never connected, so there is no session to resume.
+            None => return Err(err),
+        };
+        packet.set_clean_session(false);
+
+        loop {
+            let (connector, policy) = self
+                .reconnect
+                .as_mut()
+                .expect("Reconnect policy is not set");
+            policy.wait().await?;
+            let attempt = policy.attempts();
+            let stream = connector.connect();
+
+            self.send_event(ConnectionEvent::Reconnecting(attempt));
+
+            let stream = match stream.await {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            self.client.set_stream(stream);
+            if self.resume(packet.clone()).await.is_ok() {
+                break;
+            }
+        }
+
+        if let Some((_, policy)) = self.reconnect.as_mut() {
+            policy.reset();
+        }
+        self.send_event(ConnectionEvent::Reconnected);
+        return Ok(());
+    }
+
+    /// Sends the CONNECT, restores the subscriptions and re-sends every inflight message on a new connection.
+    async fn resume(&mut self, packet: ConnectPacket) -> Result<(), ClientError> {
+        self.client.connect(packet).await?;
+
+        if self.subscriptions.len() > 0 {
+            let id = self.next_packet_id()?;
+            let packet = SubscribePacket::new(id, self.subscriptions.clone());
+            self.client.sub(packet).await?;
+        }
+
+        let mut retry = vec![];
+        for packet in self.outgoing_qos1.iter() {
+            if let Some(retry_packet) = packet.get_retry_packet() {
+                retry.push(retry_packet);
+            }
+        }
+        for packet in self.outgoing_qos2.iter() {
+            if let Some(retry_packet) = packet.get_retry_packet() {
+                retry.push(retry_packet);
+            }
+        }
+
+        for packet in retry {
+            self.client.send_packet(packet).await?;
+        }
+        return Ok(());
+    }
+
+    fn send_event(&self, event: ConnectionEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    async fn retry_packets(&mut self) -> Result<(), ClientError> {
+        let mut retry = vec![];
+
+        for packet in self.outgoing_qos1.iter_mut() {
+            if packet.should_retry() {
+                if let Some(retry_packet) = packet.get_retry_packet() {
+                    retry.push(retry_packet);
+                    packet.update_retry_duration();
+                }
+            }
+        }
+
+        for packet in self.outgoing_qos2.iter_mut() {
+            if packet.should_retry() {
+                if let Some(retry_packet) = packet.get_retry_packet() {
+                    retry.push(retry_packet);
+                    packet.update_retry_duration();
+                }
+            }
+        }
+
+        for packet in retry {
+            self.client.send_packet(packet).await?;
+        }
+        return Ok(());
+    }
+}
+
+impl<T> From<AsyncClient<T>> for ManagedClient<T>
+where
+    T: Transport,
+{
+    fn from(client: AsyncClient<T>) -> Self {
+        return Self {
+            client,
+            outgoing_qos1: AtLeastOnceList::new(),
+            outgoing_qos2: ExactlyOnceList::new(),
+            incoming_qos2: ExactlyOnceList::new(),
+            routes: vec![],
+            received: VecDeque::new(),
+            connect_packet: None,
+            subscriptions: vec![],
+            reconnect: None,
+            events: None,
+        };
+    }
+}
+
+/// A stream of the messages received on topics matching a filter, see [ManagedClient::subscribe].
+///
+/// The stream ends once the filter is unsubscribed or the client is dropped.
+pub struct Subscription {
+    filter: TopicFilter,
+    receiver: mpsc::UnboundedReceiver<PublishPacket>,
+}
+
+impl Subscription {
+    pub fn filter(&self) -> &TopicFilter {
+        return &self.filter;
+    }
+}
+
+impl Stream for Subscription {
+    type Item = PublishPacket;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PublishPacket>> {
+        return self.receiver.poll_recv(cx);
+    }
+}
+
+#[cfg(test)]
+mod packet_ids {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use mqtt_core::{
+        io::read_packet,
+        qos::SubAckQoS,
+        v3::{SubAckPacket, UnsubAckPacket},
+    };
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    use super::*;
+    use crate::observer::{Direction, PacketRecord};
+
+    #[tokio::test]
+    async fn unsubscribe_frees_packet_ids() {
+        let (client_end, mut broker_end) = duplex(1024);
+
+        // acknowledges every SUBSCRIBE and UNSUBSCRIBE, standing in for the broker.
+        tokio::spawn(async move {
+            loop {
+                let ack = match read_packet::<_, ClientError>(&mut broker_end).await {
+                    Ok(Some(MqttPacket::Subscribe(packet))) => MqttPacket::SubAck(
+                        SubAckPacket::new(packet.id(), vec![SubAckQoS::QOS(QosLevel::AtMostOnce)]),
+                    ),
+                    Ok(Some(MqttPacket::Unsubscribe(packet))) => {
+                        MqttPacket::UnsubAck(UnsubAckPacket::new(packet.id()))
+                    }
+                    Ok(_) => continue,
+                    Err(_) => return,
+                };
+                if broker_end.write_all(&ack.encode().unwrap()).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let acks = Arc::new(AtomicUsize::new(0));
+        let mut client = ManagedClient::new(client_end);
+        let counter = acks.clone();
+        client
+            .client_mut()
+            .set_packet_observer(move |record: PacketRecord| {
+                if matches!(record.direction(), Direction::Inbound) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        let filter = TopicFilter::from_str("ids").unwrap();
+
+        // more cycles than there are client packet ids, so a leaked id would exhaust them.
+        for cycle in 1..=(u16::MAX as usize / 2 + 1) {
+            client
+                .subscribe(filter.clone(), QosLevel::AtMostOnce)
+                .await
+                .unwrap();
+            client.unsubscribe(filter.clone()).await.unwrap();
+
+            while acks.load(Ordering::SeqCst) < cycle * 2 {
+                client.poll().await.unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+use mqtt_core::v3::{MqttPacket, PacketType};
+use tokio::sync::mpsc;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Inbound => write!(f, "<-"),
+            Direction::Outbound => write!(f, "->"),
+        }
+    }
+}
+
+/// A summary of a single packet read from or written to the stream.
+#[derive(Clone, Debug)]
+pub struct PacketRecord {
+    direction: Direction,
+    packet_type: PacketType,
+    packet_id: Option<u16>,
+    // length of the packet on the wire, including the fixed header.
+    size: usize,
+    summary: String,
+}
+
+impl PacketRecord {
+    pub fn new(direction: Direction, packet: &MqttPacket, size: usize) -> Self {
+        return Self {
+            direction,
+            packet_type: packet.packet_type(),
+            packet_id: packet.packet_id(),
+            size,
+            summary: packet.to_string(),
+        };
+    }
+
+    pub fn direction(&self) -> Direction {
+        return self.direction;
+    }
+
+    pub fn packet_type(&self) -> PacketType {
+        return self.packet_type;
+    }
+
+    pub fn packet_id(&self) -> Option<u16> {
+        return self.packet_id;
+    }
+
+    pub fn size(&self) -> usize {
+        return self.size;
+    }
+
+    pub fn summary(&self) -> &str {
+        return &self.summary;
+    }
+}
+
+impl Display for PacketRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} ({} bytes)",
+            self.direction, self.summary, self.size
+        )
+    }
+}
+
+/// Receives a record of every packet sent and received by a client, see [crate::r#async::AsyncClient::set_packet_observer].
+///
+/// Observers see the packets as they are on the wire, inbound packets before they are passed to the interceptors and
+/// outbound packets after.
+pub trait PacketObserver: Send {
+    fn observe(&mut self, record: PacketRecord);
+}
+
+impl<F: FnMut(PacketRecord) + Send> PacketObserver for F {
+    fn observe(&mut self, record: PacketRecord) {
+        self(record);
+    }
+}
+
+/// Forwards packet records to a channel, records are dropped once the receiver is closed.
+pub struct ChannelObserver {
+    sender: mpsc::UnboundedSender<PacketRecord>,
+}
+
+impl ChannelObserver {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<PacketRecord>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        return (Self { sender }, receiver);
+    }
+}
+
+impl PacketObserver for ChannelObserver {
+    fn observe(&mut self, record: PacketRecord) {
+        let _ = self.sender.send(record);
+    }
+}
+
+#[cfg(test)]
+mod observer {
+    use bytes::Bytes;
+    use mqtt_core::{
+        topic::TopicName,
+        v3::{MqttPacket, PacketType, PublishPacket},
+    };
+
+    use super::{ChannelObserver, Direction, PacketObserver, PacketRecord};
+
+    #[test]
+    fn channel_records() {
+        let (mut observer, mut receiver) = ChannelObserver::new();
+
+        let mut packet = PublishPacket::new(
+            &TopicName::from_str("sensors/temp").unwrap(),
+            Bytes::from_static(b"20"),
+        );
+        packet.set_qos_atleastonce(7);
+        let packet = MqttPacket::Publish(packet);
+        let size = packet.encode().unwrap().len();
+
+        observer.observe(PacketRecord::new(Direction::Outbound, &packet, size));
+
+        let record = receiver.try_recv().unwrap();
+        assert_eq!(record.direction(), Direction::Outbound);
+        assert_eq!(record.packet_type(), PacketType::PUBLISH);
+        assert_eq!(record.packet_id(), Some(7));
+        assert_eq!(record.size(), size);
+        assert_eq!(record.summary(), packet.to_string());
+    }
+}
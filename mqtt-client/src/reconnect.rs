@@ -0,0 +1,191 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io,
+    pin::Pin,
+    time::Duration,
+};
+
+use mqtt_core::err::client::{self, ClientError};
+use tokio::time::sleep;
+
+/// Controls how long a client waits between reconnection attempts.
+///
+/// The delay starts at the initial delay and is multiplied after every failed attempt, up to the max delay.
+/// A random jitter of up to +/- the jitter fraction is applied to each delay, so a fleet of devices
+/// does not reconnect in lockstep after a broker restart.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    multiplier: f64,
+    max_delay: Duration,
+    jitter: f64,
+    max_attempts: Option<u32>,
+    attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        return Self {
+            initial_delay: Duration::from_millis(500),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(60),
+            jitter: 0.2,
+            max_attempts: None,
+            attempts: 0,
+        };
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn set_initial_delay(&mut self, delay: Duration) {
+        self.initial_delay = delay;
+    }
+
+    /// Values less than 1.0 are clamped to 1.0.
+    pub fn set_multiplier(&mut self, multiplier: f64) {
+        self.multiplier = multiplier.max(1.0);
+    }
+
+    pub fn set_max_delay(&mut self, delay: Duration) {
+        self.max_delay = delay;
+    }
+
+    /// The fraction of the delay that is randomly added or removed, clamped between 0.0 and 1.0.
+    pub fn set_jitter(&mut self, jitter: f64) {
+        self.jitter = jitter.clamp(0.0, 1.0);
+    }
+
+    /// Setting the max attempts to None will retry forever.
+    pub fn set_max_attempts(&mut self, max_attempts: Option<u32>) {
+        self.max_attempts = max_attempts;
+    }
+
+    pub fn attempts(&self) -> u32 {
+        return self.attempts;
+    }
+
+    /// Resets the attempt counter, call after a connection has been successfully established.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Returns the delay to wait before the next reconnection attempt, and counts the attempt.
+    ///
+    /// ## Error
+    ///
+    /// Returns a terminal MaxReconnectAttempts error once the max attempts have been used.
+    pub fn next_delay(&mut self) -> Result<Duration, ClientError> {
+        if let Some(max_attempts) = self.max_attempts {
+            if self.attempts >= max_attempts {
+                return Err(ClientError::new(
+                    client::ErrorKind::MaxReconnectAttempts(self.attempts),
+                    format!("Could not reconnect after {} attempts.", self.attempts),
+                ));
+            }
+        }
+
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(self.attempts as i32);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+
+        // random value in the range of -1.0..=1.0
+        let rand = (random_u64() as f64 / u64::MAX as f64) * 2.0 - 1.0;
+        let delay = delay + delay * self.jitter * rand;
+
+        self.attempts = self.attempts.saturating_add(1);
+
+        return Ok(Duration::from_secs_f64(delay.max(0.0)));
+    }
+
+    /// Sleeps for the next delay.
+    pub async fn wait(&mut self) -> Result<(), ClientError> {
+        let delay = self.next_delay()?;
+        sleep(delay).await;
+        return Ok(());
+    }
+}
+
+/// Opens a new stream to the broker, used by [crate::managed::ManagedClient::set_reconnect] to re-dial a lost connection.
+///
+/// Implemented for closures returning a future, i.e. `|| TcpStream::connect("127.0.0.1:1883")`.
+pub trait Connector<T>: Send {
+    fn connect(&mut self) -> Pin<Box<dyn Future<Output = io::Result<T>> + Send>>;
+}
+
+impl<T, F, Fut> Connector<T> for F
+where
+    F: FnMut() -> Fut + Send,
+    Fut: Future<Output = io::Result<T>> + Send + 'static,
+{
+    fn connect(&mut self) -> Pin<Box<dyn Future<Output = io::Result<T>> + Send>> {
+        return Box::pin(self());
+    }
+}
+
+/// Changes to the state of a reconnecting client's connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    /// The connection was lost, holds the error that was read from the stream.
+    Disconnected(String),
+    /// A reconnection attempt is starting, holds the attempt number starting from 1.
+    Reconnecting(u32),
+    /// The connection was re-established, subscriptions have been restored and inflight messages re-sent.
+    Reconnected,
+}
+
+// Each RandomState is seeded with different keys, which is enough randomness for jitter without pulling in a dependency.
+fn random_u64() -> u64 {
+    return RandomState::new().build_hasher().finish();
+}
+
+#[cfg(test)]
+mod policy {
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    #[test]
+    fn exponential_without_jitter() {
+        let mut policy = ReconnectPolicy::new();
+        policy.set_initial_delay(Duration::from_millis(100));
+        policy.set_max_delay(Duration::from_millis(400));
+        policy.set_jitter(0.0);
+
+        assert_eq!(policy.next_delay().unwrap(), Duration::from_millis(100));
+        assert_eq!(policy.next_delay().unwrap(), Duration::from_millis(200));
+        assert_eq!(policy.next_delay().unwrap(), Duration::from_millis(400));
+        assert_eq!(policy.next_delay().unwrap(), Duration::from_millis(400));
+
+        policy.reset();
+        assert_eq!(policy.next_delay().unwrap(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let mut policy = ReconnectPolicy::new();
+        policy.set_initial_delay(Duration::from_millis(1000));
+        policy.set_multiplier(1.0);
+        policy.set_jitter(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.next_delay().unwrap();
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_millis(1500));
+        }
+    }
+
+    #[test]
+    fn max_attempts() {
+        let mut policy = ReconnectPolicy::new();
+        policy.set_max_attempts(Some(2));
+
+        assert!(policy.next_delay().is_ok());
+        assert!(policy.next_delay().is_ok());
+        assert!(policy.next_delay().is_err());
+    }
+}
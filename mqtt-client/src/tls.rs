@@ -0,0 +1,258 @@
+use std::{path::Path, sync::Arc};
+
+use mqtt_core::err::client::{self, ClientError};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    },
+    TlsConnector,
+};
+
+/// Options used to build the rustls configuration for a client connection.
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    root_certs: rustls::RootCertStore,
+    // overrides the host name sent with SNI and used for certificate validation.
+    server_name: Option<String>,
+    alpn_protocols: Vec<Vec<u8>>,
+    accept_invalid_certs: bool,
+}
+
+impl TlsOptions {
+    pub fn new() -> Self {
+        return Self {
+            root_certs: rustls::RootCertStore::empty(),
+            server_name: None,
+            alpn_protocols: vec![],
+            accept_invalid_certs: false,
+        };
+    }
+
+    /// Adds all PEM encoded certificates in the file as trusted root certificates.
+    pub fn add_ca_file(&mut self, path: impl AsRef<Path>) -> Result<(), ClientError> {
+        let path = path.as_ref();
+        let certs = CertificateDer::pem_file_iter(path).map_err(|err| {
+            ClientError::new(
+                client::ErrorKind::TlsError,
+                format!("Could not read CA file {}: {err}", path.display()),
+            )
+        })?;
+
+        for cert in certs {
+            let cert = cert.map_err(|err| {
+                ClientError::new(
+                    client::ErrorKind::TlsError,
+                    format!("Invalid certificate in {}: {err}", path.display()),
+                )
+            })?;
+
+            self.root_certs.add(cert).map_err(|err| {
+                ClientError::new(
+                    client::ErrorKind::TlsError,
+                    format!("Could not add certificate from {}: {err}", path.display()),
+                )
+            })?;
+        }
+
+        return Ok(());
+    }
+
+    /// Overrides the SNI server name, useful for brokers behind a shared load balancer.
+    pub fn set_server_name(&mut self, server_name: &str) {
+        self.server_name = Some(server_name.to_string());
+    }
+
+    pub fn set_alpn_protocols(&mut self, protocols: Vec<Vec<u8>>) {
+        self.alpn_protocols = protocols;
+    }
+
+    /// Disables certificate validation.
+    ///
+    /// This is only intended for lab environments, the connection is open to man in the middle attacks.
+    pub fn dangerous_accept_invalid_certs(&mut self, val: bool) {
+        self.accept_invalid_certs = val;
+    }
+
+    pub fn connector(&self) -> TlsConnector {
+        let mut config = if self.accept_invalid_certs {
+            rustls::ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoCertificateVerification))
+                .with_no_client_auth()
+        } else {
+            rustls::ClientConfig::builder()
+                .with_root_certificates(self.root_certs.clone())
+                .with_no_client_auth()
+        };
+
+        config.alpn_protocols = self.alpn_protocols.clone();
+
+        return TlsConnector::from(Arc::new(config));
+    }
+
+    /// Performs the TLS handshake over the stream.
+    ///
+    /// The host is used as the server name unless it was overridden with set_server_name.
+    pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        host: &str,
+        stream: S,
+    ) -> Result<TlsStream<S>, ClientError> {
+        let server_name = self.server_name.as_deref().unwrap_or(host).to_string();
+        let server_name = ServerName::try_from(server_name)
+            .map_err(|err| ClientError::new(client::ErrorKind::TlsError, format!("{err}")))?;
+
+        let stream = self.connector().connect(server_name, stream).await?;
+        return Ok(stream);
+    }
+}
+
+#[derive(Debug)]
+struct NoCertificateVerification;
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        return Ok(ServerCertVerified::assertion());
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        return Ok(HandshakeSignatureValid::assertion());
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        return Ok(HandshakeSignatureValid::assertion());
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        return vec![
+            SignatureScheme::RSA_PKCS1_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ECDSA_NISTP521_SHA512,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::ED25519,
+        ];
+    }
+}
+
+#[cfg(test)]
+mod options {
+    use std::{env, fs, path::PathBuf, process};
+
+    use mqtt_core::err::client;
+    use tokio::io::duplex;
+    use tokio_rustls::{rustls::server::Acceptor, LazyConfigAcceptor};
+
+    use super::TlsOptions;
+
+    /// Writes the contents to a file in the temporary directory, unique to the test.
+    fn ca_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("mqtt-tls-{name}-{}.pem", process::id()));
+        fs::write(&path, contents).unwrap();
+        return path;
+    }
+
+    #[test]
+    fn missing_ca_file() {
+        let path = env::temp_dir().join(format!("mqtt-tls-missing-{}.pem", process::id()));
+
+        let err = TlsOptions::new().add_ca_file(&path).unwrap_err();
+        assert!(matches!(err.kind(), client::ErrorKind::TlsError));
+    }
+
+    #[test]
+    fn invalid_pem_ca_file() {
+        let path = ca_file(
+            "invalid_pem",
+            "-----BEGIN CERTIFICATE-----\nnot base64!\n-----END CERTIFICATE-----\n",
+        );
+
+        let res = TlsOptions::new().add_ca_file(&path);
+        let _ = fs::remove_file(&path);
+        assert!(matches!(
+            res.unwrap_err().kind(),
+            client::ErrorKind::TlsError
+        ));
+    }
+
+    #[test]
+    fn invalid_certificate_ca_file() {
+        // valid PEM, but the contents are not a certificate.
+        let path = ca_file(
+            "invalid_cert",
+            "-----BEGIN CERTIFICATE-----\naGVsbG8=\n-----END CERTIFICATE-----\n",
+        );
+
+        let res = TlsOptions::new().add_ca_file(&path);
+        let _ = fs::remove_file(&path);
+        assert!(matches!(
+            res.unwrap_err().kind(),
+            client::ErrorKind::TlsError
+        ));
+    }
+
+    #[test]
+    fn connector_alpn_protocols() {
+        let mut options = TlsOptions::new();
+        options.set_alpn_protocols(vec![b"mqtt".to_vec()]);
+
+        assert_eq!(
+            options.connector().config().alpn_protocols,
+            vec![b"mqtt".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn client_hello_server_name_and_alpn() {
+        let mut options = TlsOptions::new();
+        options.set_server_name("broker.example.com");
+        options.set_alpn_protocols(vec![b"mqtt".to_vec()]);
+
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(async move {
+            let handshake = LazyConfigAcceptor::new(Acceptor::default(), server)
+                .await
+                .unwrap();
+            let hello = handshake.client_hello();
+            return (
+                hello.server_name().map(str::to_string),
+                hello
+                    .alpn()
+                    .map(|protocols| protocols.map(|p| p.to_vec()).collect::<Vec<_>>()),
+            );
+        });
+
+        // the server hangs up after reading the ClientHello, so the handshake itself fails.
+        let _ = options.connect("load-balancer.example.com", client).await;
+
+        let (server_name, alpn) = server.await.unwrap();
+        assert_eq!(server_name.as_deref(), Some("broker.example.com"));
+        assert_eq!(alpn, Some(vec![b"mqtt".to_vec()]));
+    }
+}
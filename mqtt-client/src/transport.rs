@@ -0,0 +1,9 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A byte stream a client can carry MQTT packets over.
+///
+/// Implemented for every async stream, i.e. a [tokio::net::TcpStream], a TLS stream from [crate::tls::TlsOptions]
+/// or a [crate::websocket::WsTransport].
+pub trait Transport: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Transport for T {}
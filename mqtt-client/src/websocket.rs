@@ -0,0 +1,59 @@
+use mqtt_core::err::client::{self, ClientError};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{
+    client_async,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+    },
+};
+
+pub use mqtt_core::websocket::WsStream;
+
+/// Carries MQTT over the binary messages of a WebSocket connection, for brokers that only expose a WebSocket endpoint.
+///
+/// MQTT packets are not aligned to WebSocket messages, so the connection is exposed as a byte stream and can be
+/// passed to [crate::r#async::AsyncClient::new] like any other stream.
+pub type WsTransport<S> = WsStream<S>;
+
+/// Performs the WebSocket handshake over the stream, offering the `mqtt` subprotocol.
+///
+/// For secure WebSockets (wss), pass a stream returned by [crate::tls::TlsOptions::connect].
+///
+/// ## Examples
+///
+/// ```no_run
+/// use mqtt_client::{r#async::AsyncClient, websocket};
+/// use tokio::net::TcpStream;
+///
+/// # async fn connect() {
+/// let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
+/// let transport = websocket::connect("ws://127.0.0.1:8080/mqtt", stream).await.unwrap();
+/// let client = AsyncClient::new(transport);
+/// # }
+/// ```
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    url: &str,
+    stream: S,
+) -> Result<WsTransport<S>, ClientError> {
+    let mut request = url.into_client_request().map_err(ws_client_error)?;
+
+    // The Client MUST include "mqtt" in the list of WebSocket Sub Protocols it offers [MQTT-6.0.0-3].
+    request
+        .headers_mut()
+        .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("mqtt"));
+
+    let (inner, _) = client_async(request, stream)
+        .await
+        .map_err(ws_client_error)?;
+
+    return Ok(WsStream::new(inner));
+}
+
+fn ws_client_error(err: tungstenite::Error) -> ClientError {
+    return ClientError::new(
+        client::ErrorKind::WebSocketError,
+        format!("WebSocket handshake failed: {err}"),
+    );
+}
@@ -1,19 +1,79 @@
 use std::{error::Error, fmt::Display};
 
+/// Builds a [DecodeError] from an error kind and a format string.
+///
+/// With the "static-errors" feature the message is never formatted, so building the error does not allocate.
+macro_rules! decode_error {
+    ($kind:expr, $($arg:tt)+) => {{
+        #[cfg(not(feature = "static-errors"))]
+        let err = $crate::err::DecodeError::new($kind, format!($($arg)+));
+        #[cfg(feature = "static-errors")]
+        let err = {
+            // never called, keeps the format arguments "used" without formatting them.
+            let _ = || format!($($arg)+);
+            $crate::err::DecodeError::from_kind($kind)
+        };
+        err
+    }};
+}
+
+/// Builds an [EncodeError], see [decode_error].
+macro_rules! encode_error {
+    ($kind:expr, $($arg:tt)+) => {{
+        #[cfg(not(feature = "static-errors"))]
+        let err = $crate::err::EncodeError::new($kind, format!($($arg)+));
+        #[cfg(feature = "static-errors")]
+        let err = {
+            let _ = || format!($($arg)+);
+            $crate::err::EncodeError::from_kind($kind)
+        };
+        err
+    }};
+}
+
+pub(crate) use decode_error;
+pub(crate) use encode_error;
+
+/// With the "static-errors" feature the message is replaced by the static code of the error kind.
 #[derive(Debug, Clone)]
 pub struct EncodeError {
     kind: EncodeErrorKind,
+    #[cfg(not(feature = "static-errors"))]
     message: String,
 }
 
 impl EncodeError {
+    #[cfg(not(feature = "static-errors"))]
     pub fn new(kind: EncodeErrorKind, message: String) -> Self {
         return Self { kind, message };
     }
 
+    #[cfg(feature = "static-errors")]
+    pub fn new(kind: EncodeErrorKind, _message: String) -> Self {
+        return Self { kind };
+    }
+
+    /// Creates an error without a message, the message is the code of the error kind.
+    pub fn from_kind(kind: EncodeErrorKind) -> Self {
+        #[cfg(not(feature = "static-errors"))]
+        return Self {
+            kind,
+            message: String::from(kind.code()),
+        };
+        #[cfg(feature = "static-errors")]
+        return Self { kind };
+    }
+
     pub fn kind(&self) -> EncodeErrorKind {
         return self.kind;
     }
+
+    pub fn message(&self) -> &str {
+        #[cfg(not(feature = "static-errors"))]
+        return &self.message;
+        #[cfg(feature = "static-errors")]
+        return self.kind.code();
+    }
 }
 
 #[derive(Clone, Debug, Copy, PartialEq)]
@@ -21,28 +81,62 @@ pub enum EncodeErrorKind {
     OversizedPayload,
 }
 
+impl EncodeErrorKind {
+    pub fn code(&self) -> &'static str {
+        return match self {
+            Self::OversizedPayload => "OVERSIZED_PAYLOAD",
+        };
+    }
+}
+
 impl Error for DecodeError {}
 
+/// With the "static-errors" feature the message is replaced by the static code of the error kind.
 #[derive(Debug, Clone)]
 pub struct DecodeError {
     kind: DecodeErrorKind,
+    #[cfg(not(feature = "static-errors"))]
     message: String,
 }
 
 impl Display for DecodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}: {}", self.kind, self.message)
+        write!(f, "{:?}: {}", self.kind, self.message())
     }
 }
 
 impl DecodeError {
+    #[cfg(not(feature = "static-errors"))]
     pub fn new(kind: DecodeErrorKind, message: String) -> Self {
         return Self { kind, message };
     }
 
+    #[cfg(feature = "static-errors")]
+    pub fn new(kind: DecodeErrorKind, _message: String) -> Self {
+        return Self { kind };
+    }
+
+    /// Creates an error without a message, the message is the code of the error kind.
+    pub fn from_kind(kind: DecodeErrorKind) -> Self {
+        #[cfg(not(feature = "static-errors"))]
+        return Self {
+            kind,
+            message: String::from(kind.code()),
+        };
+        #[cfg(feature = "static-errors")]
+        return Self { kind };
+    }
+
     pub fn kind(&self) -> DecodeErrorKind {
         return self.kind;
     }
+
+    pub fn message(&self) -> &str {
+        #[cfg(not(feature = "static-errors"))]
+        return &self.message;
+        #[cfg(feature = "static-errors")]
+        return self.kind.code();
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -62,10 +156,41 @@ pub enum DecodeErrorKind {
     ImproperDisconnect,
     ProtocolError,
     Timeout,
+    InvalidProperty,
+    InvalidReasonCode,
+    PacketTooLarge,
+}
+
+impl DecodeErrorKind {
+    pub fn code(&self) -> &'static str {
+        return match self {
+            Self::FlagBits => "FLAG_BITS",
+            Self::PacketType => "PACKET_TYPE",
+            Self::WillQoS => "WILL_QOS",
+            Self::Will => "WILL",
+            Self::QoS => "QOS",
+            Self::Utf8ParseError => "UTF8_PARSE_ERROR",
+            Self::MalformedLength => "MALFORMED_LENGTH",
+            Self::MalformedTopicFilter => "MALFORMED_TOPIC_FILTER",
+            Self::MalformedTopicName => "MALFORMED_TOPIC_NAME",
+            Self::UsernamePassword => "USERNAME_PASSWORD",
+            Self::InvalidProtocol => "INVALID_PROTOCOL",
+            Self::InvalidReturnCode => "INVALID_RETURN_CODE",
+            Self::ImproperDisconnect => "IMPROPER_DISCONNECT",
+            Self::ProtocolError => "PROTOCOL_ERROR",
+            Self::Timeout => "TIMEOUT",
+            Self::InvalidProperty => "INVALID_PROPERTY",
+            Self::InvalidReasonCode => "INVALID_REASON_CODE",
+            Self::PacketTooLarge => "PACKET_TOO_LARGE",
+        };
+    }
 }
 
 pub mod client {
-    use crate::err::{DecodeError, EncodeError};
+    use crate::{
+        err::{DecodeError, EncodeError},
+        ConnectReturnCode,
+    };
     use std::fmt::Display;
 
     use tokio::io;
@@ -77,6 +202,11 @@ pub mod client {
         TopicDoesNotExist(String),
         DecodeError,
         EncodeError,
+        Timeout,
+        MaxReconnectAttempts(u32),
+        TlsError,
+        WebSocketError,
+        ConnectError(ConnectReturnCode),
     }
 
     impl Display for ErrorKind {
@@ -105,7 +235,7 @@ pub mod client {
         fn from(value: DecodeError) -> Self {
             return Self {
                 kind: ErrorKind::DecodeError,
-                message: value.message,
+                message: value.message().to_string(),
             };
         }
     }
@@ -114,7 +244,7 @@ pub mod client {
         fn from(value: EncodeError) -> Self {
             return Self {
                 kind: ErrorKind::EncodeError,
-                message: value.message,
+                message: value.message().to_string(),
             };
         }
     }
@@ -158,6 +288,13 @@ pub mod server {
         BroadcastError,
         FullMailbox(u64),
         ConnectError(ConnectReturnCode),
+        DuplicateConnect,
+        ConnectTimeout,
+        KeepAliveTimeout,
+        SessionTakenOver,
+        InflightLimitExceeded,
+        SessionStoreError,
+        TlsError,
     }
 
     impl Display for ErrorKind {
@@ -170,7 +307,7 @@ pub mod server {
         fn from(value: DecodeError) -> Self {
             return Self {
                 kind: ErrorKind::DecodeError,
-                message: value.message,
+                message: value.message().to_string(),
             };
         }
     }
@@ -179,7 +316,7 @@ pub mod server {
         fn from(value: EncodeError) -> Self {
             return Self {
                 kind: ErrorKind::EncodeError,
-                message: value.message,
+                message: value.message().to_string(),
             };
         }
     }
@@ -213,3 +350,19 @@ pub mod server {
         }
     }
 }
+
+#[cfg(test)]
+mod message {
+    use super::{decode_error, DecodeErrorKind};
+
+    #[test]
+    fn decode_error_message() {
+        let err = decode_error!(DecodeErrorKind::QoS, "Invalid QoS level: {}", 3);
+        assert_eq!(err.kind(), DecodeErrorKind::QoS);
+
+        #[cfg(not(feature = "static-errors"))]
+        assert_eq!(err.message(), "Invalid QoS level: 3");
+        #[cfg(feature = "static-errors")]
+        assert_eq!(err.message(), "QOS");
+    }
+}
@@ -1,6 +1,8 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use crate::err::{DecodeError, DecodeErrorKind, EncodeError, EncodeErrorKind};
+use crate::err::{
+    decode_error, encode_error, DecodeError, DecodeErrorKind, EncodeError, EncodeErrorKind,
+};
 
 /*
  * MQTT v3.1.1 standard, Remaining length field on the fixed header can be at
@@ -13,12 +15,10 @@ const MAX_LEN: usize = (128 as u64).pow(4) as usize;
 
 pub fn encode_packet_length(bytes: &mut BytesMut, mut len: usize) -> Result<usize, EncodeError> {
     if len >= MAX_LEN {
-        return Err(EncodeError::new(
+        return Err(encode_error!(
             EncodeErrorKind::OversizedPayload,
-            format!(
-                "Packet payload exceeded max length of 127^4, found length {}",
-                len
-            ),
+            "Packet payload exceeded max length of 127^4, found length {}",
+            len
         ));
     }
 
@@ -65,7 +65,11 @@ pub fn decode_utf8(bytes: &mut Bytes) -> Result<String, DecodeError> {
 
     if len as usize > bytes.len() {
         // return Err(DecodeError::MalformedLength);
-        return Err(DecodeError::new(DecodeErrorKind::MalformedLength,format!("Attempted invalid memory access, packet remaining length: {}, encoded length: {len}", bytes.len())));
+        return Err(decode_error!(
+            DecodeErrorKind::MalformedLength,
+            "Attempted invalid memory access, packet remaining length: {}, encoded length: {len}",
+            bytes.len()
+        ));
     }
 
     let string = String::from_utf8(bytes.slice(0..len as usize).to_vec());
@@ -74,12 +78,7 @@ pub fn decode_utf8(bytes: &mut Bytes) -> Result<String, DecodeError> {
 
     match string {
         Ok(string) => return Ok(string),
-        Err(e) => {
-            return Err(DecodeError::new(
-                DecodeErrorKind::Utf8ParseError,
-                String::from(e.to_string()),
-            ))
-        }
+        Err(e) => return Err(decode_error!(DecodeErrorKind::Utf8ParseError, "{e}")),
     }
 }
 
@@ -93,18 +92,78 @@ pub fn decode_bytes(bytes: &mut Bytes) -> Result<Bytes, DecodeError> {
 }
 
 pub fn decode_u16_len(bytes: &mut Bytes) -> Result<u16, DecodeError> {
-    let len = bytes.get_u16();
+    let len = decode_u16(bytes)?;
 
     if len as usize > bytes.len() {
-        return Err(DecodeError::new(
+        return Err(decode_error!(
             DecodeErrorKind::MalformedLength,
-            format!("Attempted invalid memory access, packet remaining length: {}, encoded length: {len}", bytes.len())
+            "Attempted invalid memory access, packet remaining length: {}, encoded length: {len}",
+            bytes.len()
         ));
     }
 
     return Ok(len);
 }
 
+/// Decodes a Variable Byte Integer and advances the buffer past it.
+///
+/// Uses the same encoding as the fixed header's remaining length, see [encode_packet_length] for encoding.
+pub fn decode_variable_int(bytes: &mut Bytes) -> Result<usize, DecodeError> {
+    let mut mult = 1;
+    let mut len: usize = 0;
+
+    for _ in 0..4 {
+        let c = decode_u8(bytes)?;
+
+        len += (c as usize & 127) * mult;
+        mult *= 128;
+
+        if (c & 128) == 0 {
+            return Ok(len);
+        }
+    }
+
+    return Err(decode_error!(
+        DecodeErrorKind::MalformedLength,
+        "Variable byte integer exceeded the maximum length of 4 bytes."
+    ));
+}
+
+/// Reads a single byte, returning an error instead of panicking when the buffer is empty.
+pub fn decode_u8(bytes: &mut Bytes) -> Result<u8, DecodeError> {
+    if bytes.remaining() < 1 {
+        return Err(decode_error!(
+            DecodeErrorKind::MalformedLength,
+            "Expected 1 byte, but the packet was empty."
+        ));
+    }
+    return Ok(bytes.get_u8());
+}
+
+/// Reads a big endian u16, returning an error instead of panicking when the buffer is too short.
+pub fn decode_u16(bytes: &mut Bytes) -> Result<u16, DecodeError> {
+    if bytes.remaining() < 2 {
+        return Err(decode_error!(
+            DecodeErrorKind::MalformedLength,
+            "Expected 2 bytes, but only {} bytes remain in the packet.",
+            bytes.remaining()
+        ));
+    }
+    return Ok(bytes.get_u16());
+}
+
+/// Reads a big endian u32, returning an error instead of panicking when the buffer is too short.
+pub fn decode_u32(bytes: &mut Bytes) -> Result<u32, DecodeError> {
+    if bytes.remaining() < 4 {
+        return Err(decode_error!(
+            DecodeErrorKind::MalformedLength,
+            "Expected 4 bytes, but only {} bytes remain in the packet.",
+            bytes.remaining()
+        ));
+    }
+    return Ok(bytes.get_u32());
+}
+
 /// Will NOT advance the internal buffer. To keep alignment with the buffer,
 /// the user is responsible for advancing the buffer's pointer.
 ///
@@ -141,12 +200,10 @@ pub fn decode_packet_length<'a>(bytes: &Bytes) -> Result<(usize, usize), DecodeE
 
     // MSB of byte siginifies a continuation of encoded length, if MSB is set or we are at max value of bits (i.e. 128), we have exceeded max allowable packet length.
     if c >= 128 {
-        return Err(DecodeError::new(
+        return Err(decode_error!(
             DecodeErrorKind::MalformedLength,
-            format!(
-                "Packet payload exceeded max length of 127^4, found length {}",
-                len
-            ),
+            "Packet payload exceeded max length of 127^4, found length {}",
+            len
         ));
     } else {
         len += (c as usize & 127) * mult;
@@ -154,6 +211,36 @@ pub fn decode_packet_length<'a>(bytes: &Bytes) -> Result<(usize, usize), DecodeE
     }
 }
 
+/// Reads the remaining length from a receive buffer that may not yet contain the full fixed header.
+///
+/// ## Returns (header_len, rest_len)
+/// where 'header_len' is the length of the fixed header including the packet type byte, and 'rest_len' is the remaining length of the packet.
+/// Returns None if the buffer ends before the encoded length is terminated.
+pub fn peek_packet_length(bytes: &[u8]) -> Result<Option<(usize, usize)>, DecodeError> {
+    let mut mult = 1;
+    let mut len: usize = 0;
+
+    for i in 1..MAX_LEN_BYTES {
+        let c = match bytes.get(i) {
+            Some(c) => *c,
+            None => return Ok(None),
+        };
+
+        len += (c as usize & 127) * mult;
+        mult *= 128;
+
+        if (c & 128) == 0 {
+            return Ok(Some((i + 1, len)));
+        }
+    }
+
+    return Err(decode_error!(
+        DecodeErrorKind::MalformedLength,
+        "Packet payload exceeded max length of 127^4, found length {}",
+        len
+    ));
+}
+
 // pub fn serialize_packet(packet: MqttPacket, int: u32) -> Vec<u8> {
 //     unimplemented!()
 // }
@@ -162,7 +249,9 @@ pub fn decode_packet_length<'a>(bytes: &Bytes) -> Result<(usize, usize), DecodeE
 mod header_length {
     use bytes::{Bytes, BytesMut};
 
-    use crate::io::{decode_packet_length, encode_packet_length};
+    use crate::io::{
+        decode_packet_length, decode_variable_int, encode_packet_length, peek_packet_length,
+    };
 
     #[test]
     fn encode_length() {
@@ -214,6 +303,33 @@ mod header_length {
         assert_eq!(encode_len, 2);
         assert_eq!(rest_len, 127);
     }
+
+    #[test]
+    fn variable_int() {
+        let mut buf = BytesMut::new();
+        encode_packet_length(&mut buf, 16_384).unwrap();
+        buf.extend_from_slice(&[1, 2]);
+
+        let mut bytes = Bytes::from(buf);
+        assert_eq!(decode_variable_int(&mut bytes).unwrap(), 16_384);
+        assert_eq!(bytes.len(), 2);
+
+        assert!(decode_variable_int(&mut Bytes::from_static(&[128, 128])).is_err());
+        assert!(decode_variable_int(&mut Bytes::from_static(&[255, 255, 255, 255, 1])).is_err());
+    }
+
+    #[test]
+    fn peek_incomplete() {
+        assert_eq!(peek_packet_length(&[]).unwrap(), None);
+        assert_eq!(peek_packet_length(&[0]).unwrap(), None);
+        assert_eq!(peek_packet_length(&[0, 255, 255]).unwrap(), None);
+
+        assert_eq!(
+            peek_packet_length(&[0, 255, 255, 255, 127]).unwrap(),
+            Some((5, (128 as usize).pow(4) - 1))
+        );
+        assert!(peek_packet_length(&[0, 128, 128, 128, 128]).is_err());
+    }
 }
 
 use futures::FutureExt;
@@ -247,41 +363,142 @@ pub async fn read_packet<
     }
 }
 
-pub async fn unfused_read_packet<
-    S: AsyncReadExt + AsyncWrite + Unpin,
+/// ## Returns (header, body)
+/// where 'header' holds the packet type byte and the encoded remaining length, and 'body' holds the variable header and payload.
+pub async fn unfused_read_frame<
+    S: AsyncReadExt + Unpin,
     E: From<io::Error> + From<err::DecodeError>,
 >(
     stream: &mut S,
-) -> Result<Option<MqttPacket>, E> {
+) -> Result<(Bytes, Bytes), E> {
     // read in the packet type and the encoded length.
-    let mut header_buf = [0; 5];
-    let f_header: FixedHeader;
+    let mut header_buf = [0; MAX_LEN_BYTES];
 
     // read in packet type.
     header_buf[0] = stream.read_u8().await?;
 
     // read in encoded packet length.
-    let mut i = 1;
-    while i < 6 {
+    let mut header_len = 1;
+    loop {
         let byte = stream.read_u8().await?;
-        header_buf[i] = byte;
+        header_buf[header_len] = byte;
+        header_len += 1;
+
         if byte < 128 {
             break;
         }
 
-        i += 1;
+        if header_len == MAX_LEN_BYTES {
+            return Err(decode_error!(
+                DecodeErrorKind::MalformedLength,
+                "Remaining length exceeded the maximum length of 4 bytes."
+            )
+            .into());
+        }
     }
 
-    let mut header_buf = Bytes::copy_from_slice(&header_buf[0..i + 1]);
-    f_header = FixedHeader::decode(&mut header_buf)?;
+    let header = Bytes::copy_from_slice(&header_buf[0..header_len]);
+    let (_, rest_len) = decode_packet_length(&header)?;
 
     let mut buf = BytesMut::new();
-    buf.resize(f_header.rest_len(), 0);
+    buf.resize(rest_len, 0);
 
-    // extract the variable header and payload then parse the variable header.
+    // extract the variable header and payload.
     stream.read_exact(&mut buf).await?;
 
-    match decode_packet(f_header, &mut buf.into()) {
+    return Ok((header, buf.into()));
+}
+
+/// Reads frames off a stream, holding on to partially received frames between calls.
+///
+/// [unfused_read_frame] loses any bytes it has read if it is dropped before it completes, [FrameReader::read_frame]
+/// is cancel safe, so it can be used as a branch of a select alongside other events.
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buf: BytesMut,
+    // bytes, None accepts packets up to the protocol maximum of 256MB.
+    max_packet_size: Option<usize>,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        return Self {
+            buf: BytesMut::new(),
+            max_packet_size: None,
+        };
+    }
+
+    /// Sets the largest packet, including its fixed header, that may be read.
+    ///
+    /// A larger packet is refused as soon as its fixed header is read, before any of its body is buffered.
+    pub fn set_max_packet_size(&mut self, max: Option<usize>) {
+        self.max_packet_size = max;
+    }
+
+    /// Reads a single packet off the stream without decoding it, see [unfused_read_frame].
+    ///
+    /// ## Returns (header, body)
+    /// where 'header' holds the packet type byte and the encoded remaining length, and 'body' holds the variable header and payload.
+    pub async fn read_frame<S: AsyncRead + Unpin, E: From<io::Error> + From<err::DecodeError>>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<(Bytes, Bytes), E> {
+        loop {
+            if let Some(frame) = self.next_frame()? {
+                return Ok(frame);
+            }
+
+            // read_buf is cancel safe, bytes are only consumed once they are in the buffer.
+            if stream.read_buf(&mut self.buf).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Stream closed before a complete packet was received.",
+                )
+                .into());
+            }
+        }
+    }
+
+    /// Splits the next complete frame off the buffer, returns None if the frame has not been fully received.
+    fn next_frame(&mut self) -> Result<Option<(Bytes, Bytes)>, err::DecodeError> {
+        let (header_len, rest_len) = match peek_packet_length(&self.buf)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        if let Some(max) = self.max_packet_size {
+            if header_len + rest_len > max {
+                return Err(decode_error!(
+                    DecodeErrorKind::PacketTooLarge,
+                    "Packet of {} bytes exceeded the maximum packet size of {max} bytes",
+                    header_len + rest_len
+                ));
+            }
+        }
+
+        if self.buf.len() < header_len + rest_len {
+            // reserve the rest of the packet so large payloads are not read in small chunks.
+            self.buf.reserve(header_len + rest_len - self.buf.len());
+            return Ok(None);
+        }
+
+        let header = self.buf.split_to(header_len).freeze();
+        let body = self.buf.split_to(rest_len).freeze();
+
+        return Ok(Some((header, body)));
+    }
+}
+
+pub async fn unfused_read_packet<
+    S: AsyncReadExt + AsyncWrite + Unpin,
+    E: From<io::Error> + From<err::DecodeError>,
+>(
+    stream: &mut S,
+) -> Result<Option<MqttPacket>, E> {
+    let (mut header, mut body) = unfused_read_frame::<_, E>(stream).await?;
+    let f_header = FixedHeader::decode(&mut header)?;
+
+    match decode_packet(f_header, &mut body) {
         Ok(packet) => {
             return Ok(Some(packet));
         }
@@ -290,3 +507,42 @@ pub async fn unfused_read_packet<
         }
     }
 }
+
+#[cfg(test)]
+mod frame_reader {
+    use crate::io::FrameReader;
+
+    #[test]
+    fn splits_frames() {
+        let mut reader = FrameReader::new();
+
+        // a PINGREQ followed by the first bytes of a PUBLISH.
+        reader.buf.extend_from_slice(&[0xC0, 0, 0x30, 5, 0, 1]);
+
+        let (header, body) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(&header[..], &[0xC0, 0]);
+        assert!(body.is_empty());
+
+        assert!(reader.next_frame().unwrap().is_none());
+
+        reader.buf.extend_from_slice(&[b'a', b'h', b'i']);
+
+        let (header, body) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(&header[..], &[0x30, 5]);
+        assert_eq!(&body[..], &[0, 1, b'a', b'h', b'i']);
+        assert!(reader.buf.is_empty());
+    }
+
+    #[test]
+    fn refuses_large_packets() {
+        let mut reader = FrameReader::new();
+        reader.set_max_packet_size(Some(4));
+
+        reader.buf.extend_from_slice(&[0x30, 2, 0, 0]);
+        assert!(reader.next_frame().unwrap().is_some());
+
+        // the remaining length alone is enough to refuse the packet.
+        reader.buf.extend_from_slice(&[0x30, 3]);
+        assert!(reader.next_frame().is_err());
+    }
+}
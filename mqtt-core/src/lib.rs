@@ -1,6 +1,6 @@
 use core::fmt::Display;
 
-use err::{DecodeError, DecodeErrorKind};
+use err::{decode_error, DecodeError, DecodeErrorKind};
 
 pub mod err;
 pub mod id;
@@ -9,6 +9,9 @@ pub mod msg_assurance;
 pub mod qos;
 pub mod topic;
 pub mod v3;
+pub mod v5;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -32,9 +35,9 @@ impl TryFrom<u8> for ConnectReturnCode {
             3 => return Ok(Self::ServerUnavailable),
             4 => return Ok(Self::BadUsernameOrPassword),
             5 => return Ok(Self::NotAuthorized),
-            _ => Err(DecodeError::new(
+            _ => Err(decode_error!(
                 DecodeErrorKind::InvalidReturnCode,
-                format!("Return code: {value}, is invalid, only values of 0-5 are valid."),
+                "Return code: {value}, is invalid, only values of 0-5 are valid."
             )),
         }
     }
@@ -64,3 +67,56 @@ impl Display for ConnectReturnCode {
         }
     }
 }
+
+/// A version of MQTT, identified by the Protocol Name and Protocol Level of a CONNECT packet.
+///
+/// v3.1 and v3.1.1 share their packet formats, so both are encoded and decoded by [v3::ConnectPacket]. MQTT 5.0
+/// is encoded and decoded by [v5::ConnectPacket].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MqttVersion {
+    V3_1,
+    V3_1_1,
+    V5,
+}
+
+impl MqttVersion {
+    /// Identifies the version a client connected with, from the Protocol Name and Protocol Level of its CONNECT packet.
+    pub fn negotiate(protocol_name: &str, level: u8) -> Result<Self, DecodeError> {
+        match (protocol_name, level) {
+            ("MQIsdp", 3) => return Ok(Self::V3_1),
+            ("MQTT", 4) => return Ok(Self::V3_1_1),
+            ("MQTT", 5) => return Ok(Self::V5),
+            _ => {
+                return Err(decode_error!(
+                    DecodeErrorKind::InvalidProtocol,
+                    "Protocol: {protocol_name} at level {level} is not a supported version of MQTT"
+                ))
+            }
+        }
+    }
+
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            Self::V3_1 => return "MQIsdp",
+            Self::V3_1_1 | Self::V5 => return "MQTT",
+        }
+    }
+
+    pub fn protocol_level(&self) -> u8 {
+        match self {
+            Self::V3_1 => return 3,
+            Self::V3_1_1 => return 4,
+            Self::V5 => return 5,
+        }
+    }
+}
+
+impl Display for MqttVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V3_1 => write!(f, "v3.1"),
+            Self::V3_1_1 => write!(f, "v3.1.1"),
+            Self::V5 => write!(f, "v5.0"),
+        }
+    }
+}
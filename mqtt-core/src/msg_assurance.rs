@@ -7,6 +7,7 @@ use crate::{
 use bytes::Bytes;
 
 use core::time::Duration;
+use std::cell::Cell;
 use std::slice::{Iter, IterMut};
 use std::sync::Arc;
 
@@ -149,6 +150,15 @@ where
         return self.inner.len();
     }
 
+    /// The number of bytes held by every packet in the list, see [PubPack::byte_len].
+    pub fn byte_len(&self) -> usize {
+        return self
+            .inner
+            .iter()
+            .map(|packet| packet.packet.byte_len())
+            .sum();
+    }
+
     pub fn iter(&self) -> Iter<'_, ExactlyOncePacket<P, I, B>> {
         return self.inner.iter();
     }
@@ -336,6 +346,15 @@ where
         return self.inner.len();
     }
 
+    /// The number of bytes held by every packet in the list, see [PubPack::byte_len].
+    pub fn byte_len(&self) -> usize {
+        return self
+            .inner
+            .iter()
+            .map(|packet| packet.packet.byte_len())
+            .sum();
+    }
+
     pub fn clean(&mut self) -> Vec<u16> {
         let mut id_idxs = vec![];
         for (idx, packet) in self.inner.iter().enumerate() {
@@ -498,12 +517,19 @@ pub enum QoS1Stage {
 
 pub trait PubPack {
     fn as_bytes(&self) -> Result<Bytes, EncodeError>;
+
+    /// The number of bytes held by the topic name and payload of the packet.
+    fn byte_len(&self) -> usize;
 }
 
 impl PubPack for Arc<PublishPacket> {
     fn as_bytes(&self) -> Result<Bytes, EncodeError> {
         self.encode()
     }
+
+    fn byte_len(&self) -> usize {
+        return self.topic().len() + self.payload().len();
+    }
 }
 
 impl Instant for std::time::Instant {
@@ -515,6 +541,59 @@ impl Instant for std::time::Instant {
     }
 }
 
+/// Allows retry logic to be driven by a paused tokio runtime, see [tokio::time::pause].
+impl Instant for tokio::time::Instant {
+    fn duration_since(&self, instant: &tokio::time::Instant) -> std::time::Duration {
+        return self.duration_since(*instant);
+    }
+    fn now() -> Self {
+        return tokio::time::Instant::now();
+    }
+}
+
+thread_local! {
+    static MANUAL_NOW: Cell<Duration> = Cell::new(Duration::ZERO);
+}
+
+/// A clock that only moves when it is told to, for testing retry and timeout logic without sleeping.
+///
+/// The current time is tracked per thread, so tests running in parallel do not affect each other.
+///
+/// ## Examples
+///
+/// ```
+/// use mqtt_core::msg_assurance::{Instant, ManualInstant};
+/// use core::time::Duration;
+///
+/// let start = ManualInstant::now();
+/// ManualInstant::advance(Duration::from_secs(5));
+///
+/// assert_eq!(start.elapsed(), Duration::from_secs(5));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ManualInstant(Duration);
+
+impl ManualInstant {
+    /// Moves the clock of the current thread forward.
+    pub fn advance(dur: Duration) {
+        MANUAL_NOW.with(|now| now.set(now.get().saturating_add(dur)));
+    }
+
+    /// Moves the clock of the current thread back to zero.
+    pub fn reset() {
+        MANUAL_NOW.with(|now| now.set(Duration::ZERO));
+    }
+}
+
+impl Instant for ManualInstant {
+    fn duration_since(&self, instant: &ManualInstant) -> std::time::Duration {
+        return self.0.saturating_sub(instant.0);
+    }
+    fn now() -> Self {
+        return Self(MANUAL_NOW.with(|now| now.get()));
+    }
+}
+
 #[derive(Debug, Clone, Ord, PartialEq, Eq, PartialOrd)]
 pub struct RetryDuration {
     dur: core::time::Duration,
@@ -540,9 +619,20 @@ impl ExponentialBackoff for RetryDuration {
     }
 }
 
+/// The clock used to track when a packet was last advanced.
+///
+/// Implemented for [std::time::Instant], [tokio::time::Instant] and [ManualInstant].
 pub trait Instant: Ord {
     fn now() -> Self;
     fn duration_since(&self, instant: &Self) -> core::time::Duration;
+
+    /// Returns the time elapsed since this instant was created.
+    fn elapsed(&self) -> core::time::Duration
+    where
+        Self: Sized,
+    {
+        return Self::now().duration_since(self);
+    }
 }
 
 /// ## Examples
@@ -597,3 +687,92 @@ pub trait ExponentialBackoff: Ord {
 
     fn set_duration(&mut self, dur: Duration);
 }
+
+#[cfg(test)]
+mod retry {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use core::time::Duration;
+
+    use super::{
+        AtLeastOnceList, ExactlyOnceList, Instant, ManualInstant, QoS2Stage, RetryDuration,
+    };
+    use crate::{
+        topic::TopicName,
+        v3::{MqttPacket, PublishPacket},
+    };
+
+    fn packet() -> Arc<PublishPacket> {
+        let topic_name = TopicName::from_str("retry").unwrap();
+        return Arc::new(PublishPacket::new(
+            &topic_name,
+            Bytes::from_static(b"retry"),
+        ));
+    }
+
+    #[test]
+    fn qos1_retry_backoff() {
+        ManualInstant::reset();
+        let mut list: AtLeastOnceList<Arc<PublishPacket>, ManualInstant, RetryDuration> =
+            AtLeastOnceList::new();
+        list.origin(packet(), 1);
+
+        let qos1 = list.iter_mut().next().unwrap();
+        assert!(!qos1.should_retry());
+
+        ManualInstant::advance(Duration::from_millis(201));
+        assert!(qos1.should_retry());
+        match qos1.get_retry_packet() {
+            Some(MqttPacket::Publish(packet)) => assert!(packet.dup()),
+            _ => panic!("Expected a PUBLISH retry packet"),
+        }
+
+        // the retry window doubles, but is measured from when the packet was last advanced.
+        qos1.update_retry_duration();
+        assert!(!qos1.should_retry());
+        ManualInstant::advance(Duration::from_millis(200));
+        assert!(qos1.should_retry());
+
+        list.acknowledge(1);
+        assert!(!list.iter().next().unwrap().should_retry());
+    }
+
+    #[test]
+    fn qos2_retry_stops_after_rec() {
+        ManualInstant::reset();
+        let mut list: ExactlyOnceList<Arc<PublishPacket>, ManualInstant, RetryDuration> =
+            ExactlyOnceList::new();
+        list.origin(packet(), 1);
+
+        ManualInstant::advance(Duration::from_secs(1));
+        assert!(list.iter().next().unwrap().should_retry());
+
+        list.receive(1).unwrap();
+        let qos2 = list.iter().next().unwrap();
+        assert_eq!(qos2.stage(), QoS2Stage::Rec);
+        assert!(!qos2.should_retry());
+    }
+
+    #[test]
+    fn list_byte_len() {
+        let mut list: AtLeastOnceList<Arc<PublishPacket>, ManualInstant, RetryDuration> =
+            AtLeastOnceList::new();
+        assert_eq!(list.byte_len(), 0);
+
+        // topic name and payload are both 5 bytes.
+        list.origin(packet(), 1);
+        list.origin(packet(), 2);
+        assert_eq!(list.byte_len(), 20);
+    }
+
+    #[test]
+    fn manual_elapsed() {
+        ManualInstant::reset();
+        let start = ManualInstant::now();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        ManualInstant::advance(Duration::from_secs(3));
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::err::{DecodeError, DecodeErrorKind};
+use crate::err::{decode_error, DecodeError, DecodeErrorKind};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash)]
 pub enum QosLevel {
@@ -25,9 +25,9 @@ impl TryFrom<u8> for QosLevel {
             _ => {
                 // value of 0b0000_0110 is the only reachable value here
                 // return Err(DecodeError::QoS);
-                return Err(DecodeError::new(
+                return Err(decode_error!(
                     DecodeErrorKind::QoS,
-                    format!("Invalid QoS: {value}, only values 0-2 are valid"),
+                    "Invalid QoS: {value}, only values 0-2 are valid"
                 ));
             }
         };
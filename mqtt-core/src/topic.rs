@@ -1,7 +1,7 @@
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::{
-    err::{DecodeError, DecodeErrorKind},
+    err::{decode_error, DecodeError, DecodeErrorKind},
     io::decode_utf8,
 };
 
@@ -17,9 +17,9 @@ impl TopicFilter {
 
     pub fn from_str(str: &'_ str) -> Result<Self, DecodeError> {
         if str.len() == 0 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::MalformedTopicFilter,
-                format!("Invalid topic filter, filter contains no bytes."),
+                "Invalid topic filter, filter contains no bytes."
             ));
         }
 
@@ -33,9 +33,9 @@ impl TopicFilter {
                 match token {
                     TopicToken::MultiLevel => {
                         if strs.peek().is_some() {
-                            return Err(DecodeError::new(
+                            return Err(decode_error!(
                                 DecodeErrorKind::MalformedTopicFilter,
-                                format!("Invalid topic filter: {str}"),
+                                "Invalid topic filter: {str}"
                             ));
                         }
                     }
@@ -55,7 +55,9 @@ impl TopicFilter {
         let mut string = String::new();
         for token in self.into_iter() {
             string += token.as_str();
+            string.push('/');
         }
+        string.pop();
         return string;
     }
 
@@ -63,13 +65,41 @@ impl TopicFilter {
     pub fn len(&self) -> usize {
         let mut len = 0;
         for token in &self.0 {
-            match token {
-                TopicToken::String(string) => len += string.len(),
-                _ => len += 1,
+            // add one for the '/' seperator.
+            len += token.as_str().len() + 1;
+        }
+
+        return len - 1;
+    }
+
+    /// Returns true if every topic name matched by `other` is also matched by this filter.
+    pub fn covers(&self, other: &TopicFilter) -> bool {
+        let mut other_iter = other.0.iter();
+
+        for token in self.0.iter() {
+            match (token, other_iter.next()) {
+                // multi-level wildcards also include the parent topic.
+                (TopicToken::MultiLevel, None) => return true,
+                // wildcards do not match $-prefixed topics, see the PartialEq impl for TopicName.
+                (TopicToken::MultiLevel, Some(TopicToken::Dollar(_))) => return false,
+                (TopicToken::MultiLevel, Some(_)) => {
+                    return other_iter.all(|token| !matches!(token, TopicToken::Dollar(_)));
+                }
+                (
+                    TopicToken::SingleLevel,
+                    Some(TopicToken::SingleLevel | TopicToken::String(_)),
+                ) => continue,
+                (TopicToken::String(string), Some(TopicToken::String(o_string)))
+                | (TopicToken::Dollar(string), Some(TopicToken::Dollar(o_string)))
+                    if string == o_string =>
+                {
+                    continue
+                }
+                _ => return false,
             }
         }
 
-        return len;
+        return other_iter.next().is_none();
     }
 }
 
@@ -85,9 +115,9 @@ impl TopicName {
 
     pub fn from_str(str: &'_ str) -> Result<Self, DecodeError> {
         if str.len() == 0 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::MalformedTopicName,
-                format!("Invalid topic name: {str}"),
+                "Invalid topic name: {str}"
             ));
         }
 
@@ -104,9 +134,9 @@ impl TopicName {
                     }
                     // TopicName tokens cannot contain wildcards.
                     _ => {
-                        return Err(DecodeError::new(
+                        return Err(decode_error!(
                             DecodeErrorKind::MalformedTopicName,
-                            format!("Invalid topic name: {str}"),
+                            "Invalid topic name: {str}"
                         ))
                     }
                 }
@@ -133,14 +163,24 @@ impl TopicName {
     pub fn len(&self) -> usize {
         let mut len = 0;
         for token in &self.0 {
-            match token {
-                TopicToken::String(string) => len += string.len() + 1,
-                _ => len += 2,
-            }
+            // add one for the '/' seperator.
+            len += token.as_str().len() + 1;
         }
 
         return len - 1;
     }
+
+    /// Writes the length prefixed UTF-8 topic name into the buffer without allocating an intermediate String.
+    pub fn encode(&self, bytes: &mut BytesMut) {
+        bytes.put_u16(self.len() as u16);
+
+        for (idx, token) in self.0.iter().enumerate() {
+            if idx != 0 {
+                bytes.put_u8(b'/');
+            }
+            bytes.put_slice(token.as_str().as_bytes());
+        }
+    }
 }
 
 impl IntoIterator for TopicName {
@@ -353,4 +393,32 @@ mod parsing {
         // testing on nested $-negations
         assert_ne!(root_sub, TopicName::from_str("other/$something").unwrap());
     }
+
+    #[test]
+    fn topic_filter_covers() {
+        let filter = |str| TopicFilter::from_str(str).unwrap();
+
+        assert!(filter("sport/#").covers(&filter("sport/tennis/+")));
+        assert!(filter("sport/#").covers(&filter("sport")));
+        assert!(filter("sport/+/player1").covers(&filter("sport/tennis/player1")));
+        assert!(filter("sport/+").covers(&filter("sport/+")));
+        assert!(filter("$SYS/#").covers(&filter("$SYS/monitor/+")));
+
+        // a narrower filter does not cover a wider one.
+        assert!(!filter("sport/tennis").covers(&filter("sport/+")));
+        assert!(!filter("sport/+").covers(&filter("sport/#")));
+        assert!(!filter("sport/+").covers(&filter("sport/tennis/player1")));
+
+        // wildcards do not cover $-prefixed topics.
+        assert!(!filter("#").covers(&filter("$SYS/#")));
+        assert!(!filter("+/monitor").covers(&filter("$SYS/monitor")));
+    }
+
+    #[test]
+    fn topic_filter_to_string() {
+        let topic_filter = TopicFilter::from_str("sport/+/player1/#").unwrap();
+
+        assert_eq!(topic_filter.len(), "sport/+/player1/#".len());
+        assert_eq!(topic_filter.to_string(), "sport/+/player1/#");
+    }
 }
@@ -0,0 +1,123 @@
+use crate::{
+    qos::QosLevel,
+    v3::{
+        MqttPacket, PacketType, PubAckPacket, PubCompPacket, PubRecPacket, PubRelPacket,
+        PublishPacket, UnsubAckPacket,
+    },
+};
+
+/// Packets that acknowledge an earlier packet by its packet Id.
+pub trait Ack {
+    fn id(&self) -> u16;
+
+    fn packet_type(&self) -> PacketType;
+
+    fn into_packet(self) -> MqttPacket
+    where
+        Self: Sized;
+}
+
+macro_rules! impl_ack {
+    ($packet:ty, $variant:ident, $packet_type:expr) => {
+        impl Ack for $packet {
+            fn id(&self) -> u16 {
+                return <$packet>::id(self);
+            }
+
+            fn packet_type(&self) -> PacketType {
+                return $packet_type;
+            }
+
+            fn into_packet(self) -> MqttPacket {
+                return MqttPacket::$variant(self);
+            }
+        }
+    };
+}
+
+impl_ack!(PubAckPacket, PubAck, PacketType::PUBACK);
+impl_ack!(PubRecPacket, PubRec, PacketType::PUBREC);
+impl_ack!(PubRelPacket, PubRel, PacketType::PUBREL);
+impl_ack!(PubCompPacket, PubComp, PacketType::PUBCOMP);
+impl_ack!(UnsubAckPacket, UnsubAck, PacketType::UNSUBACK);
+
+impl PublishPacket {
+    /// Builds the packet the receiver responds with, a PUBACK for QoS 1 or a PUBREC for QoS 2.
+    ///
+    /// Returns None for QoS 0 packets, which are not acknowledged.
+    pub fn ack_response(&self) -> Option<MqttPacket> {
+        let id = self.id()?;
+        return match self.qos() {
+            QosLevel::AtLeastOnce => Some(MqttPacket::PubAck(PubAckPacket::new(id))),
+            QosLevel::ExactlyOnce => Some(MqttPacket::PubRec(PubRecPacket::new(id))),
+            QosLevel::AtMostOnce => None,
+        };
+    }
+}
+
+impl PubRecPacket {
+    /// The PUBREL the publisher responds with.
+    pub fn next(&self) -> PubRelPacket {
+        return PubRelPacket::new(self.id());
+    }
+}
+
+impl PubRelPacket {
+    /// The PUBCOMP the receiver responds with, completing the QoS 2 exchange.
+    pub fn next(&self) -> PubCompPacket {
+        return PubCompPacket::new(self.id());
+    }
+}
+
+impl MqttPacket {
+    /// Returns the packet as an acknowledgement, None if the packet is not a PUBACK, PUBREC, PUBREL, PUBCOMP or UNSUBACK.
+    pub fn as_ack(&self) -> Option<&dyn Ack> {
+        return match self {
+            Self::PubAck(packet) => Some(packet),
+            Self::PubRec(packet) => Some(packet),
+            Self::PubRel(packet) => Some(packet),
+            Self::PubComp(packet) => Some(packet),
+            Self::UnsubAck(packet) => Some(packet),
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod ack {
+    use bytes::Bytes;
+
+    use super::Ack;
+    use crate::{
+        topic::TopicName,
+        v3::{MqttPacket, PacketType, PubRecPacket, PublishPacket},
+    };
+
+    #[test]
+    fn qos_chain() {
+        let mut packet = PublishPacket::new(
+            &TopicName::from_str("sensors/temp").unwrap(),
+            Bytes::from_static(b"20"),
+        );
+        assert_eq!(packet.ack_response(), None);
+
+        packet.set_qos_atleastonce(3);
+        let ack = packet.ack_response().unwrap();
+        assert_eq!(ack.as_ack().unwrap().packet_type(), PacketType::PUBACK);
+        assert_eq!(ack.as_ack().unwrap().id(), 3);
+
+        packet.set_qos_exactlyonce(4);
+        let rec = match packet.ack_response() {
+            Some(MqttPacket::PubRec(rec)) => rec,
+            packet => panic!("Expected PUBREC, found {packet:?}"),
+        };
+        let rel = rec.next();
+        let comp = rel.next();
+        assert_eq!(Ack::packet_type(&rel), PacketType::PUBREL);
+        assert_eq!(Ack::id(&comp), 4);
+        assert_eq!(comp.into_packet().packet_type(), PacketType::PUBCOMP);
+
+        assert!(MqttPacket::Publish(packet).as_ack().is_none());
+        assert_eq!(PubRecPacket::new(5).into_packet().packet_id(), Some(5));
+    }
+}
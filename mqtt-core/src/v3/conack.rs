@@ -1,5 +1,5 @@
 use crate::{
-    err::{DecodeError, DecodeErrorKind},
+    err::{decode_error, DecodeError, DecodeErrorKind},
     v3::PacketType,
     ConnectReturnCode,
 };
@@ -56,12 +56,10 @@ impl ConnAckPacket {
         let session_present_byte = bytes.get_u8();
 
         if (session_present_byte & 0b1111_1110) != 0 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::ProtocolError,
-                format!(
-                    "One of the reserve bits inside the fixed header were set. {:?}",
-                    bytes
-                ),
+                "One of the reserve bits inside the fixed header were set. {:?}",
+                bytes
             ));
         }
 
@@ -99,6 +97,10 @@ impl ConnAckPacket {
         return self.return_code;
     }
 
+    pub fn session_present(&self) -> bool {
+        return self.session_present;
+    }
+
     pub fn set_session_present(&mut self, bool: bool) {
         self.session_present = bool;
     }
@@ -1,9 +1,10 @@
-use crate::err::{DecodeError, DecodeErrorKind, EncodeError};
+use crate::err::{decode_error, DecodeError, DecodeErrorKind, EncodeError};
 use crate::v3::PacketType;
 use crate::{
     io::{decode_bytes, decode_utf8, encode_bytes, encode_packet_length, encode_utf8},
     qos::QosLevel,
     topic::TopicName,
+    MqttVersion,
 };
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use core::fmt::Debug;
@@ -31,8 +32,6 @@ pub struct ConnectPacket {
      * the CONNECT packet in accordance with some other specification. In the latter case, the Server MUST NOT
      * continue to process the CONNECT packet in line with this specification [MQTT-3.1.2-1].
      */
-    protocol: Protocol,
-
     /*
      * The 8 bit unsigned value that represents the revision level of the protocol used by the Client.
      *
@@ -41,7 +40,8 @@ pub struct ConnectPacket {
      * The Server MUST respond to the CONNECT Packet with a CONNACK return code 0x01 (unacceptable protocol level)
      * and then disconnect the Client if the Protocol Level is not supported by the Server [MQTT-3.1.2-2].
      */
-    level: u8,
+    // v3.1 clients send the Protocol Name "MQIsdp" with a Protocol Level of 3, the rest of the packet is unchanged.
+    version: MqttVersion,
 
     /*
      * The Connect Flags byte contains a number of parameters specifying the behavior of the MQTT connection.
@@ -221,15 +221,14 @@ pub struct ConnectPacket {
 impl ConnectPacket {
     pub fn decode(mut bytes: &mut Bytes) -> Result<Self, DecodeError> {
         // first byte is used to obtain the packet type.
-        let protocol: Protocol;
-        (protocol, bytes) = Protocol::from_bytes(bytes)?;
-
+        let protocol = decode_utf8(bytes)?;
         let level = bytes.get_u8();
 
-        if level != 4 {
-            return Err(DecodeError::new(
+        let version = MqttVersion::negotiate(&protocol, level)?;
+        if version == MqttVersion::V5 {
+            return Err(decode_error!(
                 DecodeErrorKind::InvalidProtocol,
-                format!("Mqtt V3.1.1 Requires Protocol level to be 4, instead received: {level}"),
+                "Mqtt V3 Requires Protocol level to be 3 or 4, instead received: {level}"
             ));
         }
 
@@ -247,8 +246,7 @@ impl ConnectPacket {
             let topic: String;
             topic = decode_utf8(bytes)?;
 
-            let message: String;
-            message = decode_utf8(bytes)?;
+            let message = decode_bytes(bytes)?;
 
             let qos = conn_flags.will_qos();
             let retain = conn_flags.will_retain();
@@ -282,8 +280,7 @@ impl ConnectPacket {
         }
 
         return Ok(Self {
-            protocol,
-            level,
+            version,
             conn_flags,
             keep_alive,
             client_id,
@@ -297,7 +294,7 @@ impl ConnectPacket {
         // 2 for fixed header, 1 for protocol level, 1 for connect flags, two for the keep alive.
         let mut len = 1 + 1 + 2;
         // utf-8 decode is prefixed by two bytes to denote string length.
-        len += 2 + self.protocol.len();
+        len += 2 + self.version.protocol_name().len();
 
         len += 2 + self.client_id.len();
 
@@ -320,9 +317,9 @@ impl ConnectPacket {
 
         encode_packet_length(&mut bytes, len)?;
 
-        encode_utf8(&mut bytes, self.protocol.as_str())?;
+        encode_utf8(&mut bytes, self.version.protocol_name())?;
 
-        bytes.put_u8(self.level);
+        bytes.put_u8(self.version.protocol_level());
 
         bytes.put_u8(self.conn_flags.as_byte());
 
@@ -332,7 +329,7 @@ impl ConnectPacket {
 
         if let Some(will) = &self.will {
             encode_utf8(&mut bytes, &will.will_topic.clone().to_string())?;
-            encode_utf8(&mut bytes, &will.will_message)?;
+            encode_bytes(&mut bytes, &will.will_message)?;
         }
 
         if let Some(username) = &self.username {
@@ -378,8 +375,7 @@ impl ConnectPacket {
         }
 
         return Self {
-            protocol: Protocol::MQTT,
-            level: 4,
+            version: MqttVersion::V3_1_1,
             conn_flags,
             keep_alive,
             client_id,
@@ -389,6 +385,20 @@ impl ConnectPacket {
         };
     }
 
+    /// The version of MQTT the packet is sent with, v3.1.1 unless set otherwise.
+    pub fn version(&self) -> MqttVersion {
+        return self.version;
+    }
+
+    /// Sends the packet as a v3.1 CONNECT, for brokers that do not support v3.1.1.
+    ///
+    /// MQTT 5.0 CONNECT packets are sent with [crate::v5::ConnectPacket], setting [MqttVersion::V5] has no effect.
+    pub fn set_version(&mut self, version: MqttVersion) {
+        if version != MqttVersion::V5 {
+            self.version = version;
+        }
+    }
+
     pub fn client_id(&self) -> &'_ str {
         return &self.client_id;
     }
@@ -401,6 +411,10 @@ impl ConnectPacket {
         return self.conn_flags.clean_session();
     }
 
+    pub fn set_clean_session(&mut self, val: bool) {
+        self.conn_flags.set_clean_session(val);
+    }
+
     pub fn username(&self) -> &Option<String> {
         return &self.username;
     }
@@ -439,7 +453,7 @@ pub struct Will {
      *    it does not include the two bytes length. The length is already built into the
      *    'payload' section of the PUBLISH packet.
      */
-    will_message: String,
+    will_message: Bytes,
 
     /*
      * These two bits specify the QoS level to be used when publishing the Will Message.
@@ -465,7 +479,7 @@ pub struct Will {
 impl Will {
     pub fn new(
         will_topic: TopicName,
-        will_message: String,
+        will_message: Bytes,
         will_qos: QosLevel,
         will_retain: bool,
     ) -> Self {
@@ -481,8 +495,8 @@ impl Will {
         return &self.will_topic;
     }
 
-    pub fn will_message(&self) -> String {
-        return self.will_message.clone();
+    pub fn will_message(&self) -> &Bytes {
+        return &self.will_message;
     }
 
     pub fn will_qos(&self) -> QosLevel {
@@ -513,33 +527,28 @@ impl ConnectFlags {
     /// Handles Errors for malformed Connect Flags.
     pub fn from_byte(byte: u8) -> Result<Self, DecodeError> {
         if (byte & WILL_QOS_BITS >> 3) > 3 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::WillQoS,
-                format!("QoS cannot be set to 4"),
+                "QoS cannot be set to 4"
             ));
         }
         if byte & RESERVED_BIT == RESERVED_BIT {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::ProtocolError,
-                format!("Connect packet cannot have reserved bit (index 0) set, received: {byte}"),
+                "Connect packet cannot have reserved bit (index 0) set, received: {byte}"
             ));
         }
         if byte & WILL == 0 {
             if byte & 0b0011_1000 != 0 {
-                return Err(DecodeError::new(
-                    DecodeErrorKind::Will,
-                    format!(
-                        "Optional connection Will bits were set, but the Will bit itself was unset, received: {byte}"
-                    ),
-                ));
+                return Err(decode_error!(DecodeErrorKind::Will, "Optional connection Will bits were set, but the Will bit itself was unset, received: {byte}"));
             }
         }
 
         if byte & PASSWORD == PASSWORD {
             if byte & !USERNAME == USERNAME {
-                return Err(DecodeError::new(
+                return Err(decode_error!(
                     DecodeErrorKind::UsernamePassword,
-                    format!("Password bit is set and Username bit is unset, received: {byte}"),
+                    "Password bit is set and Username bit is unset, received: {byte}"
                 ));
             }
         }
@@ -632,11 +641,7 @@ impl ConnectFlags {
     }
 
     pub fn clean_session(&self) -> bool {
-        if self.byte & CLEAN_SESSION == CLEAN_SESSION {
-            return false;
-        } else {
-            return true;
-        }
+        return self.byte & CLEAN_SESSION == CLEAN_SESSION;
     }
 
     pub fn set_clean_session(&mut self, val: bool) {
@@ -654,47 +659,14 @@ impl Default for ConnectFlags {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum Protocol {
-    MQTT,
-}
-
-impl Protocol {
-    pub fn from_bytes(bytes: &mut Bytes) -> Result<(Self, &mut Bytes), DecodeError> {
-        let protocol_name = decode_utf8(bytes)?;
-
-        match &protocol_name.as_str() {
-            &"MQTT" => return Ok((Self::MQTT, bytes)),
-            _ => {
-                return Err(DecodeError::new(
-                    DecodeErrorKind::InvalidProtocol,
-                    format!(
-                        "Only MQTT packet types are allowed, instead received type: {protocol_name}"
-                    ),
-                ))
-            }
-        }
-    }
-
-    pub fn as_str(&self) -> &str {
-        match self {
-            Self::MQTT => return "MQTT",
-        }
-    }
-
-    /// returns the number of bytes, not the number of chars.
-    pub fn len(&self) -> usize {
-        return self.as_str().len();
-    }
-}
-
 #[cfg(test)]
 mod packet {
 
     use crate::v3::{FixedHeader, MqttPacket};
 
-    use super::ConnectPacket;
-    use bytes::Buf;
+    use super::{ConnectPacket, Will};
+    use crate::{qos::QosLevel, topic::TopicName, MqttVersion};
+    use bytes::{Buf, Bytes, BytesMut};
 
     #[test]
     fn serialize_deserialize() {
@@ -716,4 +688,50 @@ mod packet {
 
         assert_eq!(packet_de, MqttPacket::Connect(packet));
     }
+
+    #[test]
+    fn binary_will() {
+        // the Will Message is binary data, not a UTF-8 encoded string.
+        let will = Will::new(
+            TopicName::from_str("will").unwrap(),
+            Bytes::from_static(&[0x00, 0xff, 0xfe]),
+            QosLevel::AtLeastOnce,
+            false,
+        );
+        let packet = ConnectPacket::new(true, 10, String::from("will"), Some(will), None, None);
+        let mut buf = packet.encode().unwrap();
+
+        let f_header = FixedHeader::decode(&mut buf).unwrap();
+        buf.advance(f_header.header_len);
+        let packet_de = MqttPacket::decode(f_header, &mut buf).expect("Could not decode packet");
+        assert_eq!(packet_de, MqttPacket::Connect(packet));
+    }
+
+    #[test]
+    fn v3_1() {
+        let mut packet = ConnectPacket::new(true, 10, String::from("legacy"), None, None, None);
+        packet.set_version(MqttVersion::V3_1);
+        let mut buf = packet.encode().unwrap();
+
+        let f_header = FixedHeader::decode(&mut buf).unwrap();
+        buf.advance(f_header.header_len);
+        // the length prefixed Protocol Name is followed by the Protocol Level.
+        assert_eq!(&buf[..9], b"\x00\x06MQIsdp\x03");
+
+        let packet_de = MqttPacket::decode(f_header, &mut buf).expect("Could not decode packet");
+        assert_eq!(packet_de, MqttPacket::Connect(packet));
+    }
+
+    #[test]
+    fn mismatched_protocol_level() {
+        let packet = ConnectPacket::new(true, 10, String::from("legacy"), None, None, None);
+        let mut buf = BytesMut::from(&packet.encode().unwrap()[..]);
+        // "MQTT" with the v3.1 Protocol Level.
+        buf[8] = 3;
+        let mut buf = buf.freeze();
+
+        let f_header = FixedHeader::decode(&mut buf).unwrap();
+        buf.advance(f_header.header_len);
+        assert!(MqttPacket::decode(f_header, &mut buf).is_err());
+    }
 }
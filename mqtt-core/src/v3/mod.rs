@@ -1,5 +1,6 @@
-use bytes::Bytes;
+use bytes::{Buf, Bytes};
 
+mod ack;
 mod conack;
 mod connect;
 mod disconnect;
@@ -15,8 +16,9 @@ mod subscribe;
 mod unsuback;
 mod unsubscribe;
 
+pub use ack::Ack;
 pub use conack::ConnAckPacket;
-pub use connect::{ConnectPacket, Will};
+pub use connect::{ConnectFlags, ConnectPacket, Will};
 pub use disconnect::DisconnectPacket;
 pub use pingreq::PingReqPacket;
 pub use pingresp::PingRespPacket;
@@ -32,8 +34,8 @@ pub use unsuback::UnsubAckPacket;
 pub use unsubscribe::UnsubscribePacket;
 
 use crate::{
-    err::{DecodeError, DecodeErrorKind, EncodeError},
-    io::decode_packet_length,
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::{decode_packet_length, peek_packet_length},
 };
 
 const PACKET_TYPE_BITS: u8 = 0b1111_0000;
@@ -43,6 +45,31 @@ pub fn decode_packet(f_header: FixedHeader, buf: &mut Bytes) -> Result<MqttPacke
     MqttPacket::decode(f_header, buf)
 }
 
+/// Decodes a single packet from the front of a rolling receive buffer.
+///
+/// ## Returns
+///
+/// None if the buffer does not yet contain a complete packet, otherwise the packet and the number of bytes it consumed.
+/// The caller is responsible for advancing their buffer by the consumed length.
+pub fn decode_from(buf: &[u8]) -> Result<Option<(MqttPacket, usize)>, DecodeError> {
+    let (header_len, rest_len) = match peek_packet_length(buf)? {
+        Some(lens) => lens,
+        None => return Ok(None),
+    };
+
+    let packet_len = header_len + rest_len;
+    if buf.len() < packet_len {
+        return Ok(None);
+    }
+
+    let mut bytes = Bytes::copy_from_slice(&buf[0..packet_len]);
+    let f_header = FixedHeader::decode(&mut bytes)?;
+    bytes.advance(header_len);
+
+    let packet = MqttPacket::decode(f_header, &mut bytes)?;
+    return Ok(Some((packet, packet_len)));
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum MqttPacket {
     ConnAck(ConnAckPacket),
@@ -62,7 +89,22 @@ pub enum MqttPacket {
 }
 
 impl MqttPacket {
+    /// Decodes the packet body from the front of the buffer and advances the buffer past it.
+    ///
+    /// Only the packet's remaining length is consumed, any trailing bytes (i.e. the start of the next packet
+    /// in a TCP receive buffer) are left untouched for the next decode call.
     pub fn decode(f_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        if bytes.len() < f_header.rest_len {
+            return Err(decode_error!(
+                DecodeErrorKind::MalformedLength,
+                "Packet remaining length is {}, but only {} bytes were available.",
+                f_header.rest_len,
+                bytes.len()
+            ));
+        }
+
+        let bytes = &mut bytes.split_to(f_header.rest_len);
+
         return match f_header.type_ {
             PacketType::CONNACK => Ok(Self::ConnAck(ConnAckPacket::decode(bytes)?)),
             PacketType::CONNECT => Ok(Self::Connect(ConnectPacket::decode(bytes)?)),
@@ -99,6 +141,61 @@ impl MqttPacket {
             Self::Unsubscribe(packet) => packet.encode(),
         };
     }
+
+    pub fn packet_type(&self) -> PacketType {
+        return match self {
+            Self::ConnAck(_) => PacketType::CONNACK,
+            Self::Connect(_) => PacketType::CONNECT,
+            Self::Disconnect(_) => PacketType::DISCONNECT,
+            Self::PingReq(_) => PacketType::PINGREQ,
+            Self::PingResp(_) => PacketType::PINGRESP,
+            Self::PubAck(_) => PacketType::PUBACK,
+            Self::PubComp(_) => PacketType::PUBCOMP,
+            Self::Publish(_) => PacketType::PUBLISH,
+            Self::PubRec(_) => PacketType::PUBREC,
+            Self::PubRel(_) => PacketType::PUBREL,
+            Self::SubAck(_) => PacketType::SUBACK,
+            Self::Subscribe(_) => PacketType::SUBSCRIBE,
+            Self::UnsubAck(_) => PacketType::UNSUBACK,
+            Self::Unsubscribe(_) => PacketType::UNSUBSCRIBE,
+        };
+    }
+
+    /// Returns the packet identifier, None if the packet type does not carry one or if it is a QoS 0 PUBLISH.
+    pub fn packet_id(&self) -> Option<u16> {
+        return match self {
+            Self::PubAck(packet) => Some(packet.id()),
+            Self::PubComp(packet) => Some(packet.id()),
+            Self::Publish(packet) => packet.id(),
+            Self::PubRec(packet) => Some(packet.id()),
+            Self::PubRel(packet) => Some(packet.id()),
+            Self::SubAck(packet) => Some(packet.id()),
+            Self::Subscribe(packet) => Some(packet.id()),
+            Self::UnsubAck(packet) => Some(packet.id()),
+            Self::Unsubscribe(packet) => Some(packet.id()),
+            _ => None,
+        };
+    }
+}
+
+impl Display for MqttPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.packet_type())?;
+
+        if let Some(id) = self.packet_id() {
+            write!(f, " id: {id}")?;
+        }
+
+        if let Self::Publish(packet) = self {
+            write!(
+                f,
+                " topic: {}, payload: {} bytes",
+                packet.topic().clone().to_string(),
+                packet.payload().len()
+            )?;
+        }
+        return Ok(());
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -112,9 +209,9 @@ pub struct FixedHeader {
 impl FixedHeader {
     pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
         if bytes.len() == 0 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::ImproperDisconnect,
-                String::from("Received packet of length zero."),
+                "Received packet of length zero."
             ));
         }
 
@@ -176,25 +273,15 @@ impl TryFrom<(PacketType, u8)> for HeaderFlags {
             PacketType::PUBREL | PacketType::SUBSCRIBE | PacketType::UNSUBSCRIBE => {
                 // these packet types all require the flag bits 4 least significant bits to be 0010.
                 if byte & PACKET_FLAG_BITS != 2 {
-                    return Err(DecodeError::new(
-                        DecodeErrorKind::FlagBits,
-                        format!(
-                            "Invalid flag bits: {} for packet type: {}, byte must be == 2 for packet type {type_}.",
-                            byte, type_
-                        ),
-                    ));
+                    return Err(decode_error!(DecodeErrorKind::FlagBits, "Invalid flag bits: {} for packet type: {}, byte must be == 2 for packet type {type_}.",
+                            byte, type_));
                 }
             }
             _ => {
                 // all other packets must have flag bits that are equal to 0.
                 if byte & PACKET_FLAG_BITS != 0 {
-                    return Err(DecodeError::new(
-                        DecodeErrorKind::FlagBits,
-                        format!(
-                            "Invalid flag bits: {} for packet type: {}, bits must be == 0 for packet type {type_}.",
-                            byte, type_
-                        ),
-                    ));
+                    return Err(decode_error!(DecodeErrorKind::FlagBits, "Invalid flag bits: {} for packet type: {}, bits must be == 0 for packet type {type_}.",
+                            byte, type_));
                 }
             }
         }
@@ -262,9 +349,9 @@ impl TryFrom<u8> for PacketType {
             0xD0 => Self::PINGRESP,
             0xE0 => Self::DISCONNECT,
             _ => {
-                return Err(DecodeError::new(
+                return Err(decode_error!(
                     DecodeErrorKind::PacketType,
-                    format!("Packet type {value} is not a valid packet."),
+                    "Packet type {value} is not a valid packet."
                 ))
             }
         };
@@ -279,7 +366,7 @@ impl Display for PacketType {
             Self::CONNACK => write!(f, "PacketType::CONNACK"),
             Self::PUBLISH => write!(f, "PacketType::PUBLISH"),
             Self::PUBACK => write!(f, "PacketType::PUBACK"),
-            Self::PUBREC => write!(f, "PacketType::PUBREQ"),
+            Self::PUBREC => write!(f, "PacketType::PUBREC"),
             Self::PUBREL => write!(f, "PacketType::PUBREL"),
             Self::PUBCOMP => write!(f, "PacketType::PUBCOMP"),
             Self::SUBSCRIBE => write!(f, "PacketType::SUBSCRIBE"),
@@ -295,9 +382,10 @@ impl Display for PacketType {
 
 #[cfg(test)]
 mod packet {
-    use bytes::Bytes;
+    use bytes::{Buf, Bytes, BytesMut};
 
-    use super::FixedHeader;
+    use super::{decode_from, FixedHeader, MqttPacket, PacketType, PubAckPacket};
+    use crate::{topic::TopicName, v3::PublishPacket};
 
     #[test]
     fn deserialize() {
@@ -307,4 +395,82 @@ mod packet {
         assert_eq!(header.header_len, 2);
         assert_eq!(header.rest_len, 100);
     }
+
+    #[test]
+    fn decode_from_partial() {
+        let packet = PubAckPacket::new(1234);
+        let buf = packet.encode();
+
+        assert!(decode_from(&buf[0..1]).unwrap().is_none());
+        assert!(decode_from(&buf[0..3]).unwrap().is_none());
+
+        let (packet_de, len) = decode_from(&buf).unwrap().expect("Packet was incomplete");
+        assert_eq!(len, 4);
+        assert_eq!(packet_de, MqttPacket::PubAck(packet));
+    }
+
+    #[test]
+    fn back_to_back_packets() {
+        let topic_name = TopicName::from_str("back/to/back").unwrap();
+        let first = PublishPacket::new(&topic_name, Bytes::from_static(b"first"));
+        let second = PubAckPacket::new(1234);
+        let third = PublishPacket::new(&topic_name, Bytes::from_static(b"third"));
+
+        let mut buf = BytesMut::new();
+        first.encode_into(&mut buf).unwrap();
+        buf.extend_from_slice(&second.encode());
+        third.encode_into(&mut buf).unwrap();
+
+        // decoding with a fixed header leaves the trailing packets in the buffer.
+        let mut bytes: Bytes = buf.clone().into();
+        let f_header = FixedHeader::decode(&mut bytes).unwrap();
+        bytes.advance(f_header.header_len);
+        let packet_de = MqttPacket::decode(f_header, &mut bytes).unwrap();
+
+        assert_eq!(packet_de, MqttPacket::Publish(first.clone()));
+        assert_eq!(
+            bytes.len(),
+            second.encode().len() + third.encode().unwrap().len()
+        );
+
+        // decode_from walks the buffer one packet at a time.
+        let mut offset = 0;
+        let mut packets = vec![];
+        while let Some((packet, len)) = decode_from(&buf[offset..]).unwrap() {
+            packets.push(packet);
+            offset += len;
+        }
+
+        assert_eq!(offset, buf.len());
+        assert_eq!(
+            packets,
+            vec![
+                MqttPacket::Publish(first),
+                MqttPacket::PubAck(second),
+                MqttPacket::Publish(third)
+            ]
+        );
+    }
+
+    #[test]
+    fn packet_type_and_display() {
+        let topic_name = TopicName::from_str("display/topic").unwrap();
+        let mut publish = PublishPacket::new(&topic_name, Bytes::from_static(b"hello"));
+        publish.set_qos_atleastonce(42);
+        let publish = MqttPacket::Publish(publish);
+
+        assert_eq!(publish.packet_type(), PacketType::PUBLISH);
+        assert_eq!(publish.packet_id(), Some(42));
+        assert_eq!(
+            publish.to_string(),
+            "PacketType::PUBLISH id: 42 topic: display/topic, payload: 5 bytes"
+        );
+
+        let puback = MqttPacket::PubAck(PubAckPacket::new(7));
+        assert_eq!(puback.packet_type(), PacketType::PUBACK);
+        assert_eq!(puback.to_string(), "PacketType::PUBACK id: 7");
+
+        let qos0 = MqttPacket::Publish(PublishPacket::new(&topic_name, Bytes::new()));
+        assert_eq!(qos0.packet_id(), None);
+    }
 }
@@ -1,6 +1,6 @@
 use bytes::{BufMut, Bytes, BytesMut};
 
-use crate::err::{DecodeError, DecodeErrorKind};
+use crate::err::{decode_error, DecodeError, DecodeErrorKind};
 
 use super::{FixedHeader, PacketType};
 
@@ -22,9 +22,9 @@ impl PingReqPacket {
 
     pub fn decode(f_header: FixedHeader) -> Result<Self, DecodeError> {
         if f_header.rest_len != 0 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::MalformedLength,
-                String::from("PINGREQ packets can only contain a fixed header."),
+                "PINGREQ packets can only contain a fixed header."
             ));
         } else {
             return Ok(Self);
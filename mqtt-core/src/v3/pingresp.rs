@@ -1,5 +1,5 @@
 use crate::{
-    err::{DecodeError, DecodeErrorKind},
+    err::{decode_error, DecodeError, DecodeErrorKind},
     v3::{FixedHeader, PacketType},
 };
 
@@ -19,9 +19,9 @@ impl PingRespPacket {
 
     pub fn decode(f_header: FixedHeader) -> Result<Self, DecodeError> {
         if f_header.rest_len != 0 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::MalformedLength,
-                String::from("PINGRESP packets can only contain a fixed header."),
+                "PINGRESP packets can only contain a fixed header."
             ));
         } else {
             return Ok(Self);
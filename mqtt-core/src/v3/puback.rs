@@ -1,5 +1,5 @@
 use crate::{
-    err::{DecodeError, DecodeErrorKind},
+    err::{decode_error, DecodeError, DecodeErrorKind},
     v3::{FixedHeader, PacketType},
 };
 use bytes::{Buf, BufMut, Bytes, BytesMut};
@@ -19,9 +19,9 @@ impl PubAckPacket {
 
     pub fn decode(f_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, DecodeError> {
         if f_header.rest_len != 2 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::MalformedLength,
-                String::from("PUBACK packets can only contain a packet id."),
+                "PUBACK packets can only contain a packet id."
             ));
         } else {
             let id = bytes.get_u16();
@@ -1,6 +1,6 @@
 use crate::{
     err::{DecodeError, EncodeError},
-    io::{decode_utf8, encode_packet_length, encode_utf8},
+    io::{decode_utf8, encode_packet_length},
     qos::QosLevel,
     topic::TopicName,
     v3::{FixedHeader, PacketType},
@@ -85,6 +85,10 @@ impl PublishPacket {
         return &self.topic_name;
     }
 
+    pub fn set_topic(&mut self, topic_name: &TopicName) {
+        self.topic_name = topic_name.clone();
+    }
+
     pub fn decode(f_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, DecodeError> {
         let topic_name_in = decode_utf8(bytes)?;
         let topic_name = TopicName::from_str(topic_name_in.as_str())?;
@@ -106,6 +110,13 @@ impl PublishPacket {
     }
 
     pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        let mut bytes = BytesMut::new();
+        self.encode_into(&mut bytes)?;
+        return Ok(bytes.into());
+    }
+
+    /// Appends the encoded packet onto the end of the buffer, allowing the caller to reuse the buffer's allocation.
+    pub fn encode_into(&self, bytes: &mut BytesMut) -> Result<(), EncodeError> {
         // add size for topic length.
         let mut len = 2 + self.topic_name.len();
         // add 2 for packet id
@@ -116,13 +127,14 @@ impl PublishPacket {
 
         len += self.payload.len();
 
-        let mut bytes = BytesMut::with_capacity(len);
+        // 1 for the packet type, and at most 4 for the encoded length.
+        bytes.reserve(len + 5);
 
         bytes.put_u8(PacketType::PUBLISH as u8 | self.flags.byte);
 
-        encode_packet_length(&mut bytes, len)?;
+        encode_packet_length(bytes, len)?;
 
-        encode_utf8(&mut bytes, &self.topic_name.clone().to_string())?;
+        self.topic_name.encode(bytes);
 
         if let Some(packet_id) = self.packet_id {
             bytes.put_u16(packet_id);
@@ -130,7 +142,34 @@ impl PublishPacket {
 
         bytes.put_slice(&self.payload);
 
-        return Ok(bytes.into());
+        return Ok(());
+    }
+
+    /// Appends a QoS 0 PUBLISH packet onto the end of the buffer from a borrowed topic and payload.
+    ///
+    /// This avoids constructing a PublishPacket, which would clone the topic and copy the payload.
+    pub fn encode_qos0_into(
+        bytes: &mut BytesMut,
+        topic_name: &TopicName,
+        payload: &[u8],
+        retain: bool,
+    ) -> Result<(), EncodeError> {
+        let len = 2 + topic_name.len() + payload.len();
+
+        let mut flags = PublishFixedHeaderFlags::zero();
+        flags.set_retain(retain);
+
+        bytes.reserve(len + 5);
+
+        bytes.put_u8(PacketType::PUBLISH as u8 | flags.byte);
+
+        encode_packet_length(bytes, len)?;
+
+        topic_name.encode(bytes);
+
+        bytes.put_slice(payload);
+
+        return Ok(());
     }
 
     pub fn qos(&self) -> QosLevel {
@@ -300,7 +339,7 @@ mod packet {
     use crate::topic::TopicName;
     use crate::v3::{FixedHeader, MqttPacket};
     use bytes::Buf;
-    use bytes::Bytes;
+    use bytes::{Bytes, BytesMut};
 
     // Generic Packet test
     #[test]
@@ -336,4 +375,25 @@ mod packet {
 
         assert_eq!(packet_de, MqttPacket::Publish(packet));
     }
+
+    // Test the borrowed QoS 0 encoding matches the owned packet encoding
+    #[test]
+    fn encode_qos0_into() {
+        let topic_name =
+            TopicName::from_str("$SYS/is/a/test").expect("Could not create topic name");
+        let mut packet = PublishPacket::new(&topic_name, Bytes::from_iter([117, 118]));
+        packet.set_retain(true);
+
+        let mut buf = BytesMut::new();
+        PublishPacket::encode_qos0_into(&mut buf, &topic_name, &[117, 118], true).unwrap();
+
+        assert_eq!(buf, packet.encode().unwrap());
+
+        let mut buf: Bytes = buf.into();
+        let f_header = FixedHeader::decode(&mut buf).unwrap();
+        buf.advance(f_header.header_len);
+        let packet_de = MqttPacket::decode(f_header, &mut buf).expect("Could not decode packet");
+
+        assert_eq!(packet_de, MqttPacket::Publish(packet));
+    }
 }
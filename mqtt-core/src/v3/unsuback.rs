@@ -1,7 +1,7 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use crate::{
-    err::{DecodeError, DecodeErrorKind},
+    err::{decode_error, DecodeError, DecodeErrorKind},
     v3::{FixedHeader, PacketType},
 };
 
@@ -20,9 +20,9 @@ impl UnsubAckPacket {
 
     pub fn decode(f_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, DecodeError> {
         if f_header.rest_len != 2 {
-            return Err(DecodeError::new(
+            return Err(decode_error!(
                 DecodeErrorKind::MalformedLength,
-                String::from("UNSUBACK packets can only contain a packet id."),
+                "UNSUBACK packets can only contain a packet id."
             ));
         } else {
             let id = bytes.get_u16();
@@ -0,0 +1,141 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{DecodeError, EncodeError},
+    io::{decode_u16, decode_u8},
+    v5::{encode_packet, PacketType, Properties, ReasonCode},
+};
+
+/*
+ * PUBACK, PUBREC, PUBREL and PUBCOMP share a layout, the packet Id followed by an optional Reason Code and Properties.
+ *
+ * The Reason Code and Property Length can be omitted if the Reason Code is 0x00 (Success) and there are no Properties.
+ * In this case the packet has a Remaining Length of 2.
+ * If the Remaining Length is less than 4 there is no Property Length and the value of 0 is used.
+ */
+macro_rules! ack_packet {
+    ($(#[$doc:meta])* $name:ident, $packet_type:expr, $flags:expr) => {
+        $(#[$doc])*
+        #[derive(PartialEq, Clone, Debug)]
+        pub struct $name {
+            id: u16,
+            reason_code: ReasonCode,
+            properties: Properties,
+        }
+
+        impl $name {
+            pub fn new(id: u16, reason_code: ReasonCode) -> Self {
+                return Self {
+                    id,
+                    reason_code,
+                    properties: Properties::new(),
+                };
+            }
+
+            pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+                let id = decode_u16(bytes)?;
+
+                let reason_code = match bytes.has_remaining() {
+                    true => ReasonCode::try_from(decode_u8(bytes)?)?,
+                    false => ReasonCode::Success,
+                };
+
+                let properties = match bytes.has_remaining() {
+                    true => Properties::decode(bytes)?,
+                    false => Properties::new(),
+                };
+
+                return Ok(Self {
+                    id,
+                    reason_code,
+                    properties,
+                });
+            }
+
+            pub fn encode(&self) -> Result<Bytes, EncodeError> {
+                let mut body = BytesMut::new();
+                body.put_u16(self.id);
+
+                if self.reason_code != ReasonCode::Success || !self.properties.is_empty() {
+                    body.put_u8(self.reason_code.into());
+                }
+
+                if !self.properties.is_empty() {
+                    self.properties.encode(&mut body)?;
+                }
+
+                return encode_packet($packet_type as u8 | $flags, &body);
+            }
+
+            pub fn id(&self) -> u16 {
+                return self.id;
+            }
+
+            pub fn reason_code(&self) -> ReasonCode {
+                return self.reason_code;
+            }
+
+            pub fn properties(&self) -> &Properties {
+                return &self.properties;
+            }
+
+            pub fn properties_mut(&mut self) -> &mut Properties {
+                return &mut self.properties;
+            }
+        }
+    };
+}
+
+ack_packet!(
+    /// The response to a PUBLISH packet with QoS 1.
+    PubAckPacket,
+    PacketType::PUBACK,
+    0
+);
+ack_packet!(
+    /// The response to a PUBLISH packet with QoS 2, the second packet of the QoS 2 protocol exchange.
+    PubRecPacket,
+    PacketType::PUBREC,
+    0
+);
+ack_packet!(
+    /// The response to a PUBREC packet, the third packet of the QoS 2 protocol exchange.
+    PubRelPacket,
+    PacketType::PUBREL,
+    0b0000_0010
+);
+ack_packet!(
+    /// The response to a PUBREL packet, the fourth and final packet of the QoS 2 protocol exchange.
+    PubCompPacket,
+    PacketType::PUBCOMP,
+    0
+);
+
+#[cfg(test)]
+mod packet {
+    use super::{PubAckPacket, PubCompPacket, PubRecPacket, PubRelPacket};
+    use crate::v5::{packet::round_trip, MqttPacket, Property, ReasonCode};
+
+    #[test]
+    fn serialize_deserialize() {
+        // the reason code and properties are omitted on success.
+        let packet = PubAckPacket::new(1234, ReasonCode::Success);
+        assert_eq!(packet.encode().unwrap().len(), 4);
+        round_trip(MqttPacket::PubAck(packet));
+
+        round_trip(MqttPacket::PubRec(PubRecPacket::new(
+            1,
+            ReasonCode::QuotaExceeded,
+        )));
+        round_trip(MqttPacket::PubRel(PubRelPacket::new(
+            2,
+            ReasonCode::PacketIdentifierNotFound,
+        )));
+
+        let mut packet = PubCompPacket::new(3, ReasonCode::Success);
+        packet
+            .properties_mut()
+            .push(Property::ReasonString(String::from("done")));
+        round_trip(MqttPacket::PubComp(packet));
+    }
+}
@@ -0,0 +1,81 @@
+use bytes::Bytes;
+
+use crate::{
+    err::{DecodeError, EncodeError},
+    v5::{
+        disconnect::{decode_reason, encode_reason},
+        PacketType, Properties, ReasonCode,
+    },
+};
+
+/*
+ * An AUTH packet is sent from Client to Server or Server to Client as part of an extended authentication exchange,
+ * such as challenge / response authentication.
+ *
+ * The Authentication Method and Authentication Data are carried as properties.
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub struct AuthPacket {
+    reason_code: ReasonCode,
+    properties: Properties,
+}
+
+impl AuthPacket {
+    pub fn new(reason_code: ReasonCode) -> Self {
+        return Self {
+            reason_code,
+            properties: Properties::new(),
+        };
+    }
+
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let (reason_code, properties) = decode_reason(bytes)?;
+        return Ok(Self {
+            reason_code,
+            properties,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        return encode_reason(PacketType::AUTH as u8, self.reason_code, &self.properties);
+    }
+
+    pub fn reason_code(&self) -> ReasonCode {
+        return self.reason_code;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use bytes::Bytes;
+
+    use super::AuthPacket;
+    use crate::v5::{packet::round_trip, MqttPacket, Property, ReasonCode};
+
+    #[test]
+    fn serialize_deserialize() {
+        let mut packet = AuthPacket::new(ReasonCode::ContinueAuthentication);
+        packet
+            .properties_mut()
+            .push(Property::AuthenticationMethod(String::from("SCRAM-SHA-1")));
+        packet
+            .properties_mut()
+            .push(Property::AuthenticationData(Bytes::from_static(
+                b"challenge",
+            )));
+
+        assert_eq!(
+            packet.properties().authentication_method(),
+            Some("SCRAM-SHA-1")
+        );
+        round_trip(MqttPacket::Auth(packet));
+    }
+}
@@ -0,0 +1,103 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::decode_u8,
+    v5::{encode_packet, PacketType, Properties, ReasonCode},
+};
+
+/*
+ * The CONNACK packet is the packet sent by the Server in response to a CONNECT packet received from a Client.
+ *
+ * If the Server sends a CONNACK packet containing a Reason code of 128 or greater it MUST then close the
+ * Network Connection [MQTT-3.2.2-7].
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub struct ConnAckPacket {
+    session_present: bool,
+    reason_code: ReasonCode,
+    properties: Properties,
+}
+
+impl ConnAckPacket {
+    pub fn new(session_present: bool, reason_code: ReasonCode) -> Self {
+        return Self {
+            session_present,
+            reason_code,
+            properties: Properties::new(),
+        };
+    }
+
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let flags = decode_u8(bytes)?;
+
+        // Bits 7-1 are reserved and MUST be set to 0 [MQTT-3.2.2-1].
+        if flags & 0b1111_1110 != 0 {
+            return Err(decode_error!(
+                DecodeErrorKind::FlagBits,
+                "CONNACK reserved flag bits must be 0, received: {flags}"
+            ));
+        }
+
+        let reason_code = ReasonCode::try_from(decode_u8(bytes)?)?;
+        let properties = Properties::decode(bytes)?;
+
+        return Ok(Self {
+            session_present: flags == 1,
+            reason_code,
+            properties,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        let mut body = BytesMut::new();
+        body.put_u8(self.session_present as u8);
+        body.put_u8(self.reason_code.into());
+        self.properties.encode(&mut body)?;
+
+        return encode_packet(PacketType::CONNACK as u8, &body);
+    }
+
+    pub fn session_present(&self) -> bool {
+        return self.session_present;
+    }
+
+    pub fn set_session_present(&mut self, val: bool) {
+        self.session_present = val;
+    }
+
+    pub fn reason_code(&self) -> ReasonCode {
+        return self.reason_code;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use super::ConnAckPacket;
+    use crate::v5::{packet::round_trip, MqttPacket, Property, ReasonCode};
+
+    #[test]
+    fn serialize_deserialize() {
+        let mut packet = ConnAckPacket::new(true, ReasonCode::Success);
+        packet
+            .properties_mut()
+            .push(Property::AssignedClientIdentifier(String::from("auto-1")));
+        packet
+            .properties_mut()
+            .push(Property::TopicAliasMaximum(10));
+        round_trip(MqttPacket::ConnAck(packet));
+
+        round_trip(MqttPacket::ConnAck(ConnAckPacket::new(
+            false,
+            ReasonCode::UnsupportedProtocolVersion,
+        )));
+    }
+}
@@ -0,0 +1,283 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::{decode_bytes, decode_u16, decode_u8, decode_utf8, encode_bytes, encode_utf8},
+    qos::QosLevel,
+    topic::TopicName,
+    v3::ConnectFlags,
+    v5::{encode_packet, PacketType, Properties, PROTOCOL_LEVEL},
+};
+
+/*
+ * After a Network Connection is established by a Client to a Server, the first packet sent from the Client to the
+ * Server MUST be a CONNECT packet [MQTT-3.1.0-1].
+ *
+ * The layout matches the v3.1.1 CONNECT packet, with the Protocol Version set to 5, a set of Properties after the
+ * Keep Alive, and a set of Will Properties before the Will Topic. The Clean Session flag is renamed to Clean Start,
+ * the lifetime of the session is instead controlled by the Session Expiry Interval property.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConnectPacket {
+    conn_flags: ConnectFlags,
+    keep_alive: u16,
+    properties: Properties,
+    client_id: String,
+    will: Option<Will>,
+    username: Option<String>,
+    password: Option<Bytes>,
+}
+
+impl ConnectPacket {
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let protocol = decode_utf8(bytes)?;
+        if protocol != "MQTT" {
+            return Err(decode_error!(
+                DecodeErrorKind::InvalidProtocol,
+                "Only MQTT packet types are allowed, instead received type: {protocol}"
+            ));
+        }
+
+        let level = decode_u8(bytes)?;
+        if level != PROTOCOL_LEVEL {
+            return Err(decode_error!(
+                DecodeErrorKind::InvalidProtocol,
+                "Mqtt V5 Requires Protocol level to be 5, instead received: {level}"
+            ));
+        }
+
+        let conn_flags = ConnectFlags::from_byte(decode_u8(bytes)?)?;
+        let keep_alive = decode_u16(bytes)?;
+        let properties = Properties::decode(bytes)?;
+
+        let client_id = decode_utf8(bytes)?;
+
+        let mut will = None;
+        if conn_flags.will() {
+            let properties = Properties::decode(bytes)?;
+            let topic = TopicName::from_str(&decode_utf8(bytes)?)?;
+            let payload = decode_bytes(bytes)?;
+
+            will = Some(Will {
+                properties,
+                topic,
+                payload,
+                qos: conn_flags.will_qos(),
+                retain: conn_flags.will_retain(),
+            });
+        }
+
+        let mut username = None;
+        if conn_flags.user_name() {
+            username = Some(decode_utf8(bytes)?);
+        }
+
+        let mut password = None;
+        if conn_flags.password() {
+            password = Some(decode_bytes(bytes)?);
+        }
+
+        return Ok(Self {
+            conn_flags,
+            keep_alive,
+            properties,
+            client_id,
+            will,
+            username,
+            password,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        let mut body = BytesMut::new();
+
+        encode_utf8(&mut body, "MQTT")?;
+        body.put_u8(PROTOCOL_LEVEL);
+        body.put_u8(self.conn_flags.as_byte());
+        body.put_u16(self.keep_alive);
+        self.properties.encode(&mut body)?;
+
+        encode_utf8(&mut body, &self.client_id)?;
+
+        if let Some(will) = &self.will {
+            will.properties.encode(&mut body)?;
+            encode_utf8(&mut body, &will.topic.clone().to_string())?;
+            encode_bytes(&mut body, &will.payload)?;
+        }
+
+        if let Some(username) = &self.username {
+            encode_utf8(&mut body, username)?;
+        }
+
+        if let Some(password) = &self.password {
+            encode_bytes(&mut body, password)?;
+        }
+
+        return encode_packet(PacketType::CONNECT as u8, &body);
+    }
+
+    pub fn new(
+        clean_start: bool,
+        keep_alive: u16,
+        client_id: String,
+        will: Option<Will>,
+        username: Option<String>,
+        password: Option<Bytes>,
+    ) -> Self {
+        let mut conn_flags = ConnectFlags::default();
+
+        conn_flags.set_user_name(username.is_some());
+        conn_flags.set_password(password.is_some());
+        conn_flags.set_clean_session(clean_start);
+
+        if let Some(will) = &will {
+            conn_flags.set_will(true);
+            conn_flags.set_will_qos(will.qos);
+            conn_flags.set_will_retain(will.retain);
+        }
+
+        return Self {
+            conn_flags,
+            keep_alive,
+            properties: Properties::new(),
+            client_id,
+            will,
+            username,
+            password,
+        };
+    }
+
+    pub fn client_id(&self) -> &str {
+        return &self.client_id;
+    }
+
+    pub fn keep_alive(&self) -> u16 {
+        return self.keep_alive;
+    }
+
+    pub fn clean_start(&self) -> bool {
+        return self.conn_flags.clean_session();
+    }
+
+    pub fn will(&self) -> &Option<Will> {
+        return &self.will;
+    }
+
+    pub fn username(&self) -> &Option<String> {
+        return &self.username;
+    }
+
+    pub fn password(&self) -> &Option<Bytes> {
+        return &self.password;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+/// The Will message of a v5 CONNECT packet, unlike v3.1.1 the payload is binary data and carries its own properties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Will {
+    properties: Properties,
+    topic: TopicName,
+    payload: Bytes,
+    qos: QosLevel,
+    retain: bool,
+}
+
+impl Will {
+    pub fn new(topic: TopicName, payload: Bytes, qos: QosLevel, retain: bool) -> Self {
+        return Self {
+            properties: Properties::new(),
+            topic,
+            payload,
+            qos,
+            retain,
+        };
+    }
+
+    pub fn topic(&self) -> &TopicName {
+        return &self.topic;
+    }
+
+    pub fn payload(&self) -> &Bytes {
+        return &self.payload;
+    }
+
+    pub fn qos(&self) -> QosLevel {
+        return self.qos;
+    }
+
+    pub fn retain(&self) -> bool {
+        return self.retain;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use bytes::Bytes;
+
+    use super::{ConnectPacket, Will};
+    use crate::{
+        qos::QosLevel,
+        topic::TopicName,
+        v5::{packet::round_trip, MqttPacket, Property},
+    };
+
+    #[test]
+    fn serialize_deserialize() {
+        round_trip(MqttPacket::Connect(ConnectPacket::new(
+            true,
+            60,
+            String::from("id_1"),
+            None,
+            None,
+            None,
+        )));
+
+        let mut will = Will::new(
+            TopicName::from_str("clients/id_2/status").unwrap(),
+            Bytes::from_static(&[0, 159, 146, 150]),
+            QosLevel::AtLeastOnce,
+            true,
+        );
+        will.properties_mut().push(Property::WillDelayInterval(30));
+
+        let mut packet = ConnectPacket::new(
+            false,
+            30,
+            String::from("id_2"),
+            Some(will),
+            Some(String::from("user")),
+            Some(Bytes::from_static(b"password")),
+        );
+        packet
+            .properties_mut()
+            .push(Property::SessionExpiryInterval(3600));
+        packet.properties_mut().push(Property::ReceiveMaximum(10));
+
+        assert!(!packet.clean_start());
+        round_trip(MqttPacket::Connect(packet));
+    }
+
+    #[test]
+    fn rejects_v3_level() {
+        let packet = crate::v3::ConnectPacket::new(true, 60, String::from("id"), None, None, None);
+        let mut buf = packet.encode().unwrap();
+
+        assert!(crate::v5::decode_from(&buf).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{DecodeError, EncodeError},
+    io::decode_u8,
+    v5::{encode_packet, PacketType, Properties, ReasonCode},
+};
+
+/*
+ * The DISCONNECT packet is the final MQTT Control Packet sent from the Client or the Server.
+ * Unlike v3.1.1 it can be sent by the Server, and it carries the reason the connection is being closed.
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub struct DisconnectPacket {
+    reason_code: ReasonCode,
+    properties: Properties,
+}
+
+impl DisconnectPacket {
+    pub fn new(reason_code: ReasonCode) -> Self {
+        return Self {
+            reason_code,
+            properties: Properties::new(),
+        };
+    }
+
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let (reason_code, properties) = decode_reason(bytes)?;
+        return Ok(Self {
+            reason_code,
+            properties,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        return encode_reason(
+            PacketType::DISCONNECT as u8,
+            self.reason_code,
+            &self.properties,
+        );
+    }
+
+    pub fn reason_code(&self) -> ReasonCode {
+        return self.reason_code;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+/// Decodes the optional Reason Code and Properties of a DISCONNECT or AUTH packet.
+///
+/// If the Remaining Length is 0 the Reason Code is 0x00 (Success) and there are no Properties.
+pub(super) fn decode_reason(bytes: &mut Bytes) -> Result<(ReasonCode, Properties), DecodeError> {
+    let reason_code = match bytes.has_remaining() {
+        true => ReasonCode::try_from(decode_u8(bytes)?)?,
+        false => ReasonCode::Success,
+    };
+
+    let properties = match bytes.has_remaining() {
+        true => Properties::decode(bytes)?,
+        false => Properties::new(),
+    };
+
+    return Ok((reason_code, properties));
+}
+
+/// Encodes a DISCONNECT or AUTH packet, omitting the Reason Code and Properties when they can be inferred.
+pub(super) fn encode_reason(
+    first_byte: u8,
+    reason_code: ReasonCode,
+    properties: &Properties,
+) -> Result<Bytes, EncodeError> {
+    let mut body = BytesMut::new();
+
+    if reason_code != ReasonCode::Success || !properties.is_empty() {
+        body.put_u8(reason_code.into());
+    }
+
+    if !properties.is_empty() {
+        properties.encode(&mut body)?;
+    }
+
+    return encode_packet(first_byte, &body);
+}
+
+#[cfg(test)]
+mod packet {
+    use super::DisconnectPacket;
+    use crate::v5::{packet::round_trip, MqttPacket, Property, ReasonCode};
+
+    #[test]
+    fn serialize_deserialize() {
+        let packet = DisconnectPacket::new(ReasonCode::Success);
+        assert_eq!(packet.encode().unwrap().len(), 2);
+        round_trip(MqttPacket::Disconnect(packet));
+
+        round_trip(MqttPacket::Disconnect(DisconnectPacket::new(
+            ReasonCode::SessionTakenOver,
+        )));
+
+        let mut packet = DisconnectPacket::new(ReasonCode::DisconnectWithWillMessage);
+        packet
+            .properties_mut()
+            .push(Property::SessionExpiryInterval(0));
+        round_trip(MqttPacket::Disconnect(packet));
+    }
+}
@@ -0,0 +1,372 @@
+//! MQTT 5.0 packet codec.
+//!
+//! https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+mod ack;
+mod auth;
+mod connack;
+mod connect;
+mod disconnect;
+mod ping;
+mod properties;
+mod publish;
+mod reason;
+mod suback;
+mod subscribe;
+mod unsubscribe;
+
+pub use ack::{PubAckPacket, PubCompPacket, PubRecPacket, PubRelPacket};
+pub use auth::AuthPacket;
+pub use connack::ConnAckPacket;
+pub use connect::{ConnectPacket, Will};
+pub use disconnect::DisconnectPacket;
+pub use ping::{PingReqPacket, PingRespPacket};
+pub use properties::{Properties, Property};
+pub use publish::PublishPacket;
+pub use reason::ReasonCode;
+pub use std::fmt::{Debug, Display};
+pub use suback::{SubAckPacket, UnsubAckPacket};
+pub use subscribe::{RetainHandling, SubscribePacket, SubscriptionOptions};
+pub use unsubscribe::UnsubscribePacket;
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::{decode_packet_length, encode_packet_length, peek_packet_length},
+};
+
+/// The value of the Protocol Version field of an MQTT 5.0 CONNECT packet.
+pub const PROTOCOL_LEVEL: u8 = 5;
+
+const PACKET_TYPE_BITS: u8 = 0b1111_0000;
+const PACKET_FLAG_BITS: u8 = 0b0000_1111;
+
+pub fn decode_packet(f_header: FixedHeader, buf: &mut Bytes) -> Result<MqttPacket, DecodeError> {
+    MqttPacket::decode(f_header, buf)
+}
+
+/// Decodes a single packet from the front of a rolling receive buffer, see [crate::v3::decode_from].
+pub fn decode_from(buf: &[u8]) -> Result<Option<(MqttPacket, usize)>, DecodeError> {
+    let (header_len, rest_len) = match peek_packet_length(buf)? {
+        Some(lens) => lens,
+        None => return Ok(None),
+    };
+
+    let packet_len = header_len + rest_len;
+    if buf.len() < packet_len {
+        return Ok(None);
+    }
+
+    let mut bytes = Bytes::copy_from_slice(&buf[0..packet_len]);
+    let f_header = FixedHeader::decode(&mut bytes)?;
+    bytes.advance(header_len);
+
+    let packet = MqttPacket::decode(f_header, &mut bytes)?;
+    return Ok(Some((packet, packet_len)));
+}
+
+/// Writes the fixed header followed by the already encoded variable header and payload.
+fn encode_packet(first_byte: u8, body: &[u8]) -> Result<Bytes, EncodeError> {
+    let mut bytes = BytesMut::with_capacity(body.len() + 5);
+    bytes.put_u8(first_byte);
+    encode_packet_length(&mut bytes, body.len())?;
+    bytes.put_slice(body);
+    return Ok(bytes.into());
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum MqttPacket {
+    Auth(AuthPacket),
+    ConnAck(ConnAckPacket),
+    Connect(ConnectPacket),
+    Disconnect(DisconnectPacket),
+    PingReq(PingReqPacket),
+    PingResp(PingRespPacket),
+    PubAck(PubAckPacket),
+    PubComp(PubCompPacket),
+    Publish(PublishPacket),
+    PubRec(PubRecPacket),
+    PubRel(PubRelPacket),
+    SubAck(SubAckPacket),
+    Subscribe(SubscribePacket),
+    UnsubAck(UnsubAckPacket),
+    Unsubscribe(UnsubscribePacket),
+}
+
+impl MqttPacket {
+    /// Decodes the packet body from the front of the buffer and advances the buffer past it.
+    pub fn decode(f_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        if bytes.len() < f_header.rest_len {
+            return Err(decode_error!(
+                DecodeErrorKind::MalformedLength,
+                "Packet remaining length is {}, but only {} bytes were available.",
+                f_header.rest_len,
+                bytes.len()
+            ));
+        }
+
+        let bytes = &mut bytes.split_to(f_header.rest_len);
+
+        return match f_header.type_ {
+            PacketType::AUTH => Ok(Self::Auth(AuthPacket::decode(bytes)?)),
+            PacketType::CONNACK => Ok(Self::ConnAck(ConnAckPacket::decode(bytes)?)),
+            PacketType::CONNECT => Ok(Self::Connect(ConnectPacket::decode(bytes)?)),
+            PacketType::DISCONNECT => Ok(Self::Disconnect(DisconnectPacket::decode(bytes)?)),
+            PacketType::PINGREQ => Ok(Self::PingReq(PingReqPacket::decode(f_header)?)),
+            PacketType::PINGRESP => Ok(Self::PingResp(PingRespPacket::decode(f_header)?)),
+            PacketType::PUBACK => Ok(Self::PubAck(PubAckPacket::decode(bytes)?)),
+            PacketType::PUBCOMP => Ok(Self::PubComp(PubCompPacket::decode(bytes)?)),
+            PacketType::PUBLISH => Ok(Self::Publish(PublishPacket::decode(f_header, bytes)?)),
+            PacketType::PUBREL => Ok(Self::PubRel(PubRelPacket::decode(bytes)?)),
+            PacketType::PUBREC => Ok(Self::PubRec(PubRecPacket::decode(bytes)?)),
+            PacketType::SUBACK => Ok(Self::SubAck(SubAckPacket::decode(bytes)?)),
+            PacketType::SUBSCRIBE => Ok(Self::Subscribe(SubscribePacket::decode(bytes)?)),
+            PacketType::UNSUBACK => Ok(Self::UnsubAck(UnsubAckPacket::decode(bytes)?)),
+            PacketType::UNSUBSCRIBE => Ok(Self::Unsubscribe(UnsubscribePacket::decode(bytes)?)),
+        };
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        return match self {
+            Self::Auth(packet) => packet.encode(),
+            Self::ConnAck(packet) => packet.encode(),
+            Self::Connect(packet) => packet.encode(),
+            Self::Disconnect(packet) => packet.encode(),
+            Self::PingReq(packet) => Ok(packet.encode()),
+            Self::PingResp(packet) => Ok(packet.encode()),
+            Self::PubAck(packet) => packet.encode(),
+            Self::PubComp(packet) => packet.encode(),
+            Self::Publish(packet) => packet.encode(),
+            Self::PubRel(packet) => packet.encode(),
+            Self::PubRec(packet) => packet.encode(),
+            Self::SubAck(packet) => packet.encode(),
+            Self::Subscribe(packet) => packet.encode(),
+            Self::UnsubAck(packet) => packet.encode(),
+            Self::Unsubscribe(packet) => packet.encode(),
+        };
+    }
+
+    pub fn packet_type(&self) -> PacketType {
+        return match self {
+            Self::Auth(_) => PacketType::AUTH,
+            Self::ConnAck(_) => PacketType::CONNACK,
+            Self::Connect(_) => PacketType::CONNECT,
+            Self::Disconnect(_) => PacketType::DISCONNECT,
+            Self::PingReq(_) => PacketType::PINGREQ,
+            Self::PingResp(_) => PacketType::PINGRESP,
+            Self::PubAck(_) => PacketType::PUBACK,
+            Self::PubComp(_) => PacketType::PUBCOMP,
+            Self::Publish(_) => PacketType::PUBLISH,
+            Self::PubRec(_) => PacketType::PUBREC,
+            Self::PubRel(_) => PacketType::PUBREL,
+            Self::SubAck(_) => PacketType::SUBACK,
+            Self::Subscribe(_) => PacketType::SUBSCRIBE,
+            Self::UnsubAck(_) => PacketType::UNSUBACK,
+            Self::Unsubscribe(_) => PacketType::UNSUBSCRIBE,
+        };
+    }
+
+    /// Returns the packet identifier, None if the packet type does not carry one or if it is a QoS 0 PUBLISH.
+    pub fn packet_id(&self) -> Option<u16> {
+        return match self {
+            Self::PubAck(packet) => Some(packet.id()),
+            Self::PubComp(packet) => Some(packet.id()),
+            Self::Publish(packet) => packet.id(),
+            Self::PubRec(packet) => Some(packet.id()),
+            Self::PubRel(packet) => Some(packet.id()),
+            Self::SubAck(packet) => Some(packet.id()),
+            Self::Subscribe(packet) => Some(packet.id()),
+            Self::UnsubAck(packet) => Some(packet.id()),
+            Self::Unsubscribe(packet) => Some(packet.id()),
+            _ => None,
+        };
+    }
+}
+
+impl Display for MqttPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.packet_type())?;
+
+        if let Some(id) = self.packet_id() {
+            write!(f, " id: {id}")?;
+        }
+
+        if let Self::Publish(packet) = self {
+            if let Some(topic) = packet.topic() {
+                write!(f, " topic: {}", topic.clone().to_string())?;
+            }
+            write!(f, " payload: {} bytes", packet.payload().len())?;
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FixedHeader {
+    pub type_: PacketType,
+    pub flags: u8,
+    rest_len: usize,
+    header_len: usize,
+}
+
+impl FixedHeader {
+    /// Will NOT advance the buffer, the caller is responsible for advancing the buffer past the header.
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        if bytes.len() == 0 {
+            return Err(decode_error!(
+                DecodeErrorKind::ImproperDisconnect,
+                "Received packet of length zero."
+            ));
+        }
+
+        let byte = bytes[0];
+        let type_ = PacketType::try_from(byte)?;
+        let flags = byte & PACKET_FLAG_BITS;
+
+        match type_ {
+            PacketType::PUBLISH => {
+                // the flags are validated when the PUBLISH packet is decoded.
+            }
+            PacketType::PUBREL | PacketType::SUBSCRIBE | PacketType::UNSUBSCRIBE => {
+                if flags != 2 {
+                    return Err(decode_error!(
+                        DecodeErrorKind::FlagBits,
+                        "Invalid flag bits: {flags} for packet type: {type_}, bits must be == 2."
+                    ));
+                }
+            }
+            _ => {
+                if flags != 0 {
+                    return Err(decode_error!(
+                        DecodeErrorKind::FlagBits,
+                        "Invalid flag bits: {flags} for packet type: {type_}, bits must be == 0."
+                    ));
+                }
+            }
+        }
+
+        let (header_len, rest_len) = decode_packet_length(bytes)?;
+
+        return Ok(Self {
+            type_,
+            flags,
+            header_len,
+            rest_len,
+        });
+    }
+
+    pub fn header_len(&self) -> usize {
+        return self.header_len;
+    }
+
+    pub fn rest_len(&self) -> usize {
+        return self.rest_len;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum PacketType {
+    CONNECT = 0b0001_0000,
+    CONNACK = 0b0010_0000,
+    PUBLISH = 0b0011_0000,
+    PUBACK = 0b0100_0000,
+    PUBREC = 0b0101_0000,
+    PUBREL = 0b0110_0000,
+    PUBCOMP = 0b0111_0000,
+    SUBSCRIBE = 0b1000_0000,
+    SUBACK = 0b1001_0000,
+    UNSUBSCRIBE = 0b1010_0000,
+    UNSUBACK = 0b1011_0000,
+    PINGREQ = 0b1100_0000,
+    PINGRESP = 0b1101_0000,
+    DISCONNECT = 0b1110_0000,
+    AUTH = 0b1111_0000,
+}
+
+impl TryFrom<u8> for PacketType {
+    type Error = DecodeError;
+    fn try_from(value: u8) -> Result<Self, DecodeError> {
+        // we only want to match on the left four bits.
+        let out = match value & PACKET_TYPE_BITS {
+            0x10 => Self::CONNECT,
+            0x20 => Self::CONNACK,
+            0x30 => Self::PUBLISH,
+            0x40 => Self::PUBACK,
+            0x50 => Self::PUBREC,
+            0x60 => Self::PUBREL,
+            0x70 => Self::PUBCOMP,
+            0x80 => Self::SUBSCRIBE,
+            0x90 => Self::SUBACK,
+            0xA0 => Self::UNSUBSCRIBE,
+            0xB0 => Self::UNSUBACK,
+            0xC0 => Self::PINGREQ,
+            0xD0 => Self::PINGRESP,
+            0xE0 => Self::DISCONNECT,
+            0xF0 => Self::AUTH,
+            _ => {
+                return Err(decode_error!(
+                    DecodeErrorKind::PacketType,
+                    "Packet type {value} is not a valid packet."
+                ))
+            }
+        };
+        return Ok(out);
+    }
+}
+
+impl Display for PacketType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PacketType::{:?}", self)
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use bytes::{Buf, Bytes, BytesMut};
+
+    use super::{
+        decode_from, FixedHeader, MqttPacket, PacketType, PubAckPacket, PublishPacket, ReasonCode,
+    };
+    use crate::topic::TopicName;
+
+    /// Encodes then decodes the packet, asserting the decoded packet is equal to the original.
+    pub(crate) fn round_trip(packet: MqttPacket) {
+        let mut buf = packet.encode().unwrap();
+
+        let f_header = FixedHeader::decode(&mut buf).unwrap();
+        buf.advance(f_header.header_len());
+        let packet_de = MqttPacket::decode(f_header, &mut buf).expect("Could not decode packet");
+
+        assert_eq!(packet_de, packet);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn auth_packet_type() {
+        let mut bytes = Bytes::from_iter([0b1111_0000, 0]);
+        let header = FixedHeader::decode(&mut bytes).expect("Could not decode header.");
+
+        assert_eq!(header.type_, PacketType::AUTH);
+        assert_eq!(PacketType::AUTH.to_string(), "PacketType::AUTH");
+    }
+
+    #[test]
+    fn back_to_back_packets() {
+        let topic_name = TopicName::from_str("back/to/back").unwrap();
+        let first = PublishPacket::new(&topic_name, Bytes::from_static(b"first"));
+        let second = PubAckPacket::new(1234, ReasonCode::NoMatchingSubscribers);
+
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&first.encode().unwrap());
+        buf.extend_from_slice(&second.encode().unwrap());
+
+        let (packet, len) = decode_from(&buf).unwrap().unwrap();
+        assert_eq!(packet, MqttPacket::Publish(first));
+        buf.advance(len);
+
+        let (packet, len) = decode_from(&buf).unwrap().unwrap();
+        assert_eq!(packet, MqttPacket::PubAck(second));
+        assert_eq!(len, buf.len());
+    }
+}
@@ -0,0 +1,73 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind},
+    v5::{FixedHeader, PacketType},
+};
+
+/*
+ * PINGREQ and PINGRESP are unchanged from v3.1.1, they only contain a fixed header.
+ */
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct PingReqPacket;
+
+impl PingReqPacket {
+    pub fn new() -> Self {
+        return Self;
+    }
+
+    pub fn decode(f_header: FixedHeader) -> Result<Self, DecodeError> {
+        if f_header.rest_len != 0 {
+            return Err(decode_error!(
+                DecodeErrorKind::MalformedLength,
+                "PINGREQ packets can only contain a fixed header."
+            ));
+        }
+        return Ok(Self);
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        bytes.put_u8(PacketType::PINGREQ as u8);
+        bytes.put_u8(0);
+        return bytes.into();
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct PingRespPacket;
+
+impl PingRespPacket {
+    pub fn new() -> Self {
+        return Self;
+    }
+
+    pub fn decode(f_header: FixedHeader) -> Result<Self, DecodeError> {
+        if f_header.rest_len != 0 {
+            return Err(decode_error!(
+                DecodeErrorKind::MalformedLength,
+                "PINGRESP packets can only contain a fixed header."
+            ));
+        }
+        return Ok(Self);
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        bytes.put_u8(PacketType::PINGRESP as u8);
+        bytes.put_u8(0);
+        return bytes.into();
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use super::{PingReqPacket, PingRespPacket};
+    use crate::v5::{packet::round_trip, MqttPacket};
+
+    #[test]
+    fn serialize_deserialize() {
+        round_trip(MqttPacket::PingReq(PingReqPacket::new()));
+        round_trip(MqttPacket::PingResp(PingRespPacket::new()));
+    }
+}
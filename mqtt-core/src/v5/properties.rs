@@ -0,0 +1,402 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::{
+        decode_bytes, decode_u16, decode_u32, decode_u8, decode_utf8, decode_variable_int,
+        encode_bytes, encode_packet_length, encode_utf8,
+    },
+};
+
+/*
+ * The last field in the Variable Header of the CONNECT, CONNACK, PUBLISH, PUBACK, PUBREC, PUBREL, PUBCOMP, SUBSCRIBE,
+ * SUBACK, UNSUBSCRIBE, UNSUBACK, DISCONNECT, and AUTH packet is a set of Properties. In the CONNECT packet there is
+ * also an optional set of Properties in the Will Properties field with the Payload.
+ *
+ * The set of Properties is composed of a Property Length followed by the Properties. A Property consists of an
+ * Identifier which defines its usage and data type, followed by a value.
+ *
+ * https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901027
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum Property {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(String),
+    ResponseTopic(String),
+    CorrelationData(Bytes),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(String),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(String),
+    AuthenticationData(Bytes),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(String),
+    ServerReference(String),
+    ReasonString(String),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQoS(u8),
+    RetainAvailable(u8),
+    UserProperty(String, String),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+impl Property {
+    /// The identifier the property is encoded with.
+    pub fn id(&self) -> u8 {
+        return match self {
+            Self::PayloadFormatIndicator(_) => 0x01,
+            Self::MessageExpiryInterval(_) => 0x02,
+            Self::ContentType(_) => 0x03,
+            Self::ResponseTopic(_) => 0x08,
+            Self::CorrelationData(_) => 0x09,
+            Self::SubscriptionIdentifier(_) => 0x0B,
+            Self::SessionExpiryInterval(_) => 0x11,
+            Self::AssignedClientIdentifier(_) => 0x12,
+            Self::ServerKeepAlive(_) => 0x13,
+            Self::AuthenticationMethod(_) => 0x15,
+            Self::AuthenticationData(_) => 0x16,
+            Self::RequestProblemInformation(_) => 0x17,
+            Self::WillDelayInterval(_) => 0x18,
+            Self::RequestResponseInformation(_) => 0x19,
+            Self::ResponseInformation(_) => 0x1A,
+            Self::ServerReference(_) => 0x1C,
+            Self::ReasonString(_) => 0x1F,
+            Self::ReceiveMaximum(_) => 0x21,
+            Self::TopicAliasMaximum(_) => 0x22,
+            Self::TopicAlias(_) => 0x23,
+            Self::MaximumQoS(_) => 0x24,
+            Self::RetainAvailable(_) => 0x25,
+            Self::UserProperty(_, _) => 0x26,
+            Self::MaximumPacketSize(_) => 0x27,
+            Self::WildcardSubscriptionAvailable(_) => 0x28,
+            Self::SubscriptionIdentifierAvailable(_) => 0x29,
+            Self::SharedSubscriptionAvailable(_) => 0x2A,
+        };
+    }
+
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let id = decode_variable_int(bytes)?;
+
+        let property = match id {
+            0x01 => Self::PayloadFormatIndicator(decode_u8(bytes)?),
+            0x02 => Self::MessageExpiryInterval(decode_u32(bytes)?),
+            0x03 => Self::ContentType(decode_utf8(bytes)?),
+            0x08 => Self::ResponseTopic(decode_utf8(bytes)?),
+            0x09 => Self::CorrelationData(decode_bytes(bytes)?),
+            0x0B => Self::SubscriptionIdentifier(decode_variable_int(bytes)? as u32),
+            0x11 => Self::SessionExpiryInterval(decode_u32(bytes)?),
+            0x12 => Self::AssignedClientIdentifier(decode_utf8(bytes)?),
+            0x13 => Self::ServerKeepAlive(decode_u16(bytes)?),
+            0x15 => Self::AuthenticationMethod(decode_utf8(bytes)?),
+            0x16 => Self::AuthenticationData(decode_bytes(bytes)?),
+            0x17 => Self::RequestProblemInformation(decode_u8(bytes)?),
+            0x18 => Self::WillDelayInterval(decode_u32(bytes)?),
+            0x19 => Self::RequestResponseInformation(decode_u8(bytes)?),
+            0x1A => Self::ResponseInformation(decode_utf8(bytes)?),
+            0x1C => Self::ServerReference(decode_utf8(bytes)?),
+            0x1F => Self::ReasonString(decode_utf8(bytes)?),
+            0x21 => Self::ReceiveMaximum(decode_u16(bytes)?),
+            0x22 => Self::TopicAliasMaximum(decode_u16(bytes)?),
+            0x23 => Self::TopicAlias(decode_u16(bytes)?),
+            0x24 => Self::MaximumQoS(decode_u8(bytes)?),
+            0x25 => Self::RetainAvailable(decode_u8(bytes)?),
+            0x26 => Self::UserProperty(decode_utf8(bytes)?, decode_utf8(bytes)?),
+            0x27 => Self::MaximumPacketSize(decode_u32(bytes)?),
+            0x28 => Self::WildcardSubscriptionAvailable(decode_u8(bytes)?),
+            0x29 => Self::SubscriptionIdentifierAvailable(decode_u8(bytes)?),
+            0x2A => Self::SharedSubscriptionAvailable(decode_u8(bytes)?),
+            _ => {
+                return Err(decode_error!(
+                    DecodeErrorKind::InvalidProperty,
+                    "Property identifier: {id} is not a valid MQTT 5 property."
+                ))
+            }
+        };
+
+        return Ok(property);
+    }
+
+    pub fn encode(&self, bytes: &mut BytesMut) -> Result<(), EncodeError> {
+        encode_packet_length(bytes, self.id() as usize)?;
+
+        match self {
+            Self::PayloadFormatIndicator(val)
+            | Self::RequestProblemInformation(val)
+            | Self::RequestResponseInformation(val)
+            | Self::MaximumQoS(val)
+            | Self::RetainAvailable(val)
+            | Self::WildcardSubscriptionAvailable(val)
+            | Self::SubscriptionIdentifierAvailable(val)
+            | Self::SharedSubscriptionAvailable(val) => bytes.put_u8(*val),
+            Self::ServerKeepAlive(val)
+            | Self::ReceiveMaximum(val)
+            | Self::TopicAliasMaximum(val)
+            | Self::TopicAlias(val) => bytes.put_u16(*val),
+            Self::MessageExpiryInterval(val)
+            | Self::SessionExpiryInterval(val)
+            | Self::WillDelayInterval(val)
+            | Self::MaximumPacketSize(val) => bytes.put_u32(*val),
+            Self::SubscriptionIdentifier(val) => {
+                encode_packet_length(bytes, *val as usize)?;
+            }
+            Self::ContentType(val)
+            | Self::ResponseTopic(val)
+            | Self::AssignedClientIdentifier(val)
+            | Self::AuthenticationMethod(val)
+            | Self::ResponseInformation(val)
+            | Self::ServerReference(val)
+            | Self::ReasonString(val) => encode_utf8(bytes, val)?,
+            Self::CorrelationData(val) | Self::AuthenticationData(val) => encode_bytes(bytes, val)?,
+            Self::UserProperty(key, val) => {
+                encode_utf8(bytes, key)?;
+                encode_utf8(bytes, val)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// User Properties and Subscription Identifiers may appear more than once, it is a Protocol Error to include
+    /// any other property more than once.
+    fn repeatable(&self) -> bool {
+        return matches!(
+            self,
+            Self::UserProperty(_, _) | Self::SubscriptionIdentifier(_)
+        );
+    }
+}
+
+/// The length prefixed set of properties of a packet, or of a Will message.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Properties(Vec<Property>);
+
+impl Properties {
+    pub fn new() -> Self {
+        return Self(vec![]);
+    }
+
+    /// Decodes the Property Length and the Properties, advancing the buffer past them.
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let len = decode_variable_int(bytes)?;
+
+        if len > bytes.remaining() {
+            return Err(decode_error!(
+                DecodeErrorKind::MalformedLength,
+                "Property length: {len} exceeded the remaining packet length: {}",
+                bytes.remaining()
+            ));
+        }
+
+        let mut buf = bytes.split_to(len);
+        let mut properties = Self::new();
+
+        while buf.has_remaining() {
+            let property = Property::decode(&mut buf)?;
+
+            if !property.repeatable() && properties.get(property.id()).is_some() {
+                return Err(decode_error!(
+                    DecodeErrorKind::ProtocolError,
+                    "Property: {:?} was included more than once.",
+                    property
+                ));
+            }
+
+            properties.0.push(property);
+        }
+
+        return Ok(properties);
+    }
+
+    /// Encodes the Property Length followed by the Properties.
+    pub fn encode(&self, bytes: &mut BytesMut) -> Result<(), EncodeError> {
+        let mut buf = BytesMut::new();
+        for property in self.0.iter() {
+            property.encode(&mut buf)?;
+        }
+
+        encode_packet_length(bytes, buf.len())?;
+        bytes.put_slice(&buf);
+        return Ok(());
+    }
+
+    /// Adds a property, replacing the existing value if the property cannot be repeated.
+    pub fn push(&mut self, property: Property) {
+        if !property.repeatable() {
+            self.0.retain(|p| p.id() != property.id());
+        }
+        self.0.push(property);
+    }
+
+    /// Returns the first property with the identifier.
+    pub fn get(&self, id: u8) -> Option<&Property> {
+        return self.0.iter().find(|p| p.id() == id);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Property> {
+        return self.0.iter();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.0.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.0.is_empty();
+    }
+
+    pub fn session_expiry_interval(&self) -> Option<u32> {
+        return self.iter().find_map(|p| match p {
+            Property::SessionExpiryInterval(val) => Some(*val),
+            _ => None,
+        });
+    }
+
+    pub fn receive_maximum(&self) -> Option<u16> {
+        return self.iter().find_map(|p| match p {
+            Property::ReceiveMaximum(val) => Some(*val),
+            _ => None,
+        });
+    }
+
+    pub fn topic_alias_maximum(&self) -> Option<u16> {
+        return self.iter().find_map(|p| match p {
+            Property::TopicAliasMaximum(val) => Some(*val),
+            _ => None,
+        });
+    }
+
+    pub fn topic_alias(&self) -> Option<u16> {
+        return self.iter().find_map(|p| match p {
+            Property::TopicAlias(val) => Some(*val),
+            _ => None,
+        });
+    }
+
+    pub fn maximum_packet_size(&self) -> Option<u32> {
+        return self.iter().find_map(|p| match p {
+            Property::MaximumPacketSize(val) => Some(*val),
+            _ => None,
+        });
+    }
+
+    pub fn will_delay_interval(&self) -> Option<u32> {
+        return self.iter().find_map(|p| match p {
+            Property::WillDelayInterval(val) => Some(*val),
+            _ => None,
+        });
+    }
+
+    pub fn reason_string(&self) -> Option<&str> {
+        return self.iter().find_map(|p| match p {
+            Property::ReasonString(val) => Some(val.as_str()),
+            _ => None,
+        });
+    }
+
+    pub fn authentication_method(&self) -> Option<&str> {
+        return self.iter().find_map(|p| match p {
+            Property::AuthenticationMethod(val) => Some(val.as_str()),
+            _ => None,
+        });
+    }
+
+    pub fn authentication_data(&self) -> Option<&Bytes> {
+        return self.iter().find_map(|p| match p {
+            Property::AuthenticationData(val) => Some(val),
+            _ => None,
+        });
+    }
+
+    pub fn user_properties(&self) -> impl Iterator<Item = (&str, &str)> {
+        return self.iter().filter_map(|p| match p {
+            Property::UserProperty(key, val) => Some((key.as_str(), val.as_str())),
+            _ => None,
+        });
+    }
+}
+
+impl From<Vec<Property>> for Properties {
+    fn from(value: Vec<Property>) -> Self {
+        let mut properties = Self::new();
+        for property in value {
+            properties.push(property);
+        }
+        return properties;
+    }
+}
+
+#[cfg(test)]
+mod properties {
+    use bytes::{Bytes, BytesMut};
+
+    use super::{Properties, Property};
+
+    #[test]
+    fn serialize_deserialize() {
+        let properties = Properties::from(vec![
+            Property::SessionExpiryInterval(3600),
+            Property::ReceiveMaximum(20),
+            Property::SubscriptionIdentifier(268_435_455),
+            Property::CorrelationData(Bytes::from_static(b"correlation")),
+            Property::UserProperty(String::from("key"), String::from("a")),
+            Property::UserProperty(String::from("key"), String::from("b")),
+        ]);
+
+        let mut buf = BytesMut::new();
+        properties.encode(&mut buf).unwrap();
+        buf.extend_from_slice(b"payload");
+
+        let mut bytes = Bytes::from(buf);
+        let properties_de = Properties::decode(&mut bytes).unwrap();
+
+        assert_eq!(properties_de, properties);
+        assert_eq!(properties_de.session_expiry_interval(), Some(3600));
+        assert_eq!(properties_de.user_properties().count(), 2);
+        // only the properties are consumed.
+        assert_eq!(bytes, Bytes::from_static(b"payload"));
+    }
+
+    #[test]
+    fn duplicate_property() {
+        // property length 6, two Receive Maximum properties.
+        let mut bytes = Bytes::from_static(&[6, 0x21, 0, 10, 0x21, 0, 20]);
+        assert!(Properties::decode(&mut bytes).is_err());
+
+        // pushing a duplicate replaces the value.
+        let mut properties = Properties::new();
+        properties.push(Property::ReceiveMaximum(10));
+        properties.push(Property::ReceiveMaximum(20));
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties.receive_maximum(), Some(20));
+    }
+
+    #[test]
+    fn invalid_property() {
+        let mut bytes = Bytes::from_static(&[2, 0x7F, 0]);
+        assert!(Properties::decode(&mut bytes).is_err());
+
+        // property length exceeds the packet.
+        let mut bytes = Bytes::from_static(&[10, 0x21, 0, 10]);
+        assert!(Properties::decode(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_string_property() {
+        // a Content Type with nothing after the property id.
+        let mut bytes = Bytes::from_static(&[1, 0x03]);
+        assert!(Properties::decode(&mut bytes).is_err());
+
+        // a Content Type ending partway through its length prefix.
+        let mut bytes = Bytes::from_static(&[2, 0x03, 0]);
+        assert!(Properties::decode(&mut bytes).is_err());
+    }
+}
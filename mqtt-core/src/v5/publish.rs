@@ -0,0 +1,237 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::{decode_u16, decode_utf8, encode_utf8},
+    qos::QosLevel,
+    topic::TopicName,
+    v5::{encode_packet, FixedHeader, PacketType, Properties},
+};
+
+const DUP: u8 = 0b0000_1000;
+const QOS_BITS: u8 = 0b0000_0110;
+const RETAIN: u8 = 0b0000_0001;
+
+/*
+ * A PUBLISH packet is sent from a Client to a Server or from a Server to a Client to transport an Application Message.
+ *
+ * Unlike v3.1.1 the Topic Name can be zero length when a Topic Alias property is set, in which case the topic is
+ * resolved from the alias previously sent on the same connection.
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub struct PublishPacket {
+    dup: bool,
+    qos: QosLevel,
+    retain: bool,
+    // None when the topic is carried by the Topic Alias property.
+    topic: Option<TopicName>,
+    packet_id: Option<u16>,
+    properties: Properties,
+    payload: Bytes,
+}
+
+impl PublishPacket {
+    pub fn new(topic: &TopicName, payload: Bytes) -> Self {
+        return Self {
+            dup: false,
+            qos: QosLevel::AtMostOnce,
+            retain: false,
+            topic: Some(topic.clone()),
+            packet_id: None,
+            properties: Properties::new(),
+            payload,
+        };
+    }
+
+    pub fn decode(f_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let qos = QosLevel::try_from((f_header.flags & QOS_BITS) >> 1)?;
+        let dup = f_header.flags & DUP == DUP;
+
+        // The DUP flag MUST be set to 0 for all QoS 0 messages [MQTT-3.3.1-2].
+        if dup && qos == QosLevel::AtMostOnce {
+            return Err(decode_error!(
+                DecodeErrorKind::FlagBits,
+                "DUP flag was set on a QoS 0 PUBLISH packet."
+            ));
+        }
+
+        let topic = decode_utf8(bytes)?;
+        let topic = match topic.len() {
+            0 => None,
+            _ => Some(TopicName::from_str(&topic)?),
+        };
+
+        let packet_id = match qos {
+            QosLevel::AtMostOnce => None,
+            _ => Some(decode_u16(bytes)?),
+        };
+
+        let properties = Properties::decode(bytes)?;
+
+        if topic.is_none() && properties.topic_alias().is_none() {
+            return Err(decode_error!(
+                DecodeErrorKind::MalformedTopicName,
+                "PUBLISH packet had an empty topic name and no topic alias."
+            ));
+        }
+
+        // the payload is the rest of the packet.
+        let payload = bytes.split_off(0);
+
+        return Ok(Self {
+            dup,
+            qos,
+            retain: f_header.flags & RETAIN == RETAIN,
+            topic,
+            packet_id,
+            properties,
+            payload,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        let mut body = BytesMut::with_capacity(self.payload.len() + 16);
+
+        match &self.topic {
+            Some(topic) => topic.encode(&mut body),
+            None => encode_utf8(&mut body, "")?,
+        }
+
+        if let Some(id) = self.packet_id {
+            body.put_u16(id);
+        }
+
+        self.properties.encode(&mut body)?;
+        body.put_slice(&self.payload);
+
+        let mut flags = (self.qos as u8) << 1;
+        if self.dup {
+            flags |= DUP;
+        }
+        if self.retain {
+            flags |= RETAIN;
+        }
+
+        return encode_packet(PacketType::PUBLISH as u8 | flags, &body);
+    }
+
+    pub fn topic(&self) -> Option<&TopicName> {
+        return self.topic.as_ref();
+    }
+
+    /// Clears the topic name, the topic must then be carried by a Topic Alias property.
+    pub fn set_topic(&mut self, topic: Option<TopicName>) {
+        self.topic = topic;
+    }
+
+    pub fn payload(&self) -> &Bytes {
+        return &self.payload;
+    }
+
+    pub fn id(&self) -> Option<u16> {
+        return self.packet_id;
+    }
+
+    pub fn qos(&self) -> QosLevel {
+        return self.qos;
+    }
+
+    pub fn set_qos_atmostonce(&mut self) {
+        self.qos = QosLevel::AtMostOnce;
+        self.packet_id = None;
+        self.dup = false;
+    }
+
+    pub fn set_qos_atleastonce(&mut self, id: u16) {
+        self.qos = QosLevel::AtLeastOnce;
+        self.packet_id = Some(id);
+    }
+
+    pub fn set_qos_exactlyonce(&mut self, id: u16) {
+        self.qos = QosLevel::ExactlyOnce;
+        self.packet_id = Some(id);
+    }
+
+    pub fn retain(&self) -> bool {
+        return self.retain;
+    }
+
+    pub fn set_retain(&mut self, val: bool) {
+        self.retain = val;
+    }
+
+    pub fn dup(&self) -> bool {
+        return self.dup;
+    }
+
+    pub fn set_dup(&mut self, val: bool) {
+        self.dup = val;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use bytes::{Buf, Bytes};
+
+    use super::PublishPacket;
+    use crate::{
+        topic::TopicName,
+        v5::{packet::round_trip, FixedHeader, MqttPacket, Property},
+    };
+
+    #[test]
+    fn serialize_deserialize() {
+        let topic = TopicName::from_str("sensors/temp").unwrap();
+        round_trip(MqttPacket::Publish(PublishPacket::new(
+            &topic,
+            Bytes::from_static(b"20"),
+        )));
+
+        let mut packet = PublishPacket::new(&topic, Bytes::from_static(b"21"));
+        packet.set_qos_exactlyonce(10);
+        packet.set_retain(true);
+        packet.set_dup(true);
+        packet
+            .properties_mut()
+            .push(Property::MessageExpiryInterval(60));
+        packet
+            .properties_mut()
+            .push(Property::ContentType(String::from("text/plain")));
+        round_trip(MqttPacket::Publish(packet));
+    }
+
+    #[test]
+    fn topic_alias() {
+        let mut packet = PublishPacket::new(
+            &TopicName::from_str("sensors/temp").unwrap(),
+            Bytes::from_static(b"20"),
+        );
+        packet.set_topic(None);
+
+        // an empty topic requires a topic alias.
+        let mut buf = packet.encode().unwrap();
+        let f_header = FixedHeader::decode(&mut buf).unwrap();
+        buf.advance(f_header.header_len());
+        assert!(MqttPacket::decode(f_header, &mut buf).is_err());
+
+        packet.properties_mut().push(Property::TopicAlias(1));
+        round_trip(MqttPacket::Publish(packet));
+    }
+
+    #[test]
+    fn truncated_topic() {
+        // a remaining length of 1 ends partway through the topic's length prefix.
+        let mut buf = Bytes::from_static(&[0x30, 0x01, 0x00]);
+        let f_header = FixedHeader::decode(&mut buf).unwrap();
+        buf.advance(f_header.header_len());
+        assert!(MqttPacket::decode(f_header, &mut buf).is_err());
+    }
+}
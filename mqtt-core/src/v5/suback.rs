@@ -0,0 +1,106 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{DecodeError, EncodeError},
+    io::{decode_u16, decode_u8},
+    v5::{encode_packet, PacketType, Properties, ReasonCode},
+};
+
+/*
+ * SUBACK and UNSUBACK share a layout, the packet Id and Properties followed by one Reason Code for each Topic Filter
+ * in the SUBSCRIBE or UNSUBSCRIBE packet being acknowledged, in the same order.
+ */
+macro_rules! reasons_packet {
+    ($(#[$doc:meta])* $name:ident, $packet_type:expr) => {
+        $(#[$doc])*
+        #[derive(PartialEq, Clone, Debug)]
+        pub struct $name {
+            id: u16,
+            properties: Properties,
+            reason_codes: Vec<ReasonCode>,
+        }
+
+        impl $name {
+            pub fn new(id: u16, reason_codes: Vec<ReasonCode>) -> Self {
+                return Self {
+                    id,
+                    properties: Properties::new(),
+                    reason_codes,
+                };
+            }
+
+            pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+                let id = decode_u16(bytes)?;
+                let properties = Properties::decode(bytes)?;
+
+                let mut reason_codes = vec![];
+                while bytes.has_remaining() {
+                    reason_codes.push(ReasonCode::try_from(decode_u8(bytes)?)?);
+                }
+
+                return Ok(Self {
+                    id,
+                    properties,
+                    reason_codes,
+                });
+            }
+
+            pub fn encode(&self) -> Result<Bytes, EncodeError> {
+                let mut body = BytesMut::new();
+                body.put_u16(self.id);
+                self.properties.encode(&mut body)?;
+
+                for code in self.reason_codes.iter() {
+                    body.put_u8((*code).into());
+                }
+
+                return encode_packet($packet_type as u8, &body);
+            }
+
+            pub fn id(&self) -> u16 {
+                return self.id;
+            }
+
+            pub fn reason_codes(&self) -> &Vec<ReasonCode> {
+                return &self.reason_codes;
+            }
+
+            pub fn properties(&self) -> &Properties {
+                return &self.properties;
+            }
+
+            pub fn properties_mut(&mut self) -> &mut Properties {
+                return &mut self.properties;
+            }
+        }
+    };
+}
+
+reasons_packet!(
+    /// The response to a SUBSCRIBE packet, each Reason Code is the granted QoS or the reason the subscription failed.
+    SubAckPacket,
+    PacketType::SUBACK
+);
+reasons_packet!(
+    /// The response to an UNSUBSCRIBE packet.
+    UnsubAckPacket,
+    PacketType::UNSUBACK
+);
+
+#[cfg(test)]
+mod packet {
+    use super::{SubAckPacket, UnsubAckPacket};
+    use crate::v5::{packet::round_trip, MqttPacket, ReasonCode};
+
+    #[test]
+    fn serialize_deserialize() {
+        round_trip(MqttPacket::SubAck(SubAckPacket::new(
+            7,
+            vec![ReasonCode::GrantedQoS2, ReasonCode::NotAuthorized],
+        )));
+        round_trip(MqttPacket::UnsubAck(UnsubAckPacket::new(
+            8,
+            vec![ReasonCode::Success, ReasonCode::NoSubscriptionExisted],
+        )));
+    }
+}
@@ -0,0 +1,233 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::{decode_u16, decode_u8, encode_utf8},
+    qos::QosLevel,
+    topic::TopicFilter,
+    v5::{encode_packet, PacketType, Properties},
+};
+
+const QOS_BITS: u8 = 0b0000_0011;
+const NO_LOCAL: u8 = 0b0000_0100;
+const RETAIN_AS_PUBLISHED: u8 = 0b0000_1000;
+const RETAIN_HANDLING_BITS: u8 = 0b0011_0000;
+const RESERVED_BITS: u8 = 0b1100_0000;
+
+/*
+ * The SUBSCRIBE packet is sent from the Client to the Server to create one or more Subscriptions.
+ *
+ * Each Topic Filter is followed by a Subscription Options byte, replacing the v3.1.1 Requested QoS byte.
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub struct SubscribePacket {
+    id: u16,
+    properties: Properties,
+    filters: Vec<(TopicFilter, SubscriptionOptions)>,
+}
+
+impl SubscribePacket {
+    pub fn new(id: u16, filters: Vec<(TopicFilter, SubscriptionOptions)>) -> Self {
+        return Self {
+            id,
+            properties: Properties::new(),
+            filters,
+        };
+    }
+
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let id = decode_u16(bytes)?;
+        let properties = Properties::decode(bytes)?;
+
+        let mut filters = vec![];
+        while bytes.has_remaining() {
+            let filter = TopicFilter::decode(bytes)?;
+            let options = SubscriptionOptions::from_byte(decode_u8(bytes)?)?;
+            filters.push((filter, options));
+        }
+
+        // The Payload MUST contain at least one Topic Filter and Subscription Options pair [MQTT-3.8.3-2].
+        if filters.len() == 0 {
+            return Err(decode_error!(
+                DecodeErrorKind::ProtocolError,
+                "SUBSCRIBE packet must contain at least one topic filter."
+            ));
+        }
+
+        return Ok(Self {
+            id,
+            properties,
+            filters,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        let mut body = BytesMut::new();
+        body.put_u16(self.id);
+        self.properties.encode(&mut body)?;
+
+        for (filter, options) in self.filters.iter() {
+            encode_utf8(&mut body, &filter.clone().to_string())?;
+            body.put_u8(options.as_byte());
+        }
+
+        return encode_packet(PacketType::SUBSCRIBE as u8 | 0b0000_0010, &body);
+    }
+
+    pub fn id(&self) -> u16 {
+        return self.id;
+    }
+
+    pub fn filters(&self) -> &Vec<(TopicFilter, SubscriptionOptions)> {
+        return &self.filters;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+/// Controls whether retained messages are sent when a subscription is established.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetainHandling {
+    SendOnSubscribe = 0,
+    SendOnNewSubscribe = 1,
+    DoNotSend = 2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SubscriptionOptions {
+    qos: QosLevel,
+    // messages MUST NOT be forwarded to a connection with a ClientID equal to the ClientID of the publishing connection.
+    no_local: bool,
+    // messages forwarded keep the RETAIN flag they were published with.
+    retain_as_published: bool,
+    retain_handling: RetainHandling,
+}
+
+impl SubscriptionOptions {
+    pub fn new(qos: QosLevel) -> Self {
+        return Self {
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: RetainHandling::SendOnSubscribe,
+        };
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, DecodeError> {
+        // Bits 6 and 7 of the Subscription Options byte are reserved and MUST be set to 0 [MQTT-3.8.3-5].
+        if byte & RESERVED_BITS != 0 {
+            return Err(decode_error!(
+                DecodeErrorKind::FlagBits,
+                "Subscription options reserved bits must be 0, received: {byte}"
+            ));
+        }
+
+        let retain_handling = match (byte & RETAIN_HANDLING_BITS) >> 4 {
+            0 => RetainHandling::SendOnSubscribe,
+            1 => RetainHandling::SendOnNewSubscribe,
+            2 => RetainHandling::DoNotSend,
+            _ => {
+                return Err(decode_error!(
+                    DecodeErrorKind::ProtocolError,
+                    "Retain handling cannot be set to 3"
+                ))
+            }
+        };
+
+        return Ok(Self {
+            qos: QosLevel::try_from(byte & QOS_BITS)?,
+            no_local: byte & NO_LOCAL == NO_LOCAL,
+            retain_as_published: byte & RETAIN_AS_PUBLISHED == RETAIN_AS_PUBLISHED,
+            retain_handling,
+        });
+    }
+
+    pub fn as_byte(&self) -> u8 {
+        let mut byte = self.qos as u8;
+        if self.no_local {
+            byte |= NO_LOCAL;
+        }
+        if self.retain_as_published {
+            byte |= RETAIN_AS_PUBLISHED;
+        }
+        return byte | ((self.retain_handling as u8) << 4);
+    }
+
+    pub fn qos(&self) -> QosLevel {
+        return self.qos;
+    }
+
+    pub fn no_local(&self) -> bool {
+        return self.no_local;
+    }
+
+    pub fn set_no_local(&mut self, val: bool) {
+        self.no_local = val;
+    }
+
+    pub fn retain_as_published(&self) -> bool {
+        return self.retain_as_published;
+    }
+
+    pub fn set_retain_as_published(&mut self, val: bool) {
+        self.retain_as_published = val;
+    }
+
+    pub fn retain_handling(&self) -> RetainHandling {
+        return self.retain_handling;
+    }
+
+    pub fn set_retain_handling(&mut self, val: RetainHandling) {
+        self.retain_handling = val;
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use super::{RetainHandling, SubscribePacket, SubscriptionOptions};
+    use crate::{
+        qos::QosLevel,
+        topic::TopicFilter,
+        v5::{packet::round_trip, MqttPacket, Property},
+    };
+
+    #[test]
+    fn serialize_deserialize() {
+        let mut options = SubscriptionOptions::new(QosLevel::ExactlyOnce);
+        options.set_no_local(true);
+        options.set_retain_as_published(true);
+        options.set_retain_handling(RetainHandling::DoNotSend);
+        assert_eq!(
+            SubscriptionOptions::from_byte(options.as_byte()).unwrap(),
+            options
+        );
+
+        let mut packet = SubscribePacket::new(
+            7,
+            vec![
+                (TopicFilter::from_str("sport/+/player1").unwrap(), options),
+                (
+                    TopicFilter::from_str("sensors/#").unwrap(),
+                    SubscriptionOptions::new(QosLevel::AtMostOnce),
+                ),
+            ],
+        );
+        packet
+            .properties_mut()
+            .push(Property::SubscriptionIdentifier(42));
+        round_trip(MqttPacket::Subscribe(packet));
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert!(SubscriptionOptions::from_byte(0b0100_0000).is_err());
+        assert!(SubscriptionOptions::from_byte(0b0011_0000).is_err());
+        assert!(SubscriptionOptions::from_byte(0b0000_0011).is_err());
+    }
+}
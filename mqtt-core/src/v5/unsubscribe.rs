@@ -0,0 +1,100 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    err::{decode_error, DecodeError, DecodeErrorKind, EncodeError},
+    io::{decode_u16, encode_utf8},
+    topic::TopicFilter,
+    v5::{encode_packet, PacketType, Properties},
+};
+
+/*
+ * An UNSUBSCRIBE packet is sent by the Client to the Server, to unsubscribe from topics.
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub struct UnsubscribePacket {
+    id: u16,
+    properties: Properties,
+    filters: Vec<TopicFilter>,
+}
+
+impl UnsubscribePacket {
+    pub fn new(id: u16, filters: Vec<TopicFilter>) -> Self {
+        return Self {
+            id,
+            properties: Properties::new(),
+            filters,
+        };
+    }
+
+    pub fn decode(bytes: &mut Bytes) -> Result<Self, DecodeError> {
+        let id = decode_u16(bytes)?;
+        let properties = Properties::decode(bytes)?;
+
+        let mut filters = vec![];
+        while bytes.has_remaining() {
+            filters.push(TopicFilter::decode(bytes)?);
+        }
+
+        // The Payload of an UNSUBSCRIBE packet MUST contain at least one Topic Filter [MQTT-3.10.3-2].
+        if filters.len() == 0 {
+            return Err(decode_error!(
+                DecodeErrorKind::ProtocolError,
+                "UNSUBSCRIBE packet must contain at least one topic filter."
+            ));
+        }
+
+        return Ok(Self {
+            id,
+            properties,
+            filters,
+        });
+    }
+
+    pub fn encode(&self) -> Result<Bytes, EncodeError> {
+        let mut body = BytesMut::new();
+        body.put_u16(self.id);
+        self.properties.encode(&mut body)?;
+
+        for filter in self.filters.iter() {
+            encode_utf8(&mut body, &filter.clone().to_string())?;
+        }
+
+        return encode_packet(PacketType::UNSUBSCRIBE as u8 | 0b0000_0010, &body);
+    }
+
+    pub fn id(&self) -> u16 {
+        return self.id;
+    }
+
+    pub fn filters(&self) -> &Vec<TopicFilter> {
+        return &self.filters;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        return &self.properties;
+    }
+
+    pub fn properties_mut(&mut self) -> &mut Properties {
+        return &mut self.properties;
+    }
+}
+
+#[cfg(test)]
+mod packet {
+    use super::UnsubscribePacket;
+    use crate::{
+        topic::TopicFilter,
+        v5::{packet::round_trip, MqttPacket},
+    };
+
+    #[test]
+    fn serialize_deserialize() {
+        round_trip(MqttPacket::Unsubscribe(UnsubscribePacket::new(
+            9,
+            vec![
+                TopicFilter::from_str("sport/+/player1").unwrap(),
+                TopicFilter::from_str("sensors/#").unwrap(),
+            ],
+        )));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{
+    tungstenite::{self, Message},
+    WebSocketStream,
+};
+
+/// Carries MQTT over the binary messages of a WebSocket connection.
+///
+/// MQTT packets are not aligned to WebSocket messages, a packet may span several messages and a message
+/// may hold several packets, so the connection is exposed as a byte stream.
+///
+/// The handshake is left to the broker and client, which wrap the resulting [WebSocketStream].
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    // bytes of the last binary message that have not been read yet.
+    read_buf: Bytes,
+    // true while a written message is being flushed to the underlying stream.
+    flushing: bool,
+}
+
+impl<S> WsStream<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        return Self {
+            inner,
+            read_buf: Bytes::new(),
+            flushing: false,
+        };
+    }
+}
+
+fn ws_error(err: tungstenite::Error) -> io::Error {
+    return io::Error::new(io::ErrorKind::Other, err);
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.read_buf.has_remaining() {
+                let len = this.read_buf.len().min(buf.remaining());
+                buf.put_slice(&this.read_buf.split_to(len));
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => this.read_buf = Bytes::from(data),
+                // a close frame or a closed connection is read as the end of the stream.
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                // MQTT Control Packets MUST be sent in WebSocket binary data frames [MQTT-6.0.0-1].
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Received a WebSocket text frame, MQTT packets must be sent in binary frames.",
+                    )));
+                }
+                // pings are answered by tungstenite.
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Poll::Ready(Err(ws_error(err))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    /// Each write is sent as a single binary message and flushed before the write completes.
+    ///
+    /// If the flush returns Pending the message has already been queued, so the caller must poll again
+    /// with the same buffer, as [tokio::io::AsyncWriteExt::write_all] does.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if !this.flushing {
+            ready!(Pin::new(&mut this.inner).poll_ready(cx)).map_err(ws_error)?;
+            Pin::new(&mut this.inner)
+                .start_send(Message::binary(buf.to_vec()))
+                .map_err(ws_error)?;
+            this.flushing = true;
+        }
+
+        // tungstenite buffers messages until the write buffer fills, which would hold back small packets indefinitely.
+        ready!(Pin::new(&mut this.inner).poll_flush(cx)).map_err(ws_error)?;
+        this.flushing = false;
+
+        return Poll::Ready(Ok(buf.len()));
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return Pin::new(&mut self.inner).poll_flush(cx).map_err(ws_error);
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        return Pin::new(&mut self.inner).poll_close(cx).map_err(ws_error);
+    }
+}